@@ -0,0 +1,33 @@
+use aimo_note_agent::{CachedNote, Note};
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+
+mod common;
+
+use common::{large_note_json, note_json};
+
+fn briefs(c: &mut Criterion) {
+    let json = large_note_json();
+
+    let mut group = c.benchmark_group("briefs");
+    group.sample_size(20);
+    group.bench_function("full_parse", |b| {
+        b.iter(|| Note::from_json(black_box(&json)).unwrap().get_brief())
+    });
+    group.bench_function("brief_only", |b| {
+        b.iter(|| Note::get_brief_from_json(black_box(&json)).unwrap())
+    });
+    group.finish();
+}
+
+fn repeated_briefs(c: &mut Criterion) {
+    let note = Note::from_json(&note_json(1000)).unwrap();
+    let mut cached = CachedNote::new(note.clone());
+
+    let mut group = c.benchmark_group("repeated_briefs");
+    group.bench_function("note", |b| b.iter(|| black_box(&note).get_brief()));
+    group.bench_function("cached_note", |b| b.iter(|| black_box(&mut cached).get_brief().len()));
+    group.finish();
+}
+
+criterion_group!(benches, briefs, repeated_briefs);
+criterion_main!(benches);
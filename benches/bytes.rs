@@ -0,0 +1,20 @@
+use aimo_note_agent::Note;
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+
+mod common;
+
+use common::large_note_json;
+
+fn decode(c: &mut Criterion) {
+    let json = large_note_json();
+    let bytes = Note::from_json(&json).unwrap().to_bytes().unwrap();
+
+    let mut group = c.benchmark_group("decode");
+    group.sample_size(20);
+    group.bench_function("json", |b| b.iter(|| Note::from_json(black_box(&json)).unwrap()));
+    group.bench_function("bytes", |b| b.iter(|| Note::from_bytes(black_box(&bytes)).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
@@ -0,0 +1,45 @@
+use aimo_note_agent::SCHEMA_VERSION;
+use serde_json::json;
+
+/// Generate a note JSON of about 2 MB, with the fields Lexical writes
+pub fn large_note_json() -> String {
+    note_json(8000)
+}
+
+/// Generate a note JSON with the number of root nodes, alternating paragraphs and lists
+#[allow(dead_code)]
+pub fn note_json(nodes: usize) -> String {
+    let text = |text: String, format: u32| {
+        json!({ "type": "text", "version": 1, "text": text, "format": format, "detail": 0, "mode": "normal", "style": "" })
+    };
+    let block = json!({ "version": 1, "direction": "ltr", "format": "", "indent": 0 });
+
+    let mut children = Vec::new();
+    for i in 0..nodes.div_ceil(2) {
+        let mut paragraph = block.clone();
+        paragraph["type"] = json!("paragraph");
+        paragraph["textFormat"] = json!(0);
+        paragraph["textStyle"] = json!("");
+        paragraph["children"] = json!([text(format!("Paragraph {i} with a few words in it"), 0), text(" and a bold tail".to_string(), 1)]);
+        children.push(paragraph);
+
+        let mut item = block.clone();
+        item["type"] = json!("listitem");
+        item["value"] = json!(1);
+        item["children"] = json!([text(format!("Item {i}"), 0)]);
+        let mut list = block.clone();
+        list["type"] = json!("list");
+        list["listType"] = json!("bullet");
+        list["start"] = json!(1);
+        list["tag"] = json!("ul");
+        list["children"] = json!([item]);
+        children.push(list);
+    }
+
+    json!({
+        "noteId": "large",
+        "schemaVersion": SCHEMA_VERSION,
+        "lexicalState": { "root": { "type": "root", "version": 1, "direction": "ltr", "format": "", "indent": 0, "children": children } },
+    })
+    .to_string()
+}
@@ -1,10 +1,10 @@
-use std::{future::Future, sync::Arc};
+use std::{collections::BTreeMap, future::Future, sync::Arc};
 
 use amico_core::{
     traits::{EventSource, Strategy}, types::{AgentEvent, Chat, ChatMessage, Interaction}, Agent, OnFinish
 };
 use anyhow::anyhow;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use tokio::{
     spawn,
     sync::{Mutex, mpsc},
@@ -12,25 +12,250 @@ use tokio::{
 };
 use tokio_with_wasm::alias as tokio;
 
-use crate::{note::Note, service::AimoModel};
+use crate::{
+    note::{
+        BriefId, BriefNode, BriefOptions, HeadingTag, LexicalNode, ListType, MessageSender, Note, OutlineEntry,
+        TextExtractOptions,
+    },
+    service::{AimoModel, ServiceError},
+};
+
+/// Resolves a mention name to a brief summary of the referenced note.
+pub trait MentionResolver {
+    fn resolve(&self, mention_name: &str) -> Option<String>;
+}
+
+impl<F> MentionResolver for F
+where
+    F: Fn(&str) -> Option<String>,
+{
+    fn resolve(&self, mention_name: &str) -> Option<String> {
+        self(mention_name)
+    }
+}
+
+impl MentionResolver for BTreeMap<String, String> {
+    fn resolve(&self, mention_name: &str) -> Option<String> {
+        self.get(mention_name).cloned()
+    }
+}
+
+/// Counts the tokens of a text, for budgeting the prompt.
+///
+/// The default is [`HeuristicTokenizer`]. Plug in the tokenizer of the model, such as
+/// a BPE tokenizer, for accurate counts.
+pub trait Tokenizer: Send + Sync {
+    fn count(&self, text: &str) -> usize;
+}
+
+impl<F> Tokenizer for F
+where
+    F: Fn(&str) -> usize + Send + Sync,
+{
+    fn count(&self, text: &str) -> usize {
+        self(text)
+    }
+}
+
+/// The default tokenizer, counting about 4 characters per token.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeuristicTokenizer;
+
+impl Tokenizer for HeuristicTokenizer {
+    fn count(&self, text: &str) -> usize {
+        estimate_tokens(text)
+    }
+}
+
+/// Limits on the size of the note shown to the model.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoteLimits {
+    /// The maximum number of briefed nodes
+    pub max_nodes: usize,
+    /// The maximum number of characters of node content
+    pub max_chars: usize,
+    /// The number of root nodes kept on each side of the cursor when a limit is exceeded
+    pub window_radius: usize,
+    /// The maximum number of tokens of node content, counted by the tokenizer, if any
+    pub max_tokens: Option<usize>,
+}
+
+impl Default for NoteLimits {
+    fn default() -> Self {
+        Self {
+            max_nodes: 500,
+            max_chars: 50_000,
+            window_radius: 20,
+            max_tokens: None,
+        }
+    }
+}
+
+impl NoteLimits {
+    /// Whether the briefs exceed the limits, counting tokens with [`HeuristicTokenizer`].
+    pub fn exceeded_by(&self, briefs: &[BriefNode]) -> bool {
+        self.exceeded_by_with(briefs, &HeuristicTokenizer)
+    }
+
+    /// Whether the briefs exceed the limits, counting tokens with the tokenizer.
+    pub fn exceeded_by_with(&self, briefs: &[BriefNode], tokenizer: &dyn Tokenizer) -> bool {
+        let chars: usize = briefs.iter().map(|brief| brief.content.chars().count()).sum();
+        let tokens_exceeded = self.max_tokens.is_some_and(|max_tokens| {
+            let tokens: usize = briefs.iter().map(|brief| tokenizer.count(&brief.content)).sum();
+            tokens > max_tokens
+        });
+        briefs.len() > self.max_nodes || chars > self.max_chars || tokens_exceeded
+    }
+}
+
+/// The tokens the chat format adds to each message, such as for the role.
+const TOKENS_PER_MESSAGE: usize = 4;
+
+/// Estimate the number of tokens of a text, at about 4 characters per token.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Estimate the number of tokens of the prompt for the messages, with the system prompt
+/// [`get_system_prompt`] builds.
+pub fn estimate_prompt_tokens(ctx: &ChatContext, messages: &[ChatMessage]) -> anyhow::Result<usize> {
+    estimate_prompt_tokens_with(ctx, messages, &NoteLimits::default(), &HeuristicTokenizer)
+}
+
+/// Count the tokens of the prompt for the messages with the tokenizer, with the note limited to the limits.
+pub fn estimate_prompt_tokens_with(
+    ctx: &ChatContext,
+    messages: &[ChatMessage],
+    limits: &NoteLimits,
+    tokenizer: &dyn Tokenizer,
+) -> anyhow::Result<usize> {
+    let system_prompt = build_system_prompt(ctx, None, limits, tokenizer)?;
+    let messages: usize = messages
+        .iter()
+        .map(|message| TOKENS_PER_MESSAGE + tokenizer.count(&message.content))
+        .sum();
+    Ok(TOKENS_PER_MESSAGE + tokenizer.count(&system_prompt) + messages)
+}
 
 pub fn get_system_prompt(ctx: &ChatContext) -> anyhow::Result<String> {
-    let brief_note = ctx.note.get_brief();
-    let brief_note_str = serde_json::to_string(&brief_note)?;
-    let cursor_position = ctx.cursor_position;
+    get_system_prompt_with_mentions(ctx, None)
+}
+
+/// Get the system prompt, including the notes referenced by mentions if a resolver is given.
+///
+/// Without a resolver, the referenced notes of the context are used.
+pub fn get_system_prompt_with_mentions(
+    ctx: &ChatContext,
+    resolver: Option<&dyn MentionResolver>,
+) -> anyhow::Result<String> {
+    build_system_prompt(ctx, resolver, &NoteLimits::default(), &HeuristicTokenizer)
+}
+
+/// Get the system prompt, windowing the note around the cursor if it exceeds the limits.
+///
+/// Fails if even the window exceeds the limits.
+pub fn get_system_prompt_with_limits(ctx: &ChatContext, limits: &NoteLimits) -> anyhow::Result<String> {
+    build_system_prompt(ctx, None, limits, &HeuristicTokenizer)
+}
+
+/// Get the system prompt like [`get_system_prompt_with_limits`], counting the tokens of the
+/// note with the tokenizer.
+pub fn get_system_prompt_with_tokenizer(
+    ctx: &ChatContext,
+    limits: &NoteLimits,
+    tokenizer: &dyn Tokenizer,
+) -> anyhow::Result<String> {
+    build_system_prompt(ctx, None, limits, tokenizer)
+}
+
+/// Build the system prompt
+fn build_system_prompt(
+    ctx: &ChatContext,
+    resolver: Option<&dyn MentionResolver>,
+    limits: &NoteLimits,
+    tokenizer: &dyn Tokenizer,
+) -> anyhow::Result<String> {
+    // Blank lines between blocks show the model the structure within a node, and
+    // empty nodes are kept so that the ids the model sees have no gaps
+    let text_options = BriefOptions {
+        text: TextExtractOptions::readable(),
+        include_empty: true,
+        include_section: true,
+    };
+    let (mut brief_note, outline) = match ctx.mode {
+        ContextMode::Full => (ctx.note.get_brief_with(&text_options), String::new()),
+        ContextMode::Section => (
+            ctx.note.get_brief_in_section_with(ctx.cursor_position.node_index, &text_options),
+            get_outline(&ctx.note),
+        ),
+    };
+    let mut omitted_notice = String::new();
+    if limits.exceeded_by_with(&brief_note, tokenizer) {
+        let window = ctx.note.get_brief_window_with(
+            ctx.cursor_position.node_index,
+            limits.window_radius,
+            limits.window_radius,
+            &text_options,
+        );
+        if limits.exceeded_by_with(&window.briefs, tokenizer) {
+            return Err(match limits.max_tokens {
+                Some(max_tokens) => anyhow!(
+                    "The note is too large: the nodes around the cursor exceed {} nodes, {} characters or {} tokens",
+                    limits.max_nodes,
+                    limits.max_chars,
+                    max_tokens
+                ),
+                None => anyhow!(
+                    "The note is too large: the nodes around the cursor exceed {} nodes or {} characters",
+                    limits.max_nodes,
+                    limits.max_chars
+                ),
+            });
+        }
+
+        tracing::info!(
+            "Note exceeds the limits, showing nodes around the cursor ({} omitted before, {} after)",
+            window.omitted_before,
+            window.omitted_after
+        );
+        omitted_notice = format!(
+            "\nThe note is too large to show in full. Only the nodes around the cursor are shown: \
+{} nodes before and {} nodes after them are omitted. The ids are the positions in the full note.\n",
+            window.omitted_before, window.omitted_after
+        );
+        brief_note = window.briefs;
+    }
+    let brief_note_str = match ctx.brief_format {
+        BriefFormat::Json => format!(
+            "Here's the structured note the user is working on. The `section` of a node is the heading it is under, \
+such as `H2: Budget`.\n\n```json\n{}\n```",
+            BriefNode::to_json(&brief_note)
+        ),
+        BriefFormat::Compact => format!(
+            "Here's the structured note the user is working on, one node per line as `id|node_type|content`. \
+In the content, a newline is written `\\n` and a backslash `\\\\`. A `# H2: Budget` line starts the nodes \
+under that heading.\n\n```text\n{}\n```",
+            BriefNode::to_compact(&brief_note)
+        ),
+    };
+    let referenced_notes = resolver
+        .or((!ctx.referenced_notes.is_empty()).then_some(&ctx.referenced_notes as &dyn MentionResolver))
+        .map(|resolver| get_referenced_notes(&ctx.note, resolver))
+        .unwrap_or_default();
+    let cursor_position = ctx.cursor_position.node_index;
     let insert_after = if cursor_position == 0 { 0 } else { cursor_position - 1 };
+    let cursor_offset = get_cursor_offset(ctx)?;
 
     let prompt = format!(
         "You are a helpful assistant, AiMo, that can help with note-taking.
 
 ## Environment Inspection
 
-Here's the structured note the user is working on:
-
-```json
 {brief_note_str}
-```
 
+Empty nodes, such as blank lines, are listed with an empty content.
+Code blocks are shown fenced with their language, and inline code in backticks.
+{omitted_notice}{outline}{referenced_notes}
 The user is currently requesting to do something at node {cursor_position} in the note.
 
 ## Your Task
@@ -38,21 +263,34 @@ The user is currently requesting to do something at node {cursor_position} in th
 You are given the content of the note that the user is working on, and the messages you have had with the user.
 You need to chat with the user to determine what they want to do with the note.
 When you have determined what the user wants to do, you need to take actions to help the user.
-You can only take one action at a time.
+Take one action per reply, or a batch of actions when the request needs several of them.
 
-Notice the user's cursor position is at node {cursor_position} in the note. Modify around the cursor position.
+Notice the user's cursor position is at node {cursor_position} in the note.{cursor_offset} Modify around the cursor position.
 If the cursor position doesn't contain any node, you can insert a new node at the cursor position. 
 (the insert_after field in the `insert_node` action should be {insert_after} here)
 
 ## Rules
 
 - You must always reply to the user in the same language as the user's messages.
-- For the `insert_node` and `modify_node` actions, you must always reply with a JSON string, and **DO NOT** include any other text or the code frame.
+- For the `set_title`, `insert_node`, `append_node`, `insert_code_block`, `insert_table`, `modify_node`, `replace_range`, `delete_node`, `move_node`, `set_heading_level`, `format_text` and `convert_to_list` actions and for batches, you must always reply with a JSON string, and **DO NOT** include any other text or the code frame.
 - You can find previous actions in the messages. If the action is not valid, the user will tell you.
 - If you find you have already take an action in the messages but the user wants you to modify your action, just re-generate the action based on the original note content.
 
 ## Available Actions
 
+### Rename the note
+
+You can change the title of the note, such as when the user asks to rename it. The title is the first H1 or H2 heading, which gets the new text, or a new H1 at the top of the note if there is none.
+
+Reply to the user with the following JSON format, but remember: Just reply with a raw JSON string, do not include any other text or the code frame.
+
+For example, to rename the note to Project Phoenix:
+
+{{
+    \"action\": \"set_title\",
+    \"content\": \"Project Phoenix\"
+}}
+
 ### Insert a new node
 
 You can insert a new node after a specific node.
@@ -69,9 +307,86 @@ For example:
     \"content\": \"Hello, world!\"
 }}
 
+For a `code` node, you can add the `language` of the code, such as \"rust\" or \"python\".
+
+For content you write for the user, such as a summary or a draft, use the `ai-embedding` node type, so that the note shows the block as generated and records the request it answers.
+
+For content that a type and text can't express, such as a list or a link, give the complete Lexical node JSON in `node` instead of `node_type` and `content`. Every node needs its `type` and `\"version\": 1`, and text nodes a `format`. For example, to insert a bullet list after node 3:
+
+{{
+    \"action\": \"insert_node\",
+    \"insert_after\": 3,
+    \"node\": {{
+        \"type\": \"list\",
+        \"version\": 1,
+        \"listType\": \"bullet\",
+        \"children\": [
+            {{ \"type\": \"listitem\", \"version\": 1, \"children\": [{{ \"type\": \"text\", \"version\": 1, \"text\": \"Milk\", \"format\": 0 }}] }},
+            {{ \"type\": \"listitem\", \"version\": 1, \"children\": [{{ \"type\": \"text\", \"version\": 1, \"text\": \"Eggs\", \"format\": 0 }}] }}
+        ]
+    }}
+}}
+
+For content of several blocks, such as a heading with a list under it, give Markdown in `content` with `\"content_format\": \"markdown\"` and no `node_type`. The blocks are inserted one after another. Headings, paragraphs, `-` and `1.` lists nested by indenting two spaces, `>` quotes, fenced code and pipe tables are converted, other Markdown such as `**bold**` stays as written. For example:
+
+{{
+    \"action\": \"insert_node\",
+    \"insert_after\": 3,
+    \"content_format\": \"markdown\",
+    \"content\": \"## Shopping\\n\\n- Milk\\n- Eggs\\n  - A dozen\"
+}}
+
+`modify_node` takes a `node` or Markdown `content` the same way, replacing the node entirely, with all the blocks of the Markdown.
+
+### Add a node at the end of the note
+
+To add content at the end of the note, such as a summary, append it instead of inserting it after the last id you see: `append_node` always adds the node after the last node of the note, whatever ids are shown to you. It takes the same `node_type` and `content` as `insert_node`.
+
+Reply to the user with the following JSON format, but remember: Just reply with a raw JSON string, do not include any other text or the code frame.
+
+For example:
+
+{{
+    \"action\": \"append_node\",
+    \"node_type\": \"paragraph\",
+    \"content\": \"In summary, the launch moves to May.\"
+}}
+
+### Insert a code block
+
+You can insert a code block after a specific node, with the `language` of the code for highlighting. Put the code itself in `content`, without a fence.
+
+Reply to the user with the following JSON format, but remember: Just reply with a raw JSON string, do not include any other text or the code frame.
+
+For example, to insert a Python snippet after node 2:
+
+{{
+    \"action\": \"insert_code_block\",
+    \"insert_after\": 2,
+    \"language\": \"python\",
+    \"content\": \"def greet(name):\\n    print(f\\\"Hello, {{name}}!\\\")\"
+}}
+
+### Insert a table
+
+You can insert a table after a specific node. Give the cell texts row by row in `rows`, the first row is the header row. Rows shorter than the longest one are filled with empty cells.
+
+Reply to the user with the following JSON format, but remember: Just reply with a raw JSON string, do not include any other text or the code frame.
+
+For example, to insert a table of two quarters after node 1:
+
+{{
+    \"action\": \"insert_table\",
+    \"insert_after\": 1,
+    \"rows\": [
+        [\"Quarter\", \"Revenue\", \"Growth\"],
+        [\"Q1\", \"10k\", \"5%\"]
+    ]
+}}
+
 ### Modify a node
 
-You can modify a specific node.
+You can modify a specific node. The new content keeps the formatting of the node's text, such as bold, so use `format_text` to change it.
 
 Reply to the user with the following JSON format, but remember: Just reply with a raw JSON string, do not include any other text or the code frame.
 
@@ -84,6 +399,120 @@ For example:
     \"content\": \"Hello, world!\"
 }}
 
+### Change a heading level
+
+You can change the level of a heading node, from `h1` (the largest) to `h6`. The node must be a heading.
+
+Reply to the user with the following JSON format, but remember: Just reply with a raw JSON string, do not include any other text or the code frame.
+
+For example, to make node 3 an H2:
+
+{{
+    \"action\": \"set_heading_level\",
+    \"id\": 3,
+    \"tag\": \"h2\"
+}}
+
+### Format the text of a node
+
+You can make all the text in a node bold, italic, underline, strikethrough or code.
+Set `remove` to true to remove the formats instead.
+
+Reply to the user with the following JSON format, but remember: Just reply with a raw JSON string, do not include any other text or the code frame.
+
+For example, to make node 2 bold and italic:
+
+{{
+    \"action\": \"format_text\",
+    \"id\": 2,
+    \"format\": [\"bold\", \"italic\"]
+}}
+
+### Convert paragraphs to a list
+
+You can turn a paragraph into a list with the paragraph as its only item. The `list_type` is either `bullet` or `number`.
+To turn a range of paragraphs into one list with an item per paragraph, set `end_id` to the last node of the range.
+
+Reply to the user with the following JSON format, but remember: Just reply with a raw JSON string, do not include any other text or the code frame.
+
+For example, to make nodes 4 to 6 a numbered list:
+
+{{
+    \"action\": \"convert_to_list\",
+    \"id\": 4,
+    \"end_id\": 6,
+    \"list_type\": \"number\"
+}}
+
+### Replace a range of nodes
+
+You can rewrite several consecutive nodes at once, such as a whole section, instead of modifying them one by one. The nodes from `start` to `end`, both included, are replaced by the new `nodes`, which may be fewer or more than the replaced ones. Each new node has a `node_type` and `content` like in `insert_node`.
+
+Reply to the user with the following JSON format, but remember: Just reply with a raw JSON string, do not include any other text or the code frame.
+
+For example, if nodes 2 to 4 are a heading and two paragraphs about the budget, to rewrite them as a heading and a single paragraph:
+
+{{
+    \"action\": \"replace_range\",
+    \"start\": 2,
+    \"end\": 4,
+    \"nodes\": [
+        {{ \"node_type\": \"h2\", \"content\": \"Budget\" }},
+        {{ \"node_type\": \"paragraph\", \"content\": \"We will spend 10k on hardware and 5k on hosting.\" }}
+    ]
+}}
+
+The nodes after the range then shift by the difference in length, here node 5 becomes node 4.
+
+### Delete a node
+
+You can delete a specific node, such as a paragraph the user wants removed. The nodes after it move up by one.
+
+Reply to the user with the following JSON format, but remember: Just reply with a raw JSON string, do not include any other text or the code frame.
+
+For example, to delete node 2:
+
+{{
+    \"action\": \"delete_node\",
+    \"id\": 2
+}}
+
+### Move a node
+
+You can move a specific node to another position, such as moving the conclusion above the summary. The node is first taken out of the note, then inserted so that it becomes node `to` among the remaining nodes.
+
+Reply to the user with the following JSON format, but remember: Just reply with a raw JSON string, do not include any other text or the code frame.
+
+For example, in a note with nodes 0 to 4, to move node 4 above node 1:
+
+{{
+    \"action\": \"move_node\",
+    \"id\": 4,
+    \"to\": 1
+}}
+
+To move node 1 below node 4 instead, use `\"to\": 4`, because node 4 becomes node 3 once node 1 is taken out.
+
+After a move, the ids of the nodes between the old and the new position shift by one. Do not chain another action on the old ids: the actions after it, including the later actions of a batch, must use the updated ids.
+
+### Do several actions at once
+
+When the user's request needs more than one action, such as inserting a heading and a paragraph under it, reply with a batch of up to {MAX_BATCH_ACTIONS} actions. They are done in order, and if one fails, none is done.
+
+The ids in each action refer to the note as left by the previous actions of the batch, not to the note shown above. For example, after inserting a node after node 2, the old node 3 is node 4.
+
+Reply to the user with the following JSON format, but remember: Just reply with a raw JSON string, do not include any other text or the code frame.
+
+For example, to insert a heading after node 2, and a paragraph under it:
+
+{{
+    \"action\": \"batch\",
+    \"actions\": [
+        {{ \"action\": \"insert_node\", \"insert_after\": 2, \"node_type\": \"h2\", \"content\": \"Next steps\" }},
+        {{ \"action\": \"insert_node\", \"insert_after\": 3, \"node_type\": \"paragraph\", \"content\": \"Book the venue.\" }}
+    ]
+}}
+
 ### Reply to the user
 
 If you can't determine what the user wants to do, you can reply to the user with a message to request more information.
@@ -103,52 +532,214 @@ Or:
     Ok(prompt)
 }
 
+/// Get the prompt section with the document outline, for when only the cursor's section is shown.
+fn get_outline(note: &Note) -> String {
+    fn render(entries: &[OutlineEntry], depth: usize, lines: &mut Vec<String>) {
+        for entry in entries {
+            lines.push(format!("{}- {} (node {})", "  ".repeat(depth), entry.text, entry.index));
+            render(&entry.children, depth + 1, lines);
+        }
+    }
+
+    let mut lines = Vec::new();
+    render(&note.outline(), 0, &mut lines);
+    if lines.is_empty() {
+        return String::new();
+    }
+
+    format!(
+        "\nOnly the section containing the cursor is shown above. The note has the following headings:\n\n{}\n",
+        lines.join("\n")
+    )
+}
+
+/// Describe the character offset of the cursor within its node.
+///
+/// Fails if the offset is beyond the text of the node.
+fn get_cursor_offset(ctx: &ChatContext) -> anyhow::Result<String> {
+    let CursorPosition { node_index, char_offset: Some(offset) } = ctx.cursor_position else {
+        return Ok(String::new());
+    };
+
+    let text = ctx
+        .note
+        .node_plain_text(node_index)
+        .ok_or_else(|| anyhow!("The cursor offset is set, but node {} is not in the note", node_index))?;
+    let len = text.chars().count();
+    if offset > len {
+        return Err(anyhow!(
+            "The cursor offset {} is beyond the {} characters of node {}",
+            offset,
+            len,
+            node_index
+        ));
+    }
+
+    Ok(format!(" The cursor is {offset} characters into node {node_index}, which has {len} characters."))
+}
+
+/// Get the prompt section with the notes referenced by mentions in the note.
+///
+/// Returns an empty string if no mention could be resolved.
+fn get_referenced_notes(note: &Note, resolver: &dyn MentionResolver) -> String {
+    let referenced: Vec<String> = note
+        .mention_names()
+        .into_iter()
+        .filter_map(|name| {
+            resolver
+                .resolve(&name)
+                .map(|summary| format!("### {name}\n\n{summary}\n"))
+        })
+        .collect();
+
+    if referenced.is_empty() {
+        return String::new();
+    }
+
+    format!(
+        "\n## Referenced Notes\n\nThe note mentions the following notes:\n\n{}",
+        referenced.join("\n")
+    )
+}
+
 /// The event source for frontend to send chat to the agent.
 #[derive(Debug)]
 pub struct ChatSource {
     chat_rx: Arc<Mutex<mpsc::Receiver<Chat>>>,
-    reply_tx: mpsc::Sender<String>,
+    reply_tx: mpsc::Sender<Result<String, ServiceError>>,
+    /// Shared with the strategy, set when a chat fails
+    errors: ErrorSlot,
 }
 
+/// The sender of the content deltas of the chat being streamed, if any.
+type DeltaSlot = Arc<std::sync::Mutex<Option<mpsc::UnboundedSender<String>>>>;
+
+/// The error of the last failed chat, which the agent runtime only logs, for the event
+/// source to send back instead of a reply.
+type ErrorSlot = Arc<std::sync::Mutex<Option<ServiceError>>>;
+
 /// The handler for communication between frontend and agent.
-#[derive(Debug)]
 pub struct ChatHandler {
     chat_tx: mpsc::Sender<Chat>,
-    reply_rx: Arc<Mutex<mpsc::Receiver<String>>>,
+    reply_rx: Arc<Mutex<mpsc::Receiver<Result<String, ServiceError>>>>,
+    limits: NoteLimits,
+    tokenizer: Arc<dyn Tokenizer>,
+    deltas: DeltaSlot,
+}
+
+impl std::fmt::Debug for ChatHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The tokenizer may be any type, so it is left out
+        f.debug_struct("ChatHandler")
+            .field("chat_tx", &self.chat_tx)
+            .field("reply_rx", &self.reply_rx)
+            .field("limits", &self.limits)
+            .field("deltas", &self.deltas)
+            .finish_non_exhaustive()
+    }
 }
 
 impl ChatHandler {
+    /// Limit the size of the note shown to the model.
+    pub fn with_limits(mut self, limits: NoteLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Count tokens with the tokenizer, for the token limit and estimates.
+    pub fn with_tokenizer(mut self, tokenizer: impl Tokenizer + 'static) -> Self {
+        self.tokenizer = Arc::new(tokenizer);
+        self
+    }
+
+    /// Estimate the number of tokens of the prompt [`ChatHandler::chat`] would send for the messages.
+    pub fn estimate_prompt_tokens(&self, ctx: &ChatContext, messages: &[ChatMessage]) -> anyhow::Result<usize> {
+        let messages: Vec<ChatMessage> = messages
+            .iter()
+            .filter(|message| !message.content.trim().is_empty())
+            .cloned()
+            .collect();
+        estimate_prompt_tokens_with(ctx, &messages, &self.limits, self.tokenizer.as_ref())
+    }
+
     /// Send a chat to the agent and wait for the reply.
     pub async fn chat(&mut self, chat: Chat, ctx: &ChatContext) -> anyhow::Result<ChatAction> {
-        // Add the system prompt to the chat.
+        let chat = self.with_system_prompt(chat, ctx)?;
+
+        // Send the chat to the agent.
+        self.chat_tx.send(chat).await.unwrap_or_else(|err| {
+            tracing::error!("Failed to send chat: {}", err);
+        });
+
+        self.receive_reply().await
+    }
+
+    /// Send a chat to the agent, calling `on_delta` with each piece of the reply as it streams in.
+    ///
+    /// Resolves with the action parsed from the whole reply.
+    pub async fn chat_stream(
+        &mut self,
+        chat: Chat,
+        ctx: &ChatContext,
+        mut on_delta: impl FnMut(&str),
+    ) -> anyhow::Result<ChatAction> {
+        let chat = self.with_system_prompt(chat, ctx)?;
+
+        // The strategy takes the sender and drops it once the completion is done
+        let (delta_tx, mut delta_rx) = mpsc::unbounded_channel();
+        *self.deltas.lock().unwrap_or_else(|err| err.into_inner()) = Some(delta_tx);
+
+        if let Err(err) = self.chat_tx.send(chat).await {
+            tracing::error!("Failed to send chat: {}", err);
+            self.deltas.lock().unwrap_or_else(|err| err.into_inner()).take();
+        }
+
+        while let Some(delta) = delta_rx.recv().await {
+            on_delta(&delta);
+        }
+
+        self.receive_reply().await
+    }
+
+    /// Add the system prompt to the chat, leaving out whitespace-only messages.
+    ///
+    /// Fails with [`ServiceError::NoMessages`] if no message is left.
+    fn with_system_prompt(&self, chat: Chat, ctx: &ChatContext) -> anyhow::Result<Chat> {
+        let chat_messages: Vec<ChatMessage> = chat
+            .messages
+            .into_iter()
+            .filter(|message| !message.content.trim().is_empty())
+            .collect();
+        if chat_messages.is_empty() {
+            return Err(ServiceError::NoMessages.into());
+        }
+
         let mut messages = Vec::new();
         messages.push(ChatMessage {
-            content: get_system_prompt(ctx)?,
+            content: get_system_prompt_with_tokenizer(ctx, &self.limits, self.tokenizer.as_ref())?,
             role: "system".to_string(),
         });
-        messages.extend(chat.messages);
+        messages.extend(chat_messages);
 
-        // Create a new chat with the system prompt.
-        let chat = Chat {
+        Ok(Chat {
             messages,
             session_id: chat.session_id,
-        };
-
-        // Send the chat to the agent.
-        self.chat_tx.send(chat).await.unwrap_or_else(|err| {
-            tracing::error!("Failed to send chat: {}", err);
-        });
+        })
+    }
 
-        // Receive the reply from the agent.
+    /// Receive the reply of the agent and parse it to a chat action
+    async fn receive_reply(&mut self) -> anyhow::Result<ChatAction> {
         let reply = self.reply_rx.lock().await.recv().await.unwrap_or_else(|| {
             tracing::error!("Failed to receive reply: channel closed");
-            "Failed to receive reply".to_string()
-        });
+            Err(ServiceError::NoReply)
+        })?;
 
         tracing::info!("Received reply: {}", reply);
 
         // Parse the reply to a chat action.
-        Ok(ChatAction::try_from_reply(reply)?)
+        let action = ChatAction::try_from_reply(reply)?;
+        tracing::info!("Parsed action: {}", action);
+        Ok(action)
     }
 }
 
@@ -158,7 +749,76 @@ impl ChatHandler {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatContext {
     pub note: Note,
-    pub cursor_position: usize,
+    pub cursor_position: CursorPosition,
+    #[serde(default)]
+    pub mode: ContextMode,
+    #[serde(default)]
+    pub brief_format: BriefFormat,
+    /// The brief summaries of the notes the note mentions, by mention name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub referenced_notes: BTreeMap<String, String>,
+}
+
+/// How much of the note is shown to the agent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextMode {
+    /// The whole note
+    #[default]
+    Full,
+    /// The heading section containing the cursor, with the outline of the note
+    Section,
+}
+
+/// How the note briefs are written in the system prompt.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BriefFormat {
+    /// A JSON array of briefs
+    #[default]
+    Json,
+    /// One `id|node_type|content` line per brief, see [`BriefNode::to_compact`]
+    Compact,
+}
+
+/// The position of the cursor in the note.
+///
+/// Deserializes from a bare root node index too, as written before offsets were tracked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CursorPosition {
+    /// The index of the root node at the cursor
+    pub node_index: usize,
+    /// The character offset of the cursor within the node's text
+    pub char_offset: Option<usize>,
+}
+
+impl From<usize> for CursorPosition {
+    fn from(node_index: usize) -> Self {
+        Self {
+            node_index,
+            char_offset: None,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CursorPosition {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Index(usize),
+            Position {
+                node_index: usize,
+                #[serde(default)]
+                char_offset: Option<usize>,
+            },
+        }
+
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Index(node_index) => node_index.into(),
+            Repr::Position { node_index, char_offset } => Self { node_index, char_offset },
+        })
+    }
 }
 
 /// The action for the agent.
@@ -171,9 +831,80 @@ pub enum ChatAction {
     InsertNode(InsertNode),
     /// The action to modify a node.
     ModifyNode(ModifyNode),
+    /// The action to change the level of a heading.
+    SetHeadingLevel(SetHeadingLevel),
+    /// The action to format the text of a node.
+    FormatText(FormatText),
+    /// The action to turn paragraphs into a list.
+    ConvertToList(ConvertToList),
+    /// The action to delete a node.
+    DeleteNode(DeleteNode),
+    /// The action to move a node to another position.
+    MoveNode(MoveNode),
+    /// The action to replace a range of nodes with new nodes.
+    ReplaceRange(ReplaceRange),
+    /// The action to insert a code block with its language.
+    InsertCodeBlock(InsertCodeBlock),
+    /// The action to insert a table.
+    InsertTable(InsertTable),
+    /// Actions done in order, where either all of them or none apply.
+    ///
+    /// Serialized as `{"action": "batch", "actions": [...]}`, like the model writes it.
+    #[serde(serialize_with = "serialize_batch", deserialize_with = "deserialize_batch")]
+    Batch(Vec<ChatAction>),
+    /// The action to add a node at the end of the note.
+    AppendNode(AppendNode),
+    /// The action to change the title of the note.
+    SetTitle(SetTitle),
 }
 
 impl ChatAction {
+    /// Get the operation id of an action that changes the note.
+    pub fn op_id(&self) -> Option<&str> {
+        match self {
+            Self::Reply(_) => None,
+            Self::InsertNode(insert) => insert.op_id.as_deref(),
+            Self::ModifyNode(modify) => modify.op_id.as_deref(),
+            Self::SetHeadingLevel(set_level) => set_level.op_id.as_deref(),
+            Self::FormatText(format) => format.op_id.as_deref(),
+            Self::ConvertToList(convert) => convert.op_id.as_deref(),
+            Self::DeleteNode(delete) => delete.op_id.as_deref(),
+            Self::MoveNode(move_node) => move_node.op_id.as_deref(),
+            Self::ReplaceRange(replace) => replace.op_id.as_deref(),
+            Self::InsertCodeBlock(insert) => insert.op_id.as_deref(),
+            Self::InsertTable(insert) => insert.op_id.as_deref(),
+            // The actions of a batch have their own operation ids
+            Self::Batch(_) => None,
+            Self::AppendNode(append) => append.op_id.as_deref(),
+            Self::SetTitle(set_title) => set_title.op_id.as_deref(),
+        }
+    }
+
+    /// Whether applying the action changes the title of the note, so that the frontend
+    /// updates the note list.
+    pub fn changes_title(&self) -> bool {
+        match self {
+            Self::SetTitle(_) => true,
+            Self::Batch(actions) => actions.iter().any(Self::changes_title),
+            _ => false,
+        }
+    }
+
+    /// Save a chat exchange in a chat session of the note, creating the session if needed.
+    ///
+    /// The last user message of the chat is saved, then the action: a reply with its text,
+    /// another action with its one-line form.
+    pub fn save_exchange(&self, note: &mut Note, session_id: &str, chat: &Chat) {
+        if let Some(message) = chat.messages.iter().rev().find(|message| message.role == "user") {
+            note.append_to_session(session_id, MessageSender::User, message.content.as_str());
+        }
+        let content = match self {
+            Self::Reply(reply) => reply.content.clone(),
+            action => action.to_string(),
+        };
+        note.append_to_session(session_id, MessageSender::Agent, content);
+    }
+
     /// Parse the reply to a chat action.
     pub fn try_from_reply(reply: String) -> anyhow::Result<Self> {
         // First, trim empty characters (spaces, newlines, etc.) from start and end
@@ -186,37 +917,317 @@ impl ChatAction {
         // Trim empty characters again after removing code frame
         let reply = reply.trim();
 
-        // If the reply starts with `{`, it's a JSON string. Try to parse it.
-        if reply.starts_with("{") {
-            let parsed_json: serde_json::Value = serde_json::from_str(reply)?;
+        // If the reply starts with `{`, or `[` for a batch, it's a JSON string. Try to parse it.
+        if reply.starts_with("{") || reply.starts_with("[") {
+            let parsed_json: serde_json::Value = match serde_json::from_str(reply) {
+                Ok(parsed_json) => parsed_json,
+                Err(err) => {
+                    // The reply may have been cut off by the token limit.
+                    let Some(repaired) = repair_truncated_json(reply) else {
+                        return Err(err.into());
+                    };
+
+                    tracing::warn!("Repairing truncated JSON reply");
+                    return serde_json::from_str(&repaired)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|parsed_json| Self::from_json(parsed_json, reply))
+                        .or_else(|err| {
+                            tracing::error!("Failed to repair truncated reply: {}", err);
+                            Ok(Self::Reply(RESEND_REPLY.into()))
+                        });
+                }
+            };
+
+            return Self::from_json(parsed_json, reply);
+        }
 
-            // Try to parse the action.
-            return parsed_json.get("action").map(|action_type| {
-                tracing::info!("Parsed action type: {}", action_type);
+        // If the reply does not start with `{`, it's a normal text reply.
+        // So we can just return it as a reply.
+        Ok(Self::Reply(reply.into()))
+    }
 
-                match action_type.as_str() {
-                    Some("insert_node") => Ok(Self::InsertNode(serde_json::from_value::<InsertNode>(parsed_json.clone())?)),
-                    Some("modify_node") => Ok(Self::ModifyNode(serde_json::from_value::<ModifyNode>(parsed_json.clone())?)),
+    /// Parse the action from a JSON reply.
+    fn from_json(parsed_json: serde_json::Value, reply: &str) -> anyhow::Result<Self> {
+        // A batch may be a bare array of actions
+        if let serde_json::Value::Array(actions) = parsed_json {
+            return Self::batch_from_json(actions);
+        }
 
-                    // If the agent choose to reply in an action, we can also handle it.
-                    Some("reply") => Ok(Self::Reply(serde_json::from_value::<Reply>(parsed_json.clone())?)),
+        // Try to parse the action.
+        parsed_json.get("action").map(|action_type| {
+            tracing::info!("Parsed action type: {}", action_type);
 
-                    // The action type is not supported. Do not treat this as a reply.
-                    // Report the error to the agent.
-                    _ =>  {
-                        tracing::error!("Invalid action: {}", action_type);
-                        Err(anyhow!("Invalid action: {}", action_type))
-                    },
+            match action_type.as_str() {
+                Some("insert_node") => {
+                    let insert = serde_json::from_value::<InsertNode>(parsed_json.clone())?;
+                    check_node_payload(&parsed_json)?;
+                    Ok(Self::InsertNode(insert))
                 }
+                Some("modify_node") => {
+                    let modify = serde_json::from_value::<ModifyNode>(parsed_json.clone())?;
+                    check_node_payload(&parsed_json)?;
+                    Ok(Self::ModifyNode(modify))
+                }
+                Some("format_text") => Ok(Self::FormatText(serde_json::from_value::<FormatText>(parsed_json.clone())?)),
+                Some("convert_to_list") => Ok(Self::ConvertToList(serde_json::from_value::<ConvertToList>(parsed_json.clone())?)),
+                Some("set_heading_level") => Ok(Self::SetHeadingLevel(serde_json::from_value::<SetHeadingLevel>(parsed_json.clone())?)),
+                Some("delete_node") => Ok(Self::DeleteNode(serde_json::from_value::<DeleteNode>(parsed_json.clone())?)),
+                Some("move_node") => Ok(Self::MoveNode(serde_json::from_value::<MoveNode>(parsed_json.clone())?)),
+                Some("set_title") => Ok(Self::SetTitle(serde_json::from_value::<SetTitle>(parsed_json.clone())?)),
+                Some("append_node") => Ok(Self::AppendNode(serde_json::from_value::<AppendNode>(parsed_json.clone())?)),
+                Some("insert_code_block") => Ok(Self::InsertCodeBlock(serde_json::from_value::<InsertCodeBlock>(parsed_json.clone())?)),
+                Some("insert_table") => Ok(Self::InsertTable(serde_json::from_value::<InsertTable>(parsed_json.clone())?)),
+                Some("replace_range") => Ok(Self::ReplaceRange(serde_json::from_value::<ReplaceRange>(parsed_json.clone())?)),
+                Some("batch") => match parsed_json.get("actions") {
+                    Some(serde_json::Value::Array(actions)) => Self::batch_from_json(actions.clone()),
+                    _ => Err(anyhow!("A batch needs an `actions` array")),
+                },
+
+                // If the agent choose to reply in an action, we can also handle it.
+                Some("reply") => Ok(Self::Reply(serde_json::from_value::<Reply>(parsed_json.clone())?)),
+
+                // The action type is not supported. Do not treat this as a reply.
+                // Report the error to the agent.
+                _ =>  {
+                    tracing::error!("Invalid action: {}", action_type);
+                    Err(anyhow!("Invalid action: {}", action_type))
+                },
+            }
+        })
+        // Default to reply if the `action` field is not found.
+        .unwrap_or(Ok(Self::Reply(reply.into())))
+    }
+
+    /// Parse the actions of a batch, which must each have an `action` field.
+    fn batch_from_json(actions: Vec<serde_json::Value>) -> anyhow::Result<Self> {
+        if actions.is_empty() {
+            return Err(anyhow!("A batch needs at least one action"));
+        }
+        if actions.len() > MAX_BATCH_ACTIONS {
+            return Err(anyhow!(
+                "A batch has at most {} actions, this one has {}",
+                MAX_BATCH_ACTIONS,
+                actions.len()
+            ));
+        }
+
+        actions
+            .into_iter()
+            .enumerate()
+            .map(|(index, action)| {
+                match action.get("action").and_then(serde_json::Value::as_str) {
+                    None => return Err(anyhow!("Action {} of the batch has no `action` field", index)),
+                    Some("batch") => return Err(anyhow!("Action {} of the batch is a batch, batches cannot be nested", index)),
+                    Some(_) => {}
+                }
+                Self::from_json(action, "").map_err(|err| anyhow!("Action {} of the batch is invalid: {}", index, err))
             })
-            // Default to reply if the `action` field is not found.
-            .unwrap_or(Ok(Self::Reply(reply.into())));
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map(Self::Batch)
+    }
+}
+
+/// The most actions the model may put in a batch.
+pub const MAX_BATCH_ACTIONS: usize = 5;
+
+/// A batch in the form the model writes it, tagged like the other actions
+#[derive(Serialize, Deserialize)]
+struct TaggedBatch<T> {
+    action: String,
+    actions: T,
+}
+
+fn serialize_batch<S: serde::Serializer>(actions: &[ChatAction], serializer: S) -> Result<S::Ok, S::Error> {
+    TaggedBatch { action: "batch".to_string(), actions }.serialize(serializer)
+}
+
+fn deserialize_batch<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<ChatAction>, D::Error> {
+    let batch = TaggedBatch::<Vec<ChatAction>>::deserialize(deserializer)?;
+    if batch.action != "batch" {
+        return Err(serde::de::Error::custom(format!("Expected a batch, got {}", batch.action)));
+    }
+    Ok(batch.actions)
+}
+
+/// The longest text shown by the one-line form of an action, in characters.
+const DISPLAY_TEXT_CHARS: usize = 60;
+
+/// A one-line summary of the action for logs, such as `InsertNode(after=0, type=text): "Hello"`.
+///
+/// Long text is cut off, and the operation id, if any, comes last.
+impl std::fmt::Display for ChatAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Reply(reply) => write!(f, "Reply: {}", display_text(&reply.content))?,
+            Self::InsertNode(insert) => {
+                match &insert.path {
+                    Some(path) => write!(f, "InsertNode(path={path}")?,
+                    None => write!(f, "InsertNode(after={}", insert.insert_after)?,
+                }
+                write_node(f, &insert.node_type, &insert.content, insert.content_format, insert.node.as_ref())?;
+            }
+            Self::ModifyNode(modify) => {
+                match &modify.path {
+                    Some(path) => write!(f, "ModifyNode(path={path}")?,
+                    None => write!(f, "ModifyNode(id={}", modify.id)?,
+                }
+                write_node(f, &modify.node_type, &modify.content, modify.content_format, modify.node.as_ref())?;
+            }
+            Self::SetHeadingLevel(set_level) => {
+                write!(f, "SetHeadingLevel(id={}, tag=h{})", set_level.id, set_level.tag.level())?
+            }
+            Self::FormatText(format) => {
+                write!(f, "FormatText(id={}, format={}", format.id, format.format.join("+"))?;
+                write!(f, "{})", if format.remove { ", remove" } else { "" })?;
+            }
+            Self::ConvertToList(convert) => {
+                write!(f, "ConvertToList(id={}", convert.id)?;
+                if let Some(end_id) = &convert.end_id {
+                    write!(f, "..{end_id}")?;
+                }
+                let list_type = match convert.list_type {
+                    ListType::Bullet => "bullet",
+                    ListType::Number => "number",
+                };
+                write!(f, ", type={list_type})")?;
+            }
+            Self::DeleteNode(delete) => write!(f, "DeleteNode(id={})", delete.id)?,
+            Self::MoveNode(move_node) => write!(f, "MoveNode(id={}, to={})", move_node.id, move_node.to)?,
+            Self::ReplaceRange(replace) => {
+                let types: Vec<&str> = replace.nodes.iter().map(|node| node.node_type.as_str()).collect();
+                write!(f, "ReplaceRange(start={}, end={}): [{}]", replace.start, replace.end, types.join(", "))?;
+            }
+            Self::InsertCodeBlock(insert) => write!(
+                f,
+                "InsertCodeBlock(after={}, language={}): {}",
+                insert.insert_after,
+                insert.language,
+                display_text(&insert.content)
+            )?,
+            Self::InsertTable(insert) => {
+                let columns = insert.rows.iter().map(Vec::len).max().unwrap_or(0);
+                write!(f, "InsertTable(after={}, rows={}, columns={columns})", insert.insert_after, insert.rows.len())?;
+            }
+            Self::Batch(actions) => {
+                write!(f, "Batch({}): [", actions.len())?;
+                for (index, action) in actions.iter().enumerate() {
+                    write!(f, "{}{action}", if index == 0 { "" } else { "; " })?;
+                }
+                write!(f, "]")?;
+            }
+            Self::AppendNode(append) => {
+                write!(f, "AppendNode(type={}): {}", append.node_type, display_text(&append.content))?
+            }
+            Self::SetTitle(set_title) => write!(f, "SetTitle: {}", display_text(&set_title.content))?,
         }
+        if let Some(op_id) = self.op_id() {
+            write!(f, " [op={op_id}]")?;
+        }
+        Ok(())
+    }
+}
 
-        // If the reply does not start with `{`, it's a normal text reply.
-        // So we can just return it as a reply.
-        Ok(Self::Reply(reply.into()))
+/// Write the node of an insert or modify action, after its position
+fn write_node(
+    f: &mut std::fmt::Formatter<'_>,
+    node_type: &str,
+    content: &str,
+    content_format: ContentFormat,
+    node: Option<&serde_json::Value>,
+) -> std::fmt::Result {
+    match node {
+        Some(node) => {
+            let node_type = node.get("type").and_then(serde_json::Value::as_str).unwrap_or("?");
+            write!(f, ", node={node_type})")
+        }
+        None if content_format == ContentFormat::Markdown => write!(f, ", markdown): {}", display_text(content)),
+        None => write!(f, ", type={node_type}): {}", display_text(content)),
+    }
+}
+
+/// Quote the text of an action, cut off after [`DISPLAY_TEXT_CHARS`] characters
+fn display_text(text: &str) -> String {
+    match text.char_indices().nth(DISPLAY_TEXT_CHARS) {
+        Some((end, _)) => format!("{:?}…", &text[..end]),
+        None => format!("{text:?}"),
+    }
+}
+
+/// Check that an action gives either its node JSON as a Lexical node or its node type and content,
+/// so that the model learns of a malformed node and a truncated action is not taken as complete.
+fn check_node_payload(action: &serde_json::Value) -> anyhow::Result<()> {
+    match action.get("node") {
+        Some(node) if !node.is_null() => {
+            LexicalNode::deserialize(node).map_err(|err| anyhow!("Invalid node JSON: {}", err))?;
+        }
+        // Markdown content has the types of its blocks
+        _ if action.get("content_format").and_then(serde_json::Value::as_str) == Some("markdown") => {
+            if action.get("content").is_none() {
+                return Err(anyhow!("missing field `content`"));
+            }
+        }
+        _ => {
+            for field in ["node_type", "content"] {
+                if action.get(field).is_none() {
+                    return Err(anyhow!("missing field `{}`", field));
+                }
+            }
+        }
     }
+    Ok(())
+}
+
+/// The reply when a truncated action cannot be repaired.
+const RESEND_REPLY: &str = "My response was cut off before it was complete. Please ask me to resend it.";
+
+/// Close an unterminated JSON object, for replies cut off by the token limit.
+///
+/// Returns `None` if the JSON is not unterminated. The result may still be invalid JSON.
+fn repair_truncated_json(json: &str) -> Option<String> {
+    let mut closers = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for c in json.chars() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => closers.push('}'),
+            '[' => closers.push(']'),
+            '}' | ']' => {
+                closers.pop();
+            }
+            _ => {}
+        }
+    }
+
+    if closers.is_empty() && !in_string {
+        return None;
+    }
+
+    let mut repaired = json.to_string();
+    if in_string {
+        // Drop a dangling escape before closing the string
+        if escaped {
+            repaired.pop();
+        }
+        repaired.push('"');
+    } else {
+        repaired.truncate(repaired.trim_end().trim_end_matches(',').len());
+        if repaired.ends_with(':') {
+            repaired.push_str("null");
+        }
+    }
+    repaired.extend(closers.iter().rev());
+    Some(repaired)
 }
 
 /// The action to reply to the chat.
@@ -248,18 +1259,215 @@ impl From<&str> for Reply {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InsertNode {
     pub action: String,
-    pub insert_after: usize,
+    /// The index or stable id of the node to insert after
+    pub insert_after: BriefId,
+    #[serde(default)]
+    pub node_type: String,
+    #[serde(default)]
+    pub content: String,
+    /// The format of `content`, Markdown for inserting several blocks at once
+    #[serde(default, skip_serializing_if = "ContentFormat::is_text")]
+    pub content_format: ContentFormat,
+    /// The complete Lexical node JSON to insert, taking precedence over `node_type` and `content`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node: Option<serde_json::Value>,
+    /// The path of the node to insert after, such as "3.1.0", taking precedence over `insert_after`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// The language of a code node, detected from the content when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// An id for the operation, so that applying it again is a no-op
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op_id: Option<String>,
+}
+
+/// The action to change the title of the note.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetTitle {
+    pub action: String,
+    /// The new title
+    pub content: String,
+    /// An id for the operation, so that applying it again is a no-op
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op_id: Option<String>,
+}
+
+/// The action to add a node at the end of the note.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppendNode {
+    pub action: String,
     pub node_type: String,
     pub content: String,
+    /// An id for the operation, so that applying it again is a no-op
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op_id: Option<String>,
+}
+
+/// The action to insert a code block with its language.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InsertCodeBlock {
+    pub action: String,
+    /// The index or stable id of the node to insert after
+    pub insert_after: BriefId,
+    /// The language of the code, such as "rust"
+    pub language: String,
+    /// The code, without a fence
+    pub content: String,
+    /// An id for the operation, so that applying it again is a no-op
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op_id: Option<String>,
+}
+
+/// The action to insert a table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InsertTable {
+    pub action: String,
+    /// The index or stable id of the node to insert after
+    pub insert_after: BriefId,
+    /// The cell texts row by row, the first row being the header row
+    pub rows: Vec<Vec<String>>,
+    /// An id for the operation, so that applying it again is a no-op
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op_id: Option<String>,
 }
 
 /// The action to modify a node.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModifyNode {
     pub action: String,
-    pub id: usize,
+    pub id: BriefId,
+    #[serde(default)]
     pub node_type: String,
+    #[serde(default)]
     pub content: String,
+    /// The format of `content`, Markdown for replacing the node with several blocks
+    #[serde(default, skip_serializing_if = "ContentFormat::is_text")]
+    pub content_format: ContentFormat,
+    /// The complete Lexical node JSON replacing the node, taking precedence over `node_type` and `content`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node: Option<serde_json::Value>,
+    /// The path of the node to modify, such as "3.1.0", taking precedence over `id`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// An id for the operation, so that applying it again is a no-op
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op_id: Option<String>,
+}
+
+impl InsertNode {
+    /// Whether the content is Markdown for root blocks, rather than a single node.
+    pub fn is_markdown(&self) -> bool {
+        self.node.is_none() && self.content_format == ContentFormat::Markdown
+    }
+}
+
+impl ModifyNode {
+    /// Whether the content is Markdown for root blocks, rather than a single node.
+    pub fn is_markdown(&self) -> bool {
+        self.node.is_none() && self.content_format == ContentFormat::Markdown
+    }
+}
+
+/// How the content of an insert or modify action is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentFormat {
+    /// The plain text of one node of the action's node type
+    #[default]
+    Text,
+    /// Markdown for one or more root blocks, see [`Note::from_markdown`]
+    Markdown,
+}
+
+impl ContentFormat {
+    fn is_text(&self) -> bool {
+        *self == Self::Text
+    }
+}
+
+/// A node to create, with the type and content of [`InsertNode`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewNodeSpec {
+    pub node_type: String,
+    pub content: String,
+}
+
+/// The action to replace a range of nodes with new nodes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplaceRange {
+    pub action: String,
+    /// The first node of the range
+    pub start: BriefId,
+    /// The last node of the range, included
+    pub end: BriefId,
+    /// The nodes replacing the range, in order
+    pub nodes: Vec<NewNodeSpec>,
+    /// An id for the operation, so that applying it again is a no-op
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op_id: Option<String>,
+}
+
+/// The action to delete a node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteNode {
+    pub action: String,
+    pub id: BriefId,
+    /// An id for the operation, so that applying it again is a no-op
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op_id: Option<String>,
+}
+
+/// The action to move a node to another position.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MoveNode {
+    pub action: String,
+    pub id: BriefId,
+    /// The index of the node once moved, counted among the other nodes
+    pub to: usize,
+    /// An id for the operation, so that applying it again is a no-op
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op_id: Option<String>,
+}
+
+/// The action to change the level of a heading.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetHeadingLevel {
+    pub action: String,
+    pub id: BriefId,
+    pub tag: HeadingTag,
+    /// An id for the operation, so that applying it again is a no-op
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op_id: Option<String>,
+}
+
+/// The action to format the text of a node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormatText {
+    pub action: String,
+    pub id: BriefId,
+    /// The format names, such as "bold" and "italic"
+    pub format: Vec<String>,
+    /// Remove the formats instead of adding them
+    #[serde(default)]
+    pub remove: bool,
+    /// An id for the operation, so that applying it again is a no-op
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op_id: Option<String>,
+}
+
+/// The action to turn paragraphs into a list.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConvertToList {
+    pub action: String,
+    pub id: BriefId,
+    /// The last node of the range to convert, `id` itself when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_id: Option<BriefId>,
+    pub list_type: ListType,
+    /// An id for the operation, so that applying it again is a no-op
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op_id: Option<String>,
 }
 
 /// Create a chat source and handler.
@@ -270,10 +1478,14 @@ pub fn create_chat() -> (ChatSource, ChatHandler) {
         ChatSource {
             chat_rx: Arc::new(Mutex::new(chat_rx)),
             reply_tx,
+            errors: ErrorSlot::default(),
         },
         ChatHandler {
             chat_tx,
             reply_rx: Arc::new(Mutex::new(reply_rx)),
+            limits: NoteLimits::default(),
+            tokenizer: Arc::new(HeuristicTokenizer),
+            deltas: DeltaSlot::default(),
         },
     )
 }
@@ -287,15 +1499,20 @@ impl EventSource for ChatSource {
     {
         let chat_rx = self.chat_rx.clone();
         let reply_tx = self.reply_tx.clone();
+        let errors = self.errors.clone();
         spawn(async move {
             while let Some(chat) = chat_rx.lock().await.recv().await {
                 let event =
                     AgentEvent::new("Chat", "ChatSource").interaction(Interaction::Chat(chat));
 
-                // Make the Strategy handle the interaction.
-                let reply = on_event(event).await.unwrap_or_else(|| {
-                    tracing::warn!("Agent did not reply to interaction");
-                    "Agent did not reply to interaction".to_string()
+                // Make the Strategy handle the interaction. The runtime only logs the error
+                // of a failed chat, so it is taken from the slot the strategy set.
+                let reply = on_event(event).await.ok_or_else(|| {
+                    let err = errors.lock().unwrap_or_else(|err| err.into_inner()).take();
+                    err.unwrap_or_else(|| {
+                        tracing::warn!("Agent did not reply to interaction");
+                        ServiceError::NoReply
+                    })
                 });
 
                 reply_tx.send(reply).await.unwrap_or_else(|err| {
@@ -311,12 +1528,23 @@ impl EventSource for ChatSource {
 /// The strategy for the agent.
 pub struct AppStrategy {
     model: AimoModel,
+    /// Shared with the chat handler, set while a chat is streamed
+    deltas: DeltaSlot,
+    /// Shared with the chat source, set when a chat fails
+    errors: ErrorSlot,
 }
 
 impl AppStrategy {
     pub fn new(jwt: String) -> Self {
+        Self::with_model(AimoModel::new(jwt))
+    }
+
+    /// Create the strategy with a configured model, such as one with another base URL.
+    pub fn with_model(model: AimoModel) -> Self {
         Self {
-            model: AimoModel::new(jwt),
+            model,
+            deltas: DeltaSlot::default(),
+            errors: ErrorSlot::default(),
         }
     }
 }
@@ -334,15 +1562,559 @@ impl Strategy for AppStrategy {
             .ok_or(anyhow!("Cannot handle non-interaction event"))?;
 
         match interaction {
-            Interaction::Chat(chat) => Ok(Some(self.model.completion(&chat.messages).await?)),
+            Interaction::Chat(chat) => {
+                let deltas = self.deltas.lock().unwrap_or_else(|err| err.into_inner()).take();
+                let reply = match deltas {
+                    Some(delta_tx) => {
+                        self.model
+                            .completion_stream(&chat.messages, |delta| {
+                                // The handler may have stopped listening, the reply still completes
+                                let _ = delta_tx.send(delta.to_string());
+                            })
+                            .await
+                    }
+                    None => self.model.completion(&chat.messages).await,
+                };
+                match reply {
+                    Ok(reply) => Ok(Some(reply)),
+                    Err(err) => {
+                        *self.errors.lock().unwrap_or_else(|err| err.into_inner()) = Some(err.clone());
+                        Err(err.into())
+                    }
+                }
+            }
         }
     }
 }
 
 /// Create an agent with a chat source and handler.
 pub fn create_agent(jwt: String) -> (Agent<AppStrategy>, ChatHandler) {
+    create_agent_with_model(AimoModel::new(jwt))
+}
+
+/// Create an agent with a chat source and handler, completing chats with the model.
+pub fn create_agent_with_model(model: AimoModel) -> (Agent<AppStrategy>, ChatHandler) {
     let (chat_source, chat_handler) = create_chat();
-    let mut agent = Agent::new(AppStrategy::new(jwt));
+    let mut strategy = AppStrategy::with_model(model);
+    strategy.deltas = chat_handler.deltas.clone();
+    strategy.errors = chat_source.errors.clone();
+    let mut agent = Agent::new(strategy);
     agent.spawn_event_source(chat_source, OnFinish::Stop);
     (agent, chat_handler)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mention_note() -> Note {
+        serde_json::from_value(serde_json::json!({
+            "noteId": null,
+            "lexicalState": {
+                "root": {
+                    "type": "root",
+                    "version": 1,
+                    "children": [
+                        {
+                            "type": "paragraph",
+                            "version": 1,
+                            "children": [
+                                { "type": "text", "version": 1, "text": "See ", "format": 0 },
+                                { "type": "mention", "version": 1, "mentionName": "Roadmap", "text": "@Roadmap", "format": 0 },
+                                { "type": "text", "version": 1, "text": " and ", "format": 0 },
+                                { "type": "mention", "version": 1, "mentionName": "Unknown", "text": "@Unknown", "format": 0 },
+                            ],
+                        }
+                    ],
+                }
+            }
+        }))
+        .expect("Should be able to parse test note")
+    }
+
+    #[test]
+    fn test_system_prompt_with_referenced_notes() {
+        let ctx = ChatContext {
+            note: mention_note(),
+            cursor_position: 0.into(),
+            mode: ContextMode::Full,
+            brief_format: BriefFormat::default(),
+            referenced_notes: BTreeMap::new(),
+        };
+        let resolver = |name: &str| (name == "Roadmap").then(|| "Q3: ship the beta.".to_string());
+
+        let prompt = get_system_prompt_with_mentions(&ctx, Some(&resolver))
+            .expect("Should build the system prompt");
+        assert!(prompt.contains("## Referenced Notes"));
+        assert!(prompt.contains("### Roadmap\n\nQ3: ship the beta."));
+        assert!(!prompt.contains("### Unknown"));
+
+        // No resolver means no change to the prompt
+        let prompt = get_system_prompt(&ctx).expect("Should build the system prompt");
+        assert!(!prompt.contains("## Referenced Notes"));
+
+        // The referenced notes may come with the context instead
+        let ctx = ChatContext {
+            referenced_notes: BTreeMap::from([("Roadmap".to_string(), "Q3: ship the beta.".to_string())]),
+            ..ctx
+        };
+        let prompt = get_system_prompt(&ctx).expect("Should build the system prompt");
+        assert!(prompt.contains("### Roadmap\n\nQ3: ship the beta."));
+        assert!(!prompt.contains("### Unknown"));
+    }
+
+    #[test]
+    fn test_estimate_prompt_tokens() {
+        let json_content = std::fs::read_to_string("assets/example_note.json")
+            .expect("Should be able to read assets/example_note.json");
+        let small = ChatContext {
+            note: serde_json::from_str(&json_content).expect("Should be able to parse example note JSON"),
+            cursor_position: 0.into(),
+            mode: ContextMode::Full,
+            brief_format: BriefFormat::default(),
+            referenced_notes: BTreeMap::new(),
+        };
+        let mut large = small.clone();
+        let paragraph = large.note.lexical_state.root.children[0].clone();
+        large.note.lexical_state.root.children.extend(std::iter::repeat_n(paragraph, 50));
+        let messages = vec![ChatMessage {
+            content: "Summarize the note".to_string(),
+            role: "user".to_string(),
+        }];
+
+        let small_estimate = estimate_prompt_tokens(&small, &messages).expect("Should estimate the prompt");
+        let large_estimate = estimate_prompt_tokens(&large, &messages).expect("Should estimate the prompt");
+        assert!(large_estimate > small_estimate);
+        let prompt = get_system_prompt(&small).unwrap();
+        assert_eq!(small_estimate, estimate_tokens(&prompt) + 5 + 2 * TOKENS_PER_MESSAGE);
+
+        let (_, handler) = create_chat();
+        assert_eq!(handler.estimate_prompt_tokens(&small, &messages).unwrap(), small_estimate);
+        assert_eq!(estimate_tokens("abcde"), 2);
+    }
+
+    #[test]
+    fn test_custom_tokenizer() {
+        let words = |text: &str| text.split_whitespace().count();
+        let children: Vec<_> = (0..30)
+            .map(|i| {
+                serde_json::json!({
+                    "type": "paragraph",
+                    "version": 1,
+                    "children": [{ "type": "text", "version": 1, "text": format!("Paragraph {i}"), "format": 0 }],
+                })
+            })
+            .collect();
+        let note: Note = serde_json::from_value(serde_json::json!({
+            "noteId": null,
+            "lexicalState": { "root": { "type": "root", "version": 1, "children": children } }
+        }))
+        .expect("Should be able to parse test note");
+        let ctx = ChatContext {
+            note,
+            cursor_position: 15.into(),
+            mode: ContextMode::Full,
+            brief_format: BriefFormat::Compact,
+            referenced_notes: BTreeMap::new(),
+        };
+
+        // The content is 60 words, but 90 tokens at 4 characters per token
+        let limits = NoteLimits { max_tokens: Some(70), window_radius: 5, ..NoteLimits::default() };
+        assert!(limits.exceeded_by(&ctx.note.get_brief()));
+        assert!(!limits.exceeded_by_with(&ctx.note.get_brief(), &words));
+        let windowed = get_system_prompt_with_limits(&ctx, &limits).expect("Should build the system prompt");
+        assert!(!windowed.contains("|Paragraph 0\n"));
+        let prompt = get_system_prompt_with_tokenizer(&ctx, &limits, &words).expect("Should build the system prompt");
+        assert!(prompt.contains("|Paragraph 0\n"));
+
+        let messages = vec![ChatMessage {
+            content: "Shorten the list".to_string(),
+            role: "user".to_string(),
+        }];
+        let (_, handler) = create_chat();
+        let handler = handler.with_limits(limits).with_tokenizer(words);
+        assert_eq!(
+            handler.estimate_prompt_tokens(&ctx, &messages).unwrap(),
+            words(&prompt) + 3 + 2 * TOKENS_PER_MESSAGE
+        );
+    }
+
+    #[test]
+    fn test_repair_truncated_insert_node() {
+        let reply = r#"{"action": "insert_node", "insert_after": 2, "node_type": "paragraph", "content": "The launch moves to"#;
+        let action = ChatAction::try_from_reply(reply.to_string()).expect("Should repair the reply");
+        let ChatAction::InsertNode(insert) = action else {
+            panic!("Should be an insert node action: {action:?}");
+        };
+        assert_eq!(insert.insert_after, BriefId::Index(2));
+        assert_eq!(insert.content, "The launch moves to");
+
+        // Cut off after a trailing comma and inside an escape
+        let reply = r#"{"action": "insert_node", "insert_after": 0, "node_type": "paragraph", "content": "Say \"hi\"","#;
+        assert!(matches!(ChatAction::try_from_reply(reply.to_string()), Ok(ChatAction::InsertNode(_))));
+        let reply = r#"{"action": "insert_node", "insert_after": 0, "node_type": "paragraph", "content": "Line\"#;
+        let Ok(ChatAction::InsertNode(insert)) = ChatAction::try_from_reply(reply.to_string()) else {
+            panic!("Should repair a dangling escape");
+        };
+        assert_eq!(insert.content, "Line");
+    }
+
+    #[test]
+    fn test_unrepairable_reply_asks_to_resend() {
+        // The content is missing, so the repaired action is incomplete
+        let reply = r#"{"action": "insert_node", "insert_after": 2, "node_type": "para"#;
+        let Ok(ChatAction::Reply(reply)) = ChatAction::try_from_reply(reply.to_string()) else {
+            panic!("Should fall back to a reply");
+        };
+        assert_eq!(reply.content, RESEND_REPLY);
+
+        // Malformed but complete JSON is still an error
+        assert!(ChatAction::try_from_reply(r#"{"action": insert_node}"#.to_string()).is_err());
+    }
+
+    #[test]
+    fn test_display_action() {
+        use serde_json::json;
+
+        let display = |reply: serde_json::Value| {
+            let action = ChatAction::try_from_reply(reply.to_string()).expect("Should parse the action");
+            action.to_string()
+        };
+
+        assert_eq!(ChatAction::Reply("Done".into()).to_string(), r#"Reply: "Done""#);
+        assert_eq!(
+            display(json!({ "action": "insert_node", "insert_after": 0, "node_type": "text", "content": "Hello" })),
+            r#"InsertNode(after=0, type=text): "Hello""#
+        );
+        assert_eq!(
+            display(json!({ "action": "insert_node", "insert_after": 0, "path": "2.1", "node_type": "text", "content": "Hi" })),
+            r#"InsertNode(path=2.1, type=text): "Hi""#
+        );
+        let list = json!({ "type": "list", "version": 1, "listType": "bullet", "children": [] });
+        assert_eq!(
+            display(json!({ "action": "insert_node", "insert_after": "k1", "node": list, "op_id": "op-1" })),
+            r#"InsertNode(after="k1", node=list) [op=op-1]"#
+        );
+        assert_eq!(
+            display(json!({ "action": "modify_node", "id": 3, "node_type": "h2", "content": "Line\nbreak" })),
+            r#"ModifyNode(id=3, type=h2): "Line\nbreak""#
+        );
+        assert_eq!(
+            display(json!({ "action": "modify_node", "id": 3, "content_format": "markdown", "content": "- A" })),
+            r#"ModifyNode(id=3, markdown): "- A""#
+        );
+        assert_eq!(
+            display(json!({ "action": "set_heading_level", "id": 1, "tag": "h3" })),
+            "SetHeadingLevel(id=1, tag=h3)"
+        );
+        assert_eq!(
+            display(json!({ "action": "format_text", "id": 2, "format": ["bold", "italic"], "remove": true })),
+            "FormatText(id=2, format=bold+italic, remove)"
+        );
+        assert_eq!(
+            display(json!({ "action": "convert_to_list", "id": 1, "end_id": 4, "list_type": "number" })),
+            "ConvertToList(id=1..4, type=number)"
+        );
+        assert_eq!(display(json!({ "action": "delete_node", "id": 5 })), "DeleteNode(id=5)");
+        assert_eq!(display(json!({ "action": "move_node", "id": 5, "to": 0 })), "MoveNode(id=5, to=0)");
+        assert_eq!(
+            display(json!({
+                "action": "replace_range", "start": 1, "end": 2,
+                "nodes": [{ "node_type": "h2", "content": "A" }, { "node_type": "paragraph", "content": "B" }],
+            })),
+            "ReplaceRange(start=1, end=2): [h2, paragraph]"
+        );
+        assert_eq!(
+            display(json!({ "action": "insert_code_block", "insert_after": 0, "language": "rust", "content": "fn main() {}" })),
+            r#"InsertCodeBlock(after=0, language=rust): "fn main() {}""#
+        );
+        assert_eq!(
+            display(json!({ "action": "insert_table", "insert_after": 0, "rows": [["A", "B"], ["1"]] })),
+            "InsertTable(after=0, rows=2, columns=2)"
+        );
+        assert_eq!(
+            display(json!([{ "action": "delete_node", "id": 1 }, { "action": "delete_node", "id": 0 }])),
+            "Batch(2): [DeleteNode(id=1); DeleteNode(id=0)]"
+        );
+        assert_eq!(
+            display(json!({ "action": "append_node", "node_type": "paragraph", "content": "x".repeat(70) })),
+            format!("AppendNode(type=paragraph): \"{}\"…", "x".repeat(DISPLAY_TEXT_CHARS))
+        );
+        assert_eq!(display(json!({ "action": "set_title", "content": "Plans" })), r#"SetTitle: "Plans""#);
+    }
+
+    #[test]
+    fn test_system_prompt_windows_oversized_note() {
+        let children: Vec<_> = (0..30)
+            .map(|i| {
+                serde_json::json!({
+                    "type": "paragraph",
+                    "version": 1,
+                    "children": [{ "type": "text", "version": 1, "text": format!("Paragraph {i}"), "format": 0 }],
+                })
+            })
+            .collect();
+        let note: Note = serde_json::from_value(serde_json::json!({
+            "noteId": null,
+            "lexicalState": { "root": { "type": "root", "version": 1, "children": children } }
+        }))
+        .expect("Should be able to parse test note");
+        let ctx = ChatContext {
+            note,
+            cursor_position: 15.into(),
+            mode: ContextMode::Full,
+            brief_format: BriefFormat::Json,
+            referenced_notes: BTreeMap::new(),
+        };
+        let limits = NoteLimits { max_nodes: 10, max_chars: 10_000, window_radius: 2, max_tokens: None };
+
+        let prompt = get_system_prompt_with_limits(&ctx, &limits).expect("Should build the system prompt");
+        for i in 13..=17 {
+            assert!(prompt.contains(&format!("\"content\":\"Paragraph {i}\"")), "Missing node {i}");
+        }
+        assert!(!prompt.contains("\"content\":\"Paragraph 12\""));
+        assert!(!prompt.contains("\"content\":\"Paragraph 18\""));
+        assert!(prompt.contains("13 nodes before and 12 nodes after them are omitted"));
+
+        // The briefs are a JSON array in ascending id order
+        let briefs = prompt.split("```json\n").nth(1).and_then(|rest| rest.split("\n```").next()).unwrap();
+        let briefs: Vec<BriefNode> = serde_json::from_str(briefs).expect("Should be a JSON array of briefs");
+        let ids: Vec<usize> = briefs.iter().map(|brief| brief.id).collect();
+        assert_eq!(ids, vec![13, 14, 15, 16, 17]);
+
+        // The whole note fits the default limits
+        let prompt = get_system_prompt(&ctx).expect("Should build the system prompt");
+        assert!(prompt.contains("\"content\":\"Paragraph 0\""));
+        assert!(!prompt.contains("omitted"));
+
+        // Even the window exceeds the character limit
+        let limits = NoteLimits { max_nodes: 10, max_chars: 20, window_radius: 2, max_tokens: None };
+        let err = get_system_prompt_with_limits(&ctx, &limits).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The note is too large: the nodes around the cursor exceed 10 nodes or 20 characters"
+        );
+        let limits = NoteLimits { max_tokens: Some(5), ..limits };
+        let err = get_system_prompt_with_limits(&ctx, &limits).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The note is too large: the nodes around the cursor exceed 10 nodes, 20 characters or 5 tokens"
+        );
+    }
+
+    #[test]
+    fn test_cursor_position_legacy_integer() {
+        let note = serde_json::to_value(mention_note()).unwrap();
+
+        let ctx: ChatContext =
+            serde_json::from_value(serde_json::json!({ "note": note, "cursor_position": 4 })).unwrap();
+        assert_eq!(ctx.cursor_position, CursorPosition { node_index: 4, char_offset: None });
+
+        let ctx: ChatContext = serde_json::from_value(serde_json::json!({
+            "note": note,
+            "cursor_position": { "node_index": 0, "char_offset": 3 },
+        }))
+        .unwrap();
+        assert_eq!(ctx.cursor_position, CursorPosition { node_index: 0, char_offset: Some(3) });
+
+        // The new form round trips
+        let json = serde_json::to_value(&ctx).unwrap();
+        assert_eq!(json["cursor_position"], serde_json::json!({ "node_index": 0, "char_offset": 3 }));
+    }
+
+    #[test]
+    fn test_system_prompt_with_cursor_offset() {
+        let mut ctx = ChatContext {
+            note: mention_note(),
+            cursor_position: CursorPosition { node_index: 0, char_offset: Some(4) },
+            mode: ContextMode::Full,
+            brief_format: BriefFormat::default(),
+            referenced_notes: BTreeMap::new(),
+        };
+        let prompt = get_system_prompt(&ctx).expect("Should build the system prompt");
+        assert!(prompt.contains("The cursor is 4 characters into node 0, which has 25 characters."));
+
+        ctx.cursor_position.char_offset = Some(26);
+        let err = get_system_prompt(&ctx).unwrap_err();
+        assert_eq!(err.to_string(), "The cursor offset 26 is beyond the 25 characters of node 0");
+
+        ctx.cursor_position = CursorPosition { node_index: 3, char_offset: Some(0) };
+        assert!(get_system_prompt(&ctx).is_err());
+    }
+
+    #[test]
+    fn test_system_prompt_section_mode() {
+        let json_content = std::fs::read_to_string("assets/example_outline_note.json")
+            .expect("Should be able to read assets/example_outline_note.json");
+        let ctx = ChatContext {
+            note: serde_json::from_str(&json_content).expect("Should be able to parse example outline note JSON"),
+            cursor_position: 4.into(),
+            mode: ContextMode::Section,
+            brief_format: BriefFormat::Compact,
+            referenced_notes: BTreeMap::new(),
+        };
+
+        let prompt = get_system_prompt(&ctx).expect("Should build the system prompt");
+        // Only the "Goals" section is in the briefs
+        assert!(prompt.contains("\n# H2: Goals\n3|heading|"));
+        assert!(prompt.contains("\n4|"));
+        assert!(!prompt.contains("\n2|"));
+        assert!(!prompt.contains("\n5|"));
+        assert!(prompt.contains("- Project Plan (node 1)\n  - Goals (node 3)\n    - Q1 Milestones (node 5)"));
+    }
+
+    #[test]
+    fn test_system_prompt_full_mode() {
+        let json_content = std::fs::read_to_string("assets/example_outline_note.json")
+            .expect("Should be able to read assets/example_outline_note.json");
+        let ctx = ChatContext {
+            note: serde_json::from_str(&json_content).expect("Should be able to parse example outline note JSON"),
+            cursor_position: 4.into(),
+            mode: ContextMode::Full,
+            brief_format: BriefFormat::default(),
+            referenced_notes: BTreeMap::new(),
+        };
+
+        // The full mode shows every brief of the note, and no outline
+        let prompt = get_system_prompt(&ctx).expect("Should build the system prompt");
+        let briefs = ctx.note.get_brief();
+        assert!(briefs.len() > 5);
+        for brief in &briefs {
+            let start = format!("{{\"id\":{},\"nodeType\":\"{}\"", brief.id, brief.node_type);
+            assert!(prompt.contains(&start), "The prompt should include node {}", brief.id);
+        }
+        assert!(!prompt.contains("The note has the following headings"));
+        assert!(!prompt.contains("- Project Plan (node 1)"));
+    }
+
+    #[tokio::test]
+    async fn test_chat_saves_exchanges_in_session() {
+        let (source, mut handler) = create_chat();
+
+        // Stand in for the agent runtime, answering two chats
+        let agent = spawn(async move {
+            let replies = [r#"{"action": "reply", "content": "Hello"}"#, r#"{"action": "delete_node", "id": 0}"#];
+            for reply in replies {
+                source.chat_rx.lock().await.recv().await.expect("Should receive the chat");
+                source.reply_tx.send(Ok(reply.to_string())).await.unwrap();
+            }
+        });
+
+        let mut ctx = ChatContext {
+            note: mention_note(),
+            cursor_position: 0.into(),
+            mode: ContextMode::Full,
+            brief_format: BriefFormat::default(),
+            referenced_notes: BTreeMap::new(),
+        };
+        let mut messages = Vec::new();
+        for content in ["Hi", "Delete the first node"] {
+            messages.push(ChatMessage { content: content.to_string(), role: "user".to_string() });
+            let chat = Chat { messages: messages.clone(), session_id: 0 };
+            let action = handler.chat(chat.clone(), &ctx).await.expect("Should get the action");
+            action.save_exchange(&mut ctx.note, "session-1", &chat);
+            messages.push(ChatMessage { content: action.to_string(), role: "assistant".to_string() });
+        }
+        agent.await.unwrap();
+
+        let session = ctx.note.find_chat_session("session-1").expect("Should create the session");
+        let saved: Vec<(&str, &str)> =
+            session.messages.iter().map(|message| (message.sender.role(), message.content.as_str())).collect();
+        assert_eq!(
+            saved,
+            vec![
+                ("user", "Hi"),
+                ("assistant", "Hello"),
+                ("user", "Delete the first node"),
+                ("assistant", "DeleteNode(id=0)"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chat_without_messages() {
+        let (source, mut handler) = create_chat();
+        let ctx = ChatContext {
+            note: mention_note(),
+            cursor_position: 0.into(),
+            mode: ContextMode::Full,
+            brief_format: BriefFormat::default(),
+            referenced_notes: BTreeMap::new(),
+        };
+
+        for messages in [vec![], vec![ChatMessage { content: " \n ".to_string(), role: "user".to_string() }]] {
+            let chat = Chat { messages, session_id: 0 };
+            let err = handler.chat(chat, &ctx).await.unwrap_err();
+            assert_eq!(err.downcast_ref::<ServiceError>(), Some(&ServiceError::NoMessages));
+        }
+        // Nothing was sent to the agent
+        assert!(source.chat_rx.lock().await.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_chat_returns_the_service_error() {
+        let model = AimoModel::new("jwt".to_string()).with_base_url("ftp://example.com");
+        let (mut agent, mut handler) = create_agent_with_model(model);
+        let ctx = ChatContext {
+            note: mention_note(),
+            cursor_position: 0.into(),
+            mode: ContextMode::Full,
+            brief_format: BriefFormat::default(),
+            referenced_notes: BTreeMap::new(),
+        };
+        let chat = Chat {
+            messages: vec![ChatMessage { content: "Hi".to_string(), role: "user".to_string() }],
+            session_id: 0,
+        };
+
+        let err = tokio::select! {
+            _ = agent.run() => panic!("The agent should keep running"),
+            result = handler.chat(chat, &ctx) => result.expect_err("The model call should fail"),
+        };
+        assert_eq!(
+            err.downcast_ref::<ServiceError>(),
+            Some(&ServiceError::InvalidUrl("ftp://example.com/chat/completions".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chat_stream_forwards_deltas() {
+        let (source, mut handler) = create_chat();
+        let deltas = handler.deltas.clone();
+
+        // Stand in for the agent runtime, streaming the reply in pieces
+        let agent = spawn(async move {
+            let chat = source.chat_rx.lock().await.recv().await.expect("Should receive the chat");
+            assert_eq!(chat.messages[0].role, "system");
+            let delta_tx = deltas.lock().unwrap().take().expect("Should stream the chat");
+            let pieces = [r#"{"action": "reply", "#, r#""content": "#, r#""Done"}"#];
+            for piece in pieces {
+                delta_tx.send(piece.to_string()).unwrap();
+            }
+            drop(delta_tx);
+            source.reply_tx.send(Ok(pieces.concat())).await.unwrap();
+        });
+
+        let ctx = ChatContext {
+            note: mention_note(),
+            cursor_position: 0.into(),
+            mode: ContextMode::Full,
+            brief_format: BriefFormat::default(),
+            referenced_notes: BTreeMap::new(),
+        };
+        let chat = Chat {
+            messages: vec![ChatMessage { content: "Hi".to_string(), role: "user".to_string() }],
+            session_id: 0,
+        };
+        let mut received = Vec::new();
+        let action = handler
+            .chat_stream(chat, &ctx, |delta| received.push(delta.to_string()))
+            .await
+            .expect("Should parse the streamed reply");
+
+        agent.await.unwrap();
+        assert_eq!(received.len(), 3);
+        assert!(matches!(action, ChatAction::Reply(reply) if reply.content == "Done"));
+        assert!(handler.deltas.lock().unwrap().is_none());
+    }
+}
@@ -0,0 +1,44 @@
+//! WASM-safe wall clock helpers.
+//!
+//! `std::time::SystemTime` panics on `wasm32-unknown-unknown`, so the browser
+//! clock is used there instead.
+
+/// Get the current time in milliseconds since the Unix epoch.
+#[cfg(target_arch = "wasm32")]
+pub fn now_millis() -> f64 {
+    js_sys::Date::now()
+}
+
+/// Get the current time in milliseconds since the Unix epoch.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now_millis() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs_f64() * 1000.0)
+        .unwrap_or_default()
+}
+
+/// Get the current time as an RFC 3339 timestamp in UTC, such as "2025-06-03T14:32:00.000Z".
+#[cfg(target_arch = "wasm32")]
+pub fn now_iso8601() -> String {
+    js_sys::Date::new_0().to_iso_string().into()
+}
+
+/// Get the current time as an RFC 3339 timestamp in UTC, such as "2025-06-03T14:32:00.000Z".
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now_iso8601() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_now_iso8601_is_rfc3339() {
+        let timestamp = now_iso8601();
+        let parsed = chrono::DateTime::parse_from_rfc3339(&timestamp).expect("Should parse as RFC 3339");
+        assert!(timestamp.ends_with('Z'));
+        assert!((parsed.timestamp_millis() as f64 - now_millis()).abs() < 60_000.0);
+    }
+}
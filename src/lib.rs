@@ -1,23 +1,28 @@
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 
 use amico_core::{
     Agent,
     types::{Chat, ChatMessage},
 };
+use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 use tokio_with_wasm::alias as tokio;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::spawn_local;
 
-mod agent;
+pub mod agent;
+mod clock;
 mod log;
-mod service;
+pub mod service;
 mod note;
 
-use agent::{AppStrategy, ChatHandler, create_agent};
-use note::Note;
+pub use note::{CachedNote, Note, SCHEMA_VERSION};
 
-use crate::agent::ChatContext;
+use agent::{AppStrategy, ChatAction, ChatHandler, create_agent};
+use note::{MessageSender, Provenance, Severity, ValidationIssue};
+use service::ServiceError;
+
+use crate::agent::{BriefFormat, ChatContext, ContextMode, CursorPosition};
 
 /// A WASM-bindgen compatible message structure that can be converted to ChatMessage.
 #[wasm_bindgen]
@@ -55,11 +60,26 @@ impl Message {
     }
 }
 
+/// Map a frontend role to a role the model accepts.
+///
+/// "ai", "bot" and "agent" are aliases of "assistant". Unknown roles fall back to "user".
+fn normalize_role(role: &str) -> &'static str {
+    match role.trim().to_ascii_lowercase().as_str() {
+        "user" => "user",
+        "system" => "system",
+        "assistant" | "ai" | "bot" | "agent" => "assistant",
+        _ => {
+            tracing::warn!("Unknown message role {:?}, sending it as a user message", role);
+            "user"
+        }
+    }
+}
+
 impl From<Message> for ChatMessage {
     fn from(message: Message) -> Self {
         ChatMessage {
             content: message.content,
-            role: message.role,
+            role: normalize_role(&message.role).to_string(),
         }
     }
 }
@@ -103,16 +123,100 @@ impl AgentWasmRuntime {
         }
     }
 
+    /// Chat with the agent about the note.
+    ///
+    /// The optional `char_offset` is the cursor position within the text of the node at `cursor_position`.
+    /// The optional `options` object may set the context `mode`, "full" or "section", the
+    /// `briefFormat` of the note in the prompt, "json" (the default) or "compact", and the
+    /// `referencedNotes`, an object of brief summaries of the mentioned notes by mention name.
+    ///
+    /// With a `sessionId` option, the last user message and the agent reply are saved in that
+    /// chat session of the note, and the chat resolves with `{ action, note }`, `note` being
+    /// the updated note JSON. Otherwise it resolves with the action.
+    #[wasm_bindgen]
+    pub async fn chat(
+        &self,
+        messages: Vec<Message>,
+        cursor_position: usize,
+        note: JsValue,
+        char_offset: Option<usize>,
+        options: JsValue,
+    ) -> Result<JsValue, JsValue> {
+        let (chat, ctx, session_id) = self.prepare_chat(messages, cursor_position, note, char_offset, options)?;
+
+        let mut handler = self.chat_handler.lock().await;
+        match handler.chat(chat.clone(), &ctx).await {
+            Ok(action) => chat_result(action, ctx, session_id, &chat),
+            Err(e) => Err(ChatError::from(e).into()),
+        }
+    }
+
+    /// Chat with the agent, calling `on_delta` with each piece of the reply as it streams in.
+    ///
+    /// Takes the same `options` and resolves like `chat`. If the callback throws, it is not
+    /// called again and the chat still completes.
+    #[wasm_bindgen]
+    pub async fn chat_stream(
+        &self,
+        messages: Vec<Message>,
+        cursor_position: usize,
+        note: JsValue,
+        char_offset: Option<usize>,
+        on_delta: js_sys::Function,
+        options: JsValue,
+    ) -> Result<JsValue, JsValue> {
+        let (chat, ctx, session_id) = self.prepare_chat(messages, cursor_position, note, char_offset, options)?;
+
+        let mut callback_failed = false;
+        let on_delta = |delta: &str| {
+            if callback_failed {
+                return;
+            }
+            if let Err(err) = on_delta.call1(&JsValue::NULL, &JsValue::from_str(delta)) {
+                tracing::warn!("Delta callback threw, no more deltas are sent: {:?}", err);
+                callback_failed = true;
+            }
+        };
+
+        let mut handler = self.chat_handler.lock().await;
+        match handler.chat_stream(chat.clone(), &ctx, on_delta).await {
+            Ok(action) => chat_result(action, ctx, session_id, &chat),
+            Err(e) => Err(ChatError::from(e).into()),
+        }
+    }
+
     #[wasm_bindgen]
-    pub async fn chat(&self, messages: Vec<Message>, cursor_position: usize, note: JsValue) -> Result<JsValue, JsValue> {
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+}
+
+impl AgentWasmRuntime {
+    /// Build the chat and its context from the arguments of a chat call, with the chat
+    /// session to save the exchange in, if any
+    fn prepare_chat(
+        &self,
+        messages: Vec<Message>,
+        cursor_position: usize,
+        note: JsValue,
+        char_offset: Option<usize>,
+        options: JsValue,
+    ) -> Result<(Chat, ChatContext, Option<String>), ChatError> {
         if !self.running {
-            return Err(JsValue::from_str(
-                "Agent is not running. Call start() first.",
-            ));
+            return Err(ChatError::new("not_running", "Agent is not running. Call start() first."));
         }
+        let options = if options.is_undefined() || options.is_null() {
+            ChatOptions::default()
+        } else {
+            serde_wasm_bindgen::from_value(options)
+                .map_err(|e| ChatError::new("invalid_options", format!("Invalid chat options: {}", e)))?
+        };
 
-        // Parse the note from the JS value.
-        let note: Note = serde_wasm_bindgen::from_value(note)?;
+        // Parse the note from the JS value, through JSON to bound its nesting depth.
+        let note_json = js_sys::JSON::stringify(&note)
+            .ok()
+            .and_then(|json| json.as_string())
+            .ok_or_else(|| ChatError::new("invalid_note", "Invalid note: not a JSON value"))?;
 
         // Convert Vec<Message> to Vec<ChatMessage>
         let chat_messages: Vec<ChatMessage> = messages.into_iter().map(|msg| msg.into()).collect();
@@ -123,21 +227,250 @@ impl AgentWasmRuntime {
             // We don't use session_id here
             session_id: 0,
         };
+        let cursor_position = CursorPosition {
+            node_index: cursor_position,
+            char_offset,
+        };
+        let session_id = options.session_id.clone();
+        let ctx = chat_context(&note_json, cursor_position, options)?;
 
-        let mut handler = self.chat_handler.lock().await;
-        match handler.chat(chat, &ChatContext {
-            note,
-            cursor_position,
-        }).await {
-            Ok(action) => Ok(serde_wasm_bindgen::to_value(&action)?),
-            Err(e) => Err(JsValue::from_str(&format!("Chat error: {}", e))),
+        Ok((chat, ctx, session_id))
+    }
+}
+
+/// The value a chat resolves with, saving the exchange in the note first if a session is given.
+fn chat_result(action: ChatAction, mut ctx: ChatContext, session_id: Option<String>, chat: &Chat) -> Result<JsValue, JsValue> {
+    let Some(session_id) = session_id else {
+        return Ok(serde_wasm_bindgen::to_value(&action)?);
+    };
+    action.save_exchange(&mut ctx.note, &session_id, chat);
+    let note = serde_json::to_string(&ctx.note).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    Ok(serde_wasm_bindgen::to_value(&SessionChatResult { action, note })?)
+}
+
+/// The result of a chat that saves the exchange in a chat session.
+#[derive(Serialize)]
+struct SessionChatResult {
+    action: ChatAction,
+    /// The note JSON with the exchange saved
+    note: String,
+}
+
+/// The optional settings of a chat call.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct ChatOptions {
+    /// How much of the note the agent sees
+    mode: ContextMode,
+    /// How the note briefs are written in the prompt
+    brief_format: BriefFormat,
+    /// The brief summaries of the notes the note mentions, by mention name
+    referenced_notes: BTreeMap<String, String>,
+    /// The chat session of the note to save the exchange in
+    session_id: Option<String>,
+}
+
+/// Parse the note of a chat and build the context the agent sees.
+fn chat_context(note_json: &str, cursor_position: CursorPosition, options: ChatOptions) -> Result<ChatContext, ChatError> {
+    let note = Note::from_json_lenient(note_json)
+        .map_err(|e| ChatError::new("invalid_note", format!("Invalid note: {}", e)))?;
+    Ok(ChatContext {
+        note,
+        cursor_position,
+        mode: options.mode,
+        brief_format: options.brief_format,
+        referenced_notes: options.referenced_notes,
+    })
+}
+
+/// A structured chat error for the frontend to branch on.
+#[derive(Debug, Serialize)]
+struct ChatError {
+    /// A short identifier of the error kind
+    code: &'static str,
+    /// A human-readable description
+    message: String,
+    /// Whether sending the same chat again may succeed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retryable: Option<bool>,
+}
+
+impl ChatError {
+    fn new(code: &'static str, message: impl Into<String>) -> Self {
+        ChatError {
+            code,
+            message: message.into(),
+            retryable: None,
         }
     }
+}
 
-    #[wasm_bindgen]
-    pub fn is_running(&self) -> bool {
-        self.running
+impl From<&ServiceError> for ChatError {
+    fn from(err: &ServiceError) -> Self {
+        ChatError {
+            code: err.kind(),
+            message: err.to_string(),
+            retryable: Some(err.is_retryable()),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ChatError {
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast_ref::<ServiceError>() {
+            Some(service_err) => service_err.into(),
+            None => ChatError::new("chat", format!("Chat error: {}", err)),
+        }
+    }
+}
+
+impl From<ChatError> for JsValue {
+    fn from(err: ChatError) -> Self {
+        serde_wasm_bindgen::to_value(&err).unwrap_or_else(|_| JsValue::from_str(&err.message))
+    }
+}
+
+/// Check whether an action returned by `chat` changes the title of the note, so that the
+/// note list needs updating. Takes the action as JSON.
+#[wasm_bindgen]
+pub fn action_changes_title(action_json: &str) -> Result<bool, JsValue> {
+    let action = ChatAction::try_from_reply(action_json.to_string())
+        .map_err(|e| JsValue::from_str(&format!("Invalid action JSON: {}", e)))?;
+    Ok(action.changes_title())
+}
+
+/// Parse a note from its JSON representation, migrating notes written with older schemas
+/// and recovering a root node of another type.
+fn parse_note(note_json: &str) -> anyhow::Result<Note> {
+    Ok(Note::from_json_lenient(note_json)?)
+}
+
+/// Parse a note JSON and get the briefs the agent will see.
+#[wasm_bindgen]
+pub fn get_note_briefs(note_json: &str) -> Result<JsValue, JsValue> {
+    let briefs = Note::get_brief_from_json(note_json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    Ok(serde_wasm_bindgen::to_value(&briefs)?)
+}
+
+/// Parse a note JSON and map the brief ids the agent uses to the Lexical keys of the nodes.
+///
+/// Returns an object with the brief ids as keys. Nodes without a key are left out.
+#[wasm_bindgen]
+pub fn get_brief_keys(note_json: &str) -> Result<JsValue, JsValue> {
+    let note = parse_note(note_json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let keys: std::collections::BTreeMap<String, String> =
+        note.brief_keys().into_iter().map(|(id, key)| (id.to_string(), key)).collect();
+    Ok(keys.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?)
+}
+
+/// Parse a note JSON and get the plain text of the root node at `id`, for a preview tooltip.
+///
+/// Fails if the note has no node at `id`.
+#[wasm_bindgen]
+pub fn node_plain_text(note_json: &str, id: usize) -> Result<String, JsValue> {
+    note_node_text(note_json, id).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Parse a note JSON and get the plain text of the root node at `id`.
+fn note_node_text(note_json: &str, id: usize) -> anyhow::Result<String> {
+    let note = parse_note(note_json)?;
+    note.node_plain_text(id).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Node {} is not in the note, which has {} nodes",
+            id,
+            note.lexical_state.root.children.len()
+        )
+    })
+}
+
+/// Parse a note JSON and export it as Markdown.
+#[wasm_bindgen]
+pub fn note_to_markdown(note_json: &str) -> Result<String, JsValue> {
+    let note = parse_note(note_json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    Ok(note.to_markdown())
+}
+
+/// Parse a note JSON and compute its document statistics.
+#[wasm_bindgen]
+pub fn note_stats(note_json: &str) -> Result<JsValue, JsValue> {
+    let note = parse_note(note_json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    Ok(serde_wasm_bindgen::to_value(&note.stats())?)
+}
+
+/// Check a note JSON before saving it.
+///
+/// Fails with a JSON array of the problems found, each with the `path` of the node, a
+/// `message` and a `severity` of "error" or "warning". Only errors fail the check, but the
+/// array then includes the warnings too. A note that does not parse has a single error
+/// with an empty path.
+#[wasm_bindgen]
+pub fn validate_note(note_json: &str) -> Result<(), JsValue> {
+    let issues = note_issues(note_json);
+    if !issues.iter().any(ValidationIssue::is_error) {
+        return Ok(());
     }
+    let issues = serde_json::to_string(&issues).expect("Validation issues should serialize");
+    Err(JsValue::from_str(&issues))
+}
+
+/// Parse and validate a note JSON, reporting a parse error as an issue at the root.
+fn note_issues(note_json: &str) -> Vec<ValidationIssue> {
+    match parse_note(note_json) {
+        Ok(note) => note.validate(),
+        Err(e) => vec![ValidationIssue {
+            path: Vec::new(),
+            message: e.to_string(),
+            severity: Severity::Error,
+        }],
+    }
+}
+
+/// Append a message to a chat session of the note, creating the session if needed.
+///
+/// Call it with the user message and the agent reply after each chat to save the
+/// conversation in the note. Returns the updated note JSON.
+#[wasm_bindgen]
+pub fn append_to_session(note_json: &str, session_id: &str, sender: &str, content: &str) -> Result<String, JsValue> {
+    let mut note = parse_note(note_json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let sender: MessageSender = serde_json::from_value(serde_json::Value::String(sender.to_string()))
+        .map_err(|_| JsValue::from_str(&format!("Invalid sender: {}", sender)))?;
+    note.append_to_session(session_id, sender, content);
+    serde_json::to_string(&note).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Apply an action returned by `chat` to a note, and return the updated note JSON.
+///
+/// The AI embeddings the action creates record the user message it answers as `prompt`,
+/// and the chat session it was generated in, when given. The op id of the action is saved
+/// in the returned JSON as `appliedOpIds`, so keep that field when saving the note for
+/// retries of the action to be ignored.
+#[wasm_bindgen]
+pub fn apply_chat_action(
+    note_json: &str,
+    action_json: &str,
+    prompt: Option<String>,
+    session_id: Option<String>,
+) -> Result<String, JsValue> {
+    let mut note = parse_note(note_json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let action = ChatAction::try_from_reply(action_json.to_string())
+        .map_err(|e| JsValue::from_str(&format!("Invalid action JSON: {}", e)))?;
+    note.apply_action_with_provenance(&action, &Provenance { prompt, session_id })
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    serde_json::to_string(&note).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Parse a note JSON and encode it in the binary form, to cache it in IndexedDB.
+#[wasm_bindgen]
+pub fn note_to_bytes(note_json: &str) -> Result<Vec<u8>, JsValue> {
+    let note = parse_note(note_json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    note.to_bytes().map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Decode a note from the binary form of `note_to_bytes`, returning its JSON.
+#[wasm_bindgen]
+pub fn note_from_bytes(bytes: &[u8]) -> Result<String, JsValue> {
+    let note = Note::from_bytes(bytes).map_err(|e| JsValue::from_str(&format!("Invalid binary note: {}", e)))?;
+    serde_json::to_string(&note).map_err(|e| JsValue::from_str(&e.to_string()))
 }
 
 /// Initialize the WASM module.
@@ -146,3 +479,239 @@ pub fn start() {
     log::init();
     tracing::info!("WASM module initialized");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_parse_note() {
+        let json_content = fs::read_to_string("assets/example_note.json")
+            .expect("Should be able to read assets/example_note.json");
+
+        let note = parse_note(&json_content).expect("Should be able to parse example note JSON");
+        assert!(!note.get_brief().is_empty(), "Should have brief nodes");
+    }
+
+    #[test]
+    fn test_note_to_markdown() {
+        let json_content = fs::read_to_string("assets/example_outline_note.json")
+            .expect("Should be able to read assets/example_outline_note.json");
+
+        let markdown = parse_note(&json_content)
+            .expect("Should be able to parse example outline note JSON")
+            .to_markdown();
+        assert!(markdown.starts_with("Draft notes for the launch.\n\n# Project Plan\n\n"));
+        assert!(markdown.contains("### Q1 Milestones\n\n- Finish onboarding\n- Invite testers"));
+    }
+
+    #[test]
+    fn test_node_plain_text() {
+        let json_content = fs::read_to_string("assets/example_note.json")
+            .expect("Should be able to read assets/example_note.json");
+
+        assert_eq!(note_node_text(&json_content, 1).unwrap(), "test");
+        let err = note_node_text(&json_content, 99).unwrap_err();
+        assert!(err.to_string().starts_with("Node 99 is not in the note, which has "), "{err}");
+        assert!(note_node_text("{ not json", 0).unwrap_err().to_string().starts_with("Invalid note JSON"));
+    }
+
+    #[test]
+    fn test_action_changes_title() {
+        let set_title = serde_json::json!({ "action": "set_title", "content": "Plan" });
+        let insert = serde_json::json!({ "action": "insert_node", "insert_after": 0, "node_type": "paragraph", "content": "Hi" });
+        let reply = serde_json::json!({ "action": "reply", "content": "Done" });
+
+        // The actions round-trip from the JSON that chat returns
+        for (action, changes_title) in [(&set_title, true), (&insert, false), (&reply, false)] {
+            let action = ChatAction::try_from_reply(action.to_string()).unwrap();
+            let action_json = serde_json::to_string(&action).unwrap();
+            assert_eq!(action_changes_title(&action_json).unwrap(), changes_title, "{action_json}");
+        }
+        let batch = serde_json::json!([insert, set_title]).to_string();
+        assert!(action_changes_title(&batch).unwrap());
+        let batch = serde_json::json!([insert, reply]).to_string();
+        assert!(!action_changes_title(&batch).unwrap());
+    }
+
+    #[test]
+    fn test_apply_chat_action() {
+        let json_content = fs::read_to_string("assets/example_outline_note.json").unwrap();
+        let action = serde_json::json!({
+            "action": "insert_node", "insert_after": 0, "node_type": "ai-embedding", "content": "A summary",
+        });
+
+        let note_json = apply_chat_action(
+            &json_content,
+            &action.to_string(),
+            Some("Summarize the note".to_string()),
+            Some("session-1".to_string()),
+        )
+        .expect("Should apply the action");
+        let note = parse_note(&note_json).unwrap();
+        let blocks = note.ai_blocks();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].content, "A summary");
+        assert_eq!(blocks[0].prompt.as_deref(), Some("Summarize the note"));
+        assert_eq!(blocks[0].session_id.as_deref(), Some("session-1"));
+    }
+
+    #[test]
+    fn test_chat_context_options() {
+        let json_content = fs::read_to_string("assets/example_outline_note.json").unwrap();
+        let cursor = CursorPosition { node_index: 3, char_offset: None };
+
+        let options: ChatOptions = serde_json::from_value(serde_json::json!({})).unwrap();
+        let ctx = chat_context(&json_content, cursor, options).unwrap();
+        assert_eq!((ctx.mode, ctx.brief_format), (ContextMode::Full, BriefFormat::Json));
+        let prompt = agent::get_system_prompt(&ctx).unwrap();
+        assert!(prompt.contains("```json\n"), "The briefs should be JSON by default");
+
+        let options: ChatOptions =
+            serde_json::from_value(serde_json::json!({ "mode": "section", "briefFormat": "compact" })).unwrap();
+        let ctx = chat_context(&json_content, cursor, options).unwrap();
+        assert_eq!((ctx.mode, ctx.brief_format), (ContextMode::Section, BriefFormat::Compact));
+        let prompt = agent::get_system_prompt(&ctx).unwrap();
+        assert!(prompt.contains("```text\n"), "The briefs should be compact when asked for");
+
+        let options: ChatOptions =
+            serde_json::from_value(serde_json::json!({ "referencedNotes": { "Roadmap": "Q3: ship the beta." } })).unwrap();
+        let ctx = chat_context(&json_content, cursor, options).unwrap();
+        assert_eq!(ctx.referenced_notes["Roadmap"], "Q3: ship the beta.");
+        let options: ChatOptions = serde_json::from_value(serde_json::json!({ "sessionId": "session-1" })).unwrap();
+        assert_eq!(options.session_id.as_deref(), Some("session-1"));
+
+        let err = chat_context("{ not json", cursor, ChatOptions::default()).unwrap_err();
+        assert_eq!(err.code, "invalid_note");
+        assert!(serde_json::from_value::<ChatOptions>(serde_json::json!({ "mode": "window" })).is_err());
+    }
+
+    #[test]
+    fn test_chat_error_shape() {
+        let error = ChatError::from(anyhow::Error::new(ServiceError::Server(503)));
+        assert_eq!(
+            serde_json::to_value(error).unwrap(),
+            serde_json::json!({ "code": "server", "message": "Server error: status 503", "retryable": true })
+        );
+
+        let error = ChatError::from(&ServiceError::Auth);
+        assert_eq!(
+            serde_json::to_value(error).unwrap(),
+            serde_json::json!({ "code": "auth", "message": "Authentication failed", "retryable": false })
+        );
+
+        // Other errors keep their message without a retry hint
+        let error = ChatError::from(anyhow::anyhow!("Invalid action: dance"));
+        assert_eq!(
+            serde_json::to_value(error).unwrap(),
+            serde_json::json!({ "code": "chat", "message": "Chat error: Invalid action: dance" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chat_error_from_failed_chat() {
+        let model = service::AimoModel::new("jwt".to_string()).with_base_url("ftp://example.com");
+        let (mut agent, mut handler) = agent::create_agent_with_model(model);
+        let json_content = fs::read_to_string("assets/example_note.json").unwrap();
+        let ctx = ChatContext {
+            note: parse_note(&json_content).unwrap(),
+            cursor_position: 0.into(),
+            mode: ContextMode::Full,
+            brief_format: BriefFormat::default(),
+            referenced_notes: BTreeMap::new(),
+        };
+        let chat = Chat {
+            messages: vec![ChatMessage { content: "Hi".to_string(), role: "user".to_string() }],
+            session_id: 0,
+        };
+
+        // The model call fails, so the chat fails with the structured error instead of a reply
+        let error = tokio::select! {
+            _ = agent.run() => panic!("The agent should keep running"),
+            result = handler.chat(chat, &ctx) => ChatError::from(result.expect_err("The chat should fail")),
+        };
+        assert_eq!(
+            serde_json::to_value(error).unwrap(),
+            serde_json::json!({
+                "code": "invalid_url",
+                "message": "Invalid completion URL: ftp://example.com/chat/completions",
+                "retryable": false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_note() {
+        for path in ["assets/example_note.json", "assets/example_table_note.json"] {
+            let json_content = fs::read_to_string(path).expect("Should be able to read the fixture");
+            assert!(validate_note(&json_content).is_ok(), "{path} should be valid");
+        }
+
+        let issues = note_issues("{ not json");
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].path.is_empty());
+        assert!(issues[0].message.starts_with("Invalid note JSON"));
+
+        let mut note: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string("assets/example_chat_note.json").unwrap()).unwrap();
+        note["lexicalState"]["root"]["version"] = 0.into();
+        note["lexicalState"]["root"]["type"] = "list".into();
+        // A root of another type is recovered, but the other issues are reported
+        let issues = note_issues(&note.to_string());
+        assert_eq!(
+            serde_json::to_value(&issues).unwrap(),
+            serde_json::json!([{ "path": [], "message": "The root node has no version", "severity": "error" }])
+        );
+
+        // A low-confidence transcript is only a warning
+        let json_content = fs::read_to_string("assets/example_voice_note.json").unwrap();
+        let issues = note_issues(&json_content);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, Severity::Warning);
+        assert!(validate_note(&json_content).is_ok(), "A note with only warnings should be valid");
+    }
+
+    #[test]
+    fn test_message_roles() {
+        let role = |role: &str| ChatMessage::from(Message::new("Hi".to_string(), role.to_string())).role;
+
+        assert_eq!(role("user"), "user");
+        assert_eq!(role("system"), "system");
+        for alias in ["assistant", "ai", "bot", "agent", " AI "] {
+            assert_eq!(role(alias), "assistant", "{alias} should be an assistant alias");
+        }
+        assert_eq!(role("moderator"), "user");
+        assert_eq!(role(""), "user");
+    }
+
+    #[test]
+    fn test_parse_note_invalid_json() {
+        let err = parse_note("{ not json").expect_err("Should fail on invalid JSON");
+        assert!(err.to_string().starts_with("Invalid note JSON"));
+
+        let err = parse_note("{}").expect_err("Should fail on a JSON without lexical state");
+        assert!(err.to_string().contains("lexicalState"));
+
+        let err = parse_note(r#"{"schemaVersion": 99}"#).expect_err("Should fail on a note from a newer app");
+        assert!(err.to_string().starts_with("The note requires a newer app"));
+    }
+
+    #[test]
+    fn test_parse_note_nesting_depth() {
+        let levels = 200;
+        let json = format!(
+            r#"{{"lexicalState":{{"root":{{"type":"root","version":1,"children":[{}{}]}}}}}}"#,
+            r#"{"type":"quote","version":1,"children":["#.repeat(levels),
+            "]}".repeat(levels),
+        );
+
+        let err = parse_note(&json).expect_err("Should reject a deeply nested note");
+        assert!(err.to_string().contains("Note nesting exceeds the maximum depth of 128"), "{err}");
+
+        let cursor = CursorPosition { node_index: 0, char_offset: None };
+        let err = chat_context(&json, cursor, ChatOptions::default()).unwrap_err();
+        assert_eq!(err.code, "invalid_note");
+        assert!(err.message.contains("Note nesting exceeds the maximum depth of 128"), "{}", err.message);
+    }
+}
@@ -1,11 +1,64 @@
+use std::collections::VecDeque;
+
+use amico_core::types::ChatMessage;
 use serde::{Deserialize, Serialize};
 
+mod apply;
+mod brief_id;
+mod bytes;
+mod cache;
+mod code;
+mod compact;
+mod diff;
+mod lite;
+mod markdown;
+mod merge;
+mod migrate;
+mod nesting;
+mod page;
+mod path;
+mod refs;
+mod search;
+mod session;
+mod stats;
+mod text;
+mod validate;
+mod walk;
+
+pub use apply::Provenance;
+pub use brief_id::BriefId;
+pub use cache::CachedNote;
+pub use code::detect_language;
+pub use migrate::{MigrateError, SCHEMA_VERSION, migrate};
+pub use path::{NodePath, PathError};
+pub use text::TextExtractOptions;
+pub use validate::{Severity, ValidationIssue};
+pub use walk::NodeIter;
+
+use brief_id::{FNV_OFFSET, fnv1a};
+
+/// The deepest JSON nesting accepted when parsing a note, matching the serde_json recursion limit.
+pub const MAX_NESTING_DEPTH: usize = 128;
+
 /// Main Note structure representing a complete note with metadata and content
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")] // Serde ignores unknown fields by default
+#[serde(rename_all = "camelCase")]
 pub struct Note {
     pub note_id: Option<String>,
+    /// The schema version the note was written with, see [`migrate`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_version: Option<u32>,
     pub lexical_state: LexicalState,
+    /// The latest operation ids applied to the note, to ignore retried actions.
+    ///
+    /// This is part of the persisted note: it is saved in the note JSON as `appliedOpIds`,
+    /// so that a retry applied to the saved note, such as through `apply_chat_action`,
+    /// is still ignored. Only the last 100 ids are kept.
+    #[serde(default, skip_serializing_if = "VecDeque::is_empty")]
+    pub applied_op_ids: VecDeque<String>,
+    /// Note metadata we don't model, such as the save timestamps
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
 /// The top-level Lexical state containing the root node
@@ -19,16 +72,45 @@ pub struct LexicalState {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BaseNodeProperties {
     pub version: u32,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub direction: Option<TextDirection>,
+    /// `Some(None)` is an explicit null, which Lexical writes for blocks without text
+    #[serde(default, deserialize_with = "deserialize_some", skip_serializing_if = "Option::is_none")]
+    pub direction: Option<Option<TextDirection>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub format: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub indent: Option<u32>,
+    /// The Lexical node key, used by the editor for reconciliation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Fields we don't model, kept so that a round trip doesn't lose them
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Default for BaseNodeProperties {
+    fn default() -> Self {
+        Self {
+            version: 1,
+            direction: None,
+            format: None,
+            indent: None,
+            key: None,
+            extra: serde_json::Map::new(),
+        }
+    }
+}
+
+/// Deserialize a present field into `Some`, so that an explicit null is kept apart from a missing field
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
 }
 
 /// Text direction enumeration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TextDirection {
     #[serde(rename = "ltr")]
     LeftToRight,
@@ -39,15 +121,31 @@ pub enum TextDirection {
 /// Root node - the top-level container
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RootNode {
-    #[serde(rename = "type")]
-    pub node_type: String, // Always "root"
+    /// Always "root", notes with another type fail to parse
+    #[serde(rename = "type", deserialize_with = "deserialize_root_type")]
+    pub node_type: String,
+    #[serde(serialize_with = "nesting::serialize_children")]
     pub children: Vec<LexicalNode>,
     #[serde(flatten)]
     pub base: BaseNodeProperties,
 }
 
+/// Deserialize the type of the root node, rejecting anything but "root"
+fn deserialize_root_type<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let node_type = String::deserialize(deserializer)?;
+    if node_type != "root" {
+        return Err(serde::de::Error::custom(format!(
+            "The root node has type \"{node_type}\" instead of \"root\""
+        )));
+    }
+    Ok(node_type)
+}
+
 /// Main node enumeration covering all possible node types
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// Dropping and cloning a node don't recurse over its depth, so deep trees built in code
+/// can't overflow the stack. Serializing them fails past [`MAX_NESTING_DEPTH`].
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum LexicalNode {
     #[serde(rename = "text")]
@@ -64,6 +162,10 @@ pub enum LexicalNode {
     Quote(QuoteNode),
     #[serde(rename = "code")]
     Code(CodeNode),
+    #[serde(rename = "code-highlight")]
+    CodeHighlight(CodeHighlightNode),
+    #[serde(rename = "linebreak")]
+    LineBreak(LineBreakNode),
     #[serde(rename = "link")]
     Link(LinkNode),
     #[serde(rename = "autolink")]
@@ -91,6 +193,144 @@ pub enum LexicalNode {
     Mention(MentionNode),
 }
 
+impl LexicalNode {
+    /// Get the Lexical type name of the node.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            LexicalNode::Text(_) => "text",
+            LexicalNode::Paragraph(_) => "paragraph",
+            LexicalNode::Heading(_) => "heading",
+            LexicalNode::List(_) => "list",
+            LexicalNode::ListItem(_) => "listitem",
+            LexicalNode::Quote(_) => "quote",
+            LexicalNode::Code(_) => "code",
+            LexicalNode::CodeHighlight(_) => "code-highlight",
+            LexicalNode::LineBreak(_) => "linebreak",
+            LexicalNode::Link(_) => "link",
+            LexicalNode::AutoLink(_) => "autolink",
+            LexicalNode::Hashtag(_) => "hashtag",
+            LexicalNode::Table(_) => "table",
+            LexicalNode::TableRow(_) => "tablerow",
+            LexicalNode::TableCell(_) => "tablecell",
+            LexicalNode::PageBreak(_) => "page-break",
+            LexicalNode::AIEmbedding(_) => "ai-embedding",
+            LexicalNode::VoiceInput(_) => "voice-input",
+            LexicalNode::ChatMessage(_) => "chat-message",
+            LexicalNode::ChatSession(_) => "chat-session",
+            LexicalNode::Mention(_) => "mention",
+        }
+    }
+
+    /// Get the properties shared by all nodes.
+    pub fn base(&self) -> &BaseNodeProperties {
+        match self {
+            LexicalNode::Text(node) => &node.base,
+            LexicalNode::Paragraph(node) => &node.base,
+            LexicalNode::Heading(node) => &node.base,
+            LexicalNode::List(node) => &node.base,
+            LexicalNode::ListItem(node) => &node.base,
+            LexicalNode::Quote(node) => &node.base,
+            LexicalNode::Code(node) => &node.base,
+            LexicalNode::CodeHighlight(node) => &node.base,
+            LexicalNode::LineBreak(node) => &node.base,
+            LexicalNode::Link(node) => &node.base,
+            LexicalNode::AutoLink(node) => &node.base,
+            LexicalNode::Hashtag(node) => &node.base,
+            LexicalNode::Table(node) => &node.base,
+            LexicalNode::TableRow(node) => &node.base,
+            LexicalNode::TableCell(node) => &node.base,
+            LexicalNode::PageBreak(node) => &node.base,
+            LexicalNode::AIEmbedding(node) => &node.base,
+            LexicalNode::VoiceInput(node) => &node.base,
+            LexicalNode::ChatMessage(node) => &node.base,
+            LexicalNode::ChatSession(node) => &node.base,
+            LexicalNode::Mention(node) => &node.base,
+        }
+    }
+
+    /// Get the mutable properties shared by all nodes.
+    pub fn base_mut(&mut self) -> &mut BaseNodeProperties {
+        match self {
+            LexicalNode::Text(node) => &mut node.base,
+            LexicalNode::Paragraph(node) => &mut node.base,
+            LexicalNode::Heading(node) => &mut node.base,
+            LexicalNode::List(node) => &mut node.base,
+            LexicalNode::ListItem(node) => &mut node.base,
+            LexicalNode::Quote(node) => &mut node.base,
+            LexicalNode::Code(node) => &mut node.base,
+            LexicalNode::CodeHighlight(node) => &mut node.base,
+            LexicalNode::LineBreak(node) => &mut node.base,
+            LexicalNode::Link(node) => &mut node.base,
+            LexicalNode::AutoLink(node) => &mut node.base,
+            LexicalNode::Hashtag(node) => &mut node.base,
+            LexicalNode::Table(node) => &mut node.base,
+            LexicalNode::TableRow(node) => &mut node.base,
+            LexicalNode::TableCell(node) => &mut node.base,
+            LexicalNode::PageBreak(node) => &mut node.base,
+            LexicalNode::AIEmbedding(node) => &mut node.base,
+            LexicalNode::VoiceInput(node) => &mut node.base,
+            LexicalNode::ChatMessage(node) => &mut node.base,
+            LexicalNode::ChatSession(node) => &mut node.base,
+            LexicalNode::Mention(node) => &mut node.base,
+        }
+    }
+
+    /// Whether the node is block content, which starts on a new line in extracted text.
+    pub fn is_block(&self) -> bool {
+        match self {
+            LexicalNode::Code(code) => code.children.is_some(),
+            LexicalNode::Paragraph(_)
+            | LexicalNode::Heading(_)
+            | LexicalNode::List(_)
+            | LexicalNode::Quote(_)
+            | LexicalNode::Table(_)
+            | LexicalNode::TableRow(_)
+            | LexicalNode::PageBreak(_)
+            | LexicalNode::AIEmbedding(_)
+            | LexicalNode::VoiceInput(_)
+            | LexicalNode::ChatMessage(_)
+            | LexicalNode::ChatSession(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Get the child nodes of a container node, if any.
+    pub fn children(&self) -> Option<&Vec<LexicalNode>> {
+        match self {
+            LexicalNode::Paragraph(node) => Some(&node.children),
+            LexicalNode::Heading(node) => Some(&node.children),
+            LexicalNode::List(node) => Some(&node.children),
+            LexicalNode::ListItem(node) => Some(&node.children),
+            LexicalNode::Quote(node) => Some(&node.children),
+            LexicalNode::Code(node) => node.children.as_ref(),
+            LexicalNode::Link(node) => Some(&node.children),
+            LexicalNode::AutoLink(node) => Some(&node.children),
+            LexicalNode::Table(node) => Some(&node.children),
+            LexicalNode::TableRow(node) => Some(&node.children),
+            LexicalNode::TableCell(node) => Some(&node.children),
+            _ => None,
+        }
+    }
+
+    /// Get the mutable child nodes of a container node, if any.
+    pub fn children_mut(&mut self) -> Option<&mut Vec<LexicalNode>> {
+        match self {
+            LexicalNode::Paragraph(node) => Some(&mut node.children),
+            LexicalNode::Heading(node) => Some(&mut node.children),
+            LexicalNode::List(node) => Some(&mut node.children),
+            LexicalNode::ListItem(node) => Some(&mut node.children),
+            LexicalNode::Quote(node) => Some(&mut node.children),
+            LexicalNode::Code(node) => node.children.as_mut(),
+            LexicalNode::Link(node) => Some(&mut node.children),
+            LexicalNode::AutoLink(node) => Some(&mut node.children),
+            LexicalNode::Table(node) => Some(&mut node.children),
+            LexicalNode::TableRow(node) => Some(&mut node.children),
+            LexicalNode::TableCell(node) => Some(&mut node.children),
+            _ => None,
+        }
+    }
+}
+
 /// Text node - basic text content
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TextNode {
@@ -107,9 +347,36 @@ pub struct TextNode {
     pub base: BaseNodeProperties,
 }
 
+impl TextNode {
+    /// Create a plain text node without formatting.
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            format: 0,
+            detail: 0,
+            mode: "normal".to_string(),
+            style: String::new(),
+            base: BaseNodeProperties::default(),
+        }
+    }
+
+    /// Get the format flag for a format name, such as "bold".
+    pub fn format_flag(name: &str) -> Option<u32> {
+        match name {
+            "bold" => Some(1),
+            "italic" => Some(2),
+            "underline" => Some(4),
+            "strikethrough" => Some(8),
+            "code" => Some(16),
+            _ => None,
+        }
+    }
+}
+
 /// Paragraph node - container for text and inline elements
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ParagraphNode {
+    #[serde(serialize_with = "nesting::serialize_children")]
     pub children: Vec<LexicalNode>,
     // Additional fields found in the example JSON
     #[serde(default, rename = "textFormat")]
@@ -124,13 +391,14 @@ pub struct ParagraphNode {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HeadingNode {
     pub tag: HeadingTag,
+    #[serde(serialize_with = "nesting::serialize_children")]
     pub children: Vec<LexicalNode>,
     #[serde(flatten)]
     pub base: BaseNodeProperties,
 }
 
 /// Heading tag enumeration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum HeadingTag {
     H1,
@@ -141,12 +409,24 @@ pub enum HeadingTag {
     H6,
 }
 
+impl HeadingTag {
+    /// Get the numeric heading level, from 1 to 6.
+    pub fn level(self) -> usize {
+        self as usize + 1
+    }
+}
+
 /// List node - ordered or unordered lists
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ListNode {
     #[serde(rename = "listType")]
     pub list_type: ListType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub start: Option<u32>,
+    /// The HTML tag of the list, "ul" or "ol"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<ListTag>,
+    #[serde(serialize_with = "nesting::serialize_children")]
     pub children: Vec<LexicalNode>,
     #[serde(flatten)]
     pub base: BaseNodeProperties,
@@ -160,10 +440,32 @@ pub enum ListType {
     Number,
 }
 
+impl ListType {
+    /// Get the HTML tag Lexical uses for the list type.
+    pub fn tag(&self) -> ListTag {
+        match self {
+            ListType::Bullet => ListTag::Ul,
+            ListType::Number => ListTag::Ol,
+        }
+    }
+}
+
+/// List tag enumeration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListTag {
+    Ul,
+    Ol,
+}
+
 /// List item node - individual items within lists
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ListItemNode {
+    #[serde(serialize_with = "nesting::serialize_children")]
     pub children: Vec<LexicalNode>,
+    /// The ordinal of the item, which Lexical keeps in sync with the list's `start`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<u32>,
     #[serde(flatten)]
     pub base: BaseNodeProperties,
 }
@@ -171,6 +473,7 @@ pub struct ListItemNode {
 /// Quote node - blockquotes
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QuoteNode {
+    #[serde(serialize_with = "nesting::serialize_children")]
     pub children: Vec<LexicalNode>,
     #[serde(flatten)]
     pub base: BaseNodeProperties,
@@ -183,13 +486,45 @@ pub struct CodeNode {
     pub text: Option<String>, // For inline code
     #[serde(skip_serializing_if = "Option::is_none")]
     pub language: Option<String>, // For code blocks
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "nesting::serialize_code_children")]
     pub children: Option<Vec<LexicalNode>>, // For code blocks
+    pub format: CodeFormat,
+    #[serde(flatten)]
+    pub base: BaseNodeProperties,
+}
+
+/// The format of a code node.
+///
+/// Lexical writes the element alignment, such as "", for code blocks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CodeFormat {
+    /// Text format flags, as on inline code
+    Flags(u32),
+    /// The block alignment
+    Align(String),
+}
+
+/// Code highlight node - a highlighted token inside a code block
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodeHighlightNode {
+    pub text: String,
+    /// The token type from the highlighter, such as "keyword"
+    #[serde(rename = "highlightType", default, skip_serializing_if = "Option::is_none")]
+    pub highlight_type: Option<String>,
+    #[serde(default)]
     pub format: u32,
     #[serde(flatten)]
     pub base: BaseNodeProperties,
 }
 
+/// Line break node - a line break within a block
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LineBreakNode {
+    #[serde(flatten)]
+    pub base: BaseNodeProperties,
+}
+
 /// Link node - hyperlinks
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LinkNode {
@@ -198,20 +533,64 @@ pub struct LinkNode {
     pub rel: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub target: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(serialize_with = "nesting::serialize_children")]
     pub children: Vec<LexicalNode>,
     #[serde(flatten)]
     pub base: BaseNodeProperties,
 }
 
+impl LinkNode {
+    /// Whether the URL is relative or uses the http, https or mailto scheme.
+    pub fn is_safe_url(&self) -> bool {
+        is_safe_url(&self.url)
+    }
+}
+
 /// Auto link node - automatically detected links
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AutoLinkNode {
     pub url: String,
+    #[serde(serialize_with = "nesting::serialize_children")]
     pub children: Vec<LexicalNode>,
     #[serde(flatten)]
     pub base: BaseNodeProperties,
 }
 
+impl AutoLinkNode {
+    /// Whether the URL is relative or uses the http, https or mailto scheme.
+    pub fn is_safe_url(&self) -> bool {
+        is_safe_url(&self.url)
+    }
+}
+
+/// Check the scheme of a URL the way a browser reads it.
+///
+/// Browsers ignore leading control characters and spaces, and tabs and newlines anywhere,
+/// so "\tjava\nscript:" is a `javascript:` URL. Protocol-relative URLs ("//host") are allowed.
+fn is_safe_url(url: &str) -> bool {
+    let url: String = url
+        .trim_start_matches(|c: char| c.is_ascii_control() || c == ' ')
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+        .collect();
+
+    // A colon before any path, query or fragment ends the scheme
+    let scheme_end = url.find([':', '/', '?', '#']);
+    let Some(end) = scheme_end.filter(|&end| url[end..].starts_with(':')) else {
+        return true;
+    };
+    let scheme = &url[..end];
+    let is_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    if !is_scheme {
+        // Not a scheme, e.g. "a b:c" is a relative path
+        return true;
+    }
+    matches!(scheme.to_ascii_lowercase().as_str(), "http" | "https" | "mailto")
+}
+
 /// Hashtag node - hashtags
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HashtagNode {
@@ -224,6 +603,7 @@ pub struct HashtagNode {
 /// Table node - tables
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TableNode {
+    #[serde(serialize_with = "nesting::serialize_children")]
     pub children: Vec<LexicalNode>, // TableRow nodes
     #[serde(flatten)]
     pub base: BaseNodeProperties,
@@ -232,6 +612,7 @@ pub struct TableNode {
 /// Table row node - table rows
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TableRowNode {
+    #[serde(serialize_with = "nesting::serialize_children")]
     pub children: Vec<LexicalNode>, // TableCell nodes
     #[serde(flatten)]
     pub base: BaseNodeProperties,
@@ -240,17 +621,78 @@ pub struct TableRowNode {
 /// Table cell node - table cells
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TableCellNode {
+    #[serde(serialize_with = "nesting::serialize_children")]
     pub children: Vec<LexicalNode>,
     #[serde(rename = "headerState")]
     pub header_state: u32,
     #[serde(rename = "colSpan")]
     pub col_span: u32,
-    #[serde(rename = "rowSpan")]
+    /// Older notes omit the row span of cells that don't span rows
+    #[serde(rename = "rowSpan", default = "default_span")]
     pub row_span: u32,
+    /// The width of the cell in pixels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<f64>,
+    /// A CSS color, such as "#f2f3f5"
+    #[serde(rename = "backgroundColor", default, skip_serializing_if = "Option::is_none")]
+    pub background_color: Option<String>,
     #[serde(flatten)]
     pub base: BaseNodeProperties,
 }
 
+fn default_span() -> u32 {
+    1
+}
+
+impl TableNode {
+    /// Create a table with a paragraph of text per cell, the first row being the header row.
+    ///
+    /// Short rows are filled with empty cells, so that every row has as many cells as the longest.
+    pub fn from_rows(rows: &[Vec<String>]) -> Self {
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let rows = rows
+            .iter()
+            .enumerate()
+            .map(|(row_index, row)| {
+                let cells = (0..columns)
+                    .map(|column| {
+                        let text = row.get(column).map(String::as_str).unwrap_or_default();
+                        let children = if text.is_empty() {
+                            Vec::new()
+                        } else {
+                            vec![LexicalNode::Text(TextNode::new(text))]
+                        };
+                        LexicalNode::TableCell(TableCellNode {
+                            children: vec![LexicalNode::Paragraph(ParagraphNode {
+                                children,
+                                text_format: 0,
+                                text_style: String::new(),
+                                base: BaseNodeProperties::default(),
+                            })],
+                            // The row header state of Lexical
+                            header_state: u32::from(row_index == 0),
+                            col_span: 1,
+                            row_span: 1,
+                            width: None,
+                            background_color: None,
+                            base: BaseNodeProperties::default(),
+                        })
+                    })
+                    .collect();
+                LexicalNode::TableRow(TableRowNode {
+                    children: cells,
+                    base: BaseNodeProperties::default(),
+                })
+            })
+            .collect();
+
+        Self {
+            children: rows,
+            base: BaseNodeProperties::default(),
+        }
+    }
+}
+
 /// Page break node - page breaks
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PageBreakNode {
@@ -264,6 +706,18 @@ pub struct AIEmbeddingNode {
     pub content: String,
     #[serde(rename = "isLoading")]
     pub is_loading: bool,
+    /// The user message the content was generated for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    /// The model that generated the content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// The chat session the content was generated in
+    #[serde(rename = "sessionId", default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// The RFC 3339 time the content was generated
+    #[serde(rename = "createdAt", default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
     #[serde(flatten)]
     pub base: BaseNodeProperties,
 }
@@ -272,10 +726,49 @@ pub struct AIEmbeddingNode {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VoiceInputNode {
     pub content: String,
+    // Missing in notes written before transcription status was tracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<VoiceInputStatus>,
+    /// The confidence of the transcription, from 0 to 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f32>,
+    /// The length of the recording in milliseconds
+    #[serde(default, rename = "durationMs", skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// The language of the transcription, such as "de"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
     #[serde(flatten)]
     pub base: BaseNodeProperties,
 }
 
+/// Voice input transcription status enumeration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VoiceInputStatus {
+    Recording,
+    Transcribing,
+    Done,
+}
+
+impl VoiceInputNode {
+    /// The confidence below which a transcript may be garbled.
+    pub const LOW_CONFIDENCE: f32 = 0.7;
+
+    /// Whether the transcription has a confidence below [`VoiceInputNode::LOW_CONFIDENCE`].
+    pub fn is_low_confidence(&self) -> bool {
+        self.confidence.is_some_and(|confidence| confidence < Self::LOW_CONFIDENCE)
+    }
+
+    /// Whether the voice input is still being recorded or transcribed.
+    pub fn is_pending(&self) -> bool {
+        matches!(
+            self.status,
+            Some(VoiceInputStatus::Recording | VoiceInputStatus::Transcribing)
+        )
+    }
+}
+
 /// Chat Message node - individual chat messages
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessageNode {
@@ -295,6 +788,17 @@ pub enum MessageSender {
     System,
 }
 
+impl MessageSender {
+    /// Get the model chat role of the sender.
+    pub fn role(&self) -> &'static str {
+        match self {
+            MessageSender::User => "user",
+            MessageSender::Agent => "assistant",
+            MessageSender::System => "system",
+        }
+    }
+}
+
 /// Chat Session node - chat sessions containing multiple messages
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatSessionNode {
@@ -308,6 +812,20 @@ pub struct ChatSessionNode {
     pub base: BaseNodeProperties,
 }
 
+impl ChatSessionNode {
+    /// Convert the session into chat messages for the model, skipping empty messages.
+    pub fn to_chat_messages(&self) -> Vec<ChatMessage> {
+        self.messages
+            .iter()
+            .filter(|msg| !msg.content.trim().is_empty())
+            .map(|msg| ChatMessage {
+                content: msg.content.clone(),
+                role: msg.sender.role().to_string(),
+            })
+            .collect()
+    }
+}
+
 /// Message within a chat session
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatSessionMessage {
@@ -335,191 +853,944 @@ pub struct BriefNode {
     pub id: usize,
     pub node_type: String,
     pub content: String,
+    /// The closest root heading at or before the node, such as "H2: Budget",
+    /// with [`BriefOptions::include_section`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section: Option<String>,
+}
+
+impl BriefNode {
+    /// Serialize briefs to JSON for the prompt, ordered by id and without whitespace.
+    pub fn to_json(briefs: &[BriefNode]) -> String {
+        serde_json::to_string(&Self::sorted(briefs)).expect("Briefs serialize to JSON")
+    }
+
+    /// Serialize briefs to indented JSON ordered by id, for debugging.
+    pub fn to_json_pretty(briefs: &[BriefNode]) -> String {
+        serde_json::to_string_pretty(&Self::sorted(briefs)).expect("Briefs serialize to JSON")
+    }
+
+    fn sorted(briefs: &[BriefNode]) -> Vec<&BriefNode> {
+        let mut sorted: Vec<&BriefNode> = briefs.iter().collect();
+        sorted.sort_by_key(|brief| brief.id);
+        sorted
+    }
+}
+
+/// A section of the note, starting at a root heading node.
+#[derive(Debug, Clone)]
+pub struct Section<'a> {
+    /// The heading text, `None` for the preamble before the first heading.
+    pub heading: Option<String>,
+    /// The heading level, `None` for the preamble before the first heading.
+    pub level: Option<HeadingTag>,
+    /// The range of root node indices covered by the section, including the heading.
+    pub range: std::ops::Range<usize>,
+    /// The root nodes in the section, including the heading.
+    pub nodes: &'a [LexicalNode],
+}
+
+/// A window of briefs around the cursor position.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BriefWindow {
+    /// The briefs within the window, with their original root indices as ids.
+    pub briefs: Vec<BriefNode>,
+    /// The number of briefed nodes omitted before the window.
+    pub omitted_before: usize,
+    /// The number of briefed nodes omitted after the window.
+    pub omitted_after: usize,
+}
+
+/// Options for building the briefs of a note.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BriefOptions {
+    /// How the text of the nodes is extracted
+    pub text: TextExtractOptions,
+    /// Whether empty nodes get a brief with empty content, so that the ids have no gaps
+    pub include_empty: bool,
+    /// Whether the briefs carry the heading of their section
+    pub include_section: bool,
+}
+
+/// Options for normalizing a note.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NormalizeOptions {
+    /// Whether root paragraphs left without children are removed
+    pub remove_empty_paragraphs: bool,
+}
+
+impl Default for NormalizeOptions {
+    fn default() -> Self {
+        Self {
+            remove_empty_paragraphs: true,
+        }
+    }
+}
+
+/// What normalizing a note changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NormalizeReport {
+    /// Text nodes merged into the text node before them
+    pub merged_text_nodes: usize,
+    pub removed_empty_text_nodes: usize,
+    pub removed_empty_paragraphs: usize,
+    pub removed_empty_list_items: usize,
+    pub removed_empty_lists: usize,
+}
+
+impl NormalizeReport {
+    /// Whether normalizing changed the note.
+    pub fn changed(&self) -> bool {
+        *self != Self::default()
+    }
+}
+
+/// An entry of the note outline, built from the root heading nodes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutlineEntry {
+    pub text: String,
+    pub level: HeadingTag,
+    /// The root index of the heading node.
+    pub index: usize,
+    pub children: Vec<OutlineEntry>,
 }
 
 impl Note {
-    /// Get the briefs for the note.
-    pub fn get_brief(&self) -> Vec<BriefNode> {
-        let mut briefs = Vec::new();
-        
-        // Process each root node with its index
-        for (index, node) in self.lexical_state.root.children.iter().enumerate() {
-            self.collect_brief_from_node(node, &mut briefs, index);
+    /// Parse a note from JSON.
+    ///
+    /// Notes nested deeper than [`MAX_NESTING_DEPTH`] are rejected before parsing,
+    /// which bounds the memory and stack used for untrusted input.
+    pub fn from_json(json: &str) -> serde_json::Result<Note> {
+        Self::check_nesting(json)?;
+        serde_json::from_str(json)
+    }
+
+    /// Parse a note from JSON through [`migrate`], accepting a root node of another type.
+    ///
+    /// The root type is corrected to "root" with a warning, for recovering notes a
+    /// buggy client saved. Notes of older schemas are migrated like the other notes.
+    /// As with [`Note::from_json`], notes nested deeper than [`MAX_NESTING_DEPTH`]
+    /// are rejected before parsing.
+    pub fn from_json_lenient(json: &str) -> Result<Note, MigrateError> {
+        Self::check_nesting(json).map_err(MigrateError::Invalid)?;
+        let mut value: serde_json::Value = serde_json::from_str(json).map_err(MigrateError::Invalid)?;
+        if let Some(node_type) = value.pointer_mut("/lexicalState/root/type")
+            && node_type.as_str() != Some("root")
+        {
+            tracing::warn!("The root node has type {} instead of \"root\", correcting it", node_type);
+            *node_type = serde_json::Value::String("root".to_string());
         }
-        
-        briefs
+        migrate(value)
     }
-    
-    /// Collect brief from a single node using its root index
-    fn collect_brief_from_node(&self, node: &LexicalNode, briefs: &mut Vec<BriefNode>, root_index: usize) {
-        let (node_type, content) = match node {
-            LexicalNode::Text(text_node) => {
-                ("text", text_node.text.clone())
-            }
-            LexicalNode::Paragraph(para) => {
-                let content = self.extract_text_from_nodes(&para.children);
-                ("paragraph", content)
-            }
-            LexicalNode::Heading(heading) => {
-                let content = self.extract_text_from_nodes(&heading.children);
-                ("heading", content)
-            }
-            LexicalNode::List(list) => {
-                let content = self.extract_text_from_nodes(&list.children);
-                ("list", content)
-            }
-            LexicalNode::ListItem(item) => {
-                let content = self.extract_text_from_nodes(&item.children);
-                ("listitem", content)
-            }
-            LexicalNode::Quote(quote) => {
-                let content = self.extract_text_from_nodes(&quote.children);
-                ("quote", content)
-            }
-            LexicalNode::Code(code) => {
-                let content = if let Some(text) = &code.text {
-                    text.clone()
-                } else if let Some(children) = &code.children {
-                    self.extract_text_from_nodes(children)
-                } else {
-                    String::new()
-                };
-                ("code", content)
-            }
-            LexicalNode::Link(link) => {
-                let content = format!("{} ({})", self.extract_text_from_nodes(&link.children), link.url);
-                ("link", content)
-            }
-            LexicalNode::AutoLink(auto_link) => {
-                let content = format!("{} ({})", self.extract_text_from_nodes(&auto_link.children), auto_link.url);
-                ("autolink", content)
-            }
-            LexicalNode::Hashtag(hashtag) => {
-                ("hashtag", hashtag.text.clone())
-            }
-            LexicalNode::Table(table) => {
-                let content = self.extract_text_from_nodes(&table.children);
-                ("table", content)
-            }
-            LexicalNode::TableRow(row) => {
-                let content = self.extract_text_from_nodes(&row.children);
-                ("tablerow", content)
+
+    /// Reject JSON nested deeper than [`MAX_NESTING_DEPTH`], without parsing it
+    fn check_nesting(json: &str) -> serde_json::Result<()> {
+        let mut depth = 0;
+        let mut in_string = false;
+        let mut escaped = false;
+        for byte in json.bytes() {
+            if in_string {
+                match byte {
+                    _ if escaped => escaped = false,
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => {}
+                }
+                continue;
             }
-            LexicalNode::TableCell(cell) => {
-                let content = self.extract_text_from_nodes(&cell.children);
-                ("tablecell", content)
+
+            match byte {
+                b'"' => in_string = true,
+                b'{' | b'[' => {
+                    depth += 1;
+                    if depth > MAX_NESTING_DEPTH {
+                        return Err(serde::de::Error::custom(format!(
+                            "Note nesting exceeds the maximum depth of {MAX_NESTING_DEPTH}"
+                        )));
+                    }
+                }
+                b'}' | b']' => depth -= 1,
+                _ => {}
             }
-            LexicalNode::PageBreak(_) => {
-                ("page-break", "---".to_string())
+        }
+        Ok(())
+    }
+
+    /// Get the heading hierarchy of the note.
+    ///
+    /// A heading is nested under the closest preceding heading with a higher
+    /// level. Skipped levels (an H4 directly after an H1) nest one level deep.
+    pub fn outline(&self) -> Vec<OutlineEntry> {
+        let mut roots = Vec::new();
+        // The chain of open entries from the top level down
+        let mut stack: Vec<OutlineEntry> = Vec::new();
+
+        for section in self.sections() {
+            let (Some(text), Some(level)) = (section.heading, section.level) else {
+                continue;
+            };
+
+            while stack.last().is_some_and(|top| top.level >= level) {
+                Self::close_outline_entry(&mut stack, &mut roots);
             }
-            LexicalNode::AIEmbedding(ai) => {
-                ("ai-embedding", ai.content.clone())
+
+            stack.push(OutlineEntry {
+                text,
+                level,
+                index: section.range.start,
+                children: Vec::new(),
+            });
+        }
+
+        while !stack.is_empty() {
+            Self::close_outline_entry(&mut stack, &mut roots);
+        }
+
+        roots
+    }
+
+    /// Helper method to attach the innermost open outline entry to its parent
+    fn close_outline_entry(stack: &mut Vec<OutlineEntry>, roots: &mut Vec<OutlineEntry>) {
+        if let Some(entry) = stack.pop() {
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(entry),
+                None => roots.push(entry),
             }
-            LexicalNode::VoiceInput(voice) => {
-                ("voice-input", voice.content.clone())
+        }
+    }
+
+    /// Split the note into sections by its root heading nodes.
+    ///
+    /// Every root heading starts a new section regardless of its level.
+    /// Content before the first heading becomes a preamble section.
+    pub fn sections(&self) -> Vec<Section<'_>> {
+        let children = &self.lexical_state.root.children;
+        let mut sections = Vec::new();
+        let mut start = 0;
+        let mut current: Option<(String, HeadingTag)> = None;
+
+        for (index, node) in children.iter().enumerate() {
+            let LexicalNode::Heading(heading) = node else {
+                continue;
+            };
+
+            // Close the previous section, skipping an empty preamble
+            if current.is_some() || index > start {
+                sections.push(Self::make_section(children, start..index, current.take()));
             }
-            LexicalNode::ChatMessage(msg) => {
-                let content = format!("[{}] {}", msg.sender.to_string(), msg.content);
-                ("chat-message", content)
+
+            start = index;
+            current = Some((self.extract_text_from_nodes(&heading.children), heading.tag));
+        }
+
+        if current.is_some() || children.len() > start {
+            sections.push(Self::make_section(children, start..children.len(), current));
+        }
+
+        sections
+    }
+
+    /// Helper method to build a section from a range of root nodes
+    fn make_section(
+        children: &[LexicalNode],
+        range: std::ops::Range<usize>,
+        heading: Option<(String, HeadingTag)>,
+    ) -> Section<'_> {
+        let (heading, level) = heading.unzip();
+        Section {
+            heading,
+            level,
+            nodes: &children[range.clone()],
+            range,
+        }
+    }
+
+    /// Generate a table of contents from the root headings of the note.
+    ///
+    /// Each heading becomes a bullet item, nested under the closest preceding
+    /// heading with a higher level (an H3 after an H2 is nested under it).
+    /// Following Lexical, a nested list is wrapped in its own list item.
+    /// Items of headings with a node key link to `#<key>`, the others are plain text.
+    pub fn generate_toc(&self) -> ListNode {
+        // Stack of open lists, each with its heading level and items
+        let mut stack: Vec<(HeadingTag, Vec<LexicalNode>)> = Vec::new();
+
+        for section in self.sections() {
+            let (Some(heading), Some(level)) = (section.heading, section.level) else {
+                continue;
+            };
+
+            // Close nested lists deeper than this heading
+            while stack.len() > 1 && stack.last().is_some_and(|(top, _)| level < *top) {
+                Self::close_toc_list(&mut stack);
             }
-            LexicalNode::ChatSession(session) => {
-                let content = session.messages.iter()
-                    .map(|msg| format!("[{}] {}", msg.sender.to_string(), msg.content))
-                    .collect::<Vec<_>>()
-                    .join("\n");
-                ("chat-session", content)
+
+            match stack.last_mut() {
+                Some((top, _)) if level > *top => stack.push((level, Vec::new())),
+                // Malformed outline: a heading above the first level joins the top list
+                Some((top, _)) if level < *top => *top = level,
+                Some(_) => {}
+                None => stack.push((level, Vec::new())),
             }
-            LexicalNode::Mention(mention) => {
-                ("mention", mention.text.clone())
+
+            let text = LexicalNode::Text(TextNode::new(heading));
+            let item = match &section.nodes[0].base().key {
+                Some(key) => LexicalNode::Link(LinkNode {
+                    url: format!("#{key}"),
+                    rel: None,
+                    target: None,
+                    title: None,
+                    children: vec![text],
+                    base: BaseNodeProperties::default(),
+                }),
+                None => text,
+            };
+
+            let depth = stack.len() as u32 - 1;
+            if let Some((_, items)) = stack.last_mut() {
+                items.push(LexicalNode::ListItem(ListItemNode {
+                    children: vec![item],
+                    value: None,
+                    base: BaseNodeProperties {
+                        indent: Some(depth),
+                        ..Default::default()
+                    },
+                }));
             }
+        }
+
+        while stack.len() > 1 {
+            Self::close_toc_list(&mut stack);
+        }
+
+        ListNode {
+            list_type: ListType::Bullet,
+            start: Some(1),
+            tag: Some(ListTag::Ul),
+            children: stack.pop().map(|(_, items)| items).unwrap_or_default(),
+            base: BaseNodeProperties::default(),
+        }
+    }
+
+    /// Helper method to wrap the innermost open list into an item of its parent list
+    fn close_toc_list(stack: &mut Vec<(HeadingTag, Vec<LexicalNode>)>) {
+        let Some((_, items)) = stack.pop() else {
+            return;
         };
-        
-        // Only add non-empty content to briefs
-        if !content.trim().is_empty() {
-            briefs.push(BriefNode {
-                id: root_index,
-                node_type: node_type.to_string(),
-                content,
-            });
+        let depth = stack.len() as u32 - 1;
+
+        if let Some((_, parent)) = stack.last_mut() {
+            parent.push(LexicalNode::ListItem(ListItemNode {
+                children: vec![LexicalNode::List(ListNode {
+                    list_type: ListType::Bullet,
+                    start: Some(1),
+                    tag: Some(ListTag::Ul),
+                    children: items,
+                    base: BaseNodeProperties::default(),
+                })],
+                value: None,
+                base: BaseNodeProperties {
+                    indent: Some(depth),
+                    ..Default::default()
+                },
+            }));
         }
     }
-    
-    /// Helper method to recursively extract text from nodes
-    fn extract_text_from_nodes(&self, nodes: &[LexicalNode]) -> String {
-        let mut text = String::new();
-        
-        for node in nodes {
-            match node {
-                LexicalNode::Text(text_node) => {
-                    text.push_str(&text_node.text);
-                }
-                LexicalNode::Paragraph(para) => {
-                    text.push_str(&self.extract_text_from_nodes(&para.children));
-                }
-                LexicalNode::Heading(heading) => {
-                    text.push_str(&self.extract_text_from_nodes(&heading.children));
-                }
-                LexicalNode::List(list) => {
-                    text.push_str(&self.extract_text_from_nodes(&list.children));
-                }
-                LexicalNode::ListItem(item) => {
-                    text.push_str("• ");
-                    text.push_str(&self.extract_text_from_nodes(&item.children));
-                }
-                LexicalNode::Quote(quote) => {
-                    text.push_str(&self.extract_text_from_nodes(&quote.children));
-                }
-                LexicalNode::Code(code) => {
-                    if let Some(code_text) = &code.text {
-                        text.push_str(code_text);
-                    }
-                    if let Some(children) = &code.children {
-                        text.push_str(&self.extract_text_from_nodes(children));
-                    }
-                }
-                LexicalNode::Link(link) => {
-                    text.push_str(&self.extract_text_from_nodes(&link.children));
-                }
-                LexicalNode::AutoLink(auto_link) => {
-                    text.push_str(&self.extract_text_from_nodes(&auto_link.children));
-                }
-                LexicalNode::Hashtag(hashtag) => {
-                    text.push_str(&hashtag.text);
-                }
-                LexicalNode::Table(table) => {
-                    text.push_str(&self.extract_text_from_nodes(&table.children));
-                }
-                LexicalNode::TableRow(row) => {
-                    text.push_str(&self.extract_text_from_nodes(&row.children));
-                }
-                LexicalNode::TableCell(cell) => {
-                    text.push_str(&self.extract_text_from_nodes(&cell.children));
-                }
-                LexicalNode::AIEmbedding(ai) => {
-                    text.push_str(&ai.content);
-                }
-                LexicalNode::VoiceInput(voice) => {
-                    text.push_str(&voice.content);
+
+    /// Get the briefs for the note.
+    pub fn get_brief(&self) -> Vec<BriefNode> {
+        self.get_brief_with(&BriefOptions::default())
+    }
+
+    /// Get the briefs for the note with the options.
+    pub fn get_brief_with(&self, opts: &BriefOptions) -> Vec<BriefNode> {
+        self.collect_briefs(|_| true, opts)
+    }
+
+    /// Get the briefs for the root nodes of the given types.
+    ///
+    /// The ids are still the original root indices. An empty filter keeps all nodes.
+    pub fn get_brief_filtered(&self, types: &[&str]) -> Vec<BriefNode> {
+        self.collect_briefs(
+            |node| types.is_empty() || types.contains(&node.type_name()),
+            &BriefOptions::default(),
+        )
+    }
+
+    /// Get the briefs for the root nodes not of the given types.
+    ///
+    /// The ids are still the original root indices. An empty filter keeps all nodes.
+    pub fn get_brief_excluding(&self, types: &[&str]) -> Vec<BriefNode> {
+        self.collect_briefs(|node| !types.contains(&node.type_name()), &BriefOptions::default())
+    }
+
+    /// Get the briefs of the root nodes around the cursor position.
+    ///
+    /// The window covers root indices `cursor - before ..= cursor + after`,
+    /// clamped to the note. A cursor beyond the end is treated as the end of the note.
+    pub fn get_brief_window(&self, cursor: usize, before: usize, after: usize) -> BriefWindow {
+        self.get_brief_window_with(cursor, before, after, &BriefOptions::default())
+    }
+
+    /// Get the briefs of the root nodes around the cursor position with the options.
+    pub fn get_brief_window_with(
+        &self,
+        cursor: usize,
+        before: usize,
+        after: usize,
+        opts: &BriefOptions,
+    ) -> BriefWindow {
+        let len = self.lexical_state.root.children.len();
+        let cursor = cursor.min(len);
+        let start = cursor.saturating_sub(before);
+        let end = cursor.saturating_add(after);
+
+        let (mut omitted_before, mut omitted_after) = (0, 0);
+        let mut briefs = Vec::new();
+        for brief in self.get_brief_with(opts) {
+            if brief.id < start {
+                omitted_before += 1;
+            } else if brief.id > end {
+                omitted_after += 1;
+            } else {
+                briefs.push(brief);
+            }
+        }
+
+        BriefWindow {
+            briefs,
+            omitted_before,
+            omitted_after,
+        }
+    }
+
+    /// Get the briefs of the section containing the root node at `index`.
+    ///
+    /// An index beyond the end of the note belongs to the last section.
+    pub fn get_brief_in_section(&self, index: usize) -> Vec<BriefNode> {
+        self.get_brief_in_section_with(index, &BriefOptions::default())
+    }
+
+    /// Get the briefs of the section containing the root node at `index` with the options.
+    pub fn get_brief_in_section_with(&self, index: usize, opts: &BriefOptions) -> Vec<BriefNode> {
+        let sections = self.sections();
+        let Some(section) = sections
+            .iter()
+            .find(|section| section.range.contains(&index))
+            .or(sections.last())
+        else {
+            return Vec::new();
+        };
+
+        let range = section.range.clone();
+        self.get_brief_with(opts)
+            .into_iter()
+            .filter(|brief| range.contains(&brief.id))
+            .collect()
+    }
+
+    /// Hash the content of the note, to tell whether an edit changed anything.
+    ///
+    /// The hash covers the structure of the nodes, their types, text and formatting,
+    /// but not the note id, node keys or unknown fields. It is stable across builds and
+    /// serialization round-trips.
+    pub fn content_hash(&self) -> u64 {
+        let mut hash = FNV_OFFSET;
+        for (path, node) in self.iter_nodes() {
+            let content = match node {
+                LexicalNode::Text(text) => format!("{}:{}", text.format, text.text),
+                LexicalNode::Heading(heading) => format!("{:?}", heading.tag),
+                LexicalNode::List(list) => format!("{:?}:{:?}", list.list_type, list.start),
+                LexicalNode::Code(code) => format!("{:?}:{:?}", code.language, code.text),
+                LexicalNode::CodeHighlight(highlight) => highlight.text.clone(),
+                LexicalNode::Link(link) => link.url.clone(),
+                LexicalNode::AutoLink(auto_link) => auto_link.url.clone(),
+                LexicalNode::Hashtag(hashtag) => hashtag.text.clone(),
+                LexicalNode::Mention(mention) => format!("{}:{}", mention.mention_name, mention.text),
+                LexicalNode::AIEmbedding(ai) => ai.content.clone(),
+                LexicalNode::VoiceInput(voice) => voice.content.clone(),
+                LexicalNode::ChatMessage(message) => format!("{}:{}", message.sender, message.content),
+                LexicalNode::ChatSession(session) => session
+                    .messages
+                    .iter()
+                    .map(|message| format!("{}:{}", message.sender, message.content))
+                    .collect::<Vec<_>>()
+                    .join("\0"),
+                _ => String::new(),
+            };
+            // The depth separates a node's children from its next siblings
+            let depth = path.0.len() as u64;
+            hash = fnv1a(hash, depth.to_le_bytes());
+            hash = fnv1a(hash, node.type_name().bytes().chain([0]).chain(content.bytes()).chain([0]));
+        }
+        hash
+    }
+
+    /// Assign a random UUID v4 as the note id, unless the note has one.
+    ///
+    /// Returns the note id.
+    pub fn ensure_id(&mut self) -> &str {
+        self.note_id.get_or_insert_with(|| {
+            let mut bytes = [0u8; 16];
+            getrandom::fill(&mut bytes).expect("Should be able to get random bytes");
+            // Set the version (4) and the RFC 4122 variant
+            bytes[6] = (bytes[6] & 0x0f) | 0x40;
+            bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+            let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+            format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+        })
+    }
+
+    /// Get the title of the note, as the UI derives it.
+    ///
+    /// This is the text of the first root H1 or H2 heading, falling back to the first
+    /// 80 characters of the first root paragraph with text.
+    pub fn title(&self) -> Option<String> {
+        self.title_of(&self.lexical_state.root.children)
+    }
+
+    /// Helper method to derive the title of a run of root nodes
+    fn title_of(&self, children: &[LexicalNode]) -> Option<String> {
+        let heading = children.iter().find_map(|node| match node {
+            LexicalNode::Heading(heading) if matches!(heading.tag, HeadingTag::H1 | HeadingTag::H2) => {
+                Some(self.extract_text_from_nodes(&heading.children).trim().to_string())
+            }
+            _ => None,
+        });
+        if let Some(heading) = heading.filter(|heading| !heading.is_empty()) {
+            return Some(heading);
+        }
+
+        children.iter().find_map(|node| match node {
+            LexicalNode::Paragraph(para) => {
+                let text = self.extract_text_from_nodes(&para.children);
+                let text = text.trim();
+                (!text.is_empty()).then(|| text.chars().take(80).collect::<String>().trim_end().to_string())
+            }
+            _ => None,
+        })
+    }
+
+    /// Set the title of the note.
+    ///
+    /// The text replaces the first root H1 or H2 heading, which [`Note::title`] reads,
+    /// or goes into a new H1 at the top of the note if there is none. A replaced
+    /// heading keeps the format of its first text node, such as bold.
+    pub fn set_title(&mut self, text: &str) {
+        let mut title = TextNode::new(text);
+        let index = self.title_heading_index();
+        let children = &mut self.lexical_state.root.children;
+
+        match index.map(|index| &mut children[index]) {
+            Some(LexicalNode::Heading(heading)) => {
+                if let Some(first) = heading.children.iter().find_map(apply::first_text) {
+                    title.format = first.format;
                 }
-                LexicalNode::ChatMessage(msg) => {
-                    text.push_str(&msg.content);
+                heading.children = vec![LexicalNode::Text(title)];
+            }
+            _ => children.insert(
+                0,
+                LexicalNode::Heading(HeadingNode {
+                    tag: HeadingTag::H1,
+                    children: vec![LexicalNode::Text(title)],
+                    base: BaseNodeProperties::default(),
+                }),
+            ),
+        }
+    }
+
+    /// The index of the root heading [`Note::set_title`] replaces, the first H1 or H2
+    pub(super) fn title_heading_index(&self) -> Option<usize> {
+        self.lexical_state.root.children.iter().position(|node| {
+            matches!(node, LexicalNode::Heading(heading) if matches!(heading.tag, HeadingTag::H1 | HeadingTag::H2))
+        })
+    }
+
+    /// Get the plain text of the root node at `index`.
+    pub fn node_plain_text(&self, index: usize) -> Option<String> {
+        self.lexical_state
+            .root
+            .children
+            .get(index)
+            .map(|node| self.render_node(node).1)
+    }
+
+    /// Get the briefs within `radius` root nodes of the cursor position, keeping their ids.
+    pub fn brief_window(&self, cursor_position: usize, radius: usize) -> Vec<BriefNode> {
+        self.get_brief_window(cursor_position, radius, radius).briefs
+    }
+
+    /// Collect the briefs for the root nodes matching the predicate
+    fn collect_briefs(&self, predicate: impl Fn(&LexicalNode) -> bool, opts: &BriefOptions) -> Vec<BriefNode> {
+        let mut briefs = Vec::new();
+        let mut section = None;
+
+        // Process each root node with its index, following the headings even for filtered out nodes
+        for (path, node) in self.iter_nodes().max_depth(1) {
+            let index = path.0[0];
+            if opts.include_section
+                && let LexicalNode::Heading(heading) = node
+            {
+                let text = self.extract_text_from_nodes(&heading.children);
+                section = Some(format!("H{}: {}", heading.tag.level(), text.trim()));
+            }
+            if predicate(node) {
+                self.collect_brief_from_node(node, &mut briefs, index, opts, section.as_ref());
+            }
+        }
+
+        briefs
+    }
+    
+    /// Get the plain text of the whole note, one root node per line.
+    ///
+    /// Uses the same rendering as the briefs, so tables come out as markdown tables.
+    pub fn to_plain_text(&self) -> String {
+        self.to_plain_text_with(&TextExtractOptions::compat())
+    }
+
+    /// Get the plain text of the whole note, separating the root nodes with the block separator.
+    pub fn to_plain_text_with(&self, opts: &TextExtractOptions) -> String {
+        self.lexical_state
+            .root
+            .children
+            .iter()
+            .map(|node| self.render_node_with(node, opts).1)
+            .filter(|content| !content.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join(&opts.block_separator)
+    }
+
+    /// Collect brief from a single node using its root index
+    fn collect_brief_from_node(
+        &self,
+        node: &LexicalNode,
+        briefs: &mut Vec<BriefNode>,
+        root_index: usize,
+        opts: &BriefOptions,
+        section: Option<&String>,
+    ) {
+        let (node_type, mut content) = self.render_node_with(node, &opts.text);
+
+        // Only add non-empty content to briefs, unless empty nodes are included as placeholders
+        if content.trim().is_empty() {
+            if !opts.include_empty {
+                return;
+            }
+            content.clear();
+        }
+
+        briefs.push(BriefNode {
+            id: root_index,
+            node_type: node_type.to_string(),
+            content,
+            section: section.cloned(),
+        });
+    }
+
+    /// Remove the empty root paragraphs following another empty root paragraph.
+    ///
+    /// Runs of blank lines shrink to a single one, so the briefs have fewer gaps.
+    /// Returns the number of paragraphs removed.
+    pub fn compact_empty_nodes(&mut self) -> usize {
+        let children = std::mem::take(&mut self.lexical_state.root.children);
+        let before = children.len();
+        let mut previous_empty = false;
+        for node in children {
+            let empty = matches!(node, LexicalNode::Paragraph(_)) && self.render_node(&node).1.trim().is_empty();
+            if !(empty && previous_empty) {
+                self.lexical_state.root.children.push(node);
+            }
+            previous_empty = empty;
+        }
+
+        before - self.lexical_state.root.children.len()
+    }
+
+    /// Render a single root node to its type name and text content
+    fn render_node(&self, node: &LexicalNode) -> (&'static str, String) {
+        self.render_node_with(node, &TextExtractOptions::compat())
+    }
+
+    /// Render a single root node to its type name and text content with the options.
+    ///
+    /// Custom nodes render empty unless the options include them.
+    fn render_node_with(&self, node: &LexicalNode, opts: &TextExtractOptions) -> (&'static str, String) {
+        match node {
+            LexicalNode::Text(text_node) => {
+                ("text", text_node.text.clone())
+            }
+            LexicalNode::Paragraph(para) => {
+                let content = self.extract_text_with(&para.children, opts);
+                ("paragraph", content)
+            }
+            LexicalNode::Heading(heading) => {
+                let content = self.extract_text_with(&heading.children, opts);
+                ("heading", content)
+            }
+            LexicalNode::List(list) => {
+                ("list", self.render_list(list, 0, opts))
+            }
+            LexicalNode::ListItem(item) => {
+                let content = self.extract_text_with(&item.children, opts);
+                ("listitem", content)
+            }
+            LexicalNode::Quote(quote) => {
+                let content = self.extract_text_with(&quote.children, opts);
+                ("quote", content)
+            }
+            LexicalNode::Code(_) => {
+                // Code blocks are fenced with their language, inline code is in backticks
+                ("code", self.extract_text_with(std::slice::from_ref(node), opts))
+            }
+            LexicalNode::CodeHighlight(highlight) => {
+                ("code-highlight", highlight.text.clone())
+            }
+            LexicalNode::LineBreak(_) => {
+                ("linebreak", "\n".to_string())
+            }
+            LexicalNode::Link(link) => {
+                let text = self.extract_text_with(&link.children, opts);
+                let content = match &link.title {
+                    Some(title) => format!("{} ({} — {})", text, link.url, title),
+                    None => format!("{} ({})", text, link.url),
+                };
+                ("link", content)
+            }
+            LexicalNode::AutoLink(auto_link) => {
+                let content = format!("{} ({})", self.extract_text_with(&auto_link.children, opts), auto_link.url);
+                ("autolink", content)
+            }
+            LexicalNode::Hashtag(hashtag) => {
+                ("hashtag", hashtag.text.clone())
+            }
+            LexicalNode::Table(table) => {
+                ("table", self.render_table(table, opts))
+            }
+            LexicalNode::TableRow(row) => {
+                let content = self.extract_text_with(&row.children, opts);
+                ("tablerow", content)
+            }
+            LexicalNode::TableCell(cell) => {
+                let content = self.extract_text_with(&cell.children, opts);
+                ("tablecell", content)
+            }
+            LexicalNode::PageBreak(_) => {
+                ("page-break", "---".to_string())
+            }
+            LexicalNode::AIEmbedding(_)
+            | LexicalNode::VoiceInput(_)
+            | LexicalNode::ChatMessage(_)
+            | LexicalNode::ChatSession(_)
+                if !opts.include_custom_nodes =>
+            {
+                (node.type_name(), String::new())
+            }
+            LexicalNode::AIEmbedding(ai) => {
+                ("ai-embedding", ai.content.clone())
+            }
+            LexicalNode::VoiceInput(voice) => {
+                // The language and confidence tell the agent when the text may be garbled
+                let hints: Vec<String> = [
+                    voice.status.map(|status| status.to_string()),
+                    voice.language.clone(),
+                    voice.confidence.map(|confidence| format!("{confidence:.2} conf")),
+                ]
+                .into_iter()
+                .flatten()
+                .collect();
+                let content = match hints.is_empty() {
+                    true => voice.content.clone(),
+                    false => format!("[{}] {}", hints.join(", "), voice.content),
+                };
+                ("voice-input", content)
+            }
+            LexicalNode::ChatMessage(msg) => {
+                let content = format!("[{}] {}", msg.sender, msg.content);
+                ("chat-message", content)
+            }
+            LexicalNode::ChatSession(session) => {
+                let content = session.messages.iter()
+                    .map(|msg| format!("[{}] {}", msg.sender, msg.content))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                ("chat-session", content)
+            }
+            LexicalNode::Mention(mention) => {
+                ("mention", mention.text.clone())
+            }
+        }
+    }
+
+    /// Get all AI embeddings in the note with their provenance, in document order.
+    pub fn ai_blocks(&self) -> Vec<&AIEmbeddingNode> {
+        self.iter_nodes()
+            .filter_map(|(_, node)| match node {
+                LexicalNode::AIEmbedding(ai) => Some(ai),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Count the AI embeddings still loading anywhere in the note.
+    pub fn pending_count(&self) -> usize {
+        self.iter_nodes()
+            .filter(|(_, node)| matches!(node, LexicalNode::AIEmbedding(ai) if ai.is_loading))
+            .count()
+    }
+
+    /// Count the voice inputs still recording or transcribing anywhere in the note.
+    pub fn pending_voice_inputs(&self) -> usize {
+        self.iter_nodes()
+            .filter(|(_, node)| matches!(node, LexicalNode::VoiceInput(voice) if voice.is_pending()))
+            .count()
+    }
+
+    /// Normalize the note tree in place with the default options, reporting what changed.
+    pub fn normalize(&mut self) -> NormalizeReport {
+        self.normalize_with(&NormalizeOptions::default())
+    }
+
+    /// Normalize the note tree in place, reporting what changed.
+    ///
+    /// Consecutive text nodes sharing the same `format`, `detail`, `style`, `mode` and
+    /// unmodeled fields within the same parent are merged into the first one, keeping its key. Empty
+    /// text nodes, list items and lists are removed, and so are empty root paragraphs
+    /// if the options say so.
+    pub fn normalize_with(&mut self, opts: &NormalizeOptions) -> NormalizeReport {
+        let mut report = NormalizeReport::default();
+        Self::normalize_nodes(&mut self.lexical_state.root.children, &mut report);
+
+        if opts.remove_empty_paragraphs {
+            let children = &mut self.lexical_state.root.children;
+            let before = children.len();
+            children.retain(|node| !matches!(node, LexicalNode::Paragraph(para) if para.children.is_empty()));
+            report.removed_empty_paragraphs = before - children.len();
+        }
+
+        report
+    }
+
+    /// Helper method to recursively normalize a list of sibling nodes
+    fn normalize_nodes(nodes: &mut Vec<LexicalNode>, report: &mut NormalizeReport) {
+        let mut normalized: Vec<LexicalNode> = Vec::with_capacity(nodes.len());
+
+        for mut node in nodes.drain(..) {
+            if let Some(children) = node.children_mut() {
+                Self::normalize_nodes(children, report);
+            }
+
+            match &node {
+                LexicalNode::Text(text_node) if text_node.text.is_empty() => {
+                    report.removed_empty_text_nodes += 1;
+                    continue;
                 }
-                LexicalNode::ChatSession(session) => {
-                    for msg in &session.messages {
-                        text.push_str(&msg.content);
-                    }
+                LexicalNode::ListItem(item) if item.children.is_empty() => {
+                    report.removed_empty_list_items += 1;
+                    continue;
                 }
-                LexicalNode::Mention(mention) => {
-                    text.push_str(&mention.text);
+                LexicalNode::List(list) if list.children.is_empty() => {
+                    report.removed_empty_lists += 1;
+                    continue;
                 }
-                LexicalNode::PageBreak(_) => {
-                    // Page breaks don't contribute to text content
+                _ => {}
+            }
+
+            // Merge into the previous sibling if it is a text node with the same formatting,
+            // and the same extra fields so that none are lost
+            if let LexicalNode::Text(text_node) = &node
+                && let Some(LexicalNode::Text(prev)) = normalized.last_mut()
+                && prev.format == text_node.format
+                && prev.detail == text_node.detail
+                && prev.style == text_node.style
+                && prev.mode == text_node.mode
+                && prev.base.extra == text_node.base.extra
+            {
+                prev.text.push_str(&text_node.text);
+                report.merged_text_nodes += 1;
+                continue;
+            }
+
+            normalized.push(node);
+        }
+
+        *nodes = normalized;
+    }
+
+    /// Set the direction of block nodes without one from their text.
+    ///
+    /// See [`Note::infer_direction_with`]. Returns the number of nodes updated.
+    pub fn infer_direction(&mut self) -> usize {
+        self.infer_direction_with(false)
+    }
+
+    /// Set the direction of block nodes from the first strong directional character in their text.
+    ///
+    /// Arabic and Hebrew letters make a node right-to-left, other letters left-to-right.
+    /// Nodes with a direction already are left untouched unless `force` is set, and so are
+    /// nodes without letters. Returns the number of nodes updated.
+    pub fn infer_direction_with(&mut self, force: bool) -> usize {
+        let mut updated = 0;
+        self.visit_mut(|_, node| {
+            let has_direction = matches!(node.base().direction, Some(Some(_)));
+            if !matches!(node, LexicalNode::ListItem(_) | LexicalNode::TableCell(_)) && !node.is_block()
+                || has_direction && !force
+            {
+                return;
+            }
+            let Some(direction) = node.children().and_then(|children| first_strong_direction(children)) else {
+                return;
+            };
+            if node.base().direction != Some(Some(direction)) {
+                node.base_mut().direction = Some(Some(direction));
+                updated += 1;
+            }
+        });
+        updated
+    }
+}
+
+/// Whether a character is a letter of a right-to-left script, such as Arabic or Hebrew
+fn is_rtl_char(c: char) -> bool {
+    matches!(c,
+        '\u{0590}'..='\u{08FF}' | '\u{FB1D}'..='\u{FDFF}' | '\u{FE70}'..='\u{FEFF}' | '\u{10800}'..='\u{10FFF}' | '\u{1E800}'..='\u{1EFFF}'
+    )
+}
+
+/// The direction of the first strong directional character in the text of the nodes
+fn first_strong_direction(nodes: &[LexicalNode]) -> Option<TextDirection> {
+    let mut stack = vec![nodes.iter()];
+    while let Some(siblings) = stack.last_mut() {
+        let Some(node) = siblings.next() else {
+            stack.pop();
+            continue;
+        };
+
+        let text = match node {
+            LexicalNode::Text(text) => &text.text,
+            LexicalNode::CodeHighlight(highlight) => &highlight.text,
+            LexicalNode::Hashtag(hashtag) => &hashtag.text,
+            LexicalNode::Mention(mention) => &mention.text,
+            node => {
+                if let Some(children) = node.children() {
+                    stack.push(children.iter());
                 }
+                continue;
             }
+        };
+        if let Some(c) = text.chars().find(|c| c.is_alphabetic()) {
+            return Some(if is_rtl_char(c) {
+                TextDirection::RightToLeft
+            } else {
+                TextDirection::LeftToRight
+            });
         }
-        
-        text
     }
+    None
 }
 
 // Helper implementation for MessageSender to string conversion
@@ -533,6 +1804,16 @@ impl std::fmt::Display for MessageSender {
     }
 }
 
+impl std::fmt::Display for VoiceInputStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VoiceInputStatus::Recording => write!(f, "recording"),
+            VoiceInputStatus::Transcribing => write!(f, "transcribing"),
+            VoiceInputStatus::Done => write!(f, "done"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -725,7 +2006,111 @@ mod tests {
         
         println!("✓ Successfully completed roundtrip serialization test");
     }
+
+    #[test]
+    fn test_root_type() {
+        let json_content = fs::read_to_string("assets/example_note.json")
+            .expect("Should be able to read assets/example_note.json");
+        let note = Note::from_json(&json_content).expect("Should be able to parse example note JSON");
+        let serialized: serde_json::Value = serde_json::to_value(&note).unwrap();
+        let original: serde_json::Value = serde_json::from_str(&json_content).unwrap();
+        assert_eq!(serialized["lexicalState"]["root"]["type"], "root");
+        assert_eq!(serialized, original);
+
+        let corrupted = json_content.replacen(r#""type": "root""#, r#""type": "paragraph""#, 1);
+        assert_ne!(corrupted, json_content);
+        let err = Note::from_json(&corrupted).unwrap_err();
+        assert!(err.to_string().contains(r#"The root node has type "paragraph" instead of "root""#), "{err}");
+        assert!(serde_json::from_str::<Note>(&corrupted).is_err());
+
+        let recovered = Note::from_json_lenient(&corrupted).expect("Should recover the corrupted note");
+        assert_eq!(recovered.lexical_state.root.node_type, "root");
+        assert_eq!(recovered.lexical_state.root.children.len(), note.lexical_state.root.children.len());
+        assert_eq!(recovered.schema_version, Some(SCHEMA_VERSION));
+        assert!(Note::from_json_lenient("[[[").is_err());
+
+        // The lenient parse migrates notes of older schemas
+        let legacy = fs::read_to_string("assets/legacy_root_text_note.json").unwrap();
+        let recovered = Note::from_json_lenient(&legacy.replacen(r#""type": "root""#, r#""type": "list""#, 1))
+            .expect("Should recover the legacy note");
+        assert_eq!(recovered.lexical_state.root.children[0].type_name(), "paragraph");
+    }
     
+    #[test]
+    fn test_brief_include_section() {
+        let note = note_from_children(serde_json::json!([
+            paragraph_json("Before any heading"),
+            heading_json("Plan", "h1"),
+            paragraph_json("Overview"),
+            heading_json("Budget", "h2"),
+            paragraph_json("Costs"),
+            heading_json(" Risks ", "h3"),
+            paragraph_json("Delays"),
+            heading_json("Next", "h2"),
+            paragraph_json("Ship"),
+        ]));
+        let opts = BriefOptions {
+            include_section: true,
+            ..Default::default()
+        };
+
+        let briefs = note.get_brief_with(&opts);
+        let sections: Vec<(usize, Option<&str>)> =
+            briefs.iter().map(|brief| (brief.id, brief.section.as_deref())).collect();
+        assert_eq!(
+            sections,
+            vec![
+                (0, None),
+                (1, Some("H1: Plan")),
+                (2, Some("H1: Plan")),
+                (3, Some("H2: Budget")),
+                (4, Some("H2: Budget")),
+                (5, Some("H3: Risks")),
+                (6, Some("H3: Risks")),
+                (7, Some("H2: Next")),
+                (8, Some("H2: Next")),
+            ]
+        );
+
+        // Filtered out headings still set the section, and the default leaves it out of the JSON
+        let filtered = note.collect_briefs(|node| node.type_name() == "paragraph", &opts);
+        assert_eq!(filtered[2].section.as_deref(), Some("H2: Budget"));
+        assert!(note.get_brief().iter().all(|brief| brief.section.is_none()));
+        assert!(!BriefNode::to_json(&note.get_brief()).contains("section"));
+        assert!(BriefNode::to_json(&briefs).contains(r#""section":"H1: Plan""#));
+    }
+
+    #[test]
+    fn test_brief_include_empty() {
+        let mut note = note_from_children(serde_json::json!([
+            paragraph_json("First"),
+            { "type": "paragraph", "version": 1, "children": [] },
+            { "type": "paragraph", "version": 1, "children": [] },
+            paragraph_json("   "),
+            paragraph_json("Second"),
+        ]));
+        let opts = BriefOptions {
+            include_empty: true,
+            ..Default::default()
+        };
+
+        let ids: Vec<usize> = note.get_brief().iter().map(|brief| brief.id).collect();
+        assert_eq!(ids, vec![0, 4]);
+        let briefs = note.get_brief_with(&opts);
+        let ids: Vec<usize> = briefs.iter().map(|brief| brief.id).collect();
+        assert_eq!(ids, vec![0, 1, 2, 3, 4]);
+        assert_eq!(briefs[3].node_type, "paragraph");
+        assert_eq!(briefs[3].content, "");
+
+        // Compacting keeps one empty paragraph of the run
+        assert_eq!(note.compact_empty_nodes(), 2);
+        let briefs = note.get_brief_with(&opts);
+        let contents: Vec<&str> = briefs.iter().map(|brief| brief.content.as_str()).collect();
+        assert_eq!(contents, vec!["First", "", "Second"]);
+        assert_eq!(briefs.last().unwrap().id, 2);
+        assert_eq!(note.compact_empty_nodes(), 0);
+    }
+
     #[test]
     fn test_empty_root_nodes() {
         let json_content = fs::read_to_string("assets/example_note.json")
@@ -771,6 +2156,1026 @@ mod tests {
         }
     }
 
+    /// Build a note from the given root children JSON.
+    pub(super) fn note_from_children(children: serde_json::Value) -> Note {
+        serde_json::from_value(serde_json::json!({
+            "noteId": null,
+            "lexicalState": {
+                "root": {
+                    "type": "root",
+                    "version": 1,
+                    "children": children,
+                }
+            }
+        }))
+        .expect("Should be able to parse test note")
+    }
+
+    /// Build a text node JSON with the given format flags.
+    pub(super) fn text_json(text: &str, format: u32) -> serde_json::Value {
+        serde_json::json!({
+            "type": "text",
+            "version": 1,
+            "text": text,
+            "format": format,
+            "detail": 0,
+            "mode": "normal",
+            "style": "",
+        })
+    }
+
+    #[test]
+    fn test_normalize_merges_adjacent_text_nodes() {
+        let mut note = note_from_children(serde_json::json!([
+            {
+                "type": "paragraph",
+                "version": 1,
+                "children": [
+                    text_json("Hello", 1),
+                    text_json(", ", 1),
+                    text_json("", 0),
+                    text_json("world", 1),
+                    text_json("!", 0),
+                ],
+            }
+        ]));
+
+        let report = note.normalize();
+        assert_eq!(report.merged_text_nodes, 2);
+        assert_eq!(report.removed_empty_text_nodes, 1);
+
+        let LexicalNode::Paragraph(para) = &note.lexical_state.root.children[0] else {
+            panic!("Expected a paragraph node");
+        };
+        assert_eq!(para.children.len(), 2);
+
+        let LexicalNode::Text(merged) = &para.children[0] else {
+            panic!("Expected a text node");
+        };
+        assert_eq!(merged.text, "Hello, world");
+        assert_eq!(merged.format, 1);
+
+        let LexicalNode::Text(rest) = &para.children[1] else {
+            panic!("Expected a text node");
+        };
+        assert_eq!(rest.text, "!");
+        assert_eq!(rest.format, 0);
+    }
+
+    #[test]
+    fn test_normalize_keeps_text_nodes_with_other_extra_fields() {
+        let mut commented = text_json(" world", 0);
+        commented["commentId"] = serde_json::json!("c1");
+        let mut note = note_from_children(serde_json::json!([
+            { "type": "paragraph", "version": 1, "children": [text_json("Hello", 0), commented, text_json("!", 0)] }
+        ]));
+
+        let report = note.normalize();
+        assert_eq!(report.merged_text_nodes, 0);
+
+        let LexicalNode::Paragraph(para) = &note.lexical_state.root.children[0] else {
+            panic!("Expected a paragraph node");
+        };
+        assert_eq!(para.children.len(), 3);
+        assert_eq!(para.children[1].base().extra.get("commentId"), Some(&serde_json::json!("c1")));
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let json_content = fs::read_to_string("assets/example_note.json")
+            .expect("Should be able to read assets/example_note.json");
+        let note = Note::from_json(&json_content).expect("Should be able to parse example note JSON");
+
+        // Stable across round trips, and ignoring the note id and node keys
+        let mut same = Note::from_json(&serde_json::to_string(&note).unwrap()).unwrap();
+        same.note_id = Some("another-id".to_string());
+        same.visit_mut(|_, node| node.base_mut().key = Some("42".to_string()));
+        assert_eq!(same.content_hash(), note.content_hash());
+
+        let mut edited = note.clone();
+        edited.transform_text(|text| text.push('!'));
+        assert_ne!(edited.content_hash(), note.content_hash());
+
+        let mut formatted = note.clone();
+        formatted.visit_mut(|_, node| {
+            if let LexicalNode::Text(text) = node {
+                text.format |= 1;
+            }
+        });
+        assert_ne!(formatted.content_hash(), note.content_hash());
+
+        // Moving a node out of its parent changes the structure
+        let flat = note_from_children(serde_json::json!([paragraph_json("One"), paragraph_json("Two")]));
+        let nested = note_from_children(serde_json::json!([{
+            "type": "paragraph",
+            "version": 1,
+            "children": [text_json("One", 0), { "type": "paragraph", "version": 1, "children": [text_json("Two", 0)] }],
+        }]));
+        assert_ne!(flat.content_hash(), nested.content_hash());
+    }
+
+    #[test]
+    fn test_ensure_id() {
+        let mut note = note_from_children(serde_json::json!([]));
+        note.note_id = None;
+        let id = note.ensure_id().to_string();
+
+        let groups: Vec<&str> = id.split('-').collect();
+        assert_eq!(groups.iter().map(|group| group.len()).collect::<Vec<_>>(), vec![8, 4, 4, 4, 12]);
+        assert!(id.chars().all(|c| matches!(c, '-' | '0'..='9' | 'a'..='f')));
+        assert!(groups[2].starts_with('4'));
+        assert!(matches!(groups[3].as_bytes()[0], b'8' | b'9' | b'a' | b'b'));
+        assert_eq!(note.note_id.as_deref(), Some(id.as_str()));
+
+        // An existing id is kept
+        assert_eq!(note.ensure_id(), id);
+        let mut other = note_from_children(serde_json::json!([]));
+        other.note_id = None;
+        assert_ne!(other.ensure_id(), id);
+    }
+
+    #[test]
+    fn test_title() {
+        let mut note = note_from_children(serde_json::json!([
+            { "type": "ai-embedding", "version": 1, "content": "Generated summary", "isLoading": false },
+            paragraph_json("Intro"),
+            heading_json("Details", "h3"),
+            heading_json("Launch plan", "h2"),
+        ]));
+        assert_eq!(note.title().as_deref(), Some("Launch plan"));
+        note.set_title("Launch plan v2");
+        assert_eq!(note.title().as_deref(), Some("Launch plan v2"));
+        assert_eq!(note.lexical_state.root.children.len(), 4);
+
+        // Without headings, the first paragraph with text is the title
+        let long = "word ".repeat(30);
+        let mut note = note_from_children(serde_json::json!([
+            paragraph_json(""),
+            paragraph_json("   "),
+            paragraph_json(&long),
+            paragraph_json("Second"),
+        ]));
+        let title = note.title().unwrap();
+        assert_eq!(title.chars().count(), 79);
+        assert!(long.starts_with(&title));
+
+        note.set_title("Groceries");
+        assert_eq!(note.title().as_deref(), Some("Groceries"));
+        assert_eq!(note.get_brief()[0].node_type, "heading");
+        assert_eq!(note.lexical_state.root.children.len(), 5);
+
+        assert_eq!(note_from_children(serde_json::json!([paragraph_json("")])).title(), None);
+    }
+
+    #[test]
+    fn test_set_title_keeps_format() {
+        let mut note = note_from_children(serde_json::json!([{
+            "type": "heading",
+            "version": 1,
+            "tag": "h1",
+            "children": [text_json("Launch", 3), text_json(" plan", 0)],
+        }]));
+        note.set_title("Launch plan v2");
+
+        let children = note.lexical_state.root.children[0].children().unwrap();
+        assert!(matches!(&children[..], [LexicalNode::Text(text)] if text.text == "Launch plan v2" && text.format == 3));
+    }
+
+    #[test]
+    fn test_infer_direction() {
+        let mut note = note_from_children(serde_json::json!([
+            paragraph_json("مرحبا بالعالم"),
+            paragraph_json("Hello world"),
+            heading_json("123 שלום", "h2"),
+            paragraph_json("42 - 7"),
+            list_json("bullet", None, vec![list_item_json(vec![text_json("Hello", 0)]), list_item_json(vec![text_json("سلام", 0)])]),
+        ]));
+        let direction = |note: &Note, path: &str| note.get_by_path(&path.parse().unwrap()).unwrap().base().direction;
+
+        assert_eq!(note.infer_direction(), 6);
+        assert_eq!(direction(&note, "0"), Some(Some(TextDirection::RightToLeft)));
+        assert_eq!(direction(&note, "1"), Some(Some(TextDirection::LeftToRight)));
+        // Digits are not strong, the Hebrew after them is
+        assert_eq!(direction(&note, "2"), Some(Some(TextDirection::RightToLeft)));
+        assert_eq!(direction(&note, "3"), None);
+        assert_eq!(direction(&note, "4"), Some(Some(TextDirection::LeftToRight)));
+        assert_eq!(direction(&note, "4.1"), Some(Some(TextDirection::RightToLeft)));
+        // Inline nodes have no direction
+        assert_eq!(direction(&note, "0.0"), None);
+
+        // An existing direction is kept unless forced
+        note.get_mut_by_path(&"0".parse().unwrap()).unwrap().base_mut().direction = Some(Some(TextDirection::LeftToRight));
+        assert_eq!(note.infer_direction(), 0);
+        assert_eq!(direction(&note, "0"), Some(Some(TextDirection::LeftToRight)));
+        assert_eq!(note.infer_direction_with(true), 1);
+        assert_eq!(direction(&note, "0"), Some(Some(TextDirection::RightToLeft)));
+    }
+
+    #[test]
+    fn test_normalize_cruft_note() {
+        let json_content = fs::read_to_string("assets/example_cruft_note.json")
+            .expect("Should be able to read assets/example_cruft_note.json");
+        let mut note: Note = serde_json::from_str(&json_content).expect("Should be able to parse example cruft note");
+        assert_eq!(note.get_brief().len(), 4);
+
+        let report = note.normalize();
+        assert_eq!(
+            report,
+            NormalizeReport {
+                merged_text_nodes: 2,
+                removed_empty_text_nodes: 3,
+                removed_empty_paragraphs: 2,
+                removed_empty_list_items: 4,
+                removed_empty_lists: 2,
+            }
+        );
+
+        let root = &serde_json::to_value(&note).unwrap()["lexicalState"]["root"]["children"];
+        let texts = |block: &serde_json::Value| -> Vec<(String, u64, Option<String>)> {
+            block["children"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|text| {
+                    (
+                        text["text"].as_str().unwrap().to_string(),
+                        text["format"].as_u64().unwrap(),
+                        text.get("key").and_then(|key| key.as_str()).map(str::to_string),
+                    )
+                })
+                .collect()
+        };
+        assert_eq!(root.as_array().unwrap().len(), 3);
+        // Merged text keeps the key of its first node
+        assert_eq!(
+            texts(&root[0]),
+            vec![("Hello ".to_string(), 0, Some("11".to_string())), ("world!".to_string(), 1, Some("13".to_string()))]
+        );
+        assert_eq!(root[1]["type"], "list");
+        assert_eq!(root[1]["children"].as_array().unwrap().len(), 1);
+        assert_eq!(root[1]["children"][0]["children"][0]["text"], "One");
+        // Text with another style is not merged
+        assert_eq!(texts(&root[2]).len(), 2);
+
+        // Normalizing again changes nothing
+        let normalized = serde_json::to_value(&note).unwrap();
+        assert!(!note.normalize().changed());
+        assert_eq!(serde_json::to_value(&note).unwrap(), normalized);
+
+        // Empty paragraphs can be kept
+        let mut note: Note = serde_json::from_str(&json_content).unwrap();
+        let report = note.normalize_with(&NormalizeOptions {
+            remove_empty_paragraphs: false,
+        });
+        assert_eq!(report.removed_empty_paragraphs, 0);
+        assert_eq!(note.lexical_state.root.children.len(), 5);
+    }
+
+    /// Build a table cell JSON containing a single paragraph.
+    pub(super) fn cell_json(text: &str, header_state: u32, col_span: u32, row_span: u32) -> serde_json::Value {
+        serde_json::json!({
+            "type": "tablecell",
+            "version": 1,
+            "headerState": header_state,
+            "colSpan": col_span,
+            "rowSpan": row_span,
+            "children": [
+                {
+                    "type": "paragraph",
+                    "version": 1,
+                    "children": if text.is_empty() { vec![] } else { vec![text_json(text, 0)] },
+                }
+            ],
+        })
+    }
+
+    /// Build a table JSON from rows of cell JSONs.
+    pub(super) fn table_json(rows: Vec<Vec<serde_json::Value>>) -> serde_json::Value {
+        let rows: Vec<serde_json::Value> = rows
+            .into_iter()
+            .map(|cells| serde_json::json!({ "type": "tablerow", "version": 1, "children": cells }))
+            .collect();
+        serde_json::json!({ "type": "table", "version": 1, "children": rows })
+    }
+
+    #[test]
+    fn test_table_brief_as_markdown() {
+        let note = note_from_children(serde_json::json!([
+            table_json(vec![
+                vec![cell_json("Quarter", 1, 1, 1), cell_json("Revenue", 1, 1, 1)],
+                vec![cell_json("Q1", 0, 1, 1), cell_json("10", 0, 1, 1)],
+                vec![cell_json("Q2", 0, 1, 1), cell_json("a|b", 0, 1, 1)],
+            ])
+        ]));
+
+        let briefs = note.get_brief();
+        assert_eq!(briefs.len(), 1);
+        assert_eq!(briefs[0].node_type, "table");
+        assert_eq!(
+            briefs[0].content,
+            "| Quarter | Revenue |\n| --- | --- |\n| Q1 | 10 |\n| Q2 | a\\|b |"
+        );
+        assert_eq!(note.to_plain_text(), briefs[0].content);
+    }
+
+    #[test]
+    fn test_table_brief_with_spans() {
+        let note = note_from_children(serde_json::json!([
+            table_json(vec![
+                vec![cell_json("Name", 1, 2, 1), cell_json("Total", 1, 1, 1)],
+                vec![cell_json("A", 0, 1, 2), cell_json("x", 0, 1, 1), cell_json("1", 0, 1, 1)],
+                vec![cell_json("y", 0, 1, 1), cell_json("2", 0, 1, 1)],
+                vec![cell_json("B", 0, 1, 1)],
+            ])
+        ]));
+
+        let briefs = note.get_brief();
+        assert_eq!(
+            briefs[0].content,
+            "| Name |  | Total |\n| --- | --- | --- |\n| A | x | 1 |\n|  | y | 2 |\n| B |  |  |"
+        );
+    }
+
+    #[test]
+    fn test_table_brief_without_header_or_rows() {
+        let note = note_from_children(serde_json::json!([
+            table_json(vec![vec![cell_json("a", 0, 1, 1), cell_json("b", 0, 1, 1)]]),
+            table_json(vec![]),
+        ]));
+
+        let briefs = note.get_brief();
+        assert_eq!(briefs.len(), 1, "Empty tables should not produce briefs");
+        assert_eq!(briefs[0].id, 0);
+        assert_eq!(briefs[0].content, "| a | b |");
+    }
+
+    /// Build a heading JSON with the given tag.
+    pub(super) fn heading_json(text: &str, tag: &str) -> serde_json::Value {
+        serde_json::json!({
+            "type": "heading",
+            "version": 1,
+            "tag": tag,
+            "children": [text_json(text, 0)],
+        })
+    }
+
+    /// Build a paragraph JSON containing a single text node.
+    pub(super) fn paragraph_json(text: &str) -> serde_json::Value {
+        serde_json::json!({
+            "type": "paragraph",
+            "version": 1,
+            "children": [text_json(text, 0)],
+        })
+    }
+
+    #[test]
+    fn test_sections_by_heading() {
+        let note = note_from_children(serde_json::json!([
+            paragraph_json("Preamble"),
+            heading_json("Intro", "h1"),
+            paragraph_json("Intro text"),
+            heading_json("Details", "h2"),
+            paragraph_json("Detail one"),
+            paragraph_json("Detail two"),
+        ]));
+
+        let sections = note.sections();
+        assert_eq!(sections.len(), 3);
+
+        assert_eq!(sections[0].heading, None);
+        assert_eq!(sections[0].level, None);
+        assert_eq!(sections[0].range, 0..1);
+
+        assert_eq!(sections[1].heading.as_deref(), Some("Intro"));
+        assert_eq!(sections[1].level, Some(HeadingTag::H1));
+        assert_eq!(sections[1].range, 1..3);
+        assert_eq!(sections[1].nodes.len(), 2);
+
+        assert_eq!(sections[2].heading.as_deref(), Some("Details"));
+        assert_eq!(sections[2].level, Some(HeadingTag::H2));
+        assert_eq!(sections[2].range, 3..6);
+        assert!(matches!(sections[2].nodes[0], LexicalNode::Heading(_)));
+    }
+
+    #[test]
+    fn test_get_brief_in_section() {
+        let json_content = fs::read_to_string("assets/example_outline_note.json")
+            .expect("Should be able to read assets/example_outline_note.json");
+        let note: Note = serde_json::from_str(&json_content).expect("Should be able to parse example outline note JSON");
+        let ids = |briefs: Vec<BriefNode>| briefs.iter().map(|b| b.id).collect::<Vec<_>>();
+
+        // The "Q1 Milestones" section spans nodes 5 and 6
+        assert_eq!(ids(note.get_brief_in_section(6)), vec![5, 6]);
+        // The "Timeline" section skips the empty paragraph at 9
+        assert_eq!(ids(note.get_brief_in_section(8)), vec![7, 8]);
+        // The preamble and the last section
+        assert_eq!(ids(note.get_brief_in_section(0)), vec![0]);
+        assert_eq!(ids(note.get_brief_in_section(42)), vec![10, 11]);
+    }
+
+    #[test]
+    fn test_node_keys_round_trip() {
+        fn collect_keys(value: &serde_json::Value, keys: &mut Vec<String>) {
+            match value {
+                serde_json::Value::Object(map) => {
+                    if let Some(serde_json::Value::String(key)) = map.get("key") {
+                        keys.push(key.clone());
+                    }
+                    map.values().for_each(|v| collect_keys(v, keys));
+                }
+                serde_json::Value::Array(items) => items.iter().for_each(|v| collect_keys(v, keys)),
+                _ => {}
+            }
+        }
+
+        let json_content = fs::read_to_string("assets/example_keyed_note.json")
+            .expect("Should be able to read assets/example_keyed_note.json");
+        let raw: serde_json::Value = serde_json::from_str(&json_content).unwrap();
+        let note: Note = serde_json::from_str(&json_content).expect("Should be able to parse example keyed note");
+        let round_trip = serde_json::to_value(&note).unwrap();
+
+        let (mut expected, mut actual) = (Vec::new(), Vec::new());
+        collect_keys(&raw, &mut expected);
+        collect_keys(&round_trip, &mut actual);
+        assert_eq!(expected.len(), 26);
+        assert_eq!(actual, expected);
+
+        // Notes without keys do not gain any
+        let note = note_from_children(serde_json::json!([paragraph_json("Plain")]));
+        assert!(!serde_json::to_string(&note).unwrap().contains("\"key\""));
+    }
+
+    #[test]
+    fn test_round_trip_is_lossless() {
+        for path in [
+            "assets/example_note.json",
+            "assets/example_outline_note.json",
+            "assets/example_chat_note.json",
+            "assets/example_keyed_note.json",
+            "assets/example_list_note.json",
+            "assets/example_code_note.json",
+            "assets/example_table_note.json",
+            "assets/example_cruft_note.json",
+        ] {
+            let json_content = fs::read_to_string(path).expect("Should be able to read the fixture");
+            let raw: serde_json::Value = serde_json::from_str(&json_content).unwrap();
+            let note: Note = serde_json::from_str(&json_content).expect("Should be able to parse the fixture");
+            assert_eq!(serde_json::to_value(&note).unwrap(), raw, "{path} should round trip");
+        }
+    }
+
+    #[test]
+    fn test_unknown_fields_are_kept_once() {
+        let note = note_from_children(serde_json::json!([{
+            "type": "heading",
+            "version": 1,
+            "tag": "h2",
+            "textStyle": "color: red",
+            "children": [{ "type": "text", "version": 1, "text": "Title", "format": 1, "futureField": [1, 2] }],
+        }]));
+
+        let LexicalNode::Heading(heading) = &note.lexical_state.root.children[0] else {
+            panic!("Should be a heading");
+        };
+        assert_eq!(heading.base.extra.get("textStyle"), Some(&serde_json::json!("color: red")));
+        // Known fields of the node and of the base are not extra
+        assert!(!heading.base.extra.contains_key("tag"));
+        assert!(!heading.base.extra.contains_key("version"));
+        assert!(!heading.base.extra.contains_key("type"));
+
+        let LexicalNode::Text(text) = &heading.children[0] else {
+            panic!("Should be a text node");
+        };
+        assert_eq!(text.base.extra.keys().collect::<Vec<_>>(), vec!["futureField"]);
+
+        let json = serde_json::to_string(&note).unwrap();
+        assert_eq!(json.matches("\"textStyle\"").count(), 1);
+        assert_eq!(json.matches("\"format\"").count(), 1);
+    }
+
+    /// Get the text of the first text child of a list item.
+    fn list_item_text(node: &LexicalNode) -> Option<&str> {
+        let LexicalNode::ListItem(item) = node else {
+            return None;
+        };
+        match item.children.first() {
+            Some(LexicalNode::Text(text)) => Some(&text.text),
+            Some(LexicalNode::Link(link)) => match link.children.first() {
+                Some(LexicalNode::Text(text)) => Some(&text.text),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Get the nested list of a list item wrapping a sub-list.
+    fn nested_list(node: &LexicalNode) -> Option<&ListNode> {
+        let LexicalNode::ListItem(item) = node else {
+            return None;
+        };
+        match item.children.first() {
+            Some(LexicalNode::List(list)) => Some(list),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_generate_toc() {
+        let json_content = fs::read_to_string("assets/example_outline_note.json")
+            .expect("Should be able to read assets/example_outline_note.json");
+
+        let note: Note = serde_json::from_str(&json_content)
+            .expect("Should be able to parse example outline note JSON");
+
+        // Project Plan (h1) > Goals (h2) > Q1 Milestones (h3), Timeline (h2); Results (h1)
+        let toc = note.generate_toc();
+        assert_eq!(toc.children.len(), 3);
+        assert_eq!(list_item_text(&toc.children[0]), Some("Project Plan"));
+        assert_eq!(list_item_text(&toc.children[2]), Some("Results"));
+
+        let level_two = nested_list(&toc.children[1]).expect("Should nest H2 headings");
+        assert_eq!(level_two.children.len(), 3);
+        assert_eq!(list_item_text(&level_two.children[0]), Some("Goals"));
+        assert_eq!(list_item_text(&level_two.children[2]), Some("Timeline"));
+
+        let level_three = nested_list(&level_two.children[1]).expect("Should nest H3 headings");
+        assert_eq!(level_three.children.len(), 1);
+        assert_eq!(list_item_text(&level_three.children[0]), Some("Q1 Milestones"));
+
+        // The TOC should be insertable into the note as a regular list node
+        let serialized = serde_json::to_value(LexicalNode::List(toc)).expect("Should serialize TOC");
+        assert_eq!(serialized["type"], "list");
+        assert_eq!(serialized["listType"], "bullet");
+    }
+
+    #[test]
+    fn test_generate_toc_links_keyed_headings() {
+        let json_content = fs::read_to_string("assets/example_outline_note.json")
+            .expect("Should be able to read assets/example_outline_note.json");
+        let mut note: Note = serde_json::from_str(&json_content).expect("Should parse example outline note");
+        let first_heading = note.lexical_state.root.children.iter_mut().find_map(|node| match node {
+            LexicalNode::Heading(heading) => Some(heading),
+            _ => None,
+        });
+        first_heading.expect("The note has a heading").base.key = Some("12".to_string());
+
+        let toc = note.generate_toc();
+        let LexicalNode::ListItem(item) = &toc.children[0] else {
+            panic!("Expected a list item");
+        };
+        let Some(LexicalNode::Link(link)) = item.children.first() else {
+            panic!("Expected the keyed heading to be linked");
+        };
+        assert_eq!(link.url, "#12");
+        assert_eq!(list_item_text(&toc.children[0]), Some("Project Plan"));
+
+        // Headings without a key stay plain text
+        let LexicalNode::ListItem(item) = &toc.children[2] else {
+            panic!("Expected a list item");
+        };
+        assert!(matches!(item.children.first(), Some(LexicalNode::Text(_))));
+    }
+
+    /// Build a list item JSON containing the given children.
+    pub(super) fn list_item_json(children: Vec<serde_json::Value>) -> serde_json::Value {
+        serde_json::json!({ "type": "listitem", "version": 1, "children": children })
+    }
+
+    /// Build a list JSON of the given type and start.
+    pub(super) fn list_json(list_type: &str, start: Option<u32>, items: Vec<serde_json::Value>) -> serde_json::Value {
+        serde_json::json!({
+            "type": "list",
+            "version": 1,
+            "listType": list_type,
+            "start": start,
+            "children": items,
+        })
+    }
+
+    #[test]
+    fn test_list_brief_numbering_and_nesting() {
+        let nested = list_json("bullet", None, vec![
+            list_item_json(vec![text_json("Sub a", 0)]),
+            list_item_json(vec![text_json("Sub b", 0)]),
+        ]);
+        let expected = "5. One\n6. Two\n  • Sub a\n  • Sub b\n7. Three";
+
+        // Lexical style: the nested list lives in its own list item
+        let note = note_from_children(serde_json::json!([
+            list_json("number", Some(5), vec![
+                list_item_json(vec![text_json("One", 0)]),
+                list_item_json(vec![text_json("Two", 0)]),
+                list_item_json(vec![nested.clone()]),
+                list_item_json(vec![text_json("Three", 0)]),
+            ])
+        ]));
+        assert_eq!(note.get_brief()[0].content, expected);
+
+        // The nested list inside the item it belongs to
+        let note = note_from_children(serde_json::json!([
+            list_json("number", Some(5), vec![
+                list_item_json(vec![text_json("One", 0)]),
+                list_item_json(vec![text_json("Two", 0), nested]),
+                list_item_json(vec![text_json("Three", 0)]),
+            ])
+        ]));
+        assert_eq!(note.get_brief()[0].content, expected);
+    }
+
+    #[test]
+    fn test_list_item_values() {
+        let json_content = fs::read_to_string("assets/example_list_note.json")
+            .expect("Should be able to read assets/example_list_note.json");
+        let note: Note = serde_json::from_str(&json_content).expect("Should be able to parse example list note");
+
+        let LexicalNode::List(list) = &note.lexical_state.root.children[1] else {
+            panic!("Should be a list");
+        };
+        assert_eq!(list.tag, Some(ListTag::Ol));
+        assert_eq!(list.start, None);
+        let LexicalNode::ListItem(item) = &list.children[0] else {
+            panic!("Should be a list item");
+        };
+        assert_eq!(item.value, Some(3));
+
+        // The numbering follows the item values when the start is absent
+        let expected = "3. Freeze the release branch\n4. Run the smoke tests\n  • Book the venue\n  • Send the invites\n5. Publish the announcement";
+        assert_eq!(note.get_brief()[1].content, expected);
+        assert!(note.to_markdown().contains("3. Freeze the release branch\n4. Run the smoke tests"));
+
+        // A missing start is not serialized as null
+        let serialized = serde_json::to_value(&note).unwrap();
+        let list = &serialized["lexicalState"]["root"]["children"][1];
+        assert!(list.get("start").is_none());
+        assert_eq!(list["tag"], "ol");
+        assert_eq!(list["children"][1]["value"], 4);
+    }
+
+    #[test]
+    fn test_code_highlight_keeps_code_verbatim() {
+        let json_content = fs::read_to_string("assets/example_code_note.json")
+            .expect("Should be able to read assets/example_code_note.json");
+        let note: Note = serde_json::from_str(&json_content).expect("Should be able to parse example code note");
+
+        let LexicalNode::Code(code) = &note.lexical_state.root.children[1] else {
+            panic!("Should be a code block");
+        };
+        let Some(LexicalNode::CodeHighlight(keyword)) = code.children.as_ref().map(|children| &children[0]) else {
+            panic!("Should be a code highlight");
+        };
+        assert_eq!(keyword.highlight_type.as_deref(), Some("keyword"));
+
+        let snippet = "fn main() {\n    let name = \"Amico\";\n    println!(\"Hello, {name}!\");\n}";
+        assert_eq!(note.get_brief()[1].content, format!("```rust\n{snippet}\n```"));
+        assert!(note.to_markdown().ends_with(&format!("```rust\n{snippet}\n```")));
+    }
+
+    #[test]
+    fn test_link_url_safety() {
+        let link = |url: &str| LinkNode {
+            url: url.to_string(),
+            rel: None,
+            target: None,
+            title: None,
+            children: Vec::new(),
+            base: BaseNodeProperties::default(),
+        };
+
+        for url in [
+            "https://example.com",
+            "HTTP://example.com",
+            "mailto:team@example.com",
+            "/notes/42",
+            "notes/42?view=full#top",
+            "#section",
+            "//cdn.example.com/file",
+            "a b:c",
+        ] {
+            assert!(link(url).is_safe_url(), "{url} should be safe");
+        }
+        for url in [
+            "javascript:alert(1)",
+            "JAVASCRIPT:alert(1)",
+            " \tjava\nscript:alert(1)",
+            "\u{0}javascript:alert(1)",
+            "data:text/html,<script>",
+            "vbscript:msgbox",
+            "file:///etc/passwd",
+        ] {
+            assert!(!link(url).is_safe_url(), "{url:?} should be unsafe");
+        }
+    }
+
+    #[test]
+    fn test_link_brief_includes_title() {
+        let link = |title: serde_json::Value| serde_json::json!({
+            "type": "link",
+            "version": 1,
+            "url": "https://example.com",
+            "title": title,
+            "children": [text_json("Docs", 0)],
+        });
+        let note = note_from_children(serde_json::json!([link("Example docs".into()), link(serde_json::Value::Null)]));
+
+        let briefs = note.get_brief();
+        assert_eq!(briefs[0].content, "Docs (https://example.com — Example docs)");
+        assert_eq!(briefs[1].content, "Docs (https://example.com)");
+        // A missing title is not serialized
+        let serialized = serde_json::to_value(&note.lexical_state.root.children[1]).unwrap();
+        assert!(serialized.get("title").is_none());
+    }
+
+    #[test]
+    fn test_table_cell_styling() {
+        let json_content = fs::read_to_string("assets/example_table_note.json")
+            .expect("Should be able to read assets/example_table_note.json");
+        let note: Note = serde_json::from_str(&json_content).expect("Should be able to parse example table note");
+
+        let LexicalNode::Table(table) = &note.lexical_state.root.children[1] else {
+            panic!("Should be a table");
+        };
+        let cells: Vec<&TableCellNode> = table
+            .children
+            .iter()
+            .filter_map(|row| row.children())
+            .flatten()
+            .filter_map(|cell| match cell {
+                LexicalNode::TableCell(cell) => Some(cell),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(cells[0].width, Some(180.0));
+        assert_eq!(cells[0].background_color.as_deref(), Some("#f2f3f5"));
+        assert_eq!(cells[3].width, Some(120.5));
+        assert_eq!(cells[3].background_color, None);
+
+        // Older notes omit the row span
+        let mut cell = cell_json("Old", 0, 1, 1);
+        cell.as_object_mut().unwrap().remove("rowSpan");
+        let note = note_from_children(serde_json::json!([table_json(vec![vec![cell]])]));
+        let serialized = serde_json::to_value(&note.lexical_state.root.children[0]).unwrap();
+        let cell = &serialized["children"][0]["children"][0];
+        assert_eq!(cell["rowSpan"], 1);
+        assert!(cell.get("width").is_none());
+        assert!(cell.get("backgroundColor").is_none());
+    }
+
+    #[test]
+    fn test_ai_blocks_without_provenance() {
+        let json_content = fs::read_to_string("assets/example_note.json")
+            .expect("Should be able to read assets/example_note.json");
+        let note: Note = serde_json::from_str(&json_content).expect("Should be able to parse example note");
+
+        let blocks = note.ai_blocks();
+        assert_eq!(blocks.len(), 12);
+        assert!(blocks.iter().all(|ai| ai.prompt.is_none() && ai.model.is_none() && ai.created_at.is_none()));
+        // Old notes don't gain the fields
+        assert!(!serde_json::to_string(&note).unwrap().contains("createdAt"));
+    }
+
+    #[test]
+    fn test_get_brief_filtered_keeps_root_ids() {
+        let json_content = fs::read_to_string("assets/example_outline_note.json")
+            .expect("Should be able to read assets/example_outline_note.json");
+
+        let note: Note = serde_json::from_str(&json_content)
+            .expect("Should be able to parse example outline note JSON");
+
+        let all = note.get_brief();
+        let headings = note.get_brief_filtered(&["heading"]);
+        let ids: Vec<usize> = headings.iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec![1, 3, 5, 7, 10]);
+
+        // Every filtered brief must match the unfiltered brief with the same id
+        for brief in &headings {
+            let original = all.iter().find(|b| b.id == brief.id).expect("Id should exist");
+            assert_eq!(original.content, brief.content);
+            assert_eq!(
+                note.lexical_state.root.children[brief.id].type_name(),
+                "heading"
+            );
+        }
+
+        let outline = note.get_brief_filtered(&["heading", "paragraph"]);
+        assert!(outline.iter().all(|b| b.node_type != "list"));
+        assert_eq!(outline.len(), all.len() - 1);
+
+        let without_headings = note.get_brief_excluding(&["heading"]);
+        let ids: Vec<usize> = without_headings.iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec![0, 2, 4, 6, 8, 11]);
+
+        // An empty filter means all nodes
+        assert_eq!(note.get_brief_filtered(&[]).len(), all.len());
+        assert_eq!(note.get_brief_excluding(&[]).len(), all.len());
+    }
+
+    #[test]
+    fn test_get_brief_window() {
+        let note = note_from_children(serde_json::Value::Array(
+            (0..10).map(|i| paragraph_json(&format!("Node {i}"))).collect(),
+        ));
+        let ids = |window: &BriefWindow| window.briefs.iter().map(|b| b.id).collect::<Vec<_>>();
+
+        let window = note.get_brief_window(5, 2, 1);
+        assert_eq!(ids(&window), vec![3, 4, 5, 6]);
+        assert_eq!((window.omitted_before, window.omitted_after), (3, 3));
+
+        let window = note.get_brief_window(0, 2, 2);
+        assert_eq!(ids(&window), vec![0, 1, 2]);
+        assert_eq!((window.omitted_before, window.omitted_after), (0, 7));
+
+        // A cursor beyond the end shows the last nodes
+        let window = note.get_brief_window(42, 2, 2);
+        assert_eq!(ids(&window), vec![8, 9]);
+        assert_eq!((window.omitted_before, window.omitted_after), (8, 0));
+
+        // A window larger than the note shows everything
+        let window = note.get_brief_window(5, 100, usize::MAX);
+        assert_eq!(window.briefs.len(), 10);
+        assert_eq!((window.omitted_before, window.omitted_after), (0, 0));
+    }
+
+    #[test]
+    fn test_brief_window() {
+        let note = note_from_children(serde_json::Value::Array(
+            (0..10).map(|i| paragraph_json(&format!("Node {i}"))).collect(),
+        ));
+        let ids = |briefs: Vec<BriefNode>| briefs.iter().map(|b| b.id).collect::<Vec<_>>();
+
+        assert_eq!(ids(note.brief_window(0, 2)), vec![0, 1, 2]);
+        assert_eq!(ids(note.brief_window(5, 2)), vec![3, 4, 5, 6, 7]);
+        assert_eq!(ids(note.brief_window(9, 2)), vec![7, 8, 9]);
+        assert_eq!(ids(note.brief_window(5, 0)), vec![5]);
+    }
+
+    #[test]
+    fn test_pending_count() {
+        let embedding = |content: &str, is_loading: bool| {
+            serde_json::json!({
+                "type": "ai-embedding",
+                "version": 1,
+                "content": content,
+                "isLoading": is_loading,
+            })
+        };
+
+        let note = note_from_children(serde_json::json!([
+            embedding("", true),
+            embedding("Done", false),
+            list_json("bullet", None, vec![
+                list_item_json(vec![embedding("", true)]),
+                list_item_json(vec![list_json("bullet", None, vec![
+                    list_item_json(vec![embedding("", true), embedding("Done", false)]),
+                ])]),
+            ]),
+            {
+                "type": "quote",
+                "version": 1,
+                "children": [embedding("", true)],
+            },
+        ]));
+
+        assert_eq!(note.pending_count(), 4);
+
+        let json_content = fs::read_to_string("assets/example_note.json")
+            .expect("Should be able to read assets/example_note.json");
+        let note: Note = serde_json::from_str(&json_content)
+            .expect("Should be able to parse example note JSON");
+        assert_eq!(note.pending_count(), 1);
+    }
+
+    #[test]
+    fn test_outline() {
+        let note = note_from_children(serde_json::json!([
+            paragraph_json("Preamble"),
+            heading_json("Intro", "h1"),
+            heading_json("Notes", "h4"),
+            heading_json("Notes", "h2"),
+            paragraph_json("Body"),
+            heading_json("Notes", "h3"),
+            heading_json("Results", "h1"),
+            heading_json("Summary", "h3"),
+        ]));
+
+        let outline = note.outline();
+        assert_eq!(outline.len(), 2);
+
+        let intro = &outline[0];
+        assert_eq!((intro.text.as_str(), intro.level, intro.index), ("Intro", HeadingTag::H1, 1));
+        assert_eq!(intro.children.len(), 2);
+
+        // The skipped-level H4 still nests under the H1
+        assert_eq!((intro.children[0].level, intro.children[0].index), (HeadingTag::H4, 2));
+        assert!(intro.children[0].children.is_empty());
+
+        // Duplicate texts are kept apart by their index
+        let notes = &intro.children[1];
+        assert_eq!((notes.text.as_str(), notes.level, notes.index), ("Notes", HeadingTag::H2, 3));
+        assert_eq!(notes.children.len(), 1);
+        assert_eq!((notes.children[0].text.as_str(), notes.children[0].index), ("Notes", 5));
+
+        let results = &outline[1];
+        assert_eq!((results.text.as_str(), results.index), ("Results", 6));
+        assert_eq!(results.children[0].level, HeadingTag::H3);
+
+        let serialized = serde_json::to_value(&outline).expect("Should serialize outline");
+        assert_eq!(serialized[0]["level"], "h1");
+        assert_eq!(serialized[0]["children"][1]["index"], 3);
+    }
+
+    #[test]
+    fn test_voice_input_metadata() {
+        let json_content = fs::read_to_string("assets/example_voice_note.json")
+            .expect("Should be able to read assets/example_voice_note.json");
+        let note = Note::from_json(&json_content).expect("Should be able to parse example voice note JSON");
+
+        let LexicalNode::VoiceInput(voice) = &note.lexical_state.root.children[1] else {
+            panic!("Expected a voice input node");
+        };
+        assert_eq!((voice.confidence, voice.duration_ms, voice.language.as_deref()), (Some(0.93), Some(4200), Some("de")));
+
+        // The nodes with and without the metadata round-trip as they were
+        let original: serde_json::Value = serde_json::from_str(&json_content).unwrap();
+        let serialized: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&note).expect("Should serialize note")).unwrap();
+        assert_eq!(serialized["lexicalState"]["root"]["children"], original["lexicalState"]["root"]["children"]);
+
+        let briefs = note.get_brief();
+        let contents: Vec<&str> = briefs.iter().map(|b| b.content.as_str()).collect();
+        assert_eq!(
+            contents,
+            vec![
+                "[done] Call the supplier about the delayed order",
+                "[done, de, 0.93 conf] Termin mit dem Lieferanten am Montag",
+                "[done, en, 0.62 conf] the quarterly numbers look grate",
+            ]
+        );
+
+        let issues = note.validate();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].path, vec![2]);
+        assert!(issues[0].message.starts_with("Low-confidence transcript (0.62)"));
+        assert_eq!(issues[0].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn test_voice_input_status() {
+        let note = note_from_children(serde_json::json!([
+            { "type": "voice-input", "version": 1, "content": "Legacy transcript" },
+            { "type": "voice-input", "version": 1, "content": "", "status": "recording" },
+            { "type": "voice-input", "version": 1, "content": "Half", "status": "transcribing" },
+            { "type": "voice-input", "version": 1, "content": "Finished", "status": "done" },
+        ]));
+
+        let LexicalNode::VoiceInput(legacy) = &note.lexical_state.root.children[0] else {
+            panic!("Expected a voice input node");
+        };
+        assert_eq!(legacy.status, None);
+        assert_eq!(note.pending_voice_inputs(), 2);
+
+        let briefs = note.get_brief();
+        let contents: Vec<&str> = briefs.iter().map(|b| b.content.as_str()).collect();
+        assert_eq!(
+            contents,
+            vec!["Legacy transcript", "[recording] ", "[transcribing] Half", "[done] Finished"]
+        );
+
+        // The missing status must not be written back
+        let serialized = serde_json::to_value(&note).expect("Should serialize note");
+        let children = &serialized["lexicalState"]["root"]["children"];
+        assert!(children[0].get("status").is_none());
+        assert_eq!(children[2]["status"], "transcribing");
+    }
+
+    #[test]
+    fn test_chat_session_to_chat_messages() {
+        let session: ChatSessionNode = serde_json::from_value(serde_json::json!({
+            "version": 1,
+            "sessionId": "session-1",
+            "messages": [
+                { "id": 1, "sender": "system", "content": "Be brief.", "timestamp": "2025-06-01T10:00:00Z" },
+                { "id": 2, "sender": "user", "content": "Summarize the note", "timestamp": "2025-06-01T10:00:01Z" },
+                { "id": 3, "sender": "agent", "content": "  ", "timestamp": "2025-06-01T10:00:02Z" },
+                { "id": 4, "sender": "agent", "content": "Here is a summary.", "timestamp": "2025-06-01T10:00:03Z" },
+            ],
+        }))
+        .expect("Should be able to parse chat session");
+
+        let messages = session.to_chat_messages();
+        let pairs: Vec<(&str, &str)> = messages
+            .iter()
+            .map(|msg| (msg.role.as_str(), msg.content.as_str()))
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![
+                ("system", "Be brief."),
+                ("user", "Summarize the note"),
+                ("assistant", "Here is a summary."),
+            ]
+        );
+    }
+
     #[test]
     fn test_get_brief() {
         let json_content = fs::read_to_string("assets/example_note.json")
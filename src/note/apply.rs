@@ -0,0 +1,1339 @@
+use anyhow::anyhow;
+use serde::Deserialize;
+
+use super::{
+    code::unfence_code, detect_language, AIEmbeddingNode, BaseNodeProperties, BriefId, CodeFormat, CodeNode, HeadingNode, HeadingTag, LexicalNode, ListItemNode, ListNode, Note, NodePath,
+    ParagraphNode, QuoteNode, TableNode, TextNode, ValidationIssue, validate::validate_subtree,
+};
+use super::markdown::markdown_to_nodes;
+use crate::agent::{
+    AppendNode, ChatAction, ConvertToList, DeleteNode, FormatText, InsertCodeBlock, InsertNode, InsertTable, ModifyNode,
+    MoveNode, ReplaceRange, SetHeadingLevel, SetTitle,
+};
+use crate::clock::now_iso8601;
+use crate::service::MODEL;
+
+/// The number of applied operation ids remembered by a note.
+const MAX_APPLIED_OP_IDS: usize = 100;
+
+/// Where the AI content inserted by an action came from.
+#[derive(Debug, Clone, Default)]
+pub struct Provenance {
+    /// The user message the action answers
+    pub prompt: Option<String>,
+    /// The chat session the action was generated in
+    pub session_id: Option<String>,
+}
+
+impl Note {
+    /// Apply an agent action to the note.
+    ///
+    /// Replies leave the note unchanged. Nodes are addressed by their root index,
+    /// or by the `path` of the action when present. An action with an `op_id`
+    /// already applied to the note is ignored, so retries are safe.
+    pub fn apply_action(&mut self, action: &ChatAction) -> anyhow::Result<()> {
+        self.apply_action_with_provenance(action, &Provenance::default())
+    }
+
+    /// Apply an agent action to the note, recording the provenance on the AI embeddings it creates.
+    ///
+    /// The model and creation time are always recorded.
+    pub fn apply_action_with_provenance(&mut self, action: &ChatAction, provenance: &Provenance) -> anyhow::Result<()> {
+        let op_id = action.op_id();
+        if let Some(op_id) = op_id
+            && self.applied_op_ids.iter().any(|applied| applied == op_id)
+        {
+            tracing::info!("Skipping the already applied operation {}", op_id);
+            return Ok(());
+        }
+
+        let result = match action {
+            ChatAction::Reply(_) => Ok(()),
+            ChatAction::InsertNode(insert) => self.apply_insert_node(insert, provenance),
+            ChatAction::ModifyNode(modify) => self.apply_modify_node(modify, provenance),
+            ChatAction::SetHeadingLevel(set_level) => self.apply_set_heading_level(set_level),
+            ChatAction::FormatText(format) => self.apply_format_text(format),
+            ChatAction::ConvertToList(convert) => self.apply_convert_to_list(convert),
+            ChatAction::DeleteNode(delete) => self.apply_delete_node(delete),
+            ChatAction::MoveNode(move_node) => self.apply_move_node(move_node),
+            ChatAction::ReplaceRange(replace) => self.apply_replace_range(replace, provenance),
+            ChatAction::InsertCodeBlock(insert) => self.apply_insert_code_block(insert),
+            ChatAction::InsertTable(insert) => self.apply_insert_table(insert),
+            ChatAction::Batch(actions) => self.apply_all_with_provenance(actions, provenance),
+            ChatAction::AppendNode(append) => self.apply_append_node(append, provenance),
+            ChatAction::SetTitle(set_title) => self.apply_set_title(set_title),
+        };
+
+        if let Some(op_id) = op_id
+            && result.is_ok()
+        {
+            if self.applied_op_ids.len() == MAX_APPLIED_OP_IDS {
+                self.applied_op_ids.pop_front();
+            }
+            self.applied_op_ids.push_back(op_id.to_string());
+        }
+        result
+    }
+
+    /// Apply agent actions in order, all of them or none.
+    ///
+    /// Each action sees the note as the previous ones left it. If an action fails,
+    /// the note is left unchanged and the error names the failed action.
+    pub fn apply_all(&mut self, actions: &[ChatAction]) -> anyhow::Result<()> {
+        self.apply_all_with_provenance(actions, &Provenance::default())
+    }
+
+    /// Apply agent actions in order like [`Note::apply_all`], recording the provenance like
+    /// [`Note::apply_action_with_provenance`].
+    pub fn apply_all_with_provenance(&mut self, actions: &[ChatAction], provenance: &Provenance) -> anyhow::Result<()> {
+        let mut note = self.clone();
+        for (index, action) in actions.iter().enumerate() {
+            note.apply_action_with_provenance(action, provenance)
+                .map_err(|err| anyhow!("Action {} of the batch failed, so none was applied: {}", index, err))?;
+        }
+        *self = note;
+        Ok(())
+    }
+
+    fn apply_insert_node(&mut self, insert: &InsertNode, provenance: &Provenance) -> anyhow::Result<()> {
+        if insert.is_markdown() {
+            return self.apply_insert_markdown(insert);
+        }
+        let node = match &insert.node {
+            Some(payload) => node_from_payload(payload, insert.path.is_none(), provenance)?,
+            None => {
+                let mut node = node_from_content(&insert.node_type, &insert.content, provenance)?;
+                if let LexicalNode::Code(code) = &mut node {
+                    code.language = insert
+                        .language
+                        .clone()
+                        .or(code.language.take())
+                        .or_else(|| detect_language(&insert.content));
+                }
+                node
+            }
+        };
+        check_links(&node)?;
+        let path = match &insert.path {
+            Some(path) => {
+                let after: NodePath = path.parse()?;
+                // Check the node exists before inserting after it
+                self.get_by_path(&after)?;
+                let (last, parent) = after.0.split_last().expect("A parsed path is not empty");
+                let mut segments = parent.to_vec();
+                segments.push(last + 1);
+                NodePath(segments)
+            }
+            None => NodePath::root(self.insert_index(&insert.insert_after)?),
+        };
+        self.insert_at_path(&path, node)?;
+        Ok(())
+    }
+
+    /// Insert the blocks of Markdown content one after another
+    fn apply_insert_markdown(&mut self, insert: &InsertNode) -> anyhow::Result<()> {
+        if insert.path.is_some() {
+            return Err(anyhow!("Markdown content is inserted as root blocks, give `insert_after` instead of `path`"));
+        }
+        let nodes = markdown_blocks(&insert.content)?;
+        let index = self.insert_index(&insert.insert_after)?;
+        self.lexical_state.root.children.splice(index..index, nodes);
+        Ok(())
+    }
+
+    fn apply_set_title(&mut self, set_title: &SetTitle) -> anyhow::Result<()> {
+        let title = set_title.content.trim();
+        if title.is_empty() {
+            return Err(anyhow!("The title is empty, give the text of the new title"));
+        }
+        self.set_title(title);
+        Ok(())
+    }
+
+    fn apply_append_node(&mut self, append: &AppendNode, provenance: &Provenance) -> anyhow::Result<()> {
+        let mut node = node_from_content(&append.node_type, &append.content, provenance)?;
+        if let LexicalNode::Code(code) = &mut node {
+            code.language = code.language.take().or_else(|| detect_language(&append.content));
+        }
+        check_links(&node)?;
+        self.lexical_state.root.children.push(node);
+        Ok(())
+    }
+
+    fn apply_insert_code_block(&mut self, insert: &InsertCodeBlock) -> anyhow::Result<()> {
+        let node = LexicalNode::Code(CodeNode {
+            text: None,
+            language: Some(insert.language.clone()),
+            children: Some(vec![LexicalNode::Text(TextNode::new(insert.content.clone()))]),
+            format: CodeFormat::Align(String::new()),
+            base: BaseNodeProperties::default(),
+        });
+        let index = self.insert_index(&insert.insert_after)?;
+        self.insert_at_path(&NodePath::root(index), node)?;
+        Ok(())
+    }
+
+    fn apply_insert_table(&mut self, insert: &InsertTable) -> anyhow::Result<()> {
+        if insert.rows.iter().all(Vec::is_empty) {
+            return Err(anyhow!("The table has no cells, give at least one row with one cell"));
+        }
+        let node = LexicalNode::Table(TableNode::from_rows(&insert.rows));
+        let index = self.insert_index(&insert.insert_after)?;
+        self.insert_at_path(&NodePath::root(index), node)?;
+        Ok(())
+    }
+
+    /// The root index of a node inserted after the node with the id, at the end when
+    /// the index is beyond the note, e.g. in an empty note
+    pub(super) fn insert_index(&self, insert_after: &BriefId) -> anyhow::Result<usize> {
+        Ok((self.brief_index(insert_after)? + 1).min(self.lexical_state.root.children.len()))
+    }
+
+    fn apply_modify_node(&mut self, modify: &ModifyNode, provenance: &Provenance) -> anyhow::Result<()> {
+        if modify.is_markdown() {
+            return self.apply_modify_markdown(modify);
+        }
+        let path = match &modify.path {
+            Some(path) => path.parse()?,
+            None => NodePath::root(self.brief_index(&modify.id)?),
+        };
+        let mut node = match &modify.node {
+            Some(payload) => node_from_payload(payload, modify.path.is_none(), provenance)?,
+            None => node_from_content(&modify.node_type, &modify.content, provenance)?,
+        };
+        check_links(&node)?;
+        let target = self.get_mut_by_path(&path)?;
+        // Keep the Lexical key so the editor updates the node in place, and the
+        // layout of the block, such as its indent and RTL direction, unless the node sets it
+        let (base, target_base) = (node.base_mut(), target.base_mut());
+        base.key = target_base.key.take();
+        base.indent = base.indent.take().or(target_base.indent.take());
+        base.direction = base.direction.take().or(target_base.direction.take());
+        base.format = base.format.take().or(target_base.format.take());
+        // Keep the format of the replaced text, such as bold, unless the node is given in full
+        if modify.node.is_none()
+            && let Some(format) = first_text(target).map(|text| text.format)
+        {
+            format_text_nodes(&mut node, format, false);
+        }
+        *target = node;
+        Ok(())
+    }
+
+    /// Replace a root node with the blocks of Markdown content
+    fn apply_modify_markdown(&mut self, modify: &ModifyNode) -> anyhow::Result<()> {
+        if modify.path.is_some() {
+            return Err(anyhow!("Markdown content replaces a root node, give `id` instead of `path`"));
+        }
+        let index = self.brief_index(&modify.id)?;
+        let len = self.lexical_state.root.children.len();
+        if index >= len {
+            return Err(anyhow!("Node {} is not in the note, which has {} nodes", index, len));
+        }
+        let mut nodes = markdown_blocks(&modify.content)?;
+        // Keep the Lexical key so the editor updates the first block in place
+        let children = &mut self.lexical_state.root.children;
+        nodes[0].base_mut().key = children[index].base_mut().key.take();
+        children.splice(index..=index, nodes);
+        Ok(())
+    }
+
+    fn apply_replace_range(&mut self, replace: &ReplaceRange, provenance: &Provenance) -> anyhow::Result<()> {
+        let start = self.brief_index(&replace.start)?;
+        let end = self.brief_index(&replace.end)?;
+        let len = self.lexical_state.root.children.len();
+        if end < start {
+            return Err(anyhow!("The end {} is before the start {}", end, start));
+        }
+        if end >= len {
+            return Err(anyhow!("Node {} is not in the note, which has {} nodes", end, len));
+        }
+
+        // Build every node before changing the note, so that a bad node replaces nothing
+        let mut nodes = Vec::with_capacity(replace.nodes.len());
+        for (index, spec) in replace.nodes.iter().enumerate() {
+            let mut node = node_from_content(&spec.node_type, &spec.content, provenance)
+                .map_err(|err| anyhow!("New node {}: {}", index, err))?;
+            if let LexicalNode::Code(code) = &mut node {
+                code.language = code.language.take().or_else(|| detect_language(&spec.content));
+            }
+            check_links(&node).map_err(|err| anyhow!("New node {}: {}", index, err))?;
+            nodes.push(node);
+        }
+        self.lexical_state.root.children.splice(start..=end, nodes);
+        Ok(())
+    }
+
+    fn apply_set_heading_level(&mut self, set_level: &SetHeadingLevel) -> anyhow::Result<()> {
+        let index = self.brief_index(&set_level.id)?;
+        match self.get_mut_by_path(&NodePath::root(index))? {
+            LexicalNode::Heading(heading) => {
+                heading.tag = set_level.tag;
+                Ok(())
+            }
+            node => Err(anyhow!(
+                "Node {} is a {} node, not a heading. Only heading nodes can change level, use modify_node to turn it into a heading",
+                set_level.id,
+                node.type_name()
+            )),
+        }
+    }
+
+    fn apply_format_text(&mut self, format: &FormatText) -> anyhow::Result<()> {
+        let mut flags = 0;
+        for name in &format.format {
+            flags |= TextNode::format_flag(name).ok_or_else(|| {
+                anyhow!(
+                    "Unknown format: {}. Use bold, italic, underline, strikethrough or code",
+                    name
+                )
+            })?;
+        }
+
+        let index = self.brief_index(&format.id)?;
+        let node = self.get_mut_by_path(&NodePath::root(index))?;
+        if format_text_nodes(node, flags, format.remove) == 0 {
+            return Err(anyhow!("Node {} has no text to format", format.id));
+        }
+        Ok(())
+    }
+
+    fn apply_delete_node(&mut self, delete: &DeleteNode) -> anyhow::Result<()> {
+        let index = self.brief_index(&delete.id)?;
+        let children = &mut self.lexical_state.root.children;
+        if index >= children.len() {
+            return Err(anyhow!("Node {} is not in the note, which has {} nodes", delete.id, children.len()));
+        }
+        children.remove(index);
+        Ok(())
+    }
+
+    fn apply_move_node(&mut self, move_node: &MoveNode) -> anyhow::Result<()> {
+        let index = self.brief_index(&move_node.id)?;
+        let len = self.lexical_state.root.children.len();
+        if index >= len {
+            return Err(anyhow!("Node {} is not in the note, which has {} nodes", move_node.id, len));
+        }
+        if move_node.to >= len {
+            return Err(anyhow!(
+                "Node {} cannot move to index {}, the last index is {}",
+                move_node.id,
+                move_node.to,
+                len - 1
+            ));
+        }
+        self.move_node(index, move_node.to)?;
+        Ok(())
+    }
+
+    fn apply_convert_to_list(&mut self, convert: &ConvertToList) -> anyhow::Result<()> {
+        let start = self.brief_index(&convert.id)?;
+        let end = match &convert.end_id {
+            Some(end_id) => self.brief_index(end_id)?,
+            None => start,
+        };
+        let children = &mut self.lexical_state.root.children;
+        if end < start {
+            return Err(anyhow!("The end id {} is before the id {}", end, start));
+        }
+        if end >= children.len() {
+            return Err(anyhow!("Node {} is not in the note, which has {} nodes", end, children.len()));
+        }
+        if let Some((index, node)) = children[start..=end]
+            .iter()
+            .enumerate()
+            .find(|(_, node)| !matches!(node, LexicalNode::Paragraph(_)))
+        {
+            return Err(anyhow!(
+                "Node {} is a {} node. Only paragraphs can be converted to a list",
+                start + index,
+                node.type_name()
+            ));
+        }
+
+        // Each paragraph becomes an item, numbered like Lexical does
+        let items = children
+            .drain(start..=end)
+            .zip(1..)
+            .map(|(mut node, value)| {
+                let LexicalNode::Paragraph(paragraph) = &mut node else {
+                    unreachable!("Only paragraphs are converted");
+                };
+                LexicalNode::ListItem(ListItemNode {
+                    children: std::mem::take(&mut paragraph.children),
+                    value: Some(value),
+                    base: BaseNodeProperties::default(),
+                })
+            })
+            .collect();
+
+        let list = ListNode {
+            list_type: convert.list_type.clone(),
+            start: Some(1),
+            tag: Some(convert.list_type.tag()),
+            children: items,
+            base: BaseNodeProperties::default(),
+        };
+        children.insert(start, LexicalNode::List(list));
+        Ok(())
+    }
+
+    /// Get the root index of a brief id, keeping indices beyond the note for the path errors
+    pub(super) fn brief_index(&self, id: &BriefId) -> anyhow::Result<usize> {
+        match id {
+            BriefId::Index(index) => Ok(*index),
+            BriefId::Key(_) => self
+                .resolve_brief_id(id)
+                .ok_or_else(|| anyhow!("Node {} is not in the note, it may have been changed or deleted", id)),
+        }
+    }
+}
+
+/// Recursively set or clear the format flags of the text nodes, returning the number of text nodes
+fn format_text_nodes(node: &mut LexicalNode, flags: u32, remove: bool) -> usize {
+    if let LexicalNode::Text(text) = node {
+        if remove {
+            text.format &= !flags;
+        } else {
+            text.format |= flags;
+        }
+        return 1;
+    }
+
+    node.children_mut()
+        .map(|children| children.iter_mut().map(|child| format_text_nodes(child, flags, remove)).sum())
+        .unwrap_or(0)
+}
+
+/// Find the first text node in document order
+pub(super) fn first_text(node: &LexicalNode) -> Option<&TextNode> {
+    match node {
+        LexicalNode::Text(text) => Some(text),
+        _ => node.children()?.iter().find_map(first_text),
+    }
+}
+
+/// Reject a node containing links with unsafe URL schemes, such as `javascript:`
+fn check_links(node: &LexicalNode) -> anyhow::Result<()> {
+    let unsafe_url = match node {
+        LexicalNode::Link(link) if !link.is_safe_url() => Some(&link.url),
+        LexicalNode::AutoLink(link) if !link.is_safe_url() => Some(&link.url),
+        _ => None,
+    };
+    if let Some(url) = unsafe_url {
+        return Err(anyhow!(
+            "Unsafe link URL \"{}\". Only http, https, mailto and relative links are allowed",
+            url
+        ));
+    }
+
+    node.children().into_iter().flatten().try_for_each(check_links)
+}
+
+/// Parse the Markdown content of an action into root blocks
+fn markdown_blocks(markdown: &str) -> anyhow::Result<Vec<LexicalNode>> {
+    let nodes = markdown_to_nodes(markdown);
+    if nodes.is_empty() {
+        return Err(anyhow!("The Markdown content has no blocks, give at least one"));
+    }
+    Ok(nodes)
+}
+
+/// Create a node from the complete Lexical node JSON of an action, checking it like a saved note
+fn node_from_payload(payload: &serde_json::Value, at_root: bool, provenance: &Provenance) -> anyhow::Result<LexicalNode> {
+    let mut node = LexicalNode::deserialize(payload).map_err(|err| anyhow!("Invalid node JSON: {}", err))?;
+    if at_root && !node.is_block() {
+        return Err(anyhow!(
+            "A {} node cannot be a root node, put it in a paragraph",
+            node.type_name()
+        ));
+    }
+    // Unsafe links get the more helpful message of check_links
+    check_links(&node)?;
+    // Warnings, such as a low-confidence transcript, leave the node usable
+    if let Some(issue) = validate_subtree(&node).into_iter().find(ValidationIssue::is_error) {
+        if issue.path.is_empty() {
+            return Err(anyhow!("Invalid node: {}", issue.message));
+        }
+        let path: Vec<String> = issue.path.iter().map(usize::to_string).collect();
+        return Err(anyhow!("Invalid node: {} (at child {})", issue.message, path.join(".")));
+    }
+    record_provenance(&mut node, provenance);
+    Ok(node)
+}
+
+/// Record the provenance on the AI embeddings of a node given in full, keeping what the node sets
+fn record_provenance(node: &mut LexicalNode, provenance: &Provenance) {
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+        if let LexicalNode::AIEmbedding(ai) = node {
+            ai.prompt = ai.prompt.take().or_else(|| provenance.prompt.clone());
+            ai.session_id = ai.session_id.take().or_else(|| provenance.session_id.clone());
+            ai.model = ai.model.take().or_else(|| Some(MODEL.to_string()));
+            ai.created_at = ai.created_at.take().or_else(|| Some(now_iso8601()));
+        }
+        stack.extend(node.children_mut().into_iter().flatten());
+    }
+}
+
+/// Create a node of the agent's node type holding the content as plain text
+fn node_from_content(node_type: &str, content: &str, provenance: &Provenance) -> anyhow::Result<LexicalNode> {
+    let text = vec![LexicalNode::Text(TextNode::new(content))];
+    let heading = |tag| {
+        LexicalNode::Heading(HeadingNode {
+            tag,
+            children: text.clone(),
+            base: BaseNodeProperties::default(),
+        })
+    };
+
+    let node = match node_type {
+        // Text cannot be a block on its own, so it goes into a paragraph
+        "paragraph" | "text" => LexicalNode::Paragraph(ParagraphNode {
+            children: text,
+            text_format: 0,
+            text_style: String::new(),
+            base: BaseNodeProperties::default(),
+        }),
+        "heading" | "h1" => heading(HeadingTag::H1),
+        "h2" => heading(HeadingTag::H2),
+        "h3" => heading(HeadingTag::H3),
+        "h4" => heading(HeadingTag::H4),
+        "h5" => heading(HeadingTag::H5),
+        "h6" => heading(HeadingTag::H6),
+        "quote" => LexicalNode::Quote(QuoteNode {
+            children: text,
+            base: BaseNodeProperties::default(),
+        }),
+        // The model sees code blocks fenced, so it may write them back fenced
+        "code" => {
+            let (language, code) = unfence_code(content).unwrap_or((None, content.to_string()));
+            LexicalNode::Code(CodeNode {
+                text: None,
+                language,
+                children: Some(vec![LexicalNode::Text(TextNode::new(code))]),
+                format: CodeFormat::Align(String::new()),
+                base: BaseNodeProperties::default(),
+            })
+        }
+        "listitem" => LexicalNode::ListItem(ListItemNode {
+            children: text,
+            value: None,
+            base: BaseNodeProperties::default(),
+        }),
+        "ai-embedding" => LexicalNode::AIEmbedding(AIEmbeddingNode {
+            content: content.to_string(),
+            is_loading: false,
+            prompt: provenance.prompt.clone(),
+            model: Some(MODEL.to_string()),
+            session_id: provenance.session_id.clone(),
+            created_at: Some(now_iso8601()),
+            base: BaseNodeProperties::default(),
+        }),
+        _ => return Err(anyhow!("Unsupported node type: {}", node_type)),
+    };
+    Ok(node)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::super::tests::{heading_json, list_item_json, list_json, note_from_children, paragraph_json, text_json};
+    use super::super::{ListTag, ListType, TextDirection};
+    use super::*;
+
+    fn list_note() -> Note {
+        note_from_children(json!([
+            paragraph_json("Intro"),
+            list_json("bullet", None, vec![
+                list_item_json(vec![text_json("First", 0)]),
+                list_item_json(vec![text_json("Second", 0)]),
+            ]),
+        ]))
+    }
+
+    fn texts(note: &Note) -> Vec<String> {
+        note.lexical_state
+            .root
+            .children
+            .iter()
+            .map(|node| note.render_node(node).1)
+            .collect()
+    }
+
+    fn action(value: serde_json::Value) -> ChatAction {
+        ChatAction::try_from_reply(value.to_string()).expect("Should parse the action")
+    }
+
+    #[test]
+    fn test_apply_flat_actions() {
+        let mut note = list_note();
+        note.apply_action(&action(json!({
+            "action": "insert_node", "insert_after": 0, "node_type": "h2", "content": "Tasks",
+        })))
+        .expect("Should insert");
+        assert_eq!(texts(&note)[1], "Tasks");
+        assert_eq!(note.lexical_state.root.children[1].type_name(), "heading");
+
+        note.apply_action(&action(json!({
+            "action": "modify_node", "id": 0, "node_type": "paragraph", "content": "Welcome",
+        })))
+        .expect("Should modify");
+        assert_eq!(texts(&note)[0], "Welcome");
+
+        let err = note
+            .apply_action(&action(json!({
+                "action": "modify_node", "id": 7, "node_type": "paragraph", "content": "Nope",
+            })))
+            .unwrap_err();
+        assert!(err.to_string().contains("out of bounds"));
+
+        let err = note
+            .apply_action(&action(json!({
+                "action": "insert_node", "insert_after": 0, "node_type": "video", "content": "Nope",
+            })))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Unsupported node type: video");
+    }
+
+    #[test]
+    fn test_apply_path_actions() {
+        let mut note = list_note();
+        note.apply_action(&action(json!({
+            "action": "insert_node", "insert_after": 0, "path": "1.0",
+            "node_type": "listitem", "content": "Between",
+        })))
+        .expect("Should insert into the list");
+        let item = note.get_by_path(&"1.1".parse().unwrap()).unwrap();
+        assert_eq!(note.render_node(item).1, "Between");
+        assert_eq!(note.render_node(note.get_by_path(&"1.2".parse().unwrap()).unwrap()).1, "Second");
+
+        note.apply_action(&action(json!({
+            "action": "modify_node", "id": 0, "path": "1.2",
+            "node_type": "listitem", "content": "Last",
+        })))
+        .expect("Should modify the list item");
+        assert_eq!(note.render_node(note.get_by_path(&"1.2".parse().unwrap()).unwrap()).1, "Last");
+        // The root node at the flat id is untouched
+        assert_eq!(texts(&note)[0], "Intro");
+
+        let err = note
+            .apply_action(&action(json!({
+                "action": "insert_node", "insert_after": 0, "path": "1.9",
+                "node_type": "listitem", "content": "Nope",
+            })))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Node path 1.9: segment 1 is out of bounds"));
+
+        let err = note
+            .apply_action(&action(json!({
+                "action": "modify_node", "id": 0, "path": "one.two",
+                "node_type": "listitem", "content": "Nope",
+            })))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Invalid node path"));
+    }
+
+    #[test]
+    fn test_apply_insert_into_empty_note() {
+        let mut note = note_from_children(json!([]));
+        note.apply_action(&action(json!({
+            "action": "insert_node", "insert_after": 0, "node_type": "text", "content": "Hello",
+        })))
+        .expect("Should insert into the empty note");
+        assert_eq!(texts(&note), vec!["Hello"]);
+        assert_eq!(note.lexical_state.root.children[0].type_name(), "paragraph");
+    }
+
+    #[test]
+    fn test_apply_insert_code_detects_language() {
+        let mut note = note_from_children(json!([paragraph_json("Intro")]));
+        note.apply_action(&action(json!({
+            "action": "insert_node", "insert_after": 0, "node_type": "code",
+            "content": "def add(a, b):\n    return a + b\n\nprint(add(1, 2))",
+        })))
+        .expect("Should insert the code block");
+        note.apply_action(&action(json!({
+            "action": "insert_node", "insert_after": 1, "node_type": "code", "content": "x = 1", "language": "ruby",
+        })))
+        .expect("Should insert the code block");
+
+        let languages: Vec<Option<&str>> = note.lexical_state.root.children[1..]
+            .iter()
+            .map(|node| match node {
+                LexicalNode::Code(code) => code.language.as_deref(),
+                _ => panic!("Should be a code block"),
+            })
+            .collect();
+        assert_eq!(languages, vec![Some("python"), Some("ruby")]);
+
+        // A fenced block, as shown in the briefs, is unwrapped
+        note.apply_action(&action(json!({
+            "action": "modify_node", "id": 2, "node_type": "code", "content": "````sql\nSELECT '```';\n````",
+        })))
+        .expect("Should modify the code block");
+        let LexicalNode::Code(code) = &note.lexical_state.root.children[2] else {
+            panic!("Should be a code block");
+        };
+        assert_eq!(code.language.as_deref(), Some("sql"));
+        assert_eq!(note.get_brief()[2].content, "````sql\nSELECT '```';\n````");
+    }
+
+    #[test]
+    fn test_apply_keeps_existing_keys() {
+        let json_content = std::fs::read_to_string("assets/example_keyed_note.json")
+            .expect("Should be able to read assets/example_keyed_note.json");
+        let mut note: Note = serde_json::from_str(&json_content).expect("Should be able to parse example keyed note");
+        let key = |note: &Note, index: usize| note.lexical_state.root.children[index].base().key.clone();
+        let heading_key = key(&note, 1);
+        assert!(heading_key.is_some());
+
+        note.apply_action(&action(json!({
+            "action": "insert_node", "insert_after": 0, "node_type": "paragraph", "content": "New",
+        })))
+        .expect("Should insert");
+        note.apply_action(&action(json!({
+            "action": "modify_node", "id": 2, "node_type": "h1", "content": "Renamed plan",
+        })))
+        .expect("Should modify");
+
+        // The new node is keyless so Lexical assigns one, the modified node keeps its key
+        assert_eq!(key(&note, 1), None);
+        assert_eq!(key(&note, 2), heading_key);
+        assert_eq!(texts(&note)[2], "Renamed plan");
+    }
+
+    #[test]
+    fn test_apply_delete_node() {
+        let mut note = note_from_children(json!([
+            paragraph_json("Keep"),
+            paragraph_json("Remove that paragraph"),
+            heading_json("Next", "h2"),
+        ]));
+
+        let delete = action(json!({ "action": "delete_node", "id": 1 }));
+        assert!(matches!(&delete, ChatAction::DeleteNode(DeleteNode { id: BriefId::Index(1), op_id: None, .. })));
+        note.apply_action(&delete).expect("Should delete the node");
+        let texts: Vec<String> = note.get_brief().into_iter().map(|brief| brief.content).collect();
+        assert_eq!(texts, vec!["Keep", "Next"]);
+
+        let err = note
+            .apply_action(&action(json!({ "action": "delete_node", "id": 2 })))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Node 2 is not in the note, which has 2 nodes");
+        assert_eq!(note.lexical_state.root.children.len(), 2);
+
+        // A missing id fails to parse
+        assert!(ChatAction::try_from_reply(json!({ "action": "delete_node" }).to_string()).is_err());
+    }
+
+    #[test]
+    fn test_apply_batch() {
+        let mut note = note_from_children(json!([heading_json("Plan", "h1"), paragraph_json("Intro")]));
+        let texts = |note: &Note| -> Vec<String> { note.get_brief().into_iter().map(|brief| brief.content).collect() };
+
+        let batch = action(json!({
+            "action": "batch",
+            "actions": [
+                { "action": "insert_node", "insert_after": 1, "node_type": "h2", "content": "Next steps" },
+                { "action": "insert_node", "insert_after": 2, "node_type": "paragraph", "content": "Book the venue" },
+            ],
+        }));
+        assert!(matches!(&batch, ChatAction::Batch(actions) if actions.len() == 2));
+        note.apply_action(&batch).expect("Should apply the batch");
+        assert_eq!(texts(&note), vec!["Plan", "Intro", "Next steps", "Book the venue"]);
+
+        // A failing action leaves the note as it was
+        let original = serde_json::to_value(&note).unwrap();
+        let actions = vec![
+            action(json!({ "action": "delete_node", "id": 0 })),
+            action(json!({ "action": "set_heading_level", "id": 0, "tag": "h3" })),
+        ];
+        let err = note.apply_all(&actions).unwrap_err();
+        assert!(err.to_string().starts_with("Action 1 of the batch failed, so none was applied: Node 0 is a paragraph node"), "{err}");
+        assert_eq!(serde_json::to_value(&note).unwrap(), original);
+    }
+
+    #[test]
+    fn test_parse_batch() {
+        // A bare array is a batch too
+        let reply = json!([
+            { "action": "delete_node", "id": 1 },
+            { "action": "reply", "content": "Done" },
+        ]);
+        let Ok(ChatAction::Batch(actions)) = ChatAction::try_from_reply(reply.to_string()) else {
+            panic!("Should parse the batch");
+        };
+        assert!(matches!(&actions[..], [ChatAction::DeleteNode(_), ChatAction::Reply(_)]));
+        // A batch serializes tagged like the other actions, and parses back
+        let serialized = serde_json::to_value(ChatAction::Batch(actions)).unwrap();
+        assert_eq!(serialized, json!({ "action": "batch", "actions": reply }));
+        for parsed in [
+            ChatAction::try_from_reply(serialized.to_string()).unwrap(),
+            serde_json::from_value(serialized.clone()).unwrap(),
+        ] {
+            assert!(matches!(&parsed, ChatAction::Batch(actions) if actions.len() == 2));
+        }
+
+        let parse_err = |reply: serde_json::Value| ChatAction::try_from_reply(reply.to_string()).unwrap_err().to_string();
+        assert_eq!(parse_err(json!([])), "A batch needs at least one action");
+        assert_eq!(parse_err(json!([{ "content": "Hi" }])), "Action 0 of the batch has no `action` field");
+        assert!(parse_err(json!([{ "action": "delete_node" }])).starts_with("Action 0 of the batch is invalid"));
+        assert!(parse_err(json!({ "action": "batch", "actions": [{ "action": "batch", "actions": [] }] })).contains("cannot be nested"));
+        let too_many = vec![json!({ "action": "delete_node", "id": 0 }); crate::agent::MAX_BATCH_ACTIONS + 1];
+        assert!(parse_err(json!(too_many)).starts_with("A batch has at most"));
+    }
+
+    #[test]
+    fn test_apply_set_title() {
+        let set_title = action(json!({ "action": "set_title", "content": "Project Phoenix" }));
+        assert!(set_title.changes_title());
+        assert!(ChatAction::Batch(vec![set_title.clone()]).changes_title());
+        assert!(!action(json!({ "action": "delete_node", "id": 0 })).changes_title());
+
+        // The first H1 or H2 gets the title
+        let mut note = note_from_children(json!([paragraph_json("Intro"), heading_json("Old name", "h2")]));
+        note.apply_action(&set_title).expect("Should rename the heading");
+        assert_eq!(note.lexical_state.root.children.len(), 2);
+        assert_eq!(note.title().as_deref(), Some("Project Phoenix"));
+        assert_eq!(note.get_brief()[1].content, "Project Phoenix");
+
+        // Without a heading, a new H1 goes first
+        let mut note = note_from_children(json!([paragraph_json("Intro"), heading_json("Details", "h3")]));
+        note.apply_action(&set_title).expect("Should insert a heading");
+        assert!(matches!(&note.lexical_state.root.children[0], LexicalNode::Heading(h) if h.tag == HeadingTag::H1));
+        assert_eq!(note.title().as_deref(), Some("Project Phoenix"));
+        assert_eq!(note.lexical_state.root.children.len(), 3);
+
+        let err = note.apply_action(&action(json!({ "action": "set_title", "content": "  " }))).unwrap_err();
+        assert!(err.to_string().starts_with("The title is empty"));
+    }
+
+    #[test]
+    fn test_apply_append_node() {
+        let append = action(json!({ "action": "append_node", "node_type": "paragraph", "content": "Summary" }));
+        assert!(matches!(&append, ChatAction::AppendNode(AppendNode { node_type, .. }) if node_type == "paragraph"));
+
+        let mut empty = note_from_children(json!([]));
+        empty.apply_action(&append).expect("Should append to an empty note");
+        assert_eq!(empty.get_brief()[0].content, "Summary");
+
+        // The node goes after the trailing empty paragraph, which the briefs don't show
+        let mut note = note_from_children(json!([heading_json("Plan", "h1"), paragraph_json("Body"), paragraph_json("")]));
+        note.apply_action(&append).expect("Should append to the note");
+        assert_eq!(note.lexical_state.root.children.len(), 4);
+        let briefs: Vec<(usize, String)> = note.get_brief().into_iter().map(|brief| (brief.id, brief.content)).collect();
+        assert_eq!(briefs, vec![(0, "Plan".to_string()), (1, "Body".to_string()), (3, "Summary".to_string())]);
+
+        let err = note
+            .apply_action(&action(json!({ "action": "append_node", "node_type": "widget", "content": "x" })))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Unsupported node type: widget");
+    }
+
+    #[test]
+    fn test_apply_insert_code_block() {
+        let mut note = note_from_children(json!([paragraph_json("Intro"), paragraph_json("Outro")]));
+
+        let insert = action(json!({
+            "action": "insert_code_block",
+            "insert_after": 0,
+            "language": "python",
+            "content": "print(\"hi\")\nprint(\"bye\")",
+        }));
+        assert!(matches!(&insert, ChatAction::InsertCodeBlock(InsertCodeBlock { language, .. }) if language == "python"));
+        note.apply_action(&insert).expect("Should insert the code block");
+
+        let LexicalNode::Code(code) = &note.lexical_state.root.children[1] else {
+            panic!("Should be a code block");
+        };
+        assert_eq!(code.language.as_deref(), Some("python"));
+        assert!(matches!(code.children.as_deref(), Some([LexicalNode::Text(text)]) if text.text == "print(\"hi\")\nprint(\"bye\")"));
+        let serialized = serde_json::to_value(&note.lexical_state.root.children[1]).unwrap();
+        assert_eq!(serialized["language"], "python");
+        assert_eq!(note.get_brief()[1].content, "```python\nprint(\"hi\")\nprint(\"bye\")\n```");
+
+        // The language is required
+        let missing = json!({ "action": "insert_code_block", "insert_after": 0, "content": "x = 1" });
+        assert!(ChatAction::try_from_reply(missing.to_string()).is_err());
+    }
+
+    #[test]
+    fn test_apply_insert_table() {
+        let mut note = note_from_children(json!([paragraph_json("Intro")]));
+
+        let insert = action(json!({
+            "action": "insert_table",
+            "insert_after": 0,
+            "rows": [["Name", "Role", "Team"], ["Ada", "Engineer"]],
+        }));
+        assert!(matches!(&insert, ChatAction::InsertTable(InsertTable { rows, .. }) if rows.len() == 2));
+        note.apply_action(&insert).expect("Should insert the table");
+
+        let LexicalNode::Table(table) = &note.lexical_state.root.children[1] else {
+            panic!("Should be a table");
+        };
+        assert_eq!(table.children.len(), 2);
+        let cells: Vec<(u32, u32, u32)> = table
+            .children
+            .iter()
+            .flat_map(|row| row.children().expect("Rows have cells"))
+            .map(|cell| match cell {
+                LexicalNode::TableCell(cell) => (cell.header_state, cell.col_span, cell.row_span),
+                _ => panic!("Should be a cell"),
+            })
+            .collect();
+        assert_eq!(cells, vec![(1, 1, 1), (1, 1, 1), (1, 1, 1), (0, 1, 1), (0, 1, 1), (0, 1, 1)]);
+        assert_eq!(
+            note.get_brief()[1].content,
+            "| Name | Role | Team |\n| --- | --- | --- |\n| Ada | Engineer |  |"
+        );
+
+        let err = note
+            .apply_action(&action(json!({ "action": "insert_table", "insert_after": 0, "rows": [[]] })))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("The table has no cells"), "{err}");
+    }
+
+    #[test]
+    fn test_apply_replace_range() {
+        let note = note_from_children(json!([
+            heading_json("Plan", "h1"),
+            paragraph_json("One"),
+            paragraph_json("Two"),
+            paragraph_json("Three"),
+            paragraph_json("End"),
+        ]));
+        let texts = |note: &Note| -> Vec<String> { note.get_brief().into_iter().map(|brief| brief.content).collect() };
+        let replace = |nodes: Vec<&str>| {
+            let nodes: Vec<_> = nodes.into_iter().map(|content| json!({ "node_type": "paragraph", "content": content })).collect();
+            action(json!({ "action": "replace_range", "start": 1, "end": 3, "nodes": nodes }))
+        };
+
+        let mut shrunk = note.clone();
+        shrunk.apply_action(&replace(vec!["All in one"])).expect("Should shrink the range");
+        assert_eq!(texts(&shrunk), vec!["Plan", "All in one", "End"]);
+
+        let mut grown = note.clone();
+        grown.apply_action(&replace(vec!["A", "B", "C", "D"])).expect("Should grow the range");
+        assert_eq!(texts(&grown), vec!["Plan", "A", "B", "C", "D", "End"]);
+
+        let mut same = note.clone();
+        let action_same = replace(vec!["1", "2", "3"]);
+        assert!(matches!(&action_same, ChatAction::ReplaceRange(ReplaceRange { nodes, .. }) if nodes.len() == 3));
+        same.apply_action(&action_same).expect("Should replace the range");
+        assert_eq!(texts(&same), vec!["Plan", "1", "2", "3", "End"]);
+
+        let mut heading = note.clone();
+        heading
+            .apply_action(&action(json!({
+                "action": "replace_range",
+                "start": 0,
+                "end": 0,
+                "nodes": [{ "node_type": "h2", "content": "Renamed" }],
+            })))
+            .expect("Should replace a single node");
+        assert!(matches!(&heading.lexical_state.root.children[0], LexicalNode::Heading(h) if h.tag == HeadingTag::H2));
+    }
+
+    #[test]
+    fn test_apply_replace_range_errors() {
+        let mut note = note_from_children(json!([paragraph_json("One"), paragraph_json("Two"), paragraph_json("Three")]));
+        let original = note.clone();
+        let replace = |start: usize, end: usize, nodes: serde_json::Value| {
+            action(json!({ "action": "replace_range", "start": start, "end": end, "nodes": nodes }))
+        };
+        let nodes = json!([{ "node_type": "paragraph", "content": "New" }]);
+
+        let err = note.apply_action(&replace(2, 1, nodes.clone())).unwrap_err();
+        assert_eq!(err.to_string(), "The end 1 is before the start 2");
+        let err = note.apply_action(&replace(1, 3, nodes)).unwrap_err();
+        assert_eq!(err.to_string(), "Node 3 is not in the note, which has 3 nodes");
+
+        // A bad node in the middle replaces nothing
+        let err = note
+            .apply_action(&replace(0, 1, json!([
+                { "node_type": "paragraph", "content": "Fine" },
+                { "node_type": "widget", "content": "Bad" },
+            ])))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("New node 1: "), "{err}");
+        assert_eq!(serde_json::to_value(&note).unwrap(), serde_json::to_value(&original).unwrap());
+
+        // The nodes are required
+        assert!(ChatAction::try_from_reply(json!({ "action": "replace_range", "start": 0, "end": 1 }).to_string()).is_err());
+    }
+
+    #[test]
+    fn test_apply_move_node() {
+        let mut note = note_from_children(json!([
+            heading_json("Report", "h1"),
+            paragraph_json("Summary"),
+            paragraph_json("Details"),
+            paragraph_json("Conclusion"),
+        ]));
+        let texts = |note: &Note| -> Vec<String> { note.get_brief().into_iter().map(|brief| brief.content).collect() };
+
+        let move_up = action(json!({ "action": "move_node", "id": 3, "to": 1 }));
+        assert!(matches!(&move_up, ChatAction::MoveNode(MoveNode { id: BriefId::Index(3), to: 1, .. })));
+        note.apply_action(&move_up).expect("Should move the node up");
+        assert_eq!(texts(&note), vec!["Report", "Conclusion", "Summary", "Details"]);
+
+        // The index counts the nodes after the moved one is taken out
+        note.apply_action(&action(json!({ "action": "move_node", "id": 1, "to": 3 })))
+            .expect("Should move the node down");
+        assert_eq!(texts(&note), vec!["Report", "Summary", "Details", "Conclusion"]);
+
+        let err = note
+            .apply_action(&action(json!({ "action": "move_node", "id": 0, "to": 4 })))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Node 0 cannot move to index 4, the last index is 3");
+        let err = note
+            .apply_action(&action(json!({ "action": "move_node", "id": 7, "to": 0 })))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Node 7 is not in the note, which has 4 nodes");
+        assert_eq!(texts(&note), vec!["Report", "Summary", "Details", "Conclusion"]);
+
+        // A missing or negative index fails to parse
+        assert!(ChatAction::try_from_reply(json!({ "action": "move_node", "id": 1 }).to_string()).is_err());
+        assert!(ChatAction::try_from_reply(json!({ "action": "move_node", "id": 1, "to": -1 }).to_string()).is_err());
+    }
+
+    #[test]
+    fn test_apply_set_heading_level() {
+        let mut note = note_from_children(json!([
+            paragraph_json("Intro"),
+            heading_json("Budget", "h1"),
+        ]));
+
+        let set_level = action(json!({ "action": "set_heading_level", "id": 1, "tag": "h2" }));
+        assert!(matches!(&set_level, ChatAction::SetHeadingLevel(SetHeadingLevel { id: BriefId::Index(1), tag: HeadingTag::H2, .. })));
+        note.apply_action(&set_level).expect("Should change the heading level");
+        let LexicalNode::Heading(heading) = &note.lexical_state.root.children[1] else {
+            panic!("Should still be a heading");
+        };
+        assert_eq!(heading.tag, HeadingTag::H2);
+
+        let err = note
+            .apply_action(&action(json!({ "action": "set_heading_level", "id": 0, "tag": "h2" })))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Node 0 is a paragraph node, not a heading."));
+
+        // Unknown tags fail to parse
+        let reply = json!({ "action": "set_heading_level", "id": 1, "tag": "h7" }).to_string();
+        assert!(ChatAction::try_from_reply(reply).is_err());
+    }
+
+    #[test]
+    fn test_apply_format_text() {
+        let mut note = list_note();
+        let text_formats = |note: &Note| {
+            let mut formats = Vec::new();
+            for item in note.lexical_state.root.children[1].children().unwrap() {
+                if let LexicalNode::Text(text) = &item.children().unwrap()[0] {
+                    formats.push(text.format);
+                }
+            }
+            formats
+        };
+
+        note.apply_action(&action(json!({ "action": "format_text", "id": 1, "format": ["bold", "italic"] })))
+            .expect("Should format the list");
+        assert_eq!(text_formats(&note), vec![3, 3]);
+
+        note.apply_action(&action(json!({
+            "action": "format_text", "id": 1, "format": ["bold"], "remove": true,
+        })))
+        .expect("Should remove the format");
+        assert_eq!(text_formats(&note), vec![2, 2]);
+
+        let err = note
+            .apply_action(&action(json!({ "action": "format_text", "id": 1, "format": ["sparkly"] })))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Unknown format: sparkly."));
+
+        let mut note = note_from_children(json!([{ "type": "page-break", "version": 1 }]));
+        let err = note
+            .apply_action(&action(json!({ "action": "format_text", "id": 0, "format": ["bold"] })))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Node 0 has no text to format");
+    }
+
+    #[test]
+    fn test_apply_convert_to_list() {
+        let mut note = note_from_children(json!([
+            heading_json("Plan", "h1"),
+            paragraph_json("First"),
+            paragraph_json("Second"),
+        ]));
+
+        note.apply_action(&action(json!({ "action": "convert_to_list", "id": 1, "list_type": "bullet" })))
+            .expect("Should convert the paragraph");
+        assert_eq!(note.lexical_state.root.children.len(), 3);
+        let LexicalNode::List(list) = &note.lexical_state.root.children[1] else {
+            panic!("Should be a list");
+        };
+        assert!(matches!(list.list_type, ListType::Bullet));
+        assert_eq!(list.tag, Some(ListTag::Ul));
+        assert_eq!(list.children.len(), 1);
+        let LexicalNode::ListItem(item) = &list.children[0] else {
+            panic!("Should be a list item");
+        };
+        assert_eq!(item.value, Some(1));
+        assert!(matches!(&item.children[..], [LexicalNode::Text(text)] if text.text == "First"));
+
+        // A range becomes one list with an item per paragraph
+        let mut note = note_from_children(json!([paragraph_json("One"), paragraph_json("Two")]));
+        note.apply_action(&action(json!({ "action": "convert_to_list", "id": 0, "end_id": 1, "list_type": "number" })))
+            .expect("Should convert the range");
+        assert_eq!(note.get_brief()[0].content, "1. One\n2. Two");
+
+        let err = note
+            .apply_action(&action(json!({ "action": "convert_to_list", "id": 0, "list_type": "bullet" })))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Node 0 is a list node. Only paragraphs can be converted to a list");
+
+        // The list type must be a known one
+        let reply = json!({ "action": "convert_to_list", "id": 0, "list_type": "checklist" });
+        assert!(ChatAction::try_from_reply(reply.to_string()).is_err());
+    }
+
+    #[test]
+    fn test_check_links_rejects_unsafe_urls() {
+        let paragraph = |url: &str| -> LexicalNode {
+            serde_json::from_value(json!({
+                "type": "paragraph",
+                "version": 1,
+                "children": [{ "type": "link", "version": 1, "url": url, "children": [text_json("here", 0)] }],
+            }))
+            .unwrap()
+        };
+
+        assert!(check_links(&paragraph("https://example.com")).is_ok());
+        let err = check_links(&paragraph("javascript:alert(1)")).unwrap_err();
+        assert!(err.to_string().starts_with("Unsafe link URL \"javascript:alert(1)\"."));
+    }
+
+    #[test]
+    fn test_apply_modify_keeps_format() {
+        let mut note = note_from_children(json!([{
+            "type": "paragraph",
+            "version": 1,
+            "children": [text_json("Bold", 1), text_json(" and plain", 0)],
+        }]));
+
+        note.apply_action(&action(json!({ "action": "modify_node", "id": 0, "node_type": "paragraph", "content": "Still bold" })))
+            .expect("Should modify the paragraph");
+        let children = note.lexical_state.root.children[0].children().unwrap();
+        assert!(matches!(&children[..], [LexicalNode::Text(text)] if text.text == "Still bold" && text.format == 1));
+    }
+
+    #[test]
+    fn test_apply_modify_keeps_indent_and_direction() {
+        let mut note = note_from_children(json!([{
+            "type": "paragraph",
+            "version": 1,
+            "direction": "rtl",
+            "format": "right",
+            "indent": 2,
+            "children": [text_json("مرحبا", 0)],
+        }]));
+
+        note.apply_action(&action(json!({ "action": "modify_node", "id": 0, "node_type": "paragraph", "content": "أهلا" })))
+            .expect("Should modify the paragraph");
+        let base = note.lexical_state.root.children[0].base();
+        assert_eq!(base.indent, Some(2));
+        assert_eq!(base.direction, Some(Some(TextDirection::RightToLeft)));
+        assert_eq!(base.format.as_deref(), Some("right"));
+        assert_eq!(note.get_brief()[0].content, "أهلا");
+    }
+
+    #[test]
+    fn test_apply_node_payload() {
+        let mut note = list_note();
+        let list = list_json("number", Some(3), vec![
+            list_item_json(vec![text_json("Third", 0)]),
+            list_item_json(vec![text_json("Fourth", 1)]),
+        ]);
+        note.apply_action(&action(json!({ "action": "insert_node", "insert_after": 0, "node": list })))
+            .expect("Should insert the list");
+        assert_eq!(texts(&note)[1], "3. Third\n4. Fourth");
+        let LexicalNode::List(list) = &note.lexical_state.root.children[1] else {
+            panic!("Should be a list");
+        };
+        assert_eq!(list.start, Some(3));
+
+        let link = json!({
+            "type": "paragraph",
+            "version": 1,
+            "children": [
+                text_json("See ", 0),
+                { "type": "link", "version": 1, "url": "https://example.com", "children": [text_json("the docs", 0)] },
+            ],
+        });
+        note.apply_action(&action(json!({ "action": "modify_node", "id": 0, "node": link })))
+            .expect("Should replace the paragraph");
+        let LexicalNode::Paragraph(paragraph) = &note.lexical_state.root.children[0] else {
+            panic!("Should be a paragraph");
+        };
+        assert!(matches!(&paragraph.children[1], LexicalNode::Link(link) if link.url == "https://example.com"));
+
+        // A node that parses but is not usable is rejected before the note changes
+        let before = serde_json::to_value(&note).unwrap();
+        let text = json!({ "action": "insert_node", "insert_after": 0, "node": text_json("Loose", 0) });
+        let err = note.apply_action(&action(text)).unwrap_err();
+        assert_eq!(err.to_string(), "A text node cannot be a root node, put it in a paragraph");
+        let unversioned = json!({ "type": "paragraph", "version": 0, "children": [] });
+        let err = note
+            .apply_action(&action(json!({ "action": "insert_node", "insert_after": 0, "node": unversioned })))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Invalid node: The paragraph node has no version");
+        let mut old_text = text_json("Old", 0);
+        old_text["version"] = json!(0);
+        let nested = json!({ "type": "paragraph", "version": 1, "children": [old_text] });
+        let err = note
+            .apply_action(&action(json!({ "action": "insert_node", "insert_after": 0, "node": nested })))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Invalid node: The text node has no version (at child 0)");
+        assert_eq!(serde_json::to_value(&note).unwrap(), before);
+    }
+
+    #[test]
+    fn test_apply_node_payload_warnings_and_provenance() {
+        let mut note = list_note();
+        let provenance = Provenance {
+            prompt: Some("Transcribe and summarize".to_string()),
+            session_id: Some("session-1".to_string()),
+        };
+
+        // A low-confidence transcript is only a warning
+        let voice = json!({ "type": "voice-input", "version": 1, "content": "grate numbers", "confidence": 0.4 });
+        note.apply_action_with_provenance(&action(json!({ "action": "insert_node", "insert_after": 0, "node": voice })), &provenance)
+            .expect("Should insert the voice input");
+        assert!(matches!(&note.lexical_state.root.children[1], LexicalNode::VoiceInput(_)));
+
+        let summary = json!({
+            "type": "ai-embedding", "version": 1, "content": "A summary", "isLoading": false, "sessionId": "session-0",
+        });
+        note.apply_action_with_provenance(&action(json!({ "action": "insert_node", "insert_after": 1, "node": summary })), &provenance)
+            .expect("Should insert the AI embedding");
+        let blocks = note.ai_blocks();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].prompt.as_deref(), Some("Transcribe and summarize"));
+        // What the node sets is kept
+        assert_eq!(blocks[0].session_id.as_deref(), Some("session-0"));
+        assert_eq!(blocks[0].model.as_deref(), Some("aimo-chat"));
+        assert!(blocks[0].created_at.is_some());
+    }
+
+    #[test]
+    fn test_apply_markdown_content() {
+        let mut note = list_note();
+        note.apply_action(&action(json!({
+            "action": "insert_node",
+            "insert_after": 0,
+            "content_format": "markdown",
+            "content": "## Shopping\n\n- Milk\n- Eggs\n  1. A dozen",
+        })))
+        .expect("Should insert the blocks");
+        let types: Vec<&str> = note.lexical_state.root.children.iter().map(LexicalNode::type_name).collect();
+        assert_eq!(types, vec!["paragraph", "heading", "list", "list"]);
+        let LexicalNode::Heading(heading) = &note.lexical_state.root.children[1] else {
+            panic!("Should be a heading");
+        };
+        assert_eq!(heading.tag, HeadingTag::H2);
+        let LexicalNode::List(list) = &note.lexical_state.root.children[2] else {
+            panic!("Should be a list");
+        };
+        assert!(matches!(list.list_type, ListType::Bullet));
+        assert!(matches!(&list.children[..], [_, _, LexicalNode::ListItem(item)]
+            if matches!(&item.children[..], [LexicalNode::List(nested)] if matches!(nested.list_type, ListType::Number))));
+        assert_eq!(texts(&note)[2], "• Milk\n• Eggs\n  1. A dozen");
+
+        // Modifying replaces the node with all the blocks
+        note.lexical_state.root.children[0].base_mut().key = Some("intro".to_string());
+        note.apply_action(&action(json!({
+            "action": "modify_node", "id": 0, "content_format": "markdown", "content": "# Plan\n\nBuy food",
+        })))
+        .expect("Should replace the node");
+        assert_eq!(texts(&note)[..3], ["Plan", "Buy food", "Shopping"]);
+        assert_eq!(note.lexical_state.root.children[0].base().key.as_deref(), Some("intro"));
+
+        let before = serde_json::to_value(&note).unwrap();
+        let err = note
+            .apply_action(&action(json!({ "action": "insert_node", "insert_after": 0, "content_format": "markdown", "content": "\n" })))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "The Markdown content has no blocks, give at least one");
+        let err = note
+            .apply_action(&action(json!({
+                "action": "modify_node", "id": 0, "path": "0.0", "content_format": "markdown", "content": "Text",
+            })))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Markdown content replaces a root node"), "{err}");
+        assert_eq!(serde_json::to_value(&note).unwrap(), before);
+    }
+
+    #[test]
+    fn test_parse_malformed_node_payload() {
+        let reply = json!({ "action": "insert_node", "insert_after": 0, "node": { "type": "heading", "version": 1 } });
+        let err = ChatAction::try_from_reply(reply.to_string()).unwrap_err();
+        assert!(err.to_string().starts_with("Invalid node JSON: missing field `tag`"), "{err}");
+
+        let reply = json!({ "action": "insert_node", "insert_after": 0, "content": "No type" });
+        let err = ChatAction::try_from_reply(reply.to_string()).unwrap_err();
+        assert_eq!(err.to_string(), "missing field `node_type`");
+    }
+
+    #[test]
+    fn test_apply_records_ai_provenance() {
+        let mut note = note_from_children(json!([paragraph_json("Intro")]));
+        let provenance = Provenance {
+            prompt: Some("Summarize the intro".to_string()),
+            session_id: Some("session-1".to_string()),
+        };
+
+        let insert = action(json!({ "action": "insert_node", "insert_after": 0, "node_type": "ai-embedding", "content": "A summary" }));
+        note.apply_action_with_provenance(&insert, &provenance).expect("Should insert the AI embedding");
+        let insert = action(json!({ "action": "insert_node", "insert_after": 1, "node_type": "ai-embedding", "content": "More" }));
+        note.apply_action(&insert).expect("Should insert without provenance");
+
+        let blocks = note.ai_blocks();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].content, "A summary");
+        assert_eq!(blocks[0].prompt.as_deref(), Some("Summarize the intro"));
+        assert_eq!(blocks[0].session_id.as_deref(), Some("session-1"));
+        // The model and time are recorded even without a provenance
+        assert_eq!(blocks[1].model.as_deref(), Some("aimo-chat"));
+        assert!(blocks[1].prompt.is_none());
+        let created_at = blocks[1].created_at.as_deref().expect("Should record the creation time");
+        assert!(chrono::DateTime::parse_from_rfc3339(created_at).is_ok());
+
+        let serialized = serde_json::to_value(&note.lexical_state.root.children[1]).unwrap();
+        assert_eq!(serialized["prompt"], "Summarize the intro");
+        assert_eq!(serialized["sessionId"], "session-1");
+    }
+
+    #[test]
+    fn test_apply_is_idempotent_with_op_id() {
+        let mut note = note_from_children(json!([paragraph_json("Intro")]));
+        let insert = action(json!({
+            "action": "insert_node", "insert_after": 0, "node_type": "paragraph", "content": "Once", "op_id": "op-1",
+        }));
+
+        note.apply_action(&insert).expect("Should insert the paragraph");
+        note.apply_action(&insert).expect("Should ignore the retry");
+        assert_eq!(note.lexical_state.root.children.len(), 2);
+
+        // The applied ids survive a round trip through JSON
+        let mut note: Note = serde_json::from_value(serde_json::to_value(&note).unwrap()).unwrap();
+        note.apply_action(&insert).expect("Should ignore the retry");
+        assert_eq!(note.lexical_state.root.children.len(), 2);
+
+        // Actions without an id are applied every time
+        let insert = action(json!({ "action": "insert_node", "insert_after": 0, "node_type": "paragraph", "content": "Twice" }));
+        note.apply_action(&insert).unwrap();
+        note.apply_action(&insert).unwrap();
+        assert_eq!(note.lexical_state.root.children.len(), 4);
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{LexicalNode, Note};
+
+/// The prefix of brief ids derived from the node content.
+const HASH_PREFIX: &str = "h:";
+
+/// The id of a brief node.
+///
+/// A root index is only valid until the next insertion or deletion. A key is the
+/// Lexical node key, or a hash of the node content prefixed with "h:" for notes
+/// without keys, so it still identifies the node after other nodes are inserted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BriefId {
+    Index(usize),
+    Key(String),
+}
+
+impl From<usize> for BriefId {
+    fn from(index: usize) -> Self {
+        Self::Index(index)
+    }
+}
+
+impl std::fmt::Display for BriefId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "{index}"),
+            Self::Key(key) => write!(f, "\"{key}\""),
+        }
+    }
+}
+
+/// A brief node identified by a key that survives edits.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyedBriefNode {
+    pub id: BriefId,
+    pub node_type: String,
+    pub content: String,
+}
+
+impl Note {
+    /// Get the briefs of the note with stable ids.
+    ///
+    /// Nodes with a Lexical key are identified by it, the others by a hash of their
+    /// type and text. Identical nodes without keys share an id, which resolves to the first.
+    pub fn get_brief_keyed(&self) -> Vec<KeyedBriefNode> {
+        self.get_brief()
+            .into_iter()
+            .map(|brief| KeyedBriefNode {
+                id: self.stable_id(brief.id),
+                node_type: brief.node_type,
+                content: brief.content,
+            })
+            .collect()
+    }
+
+    /// Map the ids of the briefs to the Lexical keys of their nodes.
+    ///
+    /// The editor applies the agent's actions by key, so this translates the positional
+    /// ids the agent uses. Every root node is mapped, including the empty nodes the agent
+    /// sees in the prompt but the default briefs skip. Nodes without a key are left out.
+    pub fn brief_keys(&self) -> BTreeMap<usize, String> {
+        self.lexical_state
+            .root
+            .children
+            .iter()
+            .enumerate()
+            .filter_map(|(index, node)| Some((index, node.base().key.clone()?)))
+            .collect()
+    }
+
+    /// Get the current root index of the node with the given brief id.
+    pub fn resolve_brief_id(&self, id: &BriefId) -> Option<usize> {
+        let children = &self.lexical_state.root.children;
+        match id {
+            BriefId::Index(index) => (*index < children.len()).then_some(*index),
+            BriefId::Key(key) => match key.strip_prefix(HASH_PREFIX) {
+                Some(hash) => children.iter().position(|node| self.content_hash_of(node) == hash),
+                None => children
+                    .iter()
+                    .position(|node| node.base().key.as_deref() == Some(key.as_str())),
+            },
+        }
+    }
+
+    /// Get the stable id of the root node at the index
+    fn stable_id(&self, index: usize) -> BriefId {
+        let node = &self.lexical_state.root.children[index];
+        match &node.base().key {
+            Some(key) => BriefId::Key(key.clone()),
+            None => BriefId::Key(format!("{HASH_PREFIX}{}", self.content_hash_of(node))),
+        }
+    }
+
+    /// Hash the type and text of a node with 64-bit FNV-1a, which is stable across builds
+    fn content_hash_of(&self, node: &LexicalNode) -> String {
+        let (node_type, text) = self.render_node(node);
+        let hash = fnv1a(FNV_OFFSET, node_type.bytes().chain([0]).chain(text.bytes()));
+        format!("{hash:016x}")
+    }
+}
+
+/// The initial hash of 64-bit FNV-1a.
+pub(super) const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Continue a 64-bit FNV-1a hash with the bytes.
+pub(super) fn fnv1a(mut hash: u64, bytes: impl IntoIterator<Item = u8>) -> u64 {
+    for byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+
+    use super::super::tests::{heading_json, note_from_children, paragraph_json};
+    use super::*;
+    use crate::agent::ChatAction;
+
+    fn action(value: serde_json::Value) -> ChatAction {
+        ChatAction::try_from_reply(value.to_string()).expect("Should parse the action")
+    }
+
+    #[test]
+    fn test_keyed_id_survives_insertion() {
+        let mut note = note_from_children(json!([
+            heading_json("Plan", "h1"),
+            paragraph_json("First"),
+            paragraph_json("Second"),
+        ]));
+        let id = note.get_brief_keyed()[2].id.clone();
+        assert!(matches!(&id, BriefId::Key(key) if key.starts_with("h:")));
+
+        note.apply_action(&action(json!({ "action": "insert_node", "insert_after": 0, "node_type": "paragraph", "content": "New" })))
+            .expect("Should insert the paragraph");
+        assert_eq!(note.resolve_brief_id(&id), Some(3));
+
+        // Actions accept the keyed id in place of the index
+        note.apply_action(&action(json!({ "action": "modify_node", "id": id, "node_type": "paragraph", "content": "Second, revised" })))
+            .expect("Should modify the paragraph");
+        assert_eq!(note.get_brief()[3].content, "Second, revised");
+        assert_eq!(note.resolve_brief_id(&id), None);
+    }
+
+    #[test]
+    fn test_keyed_id_uses_lexical_key() {
+        let json_content = fs::read_to_string("assets/example_keyed_note.json")
+            .expect("Should be able to read assets/example_keyed_note.json");
+        let mut note: Note = serde_json::from_str(&json_content).expect("Should be able to parse example keyed note");
+
+        let brief = &note.get_brief_keyed()[1];
+        let BriefId::Key(key) = &brief.id else {
+            panic!("Should be keyed");
+        };
+        assert_eq!(note.lexical_state.root.children[1].base().key.as_deref(), Some(key.as_str()));
+
+        let id = brief.id.clone();
+        note.lexical_state.root.children.insert(0, note.lexical_state.root.children[0].clone());
+        note.lexical_state.root.children[0].base_mut().key = None;
+        assert_eq!(note.resolve_brief_id(&id), Some(2));
+
+        // Indices still resolve while they are in the note
+        assert_eq!(note.resolve_brief_id(&BriefId::Index(2)), Some(2));
+        assert_eq!(note.resolve_brief_id(&BriefId::Index(100)), None);
+        assert_eq!(note.resolve_brief_id(&BriefId::Key("missing".to_string())), None);
+    }
+
+    #[test]
+    fn test_brief_keys() {
+        let json_content = fs::read_to_string("assets/example_keyed_note.json")
+            .expect("Should be able to read assets/example_keyed_note.json");
+        let mut note: Note = serde_json::from_str(&json_content).expect("Should be able to parse example keyed note");
+
+        let keys = note.brief_keys();
+        assert_eq!(keys.len(), note.lexical_state.root.children.len());
+        for (id, key) in &keys {
+            assert_eq!(note.lexical_state.root.children[*id].base().key.as_ref(), Some(key));
+        }
+        assert_eq!(keys.get(&1).map(String::as_str), Some("4"));
+
+        // A node without a key has no entry
+        note.lexical_state.root.children[1].base_mut().key = None;
+        let keys = note.brief_keys();
+        assert!(!keys.contains_key(&1));
+        assert_eq!(keys.get(&2).map(String::as_str), Some("6"));
+
+        // An empty node has no default brief, but the agent may still target it
+        let LexicalNode::Paragraph(paragraph) = &mut note.lexical_state.root.children[2] else {
+            panic!("Expected a paragraph");
+        };
+        paragraph.children.clear();
+        assert!(note.get_brief().iter().all(|brief| brief.id != 2));
+        assert_eq!(note.brief_keys().get(&2).map(String::as_str), Some("6"));
+    }
+}
@@ -0,0 +1,76 @@
+use anyhow::anyhow;
+
+use super::Note;
+
+/// The version of the binary note format, the first byte of every encoded note.
+pub const BYTES_FORMAT_VERSION: u8 = 1;
+
+impl Note {
+    /// Encode the note in a compact binary form, to cache it instead of its JSON.
+    ///
+    /// The form is a format version byte followed by the note as MessagePack, with
+    /// named fields so that it describes itself like the JSON. Decoding it with
+    /// [`Note::from_bytes`] gives back the same note, unknown fields included.
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let mut bytes = vec![BYTES_FORMAT_VERSION];
+        rmp_serde::encode::write_named(&mut bytes, self)?;
+        Ok(bytes)
+    }
+
+    /// Decode a note encoded by [`Note::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Note> {
+        match bytes.split_first() {
+            Some((&BYTES_FORMAT_VERSION, note)) => Ok(rmp_serde::from_slice(note)?),
+            Some((version, _)) => Err(anyhow!("Unknown binary note format version {}", version)),
+            None => Err(anyhow!("The binary note is empty")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_bytes_round_trip() {
+        for fixture in [
+            "assets/example_note.json",
+            "assets/example_outline_note.json",
+            "assets/example_chat_note.json",
+            "assets/example_code_note.json",
+            "assets/example_table_note.json",
+            "assets/example_list_note.json",
+            "assets/example_keyed_note.json",
+            "assets/example_cruft_note.json",
+        ] {
+            let json_content = fs::read_to_string(fixture).expect("Should be able to read the fixture");
+            let note = Note::from_json(&json_content).expect("Should be able to parse the fixture");
+
+            let bytes = note.to_bytes().expect("Should encode the note");
+            assert_eq!(bytes[0], BYTES_FORMAT_VERSION);
+            assert!(bytes.len() < json_content.len(), "{fixture} should be smaller than its JSON");
+            let decoded = Note::from_bytes(&bytes).expect("Should decode the note");
+            assert_eq!(
+                serde_json::to_value(&decoded).unwrap(),
+                serde_json::to_value(&note).unwrap(),
+                "{fixture} should round-trip exactly"
+            );
+        }
+    }
+
+    #[test]
+    fn test_bytes_errors() {
+        let mut bytes = Note::from_json(&fs::read_to_string("assets/example_note.json").unwrap())
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+
+        assert_eq!(Note::from_bytes(&[]).unwrap_err().to_string(), "The binary note is empty");
+        bytes[0] = 2;
+        assert_eq!(Note::from_bytes(&bytes).unwrap_err().to_string(), "Unknown binary note format version 2");
+        bytes[0] = BYTES_FORMAT_VERSION;
+        assert!(Note::from_bytes(&bytes[..bytes.len() / 2]).is_err());
+    }
+}
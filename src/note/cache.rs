@@ -0,0 +1,306 @@
+use super::{BriefNode, LexicalNode, NodePath, Note, PathError, Provenance};
+use crate::agent::ChatAction;
+
+/// A note that remembers the briefs and plain text of its root nodes between turns.
+///
+/// Changes made through [`CachedNote::apply_action`] and [`CachedNote::insert_at_path`]
+/// only recompute the root nodes they touch. Changes through [`CachedNote::note_mut`]
+/// recompute everything.
+#[derive(Debug, Clone)]
+pub struct CachedNote {
+    note: Note,
+    /// The rendered type and text of each root node, `None` until computed
+    rendered: Vec<Option<(&'static str, String)>>,
+    /// The briefs assembled from the renders, `None` after any change
+    briefs: Option<Vec<BriefNode>>,
+}
+
+/// The root nodes an action changes.
+enum Touched {
+    /// No root node changes
+    Nothing,
+    /// The root node at the index changes in place
+    Root(usize),
+    /// A root node is inserted at the index
+    Inserted(usize),
+    /// The root node at the index is removed
+    Removed(usize),
+    /// The root node at `from` moves to `to`
+    Moved { from: usize, to: usize },
+    /// Any root node may change
+    All,
+}
+
+impl From<Note> for CachedNote {
+    fn from(note: Note) -> Self {
+        Self::new(note)
+    }
+}
+
+impl CachedNote {
+    /// Wrap a note, with nothing computed yet.
+    pub fn new(note: Note) -> Self {
+        let rendered = vec![None; note.lexical_state.root.children.len()];
+        Self {
+            note,
+            rendered,
+            briefs: None,
+        }
+    }
+
+    /// Get the note.
+    pub fn note(&self) -> &Note {
+        &self.note
+    }
+
+    /// Get the note for a direct change, which clears the cache.
+    pub fn note_mut(&mut self) -> &mut Note {
+        self.invalidate_all();
+        &mut self.note
+    }
+
+    /// Unwrap the note.
+    pub fn into_note(self) -> Note {
+        self.note
+    }
+
+    /// Whether the brief of the root node at the index is computed.
+    pub fn is_cached(&self, index: usize) -> bool {
+        self.rendered.get(index).is_some_and(Option::is_some)
+    }
+
+    /// Get the briefs of the note, the same as [`Note::get_brief`].
+    ///
+    /// The briefs of an unchanged note are returned without any work.
+    pub fn get_brief(&mut self) -> &[BriefNode] {
+        if self.briefs.is_none() {
+            self.refresh();
+            self.briefs = Some(self.assemble_briefs());
+        }
+        self.briefs.as_deref().expect("Should be assembled")
+    }
+
+    /// Build the briefs from the refreshed renders
+    fn assemble_briefs(&self) -> Vec<BriefNode> {
+        self.rendered
+            .iter()
+            .enumerate()
+            .filter_map(|(id, rendered)| {
+                let (node_type, content) = rendered.as_ref().expect("Should be refreshed");
+                (!content.trim().is_empty()).then(|| BriefNode {
+                    id,
+                    node_type: node_type.to_string(),
+                    content: content.clone(),
+                    section: None,
+                })
+            })
+            .collect()
+    }
+
+    /// Get the plain text of the note, the same as [`Note::to_plain_text`].
+    pub fn to_plain_text(&mut self) -> String {
+        self.refresh();
+        self.rendered
+            .iter()
+            .map(|rendered| rendered.as_ref().expect("Should be refreshed").1.as_str())
+            .filter(|content| !content.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Apply an agent action like [`Note::apply_action`], recomputing only the root nodes it changes.
+    pub fn apply_action(&mut self, action: &ChatAction) -> anyhow::Result<()> {
+        self.apply_action_with_provenance(action, &Provenance::default())
+    }
+
+    /// Apply an agent action like [`Note::apply_action_with_provenance`], recomputing only the root nodes it changes.
+    pub fn apply_action_with_provenance(&mut self, action: &ChatAction, provenance: &Provenance) -> anyhow::Result<()> {
+        let touched = self.touched_by(action);
+        let result = self.note.apply_action_with_provenance(action, provenance);
+        if result.is_ok() {
+            self.invalidate(touched);
+        }
+        result
+    }
+
+    /// Insert a node like [`Note::insert_at_path`], recomputing only the root node it changes.
+    pub fn insert_at_path(&mut self, path: &NodePath, node: LexicalNode) -> Result<(), PathError> {
+        self.note.insert_at_path(path, node)?;
+        self.invalidate(match path.0[..] {
+            [index] => Touched::Inserted(index),
+            _ => Touched::Root(path.0[0]),
+        });
+        Ok(())
+    }
+
+    /// Find the root nodes an action will change, before it is applied
+    fn touched_by(&self, action: &ChatAction) -> Touched {
+        let root_of = |path: &Option<String>, id| match path {
+            Some(path) => path.parse::<NodePath>().ok().map(|path| path.0[0]),
+            None => self.note.brief_index(id).ok(),
+        };
+        let touched = match action {
+            ChatAction::Reply(_) => Some(Touched::Nothing),
+            // Markdown content may change the number of root nodes
+            ChatAction::ModifyNode(modify) if modify.is_markdown() => None,
+            ChatAction::InsertNode(insert) if insert.is_markdown() => None,
+            ChatAction::ModifyNode(modify) => root_of(&modify.path, &modify.id).map(Touched::Root),
+            ChatAction::SetHeadingLevel(set_level) => self.note.brief_index(&set_level.id).ok().map(Touched::Root),
+            ChatAction::FormatText(format) => self.note.brief_index(&format.id).ok().map(Touched::Root),
+            ChatAction::InsertNode(insert) => match &insert.path {
+                Some(path) => path.parse::<NodePath>().ok().map(|path| match path.0[..] {
+                    [index] => Touched::Inserted(index + 1),
+                    _ => Touched::Root(path.0[0]),
+                }),
+                None => self.note.insert_index(&insert.insert_after).ok().map(Touched::Inserted),
+            },
+            ChatAction::SetTitle(_) => Some(match self.note.title_heading_index() {
+                Some(index) => Touched::Root(index),
+                None => Touched::Inserted(0),
+            }),
+            ChatAction::AppendNode(_) => Some(Touched::Inserted(self.note.lexical_state.root.children.len())),
+            ChatAction::InsertCodeBlock(insert) => self.note.insert_index(&insert.insert_after).ok().map(Touched::Inserted),
+            ChatAction::InsertTable(insert) => self.note.insert_index(&insert.insert_after).ok().map(Touched::Inserted),
+            ChatAction::DeleteNode(delete) => self.note.brief_index(&delete.id).ok().map(Touched::Removed),
+            ChatAction::MoveNode(move_node) => {
+                let to = move_node.to;
+                self.note.brief_index(&move_node.id).ok().map(|from| Touched::Moved { from, to })
+            }
+            ChatAction::ConvertToList(_) | ChatAction::ReplaceRange(_) | ChatAction::Batch(_) => None,
+        };
+        touched.unwrap_or(Touched::All)
+    }
+
+    /// Drop the computed renders the change made stale
+    fn invalidate(&mut self, touched: Touched) {
+        if !matches!(touched, Touched::Nothing) {
+            self.briefs = None;
+        }
+        match touched {
+            Touched::Root(index) => {
+                if let Some(rendered) = self.rendered.get_mut(index) {
+                    *rendered = None;
+                }
+            }
+            Touched::Inserted(index) if index <= self.rendered.len() => self.rendered.insert(index, None),
+            Touched::Removed(index) if index < self.rendered.len() => {
+                self.rendered.remove(index);
+            }
+            Touched::Moved { from, to } if from.max(to) < self.rendered.len() => {
+                let rendered = self.rendered.remove(from);
+                self.rendered.insert(to, rendered);
+            }
+            Touched::Nothing => {}
+            _ => self.invalidate_all(),
+        }
+        // A change the guess missed, such as a skipped retry, must not leave renders misaligned
+        if self.rendered.len() != self.note.lexical_state.root.children.len() {
+            self.invalidate_all();
+        }
+    }
+
+    fn invalidate_all(&mut self) {
+        self.briefs = None;
+        self.rendered = vec![None; self.note.lexical_state.root.children.len()];
+    }
+
+    /// Render the root nodes without a computed render
+    fn refresh(&mut self) {
+        if self.rendered.len() != self.note.lexical_state.root.children.len() {
+            self.invalidate_all();
+        }
+        for (node, rendered) in self.note.lexical_state.root.children.iter().zip(&mut self.rendered) {
+            if rendered.is_none() {
+                *rendered = Some(self.note.render_node(node));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::note::TextNode;
+
+    fn example() -> Note {
+        let json_content = fs::read_to_string("assets/example_note.json")
+            .expect("Should be able to read assets/example_note.json");
+        Note::from_json(&json_content).expect("Should be able to parse example note JSON")
+    }
+
+    fn briefs(briefs: &[BriefNode]) -> Vec<(usize, &str, &str)> {
+        briefs.iter().map(|brief| (brief.id, brief.node_type.as_str(), brief.content.as_str())).collect()
+    }
+
+    #[test]
+    fn test_cached_brief_matches_note() {
+        let note = example();
+        let mut cached = CachedNote::new(note.clone());
+        assert!(!cached.is_cached(0));
+        assert_eq!(briefs(cached.get_brief()), briefs(&note.get_brief()));
+        assert_eq!(cached.to_plain_text(), note.to_plain_text());
+        assert!((0..note.lexical_state.root.children.len()).all(|index| cached.is_cached(index)));
+    }
+
+    #[test]
+    fn test_modify_recomputes_only_the_node() {
+        let mut cached = CachedNote::new(example());
+        cached.get_brief();
+        let count = cached.note().lexical_state.root.children.len();
+
+        let action = ChatAction::try_from_reply(
+            r#"{"action": "modify_node", "id": 1, "node_type": "paragraph", "content": "Changed"}"#.to_string(),
+        )
+        .unwrap();
+        cached.apply_action(&action).unwrap();
+        let stale: Vec<usize> = (0..count).filter(|&index| !cached.is_cached(index)).collect();
+        assert_eq!(stale, vec![1]);
+
+        let expected = cached.note().get_brief();
+        let brief = cached.get_brief();
+        assert_eq!(brief.iter().find(|brief| brief.id == 1).unwrap().content, "Changed");
+        assert_eq!(briefs(brief), briefs(&expected));
+    }
+
+    #[test]
+    fn test_insert_shifts_the_cache() {
+        let mut cached = CachedNote::new(example());
+        cached.get_brief();
+        let count = cached.note().lexical_state.root.children.len();
+
+        let action = ChatAction::try_from_reply(
+            r#"{"action": "insert_node", "insert_after": 0, "node_type": "paragraph", "content": "New"}"#.to_string(),
+        )
+        .unwrap();
+        cached.apply_action(&action).unwrap();
+        let stale: Vec<usize> = (0..=count).filter(|&index| !cached.is_cached(index)).collect();
+        assert_eq!(stale, vec![1]);
+        let expected = cached.note().get_brief();
+        assert_eq!(briefs(cached.get_brief()), briefs(&expected));
+
+        let node = LexicalNode::Text(TextNode::new("Nested"));
+        cached.insert_at_path(&"2.0".parse().unwrap(), node).unwrap();
+        assert!(!cached.is_cached(2) && cached.is_cached(3));
+        assert_eq!(cached.to_plain_text(), cached.note().to_plain_text());
+
+        let delete = ChatAction::try_from_reply(r#"{"action": "delete_node", "id": 0}"#.to_string()).unwrap();
+        cached.apply_action(&delete).unwrap();
+        // The other renders only shift
+        let count = cached.note().lexical_state.root.children.len();
+        assert!((0..count).all(|index| cached.is_cached(index)));
+        let expected = cached.note().get_brief();
+        assert_eq!(briefs(cached.get_brief()), briefs(&expected));
+
+        let last = cached.note().lexical_state.root.children.len() - 1;
+        let move_node = ChatAction::try_from_reply(format!(r#"{{"action": "move_node", "id": {last}, "to": 0}}"#)).unwrap();
+        cached.apply_action(&move_node).unwrap();
+        assert!((0..=last).all(|index| cached.is_cached(index)));
+        let expected = cached.note().get_brief();
+        assert_eq!(briefs(cached.get_brief()), briefs(&expected));
+
+        cached.note_mut().lexical_state.root.children.clear();
+        assert!(cached.get_brief().is_empty());
+    }
+}
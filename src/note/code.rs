@@ -0,0 +1,163 @@
+/// Keywords hinting at a language, with their weights.
+const RUST_HINTS: &[(&str, usize)] = &[
+    ("fn ", 2),
+    ("let mut ", 3),
+    ("impl ", 3),
+    ("pub fn ", 3),
+    ("use std", 3),
+    ("println!", 3),
+    ("::", 1),
+    ("-> ", 1),
+    ("&str", 2),
+    ("&self", 3),
+    ("match ", 1),
+];
+const PYTHON_HINTS: &[(&str, usize)] = &[
+    ("def ", 3),
+    ("elif ", 3),
+    ("import ", 1),
+    ("self.", 1),
+    ("print(", 2),
+    ("None", 1),
+    ("True", 1),
+    ("False", 1),
+    ("__init__", 3),
+    ("lambda ", 2),
+];
+const JAVASCRIPT_HINTS: &[(&str, usize)] = &[
+    ("function ", 3),
+    ("const ", 2),
+    ("=> ", 1),
+    ("console.log", 3),
+    ("var ", 2),
+    ("===", 3),
+    ("require(", 3),
+    ("export ", 2),
+    ("null", 1),
+    ("undefined", 3),
+];
+
+/// Guess the language of a code snippet, among "rust", "python", "javascript" and "json".
+///
+/// A shebang or a snippet that parses as JSON decides on its own, otherwise the
+/// language with the most keyword hints wins. Returns `None` without hints or on a tie.
+pub fn detect_language(code: &str) -> Option<String> {
+    let code = code.trim();
+    if code.is_empty() {
+        return None;
+    }
+
+    if let Some(shebang) = code.lines().next().and_then(|line| line.strip_prefix("#!")) {
+        if shebang.contains("python") {
+            return Some("python".to_string());
+        }
+        if shebang.contains("node") {
+            return Some("javascript".to_string());
+        }
+    }
+
+    if (code.starts_with('{') || code.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(code).is_ok()
+    {
+        return Some("json".to_string());
+    }
+
+    let score = |hints: &[(&str, usize)]| -> usize {
+        hints
+            .iter()
+            .map(|(hint, weight)| code.matches(hint).count() * weight)
+            .sum()
+    };
+    let mut scores = [
+        ("rust", score(RUST_HINTS)),
+        ("python", score(PYTHON_HINTS)),
+        ("javascript", score(JAVASCRIPT_HINTS)),
+    ];
+    scores.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+
+    match scores {
+        [(_, 0), ..] => None,
+        [(_, best), (_, second), _] if best == second => None,
+        [(language, _), ..] => Some(language.to_string()),
+    }
+}
+
+/// The length of the longest run of backticks in the text
+fn longest_backtick_run(text: &str) -> usize {
+    text.split(|c| c != '`').map(str::len).max().unwrap_or(0)
+}
+
+/// Wrap a code block in a fence with its language.
+///
+/// The fence is longer than any backtick run in the code, and at least three backticks.
+pub(super) fn fence_code(code: &str, language: Option<&str>) -> String {
+    let fence = "`".repeat((longest_backtick_run(code) + 1).max(3));
+    format!("{fence}{}\n{code}\n{fence}", language.unwrap_or_default())
+}
+
+/// Wrap inline code in backticks, more than any backtick run in the code.
+pub(super) fn inline_code(code: &str) -> String {
+    let ticks = "`".repeat(longest_backtick_run(code) + 1);
+    // Padding keeps backticks at the ends apart from the delimiters
+    if code.starts_with('`') || code.ends_with('`') {
+        format!("{ticks} {code} {ticks}")
+    } else {
+        format!("{ticks}{code}{ticks}")
+    }
+}
+
+/// Split a fenced code block into its language and code, `None` if the text is not fenced.
+pub(super) fn unfence_code(text: &str) -> Option<(Option<String>, String)> {
+    let text = text.trim();
+    let (first, rest) = text.split_once('\n')?;
+    let ticks = first.len() - first.trim_start_matches('`').len();
+    if ticks < 3 {
+        return None;
+    }
+
+    let (code, last) = rest.rsplit_once('\n').unwrap_or(("", rest));
+    if last.trim() != &first[..ticks] {
+        return None;
+    }
+    let language = first[ticks..].trim();
+    Some(((!language.is_empty()).then(|| language.to_string()), code.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_language() {
+        let rust = "fn main() {\n    let mut names: Vec<&str> = Vec::new();\n    println!(\"{names:?}\");\n}";
+        assert_eq!(detect_language(rust).as_deref(), Some("rust"));
+
+        let python = "def greet(name):\n    if name is None:\n        return\n    print(f\"Hello, {name}\")";
+        assert_eq!(detect_language(python).as_deref(), Some("python"));
+
+        let javascript = "const greet = (name) => {\n  console.log(`Hello, ${name}`);\n};";
+        assert_eq!(detect_language(javascript).as_deref(), Some("javascript"));
+
+        assert_eq!(detect_language("{\"name\": \"Ada\", \"tags\": [1, 2]}").as_deref(), Some("json"));
+        assert_eq!(detect_language("#!/usr/bin/env python3\nx = 1").as_deref(), Some("python"));
+        assert_eq!(detect_language("#!/usr/bin/env node\nx = 1").as_deref(), Some("javascript"));
+
+        // Prose and empty snippets have no language
+        assert_eq!(detect_language("Remember to buy milk"), None);
+        assert_eq!(detect_language("  \n"), None);
+    }
+
+    #[test]
+    fn test_fences() {
+        assert_eq!(fence_code("x = 1", Some("python")), "```python\nx = 1\n```");
+        assert_eq!(fence_code("echo ```", None), "````\necho ```\n````");
+        assert_eq!(inline_code("a + b"), "`a + b`");
+        assert_eq!(inline_code("`tick`"), "`` `tick` ``");
+
+        let fenced = fence_code("let s = \"```\";\nlet t = 1;", Some("rust"));
+        assert_eq!(unfence_code(&fenced), Some((Some("rust".to_string()), "let s = \"```\";\nlet t = 1;".to_string())));
+        assert_eq!(unfence_code("```\n```"), Some((None, String::new())));
+        assert_eq!(unfence_code("x = 1"), None);
+        assert_eq!(unfence_code("````\nx = 1\n```"), None);
+    }
+}
@@ -0,0 +1,172 @@
+use super::BriefNode;
+
+impl BriefNode {
+    /// Serialize briefs to the compact line format shown to the model.
+    ///
+    /// Each brief is one line, `id|node_type|content`. In the content, a backslash is
+    /// written `\\`, a newline `\n` and a carriage return `\r`, so a brief never spans
+    /// lines. The content is last, so it may contain `|` as is.
+    ///
+    /// Briefs with a section are grouped under a `# section` line, written when the
+    /// section changes.
+    pub fn to_compact(briefs: &[BriefNode]) -> String {
+        let mut out = String::new();
+        let mut section = None;
+        for brief in briefs {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            if let Some(heading) = &brief.section
+                && section != Some(heading)
+            {
+                out.push_str(&format!("# {}\n", heading.replace(['\n', '\r'], " ")));
+                section = Some(heading);
+            }
+            out.push_str(&format!("{}|{}|", brief.id, brief.node_type));
+            for c in brief.content.chars() {
+                match c {
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    '\r' => out.push_str("\\r"),
+                    c => out.push(c),
+                }
+            }
+        }
+        out
+    }
+
+    /// Parse briefs written by [`BriefNode::to_compact`], `None` if a line is malformed.
+    pub fn from_compact(text: &str) -> Option<Vec<BriefNode>> {
+        let mut briefs = Vec::new();
+        let mut section = None;
+        for line in text.lines() {
+            if let Some(heading) = line.strip_prefix("# ") {
+                section = Some(heading.to_string());
+                continue;
+            }
+
+            let mut fields = line.splitn(3, '|');
+            let id = fields.next()?.parse().ok()?;
+            let node_type = fields.next()?.to_string();
+            let escaped = fields.next()?;
+
+            let mut content = String::with_capacity(escaped.len());
+            let mut chars = escaped.chars();
+            while let Some(c) = chars.next() {
+                if c != '\\' {
+                    content.push(c);
+                    continue;
+                }
+                match chars.next()? {
+                    '\\' => content.push('\\'),
+                    'n' => content.push('\n'),
+                    'r' => content.push('\r'),
+                    _ => return None,
+                }
+            }
+            briefs.push(BriefNode {
+                id,
+                node_type,
+                content,
+                section: section.clone(),
+            });
+        }
+        Some(briefs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::note::Note;
+
+    fn brief(id: usize, node_type: &str, content: &str) -> BriefNode {
+        BriefNode {
+            id,
+            node_type: node_type.to_string(),
+            content: content.to_string(),
+            section: None,
+        }
+    }
+
+    #[test]
+    fn test_compact_round_trip() {
+        let briefs = vec![
+            brief(0, "heading", "Plan | Q3"),
+            brief(1, "paragraph", ""),
+            brief(2, "code", "a\\nb\nc\r\nd"),
+        ];
+
+        let compact = BriefNode::to_compact(&briefs);
+        assert_eq!(compact, "0|heading|Plan | Q3\n1|paragraph|\n2|code|a\\\\nb\\nc\\r\\nd");
+
+        let parsed = BriefNode::from_compact(&compact).expect("Should parse the compact briefs");
+        assert_eq!(parsed.len(), briefs.len());
+        for (parsed, brief) in parsed.iter().zip(&briefs) {
+            assert_eq!((parsed.id, &parsed.node_type, &parsed.content), (brief.id, &brief.node_type, &brief.content));
+        }
+
+        assert!(BriefNode::from_compact("x|paragraph|Hi").is_none());
+        assert!(BriefNode::from_compact("0|paragraph").is_none());
+        assert!(BriefNode::from_compact("0|paragraph|trailing \\").is_none());
+        assert!(BriefNode::from_compact("0|paragraph|bad \\t escape").is_none());
+    }
+
+    #[test]
+    fn test_compact_sections() {
+        let in_section = |id, content, section: &str| BriefNode {
+            section: Some(section.to_string()),
+            ..brief(id, "paragraph", content)
+        };
+        let briefs = vec![
+            brief(0, "paragraph", "Preamble"),
+            in_section(2, "Cost", "H2: Budget"),
+            in_section(3, "Time", "H2: Budget"),
+            in_section(5, "Risk", "H3: Risks"),
+        ];
+
+        let compact = BriefNode::to_compact(&briefs);
+        assert_eq!(compact, "0|paragraph|Preamble\n# H2: Budget\n2|paragraph|Cost\n3|paragraph|Time\n# H3: Risks\n5|paragraph|Risk");
+
+        let parsed = BriefNode::from_compact(&compact).expect("Should parse the compact briefs");
+        let sections: Vec<Option<&str>> = parsed.iter().map(|brief| brief.section.as_deref()).collect();
+        assert_eq!(sections, vec![None, Some("H2: Budget"), Some("H2: Budget"), Some("H3: Risks")]);
+    }
+
+    #[test]
+    fn test_json_is_ordered_by_id() {
+        let briefs = vec![
+            brief(4, "paragraph", "Later"),
+            brief(1, "heading", "Title"),
+        ];
+
+        assert_eq!(
+            BriefNode::to_json(&briefs),
+            r#"[{"id":1,"nodeType":"heading","content":"Title"},{"id":4,"nodeType":"paragraph","content":"Later"}]"#
+        );
+        let pretty = BriefNode::to_json_pretty(&briefs);
+        assert!(pretty.starts_with("[\n  {\n    \"id\": 1,"));
+        let value = |json: &str| serde_json::from_str::<serde_json::Value>(json).unwrap();
+        assert_eq!(value(&pretty), value(&BriefNode::to_json(&briefs)));
+    }
+
+    #[test]
+    fn test_compact_is_smaller_than_json() {
+        let json_content = fs::read_to_string("assets/example_note.json")
+            .expect("Should be able to read assets/example_note.json");
+        let note = Note::from_json(&json_content).expect("Should be able to parse example note JSON");
+        let briefs = note.get_brief();
+
+        let json = BriefNode::to_json(&briefs);
+        let compact = BriefNode::to_compact(&briefs);
+        assert!(
+            compact.len() * 10 <= json.len() * 7,
+            "The compact briefs ({} bytes) should be at least 30% smaller than JSON ({} bytes)",
+            compact.len(),
+            json.len()
+        );
+        assert_eq!(BriefNode::from_compact(&compact).unwrap().len(), briefs.len());
+    }
+}
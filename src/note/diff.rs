@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ops::{Index, IndexMut};
+
+use serde::Serialize;
+
+use super::{FNV_OFFSET, LexicalNode, Note, fnv1a};
+
+/// A change between two versions of a note's root children.
+///
+/// `Deleted` and `Moved::from` index the old note, the other indices index the new note.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum NoteChange {
+    #[serde(rename_all = "camelCase")]
+    Inserted { index: usize, node_type: String },
+    #[serde(rename_all = "camelCase")]
+    Deleted { index: usize, node_type: String },
+    #[serde(rename_all = "camelCase")]
+    Modified { index: usize, old_text: String, new_text: String },
+    Moved { from: usize, to: usize },
+}
+
+/// The comparable content of a root child
+#[derive(PartialEq, Eq, Hash)]
+pub(super) struct NodeKey {
+    pub(super) node_type: &'static str,
+    pub(super) text: String,
+    /// A hash of the whole node without its Lexical keys, so that format-only edits differ
+    pub(super) structure: u64,
+}
+
+impl Note {
+    /// Compare the root children of the note with `other`.
+    ///
+    /// Nodes are aligned by their content, including formatting but not the Lexical keys,
+    /// so an insertion does not report the following nodes as modified. Changes are listed in document order,
+    /// followed by the moves.
+    pub fn diff(&self, other: &Note) -> Vec<NoteChange> {
+        let old = self.node_keys();
+        let new = other.node_keys();
+        let anchors = longest_common_subsequence(&old, &new);
+
+        // The unaligned ranges between consecutive anchors
+        let mut gaps = Vec::new();
+        let (mut old_start, mut new_start) = (0, 0);
+        for &(i, j) in anchors.iter().chain(&[(old.len(), new.len())]) {
+            gaps.push((old_start..i, new_start..j));
+            (old_start, new_start) = (i + 1, j + 1);
+        }
+
+        // Identical nodes unaligned on both sides were moved
+        let mut old_used = vec![false; old.len()];
+        let mut new_used = vec![false; new.len()];
+        for &(i, j) in &anchors {
+            old_used[i] = true;
+            new_used[j] = true;
+        }
+        let mut moves = Vec::new();
+        for (from, key) in old.iter().enumerate() {
+            if old_used[from] {
+                continue;
+            }
+            if let Some(to) = (0..new.len()).find(|&to| !new_used[to] && new[to] == *key) {
+                old_used[from] = true;
+                new_used[to] = true;
+                moves.push(NoteChange::Moved { from, to });
+            }
+        }
+
+        let mut changes = Vec::new();
+        for (old_range, new_range) in gaps {
+            let mut deleted: Vec<usize> = old_range.filter(|&i| !old_used[i]).collect();
+            let mut inserted: Vec<usize> = new_range.filter(|&j| !new_used[j]).collect();
+
+            // Pair the nodes of the same type in the gap as modifications
+            let mut modified = Vec::new();
+            deleted.retain(|&i| {
+                let paired = inserted.iter().position(|&j| new[j].node_type == old[i].node_type);
+                match paired {
+                    Some(pos) => {
+                        let j = inserted.remove(pos);
+                        modified.push(NoteChange::Modified {
+                            index: j,
+                            old_text: old[i].text.clone(),
+                            new_text: new[j].text.clone(),
+                        });
+                        false
+                    }
+                    None => true,
+                }
+            });
+
+            changes.extend(deleted.into_iter().map(|index| NoteChange::Deleted {
+                index,
+                node_type: old[index].node_type.to_string(),
+            }));
+            changes.extend(modified);
+            changes.extend(inserted.into_iter().map(|index| NoteChange::Inserted {
+                index,
+                node_type: new[index].node_type.to_string(),
+            }));
+        }
+
+        changes.extend(moves);
+        changes
+    }
+
+    /// Get the comparable content of the root children
+    pub(super) fn node_keys(&self) -> Vec<NodeKey> {
+        self.lexical_state
+            .root
+            .children
+            .iter()
+            .map(|node| NodeKey {
+                node_type: node.type_name(),
+                text: self.render_node(node).1,
+                structure: structure_hash(node),
+            })
+            .collect()
+    }
+}
+
+/// Hash the serialized node with the Lexical keys of its subtree cleared
+fn structure_hash(node: &LexicalNode) -> u64 {
+    let mut node = node.clone();
+    let mut stack = vec![&mut node];
+    while let Some(node) = stack.pop() {
+        node.base_mut().key = None;
+        stack.extend(node.children_mut().into_iter().flatten());
+    }
+    // Nodes too deep to serialize are compared by their type and text alone
+    let json = serde_json::to_vec(&node).unwrap_or_default();
+    fnv1a(FNV_OFFSET, json)
+}
+
+/// Get the index pairs of a longest common subsequence, in order.
+///
+/// Uses the linear space variant of Myers' algorithm, so memory stays linear in the
+/// length of the sequences and time grows with the number of differences. Items are
+/// compared by id there, so that each comparison is cheap.
+pub(super) fn longest_common_subsequence<T: Eq + Hash>(old: &[T], new: &[T]) -> Vec<(usize, usize)> {
+    let mut ids = HashMap::new();
+    let mut id = |item| {
+        let next = ids.len();
+        *ids.entry(item).or_insert(next)
+    };
+    let old: Vec<usize> = old.iter().map(&mut id).collect();
+    let new: Vec<usize> = new.iter().map(&mut id).collect();
+
+    let max_d = (old.len() + new.len()).div_ceil(2) + 1;
+    let mut forward = Diagonals::new(max_d);
+    let mut backward = Diagonals::new(max_d);
+    let mut pairs = Vec::new();
+    align(&old, &new, (0, 0), &mut forward, &mut backward, &mut pairs);
+    pairs
+}
+
+/// Add the index pairs of a longest common subsequence of `old` and `new`, whose
+/// first items are at `offset` in the whole sequences
+fn align(
+    old: &[usize],
+    new: &[usize],
+    offset: (usize, usize),
+    forward: &mut Diagonals,
+    backward: &mut Diagonals,
+    pairs: &mut Vec<(usize, usize)>,
+) {
+    // The common prefix and suffix are aligned as they are
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    pairs.extend((0..prefix).map(|k| (offset.0 + k, offset.1 + k)));
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let offset = (offset.0 + prefix, offset.1 + prefix);
+    let suffix = old.iter().rev().zip(new.iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old, new) = (&old[..old.len() - suffix], &new[..new.len() - suffix]);
+
+    // Split at the middle snake, which halves the differences on each side
+    if !old.is_empty() && !new.is_empty() {
+        let (x, y) = middle_snake(old, new, forward, backward);
+        align(&old[..x], &new[..y], offset, forward, backward, pairs);
+        align(&old[x..], &new[y..], (offset.0 + x, offset.1 + y), forward, backward, pairs);
+    }
+
+    pairs.extend((0..suffix).map(|k| (offset.0 + old.len() + k, offset.1 + new.len() + k)));
+}
+
+/// Find where the furthest reaching paths from both ends meet, as the start of a
+/// snake on an optimal path. The sequences differ in their first and last items.
+fn middle_snake(old: &[usize], new: &[usize], forward: &mut Diagonals, backward: &mut Diagonals) -> (usize, usize) {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    forward[1] = 0;
+    backward[1] = 0;
+
+    for d in 0..=(n + m + 1) / 2 {
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || k != d && forward[k - 1] < forward[k + 1] {
+                forward[k + 1]
+            } else {
+                forward[k - 1] + 1
+            };
+            let start = (x, x - k);
+            while x < n && (0..m).contains(&(x - k)) && old[x as usize] == new[(x - k) as usize] {
+                x += 1;
+            }
+            forward[k] = x;
+            if odd && (k - delta).abs() < d && x + backward[delta - k] >= n {
+                return (start.0 as usize, start.1 as usize);
+            }
+        }
+
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || k != d && backward[k - 1] < backward[k + 1] {
+                backward[k + 1]
+            } else {
+                backward[k - 1] + 1
+            };
+            while x < n
+                && (0..m).contains(&(x - k))
+                && old[(n - x - 1) as usize] == new[(m - x + k - 1) as usize]
+            {
+                x += 1;
+            }
+            backward[k] = x;
+            if !odd && (k - delta).abs() <= d && x + forward[delta - k] >= n {
+                return ((n - x) as usize, (m - x + k) as usize);
+            }
+        }
+    }
+    unreachable!("The paths meet within half of the edits")
+}
+
+/// The furthest x reached on each diagonal k = x - y, for k in -max_d..=max_d
+struct Diagonals {
+    x: Vec<isize>,
+    offset: isize,
+}
+
+impl Diagonals {
+    fn new(max_d: usize) -> Self {
+        Self {
+            x: vec![0; 2 * max_d + 1],
+            offset: max_d as isize,
+        }
+    }
+}
+
+impl Index<isize> for Diagonals {
+    type Output = isize;
+
+    fn index(&self, k: isize) -> &isize {
+        &self.x[(k + self.offset) as usize]
+    }
+}
+
+impl IndexMut<isize> for Diagonals {
+    fn index_mut(&mut self, k: isize) -> &mut isize {
+        &mut self.x[(k + self.offset) as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::super::tests::{heading_json, note_from_children, paragraph_json};
+    use super::*;
+
+    fn base_note() -> Note {
+        note_from_children(json!([
+            heading_json("Plan", "h1"),
+            paragraph_json("First"),
+            paragraph_json("Second"),
+            paragraph_json("Third"),
+        ]))
+    }
+
+    #[test]
+    fn test_diff_insert_in_middle() {
+        let other = note_from_children(json!([
+            heading_json("Plan", "h1"),
+            paragraph_json("First"),
+            paragraph_json("New"),
+            paragraph_json("Second"),
+            paragraph_json("Third"),
+        ]));
+
+        assert_eq!(
+            base_note().diff(&other),
+            vec![NoteChange::Inserted { index: 2, node_type: "paragraph".to_string() }]
+        );
+    }
+
+    #[test]
+    fn test_diff_delete_at_end() {
+        let other = note_from_children(json!([
+            heading_json("Plan", "h1"),
+            paragraph_json("First"),
+            paragraph_json("Second"),
+        ]));
+
+        assert_eq!(
+            base_note().diff(&other),
+            vec![NoteChange::Deleted { index: 3, node_type: "paragraph".to_string() }]
+        );
+    }
+
+    #[test]
+    fn test_diff_modify_paragraph() {
+        let other = note_from_children(json!([
+            heading_json("Plan", "h1"),
+            paragraph_json("First"),
+            paragraph_json("Second, revised"),
+            paragraph_json("Third"),
+        ]));
+
+        let changes = base_note().diff(&other);
+        assert_eq!(
+            changes,
+            vec![NoteChange::Modified {
+                index: 2,
+                old_text: "Second".to_string(),
+                new_text: "Second, revised".to_string(),
+            }]
+        );
+
+        // The changes serialize for the wasm boundary
+        assert_eq!(
+            serde_json::to_value(&changes).unwrap(),
+            json!([{ "type": "modified", "index": 2, "oldText": "Second", "newText": "Second, revised" }])
+        );
+    }
+
+    #[test]
+    fn test_diff_large_note() {
+        let paragraphs = |texts: Vec<String>| {
+            note_from_children(serde_json::Value::Array(texts.iter().map(|text| paragraph_json(text)).collect()))
+        };
+        let texts: Vec<String> = (0..8000).map(|i| format!("Paragraph {i}")).collect();
+        let old = paragraphs(texts.clone());
+
+        // Edits at both ends and every 100th node leave no long common prefix or suffix
+        let mut new_texts = texts.clone();
+        for i in (0..8000).step_by(100) {
+            new_texts[i] = format!("Edited {i}");
+        }
+        new_texts.insert(4000, "Inserted".to_string());
+        new_texts.push("Last".to_string());
+        let changes = old.diff(&paragraphs(new_texts));
+
+        let modified = changes.iter().filter(|change| matches!(change, NoteChange::Modified { .. })).count();
+        assert_eq!(modified, 80);
+        let inserted: Vec<&NoteChange> = changes.iter().filter(|change| matches!(change, NoteChange::Inserted { .. })).collect();
+        assert_eq!(inserted.len(), 2);
+        assert_eq!(changes.len(), 82);
+        assert_eq!(longest_common_subsequence(&texts, &texts).len(), 8000);
+    }
+
+    #[test]
+    fn test_diff_swap_adjacent() {
+        let other = note_from_children(json!([
+            heading_json("Plan", "h1"),
+            paragraph_json("Second"),
+            paragraph_json("First"),
+            paragraph_json("Third"),
+        ]));
+
+        assert_eq!(base_note().diff(&other), vec![NoteChange::Moved { from: 1, to: 2 }]);
+        assert_eq!(base_note().diff(&base_note()), vec![]);
+    }
+}
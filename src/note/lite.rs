@@ -0,0 +1,350 @@
+use std::borrow::Cow;
+
+use serde::Deserialize;
+use serde::de::Error as _;
+
+use super::{
+    AIEmbeddingNode, AutoLinkNode, BaseNodeProperties, BriefNode, ChatMessageNode, ChatSessionMessage,
+    ChatSessionNode, CodeFormat, CodeHighlightNode, CodeNode, HashtagNode, HeadingNode, HeadingTag, LexicalNode,
+    LexicalState, LineBreakNode, LinkNode, ListItemNode, ListNode, ListTag, ListType, MentionNode, MessageSender,
+    Note, PageBreakNode, ParagraphNode, QuoteNode, RootNode, TableCellNode, TableNode, TableRowNode, TextNode,
+    VoiceInputNode, VoiceInputStatus,
+    migrate::{MigrateError, SCHEMA_VERSION, migrate},
+};
+
+/// A note with only the fields the briefs read.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LiteNote<'a> {
+    #[serde(default)]
+    schema_version: Option<u32>,
+    #[serde(borrow)]
+    lexical_state: LiteState<'a>,
+}
+
+#[derive(Deserialize)]
+struct LiteState<'a> {
+    #[serde(borrow)]
+    root: LiteRoot<'a>,
+}
+
+#[derive(Deserialize)]
+struct LiteRoot<'a> {
+    #[serde(rename = "type", deserialize_with = "super::deserialize_root_type")]
+    _node_type: String,
+    #[serde(borrow)]
+    children: Vec<LiteNode<'a>>,
+}
+
+/// Any node, with the fields the briefs of all node types read.
+///
+/// Strings borrow from the input unless they have escapes, and the other fields,
+/// such as styles and base properties, are skipped without being stored.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LiteNode<'a> {
+    #[serde(rename = "type", borrow)]
+    node_type: Cow<'a, str>,
+    #[serde(default, borrow)]
+    children: Option<Vec<LiteNode<'a>>>,
+    #[serde(default, borrow)]
+    text: Option<Cow<'a, str>>,
+    #[serde(default, borrow)]
+    content: Option<Cow<'a, str>>,
+    #[serde(default, borrow)]
+    tag: Option<Cow<'a, str>>,
+    #[serde(default)]
+    list_type: Option<ListType>,
+    #[serde(default)]
+    start: Option<u32>,
+    #[serde(default)]
+    value: Option<u32>,
+    #[serde(default, borrow)]
+    language: Option<Cow<'a, str>>,
+    #[serde(default, borrow)]
+    url: Option<Cow<'a, str>>,
+    #[serde(default, borrow)]
+    title: Option<Cow<'a, str>>,
+    #[serde(default)]
+    header_state: u32,
+    #[serde(default)]
+    col_span: Option<u32>,
+    #[serde(default)]
+    row_span: Option<u32>,
+    #[serde(default)]
+    is_loading: bool,
+    #[serde(default)]
+    status: Option<VoiceInputStatus>,
+    #[serde(default)]
+    confidence: Option<f32>,
+    #[serde(default)]
+    sender: Option<MessageSender>,
+    #[serde(default)]
+    messages: Vec<LiteMessage<'a>>,
+    #[serde(default, borrow)]
+    mention_name: Option<Cow<'a, str>>,
+}
+
+#[derive(Deserialize)]
+struct LiteMessage<'a> {
+    sender: MessageSender,
+    #[serde(borrow)]
+    content: Cow<'a, str>,
+}
+
+fn owned(text: Option<Cow<'_, str>>) -> String {
+    text.map(Cow::into_owned).unwrap_or_default()
+}
+
+impl LiteNode<'_> {
+    /// Build the node the briefs are rendered from, with default base properties
+    fn into_node(self) -> serde_json::Result<LexicalNode> {
+        let base = BaseNodeProperties::default;
+        let mut code_children = self
+            .children
+            .map(|children| children.into_iter().map(LiteNode::into_node).collect::<serde_json::Result<Vec<_>>>())
+            .transpose()?;
+        // Inline code has no children, unlike an empty code block
+        let children = match &*self.node_type {
+            "code" => Vec::new(),
+            _ => code_children.take().unwrap_or_default(),
+        };
+        let missing = |field| serde_json::Error::missing_field(field);
+
+        let node = match &*self.node_type {
+            "text" => LexicalNode::Text(TextNode::new(owned(self.text))),
+            "paragraph" => LexicalNode::Paragraph(ParagraphNode {
+                children,
+                text_format: 0,
+                text_style: String::new(),
+                base: base(),
+            }),
+            "heading" => {
+                let tag = self.tag.ok_or_else(|| missing("tag"))?;
+                LexicalNode::Heading(HeadingNode {
+                    tag: HeadingTag::deserialize(serde::de::value::StrDeserializer::<serde_json::Error>::new(&tag))?,
+                    children,
+                    base: base(),
+                })
+            }
+            "list" => LexicalNode::List(ListNode {
+                list_type: self.list_type.ok_or_else(|| missing("listType"))?,
+                start: self.start,
+                tag: self
+                    .tag
+                    .map(|tag| ListTag::deserialize(serde::de::value::StrDeserializer::<serde_json::Error>::new(&tag)))
+                    .transpose()?,
+                children,
+                base: base(),
+            }),
+            "listitem" => LexicalNode::ListItem(ListItemNode {
+                children,
+                value: self.value,
+                base: base(),
+            }),
+            "quote" => LexicalNode::Quote(QuoteNode { children, base: base() }),
+            "code" => LexicalNode::Code(CodeNode {
+                text: self.text.map(Cow::into_owned),
+                language: self.language.map(Cow::into_owned),
+                children: code_children,
+                format: CodeFormat::Align(String::new()),
+                base: base(),
+            }),
+            "code-highlight" => LexicalNode::CodeHighlight(CodeHighlightNode {
+                text: owned(self.text),
+                highlight_type: None,
+                format: 0,
+                base: base(),
+            }),
+            "linebreak" => LexicalNode::LineBreak(LineBreakNode { base: base() }),
+            "link" => LexicalNode::Link(LinkNode {
+                url: owned(self.url),
+                rel: None,
+                target: None,
+                title: self.title.map(Cow::into_owned),
+                children,
+                base: base(),
+            }),
+            "autolink" => LexicalNode::AutoLink(AutoLinkNode {
+                url: owned(self.url),
+                children,
+                base: base(),
+            }),
+            "hashtag" => LexicalNode::Hashtag(HashtagNode {
+                text: owned(self.text),
+                format: 0,
+                base: base(),
+            }),
+            "table" => LexicalNode::Table(TableNode { children, base: base() }),
+            "tablerow" => LexicalNode::TableRow(TableRowNode { children, base: base() }),
+            "tablecell" => LexicalNode::TableCell(TableCellNode {
+                children,
+                header_state: self.header_state,
+                col_span: self.col_span.unwrap_or(1),
+                row_span: self.row_span.unwrap_or(1),
+                width: None,
+                background_color: None,
+                base: base(),
+            }),
+            "page-break" => LexicalNode::PageBreak(PageBreakNode { base: base() }),
+            "ai-embedding" => LexicalNode::AIEmbedding(AIEmbeddingNode {
+                content: owned(self.content),
+                is_loading: self.is_loading,
+                prompt: None,
+                model: None,
+                session_id: None,
+                created_at: None,
+                base: base(),
+            }),
+            "voice-input" => LexicalNode::VoiceInput(VoiceInputNode {
+                content: owned(self.content),
+                status: self.status,
+                confidence: self.confidence,
+                duration_ms: None,
+                language: self.language.map(Cow::into_owned),
+                base: base(),
+            }),
+            "chat-message" => LexicalNode::ChatMessage(ChatMessageNode {
+                sender: self.sender.ok_or_else(|| missing("sender"))?,
+                content: owned(self.content),
+                timestamp: String::new(),
+                base: base(),
+            }),
+            "chat-session" => LexicalNode::ChatSession(ChatSessionNode {
+                session_id: String::new(),
+                messages: self
+                    .messages
+                    .into_iter()
+                    .map(|message| ChatSessionMessage {
+                        id: 0,
+                        sender: message.sender,
+                        content: message.content.into_owned(),
+                        timestamp: String::new(),
+                    })
+                    .collect(),
+                base: base(),
+            }),
+            "mention" => LexicalNode::Mention(MentionNode {
+                mention_name: owned(self.mention_name),
+                text: owned(self.text),
+                format: 0,
+                base: base(),
+            }),
+            other => return Err(serde_json::Error::unknown_variant(other, &[])),
+        };
+        Ok(node)
+    }
+}
+
+impl Note {
+    /// Get the briefs of a note JSON without parsing the full note.
+    ///
+    /// Only the fields the briefs read are parsed, so this is much faster than
+    /// [`migrate`] followed by [`Note::get_brief`] on large notes, with the same briefs.
+    /// The input is checked less strictly: fields the briefs don't read may be missing or
+    /// malformed. Notes of an older schema take the full path through the migrations.
+    pub fn get_brief_from_json(json: &str) -> Result<Vec<BriefNode>, MigrateError> {
+        // The recursion limit of serde_json bounds the nesting like MAX_NESTING_DEPTH
+        let lite: LiteNote = serde_json::from_str(json).map_err(MigrateError::Invalid)?;
+        match lite.schema_version.unwrap_or(0) {
+            version if version > SCHEMA_VERSION => return Err(MigrateError::NewerVersion { version }),
+            version if version < SCHEMA_VERSION => {
+                let value = serde_json::from_str(json).map_err(MigrateError::Invalid)?;
+                return Ok(migrate(value)?.get_brief());
+            }
+            _ => {}
+        }
+
+        let children = lite
+            .lexical_state
+            .root
+            .children
+            .into_iter()
+            .map(LiteNode::into_node)
+            .collect::<serde_json::Result<Vec<_>>>()
+            .map_err(MigrateError::Invalid)?;
+
+        let note = Note {
+            note_id: None,
+            schema_version: Some(SCHEMA_VERSION),
+            lexical_state: LexicalState {
+                root: RootNode {
+                    node_type: "root".to_string(),
+                    children,
+                    base: BaseNodeProperties::default(),
+                },
+            },
+            applied_op_ids: Default::default(),
+            extra: serde_json::Map::new(),
+        };
+        Ok(note.get_brief())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// Mark a note JSON as written with the current schema, so it takes the lite path
+    fn current_schema(json: &str) -> String {
+        let mut value: serde_json::Value = serde_json::from_str(json).unwrap();
+        value["schemaVersion"] = SCHEMA_VERSION.into();
+        value.to_string()
+    }
+
+    #[test]
+    fn test_brief_from_json_matches_full_parse() {
+        for fixture in [
+            "assets/example_note.json",
+            "assets/example_outline_note.json",
+            "assets/example_chat_note.json",
+            "assets/example_code_note.json",
+            "assets/example_table_note.json",
+            "assets/example_cruft_note.json",
+            "assets/example_voice_note.json",
+        ] {
+            let json_content = fs::read_to_string(fixture).expect("Should be able to read the fixture");
+            let expected = migrate(serde_json::from_str(&json_content).unwrap())
+                .expect("Should be able to parse the fixture")
+                .get_brief();
+            let expected: Vec<_> = expected.iter().map(|b| (b.id, &b.node_type, &b.content)).collect();
+
+            // The fixtures predate versioning, so they are migrated first
+            for json in [json_content.clone(), current_schema(&json_content)] {
+                let briefs = Note::get_brief_from_json(&json).expect("Should brief the fixture");
+                let actual: Vec<_> = briefs.iter().map(|b| (b.id, &b.node_type, &b.content)).collect();
+                assert_eq!(actual, expected, "{fixture} should have the same briefs");
+            }
+        }
+
+        // A note of an older schema is migrated, wrapping the text at the root in a paragraph
+        let json_content = fs::read_to_string("assets/legacy_root_text_note.json").unwrap();
+        let briefs = Note::get_brief_from_json(&json_content).expect("Should brief the legacy note");
+        assert_eq!(briefs[0].node_type, "paragraph");
+        assert_eq!(briefs[0].content, "Written before blocks");
+    }
+
+    #[test]
+    fn test_brief_from_json_rejects_bad_input() {
+        let note = |child: &str| {
+            format!(r#"{{"noteId":null,"schemaVersion":2,"lexicalState":{{"root":{{"type":"root","version":1,"children":[{child}]}}}}}}"#)
+        };
+
+        let err = Note::get_brief_from_json(&note(r#"{"type":"widget","version":1}"#)).unwrap_err();
+        assert!(err.to_string().contains("unknown variant `widget`"), "{err}");
+        let err = Note::get_brief_from_json(&note(r#"{"type":"heading","version":1,"children":[]}"#)).unwrap_err();
+        assert!(err.to_string().contains("missing field `tag`"), "{err}");
+        let deep = format!("{}{}", "[".repeat(200), "]".repeat(200));
+        assert!(Note::get_brief_from_json(&note(&deep)).is_err());
+        assert!(Note::get_brief_from_json("not json").is_err());
+
+        // The checks of the full parse apply
+        let err = Note::get_brief_from_json(&note("").replace(r#""type":"root""#, r#""type":"list""#)).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid note JSON: The root node has type \"list\" instead of \"root\" at line 1 column 70");
+        let err = Note::get_brief_from_json(&note("").replace(r#""schemaVersion":2"#, r#""schemaVersion":99"#)).unwrap_err();
+        assert!(matches!(err, MigrateError::NewerVersion { version: 99 }), "{err}");
+    }
+}
+
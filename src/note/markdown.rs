@@ -0,0 +1,698 @@
+use super::code::inline_code;
+use super::{
+    BaseNodeProperties, CodeFormat, CodeNode, HeadingNode, HeadingTag, LexicalNode, LexicalState, LineBreakNode,
+    ListItemNode, ListNode, ListType, Note, PageBreakNode, ParagraphNode, QuoteNode, RootNode, TableNode, TextExtractOptions,
+    TextNode,
+};
+
+impl Note {
+    /// Export the note as Markdown.
+    ///
+    /// Root nodes are rendered as blocks separated by blank lines, empty blocks
+    /// are skipped. Underline has no Markdown equivalent and is dropped.
+    pub fn to_markdown(&self) -> String {
+        self.lexical_state
+            .root
+            .children
+            .iter()
+            .map(|node| self.markdown_block(node))
+            .filter(|block| !block.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    /// Render a block node as Markdown
+    fn markdown_block(&self, node: &LexicalNode) -> String {
+        match node {
+            LexicalNode::Paragraph(para) => markdown_inline(&para.children),
+            LexicalNode::Heading(heading) => {
+                let hashes = "#".repeat(heading.tag.level());
+                format!("{hashes} {}", markdown_inline(&heading.children))
+            }
+            LexicalNode::List(list) => self.markdown_list(list, ""),
+            LexicalNode::ListItem(item) => format!("- {}", markdown_inline(&item.children)),
+            LexicalNode::Quote(quote) => markdown_inline(&quote.children)
+                .lines()
+                .map(|line| format!("> {line}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            // Rendered as in the briefs, with a fence longer than the backticks in the code
+            LexicalNode::Code(_) => self.extract_text_from_nodes(std::slice::from_ref(node)),
+            LexicalNode::Table(table) => self.render_table(table, &TextExtractOptions::compat()),
+            LexicalNode::PageBreak(_) => "---".to_string(),
+            LexicalNode::AIEmbedding(ai) => ai.content.clone(),
+            LexicalNode::VoiceInput(voice) => voice.content.clone(),
+            LexicalNode::ChatMessage(msg) => format!("**{}**: {}", msg.sender, msg.content),
+            LexicalNode::ChatSession(session) => session
+                .messages
+                .iter()
+                .map(|msg| format!("**{}**: {}", msg.sender, msg.content))
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+            _ => markdown_inline(std::slice::from_ref(node)),
+        }
+    }
+
+    /// Render a list as Markdown, nesting sub-lists under the previous item
+    fn markdown_list(&self, list: &ListNode, indent: &str) -> String {
+        let mut number = list.start.unwrap_or(1);
+        let mut lines = Vec::new();
+        // Nested lists are indented to the content column of the previous item
+        let mut nested_indent = format!("{indent}  ");
+
+        for child in &list.children {
+            let LexicalNode::ListItem(item) = child else {
+                lines.push(format!("{indent}{}", self.markdown_block(child)));
+                continue;
+            };
+
+            let (nested, inline): (Vec<&LexicalNode>, Vec<&LexicalNode>) = item
+                .children
+                .iter()
+                .partition(|node| matches!(node, LexicalNode::List(_)));
+
+            if !inline.is_empty() {
+                number = item.value.unwrap_or(number);
+                let marker = match list.list_type {
+                    ListType::Bullet => "-".to_string(),
+                    ListType::Number => format!("{number}."),
+                };
+                let text: String = inline
+                    .into_iter()
+                    .map(|node| markdown_inline(std::slice::from_ref(node)))
+                    .collect();
+                lines.push(format!("{indent}{marker} {text}"));
+                nested_indent = format!("{indent}{}", " ".repeat(marker.len() + 1));
+                number += 1;
+            }
+
+            for node in nested {
+                if let LexicalNode::List(nested_list) = node {
+                    lines.push(self.markdown_list(nested_list, &nested_indent));
+                }
+            }
+        }
+
+        lines.join("\n")
+    }
+}
+
+impl Note {
+    /// Import a note from Markdown.
+    ///
+    /// The blocks are parsed: ATX headings, paragraphs, fenced code blocks, quotes, thematic
+    /// breaks as page breaks, bullet and numbered lists nested by indent and GitHub-style
+    /// pipe tables. Inline formatting is kept as
+    /// text, only backslash escapes are undone.
+    pub fn from_markdown(markdown: &str) -> Note {
+        Note {
+            note_id: None,
+            schema_version: None,
+            lexical_state: LexicalState {
+                root: RootNode {
+                    node_type: "root".to_string(),
+                    children: markdown_to_nodes(markdown),
+                    base: BaseNodeProperties::default(),
+                },
+            },
+            applied_op_ids: Default::default(),
+            extra: serde_json::Map::new(),
+        }
+    }
+}
+
+/// Parse Markdown into root nodes, see [`Note::from_markdown`]
+pub(super) fn markdown_to_nodes(markdown: &str) -> Vec<LexicalNode> {
+    let lines: Vec<&str> = markdown.lines().collect();
+    let mut nodes = Vec::new();
+    let mut index = 0;
+
+    while index < lines.len() {
+        let line = lines[index];
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            index += 1;
+            continue;
+        }
+
+        if let Some((fence, language)) = open_fence(trimmed) {
+            let end = lines[index + 1..]
+                .iter()
+                .position(|line| closes_fence(line.trim(), fence))
+                .map_or(lines.len(), |offset| index + 1 + offset);
+            let code = lines[index + 1..end].join("\n");
+            let children = if code.is_empty() {
+                Vec::new()
+            } else {
+                vec![LexicalNode::Text(TextNode::new(code))]
+            };
+            nodes.push(LexicalNode::Code(CodeNode {
+                text: None,
+                language,
+                children: Some(children),
+                format: CodeFormat::Align(String::new()),
+                base: BaseNodeProperties::default(),
+            }));
+            index = end + 1;
+        } else if let Some((tag, text)) = heading(trimmed) {
+            nodes.push(LexicalNode::Heading(HeadingNode {
+                tag,
+                children: inline_nodes(&[text]),
+                base: BaseNodeProperties::default(),
+            }));
+            index += 1;
+        } else if is_thematic_break(trimmed) {
+            nodes.push(LexicalNode::PageBreak(PageBreakNode {
+                base: BaseNodeProperties::default(),
+            }));
+            index += 1;
+        } else if list_marker(line).is_some() {
+            nodes.push(LexicalNode::List(parse_list(&lines, &mut index)));
+        } else if lines.get(index + 1).is_some_and(|next| starts_table(line, next)) {
+            // The alignment row only marks the header row, its alignment is ignored
+            let mut rows = vec![table_cells(line)];
+            index += 2;
+            while let Some(row) = lines.get(index).filter(|row| !row.trim().is_empty() && row.contains('|')) {
+                rows.push(table_cells(row));
+                index += 1;
+            }
+            nodes.push(LexicalNode::Table(TableNode::from_rows(&rows)));
+        } else if trimmed.starts_with('>') {
+            let mut quoted = Vec::new();
+            while let Some(text) = lines.get(index).and_then(|line| line.trim().strip_prefix('>')) {
+                quoted.push(text.strip_prefix(' ').unwrap_or(text));
+                index += 1;
+            }
+            nodes.push(LexicalNode::Quote(QuoteNode {
+                children: inline_nodes(&quoted),
+                base: BaseNodeProperties::default(),
+            }));
+        } else {
+            let start = index;
+            index += 1;
+            while let Some(next) = lines.get(index)
+                && !next.trim().is_empty()
+                && !starts_block(next.trim())
+                && !lines.get(index + 1).is_some_and(|after| starts_table(next, after))
+            {
+                index += 1;
+            }
+            nodes.push(LexicalNode::Paragraph(ParagraphNode {
+                children: inline_nodes(&lines[start..index]),
+                text_format: 0,
+                text_style: String::new(),
+                base: BaseNodeProperties::default(),
+            }));
+        }
+    }
+
+    nodes
+}
+
+/// Whether the trimmed line starts a block other than a paragraph, ending a paragraph before it
+fn starts_block(line: &str) -> bool {
+    open_fence(line).is_some()
+        || heading(line).is_some()
+        || is_thematic_break(line)
+        || line.starts_with('>')
+        // Like CommonMark, a numbered list only interrupts a paragraph when it starts at 1
+        || list_marker(line).is_some_and(|marker| marker.number.is_none_or(|number| number == 1))
+}
+
+/// The marker of a list item line
+struct ListMarker<'a> {
+    /// The spaces before the marker
+    indent: usize,
+    /// The number of a numbered item, `None` for a bullet
+    number: Option<u32>,
+    /// The text after the marker
+    text: &'a str,
+}
+
+/// The marker of a line starting a list item, such as "- " or "2. "
+fn list_marker(line: &str) -> Option<ListMarker<'_>> {
+    let rest = line.trim_start();
+    let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (number, text) = match digits {
+        0 => (None, rest.strip_prefix(['-', '*', '+'])?),
+        1..=9 => (Some(rest[..digits].parse().ok()?), rest[digits..].strip_prefix(['.', ')'])?),
+        _ => return None,
+    };
+    if !(text.is_empty() || text.starts_with(' ')) {
+        return None;
+    }
+    Some(ListMarker {
+        indent: line.len() - rest.len(),
+        number,
+        text: text.trim(),
+    })
+}
+
+/// Parse the list whose first item is at the line, moving the index past it.
+///
+/// Items indented past the first item's marker are a nested list, in an item of its own
+/// as Lexical nests lists. A blank line between items doesn't end the list.
+fn parse_list(lines: &[&str], index: &mut usize) -> ListNode {
+    let first = list_marker(lines[*index]).expect("The line should start a list item");
+    let list_type = match first.number {
+        Some(_) => ListType::Number,
+        None => ListType::Bullet,
+    };
+    let start = first.number.unwrap_or(1);
+    let mut value = start;
+    let mut children = Vec::new();
+
+    while let Some(marker) = lines.get(*index).and_then(|line| list_marker(line)) {
+        if marker.indent > first.indent + 1 {
+            children.push(LexicalNode::ListItem(ListItemNode {
+                children: vec![LexicalNode::List(parse_list(lines, index))],
+                value: None,
+                base: BaseNodeProperties::default(),
+            }));
+        } else if marker.indent < first.indent || marker.number.is_some() != first.number.is_some() {
+            break;
+        } else {
+            let mut text = vec![marker.text];
+            *index += 1;
+            // Lines continuing the text of the item
+            while let Some(next) = lines.get(*index)
+                && !next.trim().is_empty()
+                && list_marker(next).is_none()
+                && !starts_block(next.trim())
+            {
+                text.push(next);
+                *index += 1;
+            }
+            children.push(LexicalNode::ListItem(ListItemNode {
+                children: inline_nodes(&text),
+                value: Some(value),
+                base: BaseNodeProperties::default(),
+            }));
+            value += 1;
+        }
+
+        let blank = lines[*index..].iter().take_while(|line| line.trim().is_empty()).count();
+        if blank > 0
+            && lines
+                .get(*index + blank)
+                .and_then(|line| list_marker(line))
+                .is_some_and(|marker| marker.indent >= first.indent)
+        {
+            *index += blank;
+        }
+    }
+
+    ListNode {
+        tag: Some(list_type.tag()),
+        list_type,
+        start: Some(start),
+        children,
+        base: BaseNodeProperties::default(),
+    }
+}
+
+/// The fence and language of a line opening a fenced code block
+fn open_fence(line: &str) -> Option<(&str, Option<String>)> {
+    let marker = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let length = line.len() - line.trim_start_matches(marker).len();
+    if length < 3 {
+        return None;
+    }
+    let language = line[length..].trim();
+    Some((&line[..length], (!language.is_empty()).then(|| language.to_string())))
+}
+
+/// Whether the trimmed line closes the fenced code block opened by `fence`
+fn closes_fence(line: &str, fence: &str) -> bool {
+    let marker = fence.chars().next().expect("Fences are not empty");
+    line.len() >= fence.len() && line.chars().all(|c| c == marker)
+}
+
+/// The tag and text of an ATX heading line
+fn heading(line: &str) -> Option<(HeadingTag, &str)> {
+    let level = line.len() - line.trim_start_matches('#').len();
+    let text = &line[level..];
+    if !(1..=6).contains(&level) || !(text.is_empty() || text.starts_with(' ')) {
+        return None;
+    }
+    let tag = [HeadingTag::H1, HeadingTag::H2, HeadingTag::H3, HeadingTag::H4, HeadingTag::H5, HeadingTag::H6][level - 1];
+    // An optional closing sequence of hashes is not part of the text
+    let text = text.trim().trim_end_matches('#').trim_end();
+    Some((tag, text))
+}
+
+/// Whether the trimmed line is a thematic break, such as "---"
+fn is_thematic_break(line: &str) -> bool {
+    let marks: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    marks.len() >= 3
+        && marks.chars().next().is_some_and(|mark| matches!(mark, '-' | '*' | '_') && marks.chars().all(|c| c == mark))
+}
+
+/// Whether a header row and the alignment row after it start a pipe table
+fn starts_table(header: &str, alignment: &str) -> bool {
+    if !header.contains('|') {
+        return false;
+    }
+    let cells = table_cells(alignment);
+    cells.len() == table_cells(header).len()
+        && cells.iter().all(|cell| {
+            let dashes = cell.strip_prefix(':').unwrap_or(cell);
+            let dashes = dashes.strip_suffix(':').unwrap_or(dashes);
+            !dashes.is_empty() && dashes.chars().all(|c| c == '-')
+        })
+}
+
+/// Split a pipe table row into the text of its cells
+fn table_cells(row: &str) -> Vec<String> {
+    let row = row.trim();
+    let row = row.strip_prefix('|').unwrap_or(row);
+    let row = match row.strip_suffix('|') {
+        Some(stripped) if !stripped.ends_with('\\') => stripped,
+        _ => row,
+    };
+
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut chars = row.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                cell.push(c);
+                cell.extend(chars.next());
+            }
+            '|' => cells.push(unescape_markdown(std::mem::take(&mut cell).trim())),
+            _ => cell.push(c),
+        }
+    }
+    cells.push(unescape_markdown(cell.trim()));
+    cells
+}
+
+/// The inline nodes of the lines of a block, with a line break after lines ending in two spaces
+fn inline_nodes(lines: &[&str]) -> Vec<LexicalNode> {
+    let mut nodes = Vec::new();
+    let mut text = String::new();
+
+    for (index, line) in lines.iter().enumerate() {
+        if !text.is_empty() {
+            text.push(' ');
+        }
+        text.push_str(&unescape_markdown(line.trim()));
+        if line.ends_with("  ") && index + 1 < lines.len() {
+            nodes.push(LexicalNode::Text(TextNode::new(std::mem::take(&mut text))));
+            nodes.push(LexicalNode::LineBreak(LineBreakNode {
+                base: BaseNodeProperties::default(),
+            }));
+        }
+    }
+    if !text.is_empty() {
+        nodes.push(LexicalNode::Text(TextNode::new(text)));
+    }
+    nodes
+}
+
+/// Undo the backslash escapes of ASCII punctuation
+fn unescape_markdown(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\'
+            && let Some(next) = chars.peek().filter(|next| next.is_ascii_punctuation())
+        {
+            unescaped.push(*next);
+            chars.next();
+            continue;
+        }
+        unescaped.push(c);
+    }
+    unescaped
+}
+
+/// Render inline nodes as Markdown
+fn markdown_inline(nodes: &[LexicalNode]) -> String {
+    let mut text = String::new();
+
+    for node in nodes {
+        match node {
+            LexicalNode::Text(text_node) => text.push_str(&markdown_text(text_node)),
+            LexicalNode::Link(link) => {
+                text.push_str(&format!("[{}]({})", markdown_inline(&link.children), link.url));
+            }
+            LexicalNode::AutoLink(auto_link) => {
+                text.push_str(&format!("<{}>", auto_link.url));
+            }
+            LexicalNode::CodeHighlight(highlight) => text.push_str(&escape_markdown(&highlight.text)),
+            // A hard line break
+            LexicalNode::LineBreak(_) => text.push_str("  \n"),
+            LexicalNode::Hashtag(hashtag) => text.push_str(&escape_markdown(&hashtag.text)),
+            LexicalNode::Mention(mention) => text.push_str(&escape_markdown(&mention.text)),
+            LexicalNode::Code(code) => match (&code.text, &code.children) {
+                (Some(code_text), _) => text.push_str(&inline_code(code_text)),
+                (None, Some(children)) => text.push_str(&markdown_inline(children)),
+                (None, None) => {}
+            },
+            LexicalNode::AIEmbedding(ai) => text.push_str(&ai.content),
+            LexicalNode::VoiceInput(voice) => text.push_str(&voice.content),
+            LexicalNode::ChatMessage(msg) => text.push_str(&msg.content),
+            LexicalNode::PageBreak(_) | LexicalNode::ChatSession(_) => {}
+            _ => {
+                if let Some(children) = node.children() {
+                    text.push_str(&markdown_inline(children));
+                }
+            }
+        }
+    }
+
+    text
+}
+
+/// Render a text node with its format flags as Markdown
+fn markdown_text(node: &TextNode) -> String {
+    if node.text.is_empty() {
+        return String::new();
+    }
+
+    // Inline code (16) content is not escaped
+    if node.format & 16 != 0 {
+        return inline_code(&node.text);
+    }
+
+    let mut text = escape_markdown(&node.text);
+    if node.format & 8 != 0 {
+        text = format!("~~{text}~~");
+    }
+    if node.format & 2 != 0 {
+        text = format!("*{text}*");
+    }
+    if node.format & 1 != 0 {
+        text = format!("**{text}**");
+    }
+    text
+}
+
+/// Escape characters with an inline meaning in Markdown
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{
+        cell_json, heading_json, list_item_json, list_json, note_from_children, paragraph_json, table_json,
+        text_json,
+    };
+    use super::*;
+
+    /// The text and header state of the cells of the root table at the index, row by row
+    fn cell_grid(note: &Note, index: usize) -> Vec<Vec<(String, u32)>> {
+        let node = &note.lexical_state.root.children[index];
+        let LexicalNode::Table(table) = node else {
+            panic!("Should be a table, not {}", node.type_name());
+        };
+        table
+            .children
+            .iter()
+            .map(|row| {
+                row.children()
+                    .expect("Rows have cells")
+                    .iter()
+                    .map(|cell| match cell {
+                        LexicalNode::TableCell(cell) => (note.extract_text_from_nodes(&cell.children), cell.header_state),
+                        _ => panic!("Should be a cell"),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_to_markdown() {
+        let note = note_from_children(serde_json::json!([
+            heading_json("Plan", "h2"),
+            {
+                "type": "paragraph",
+                "version": 1,
+                "children": [
+                    text_json("Bold", 1),
+                    text_json(" and ", 0),
+                    text_json("both", 3),
+                    text_json(" 2*3", 0),
+                ],
+            },
+            paragraph_json(""),
+            list_json("number", Some(3), vec![
+                list_item_json(vec![text_json("First", 0)]),
+                list_item_json(vec![list_json("bullet", None, vec![
+                    list_item_json(vec![text_json("Nested", 0)]),
+                ])]),
+                list_item_json(vec![text_json("Second", 0)]),
+            ]),
+            {
+                "type": "code",
+                "version": 1,
+                "format": 0,
+                "language": "rust",
+                "children": [text_json("fn main() {}", 0)],
+            },
+        ]));
+
+        assert_eq!(
+            note.to_markdown(),
+            "## Plan\n\n\
+             **Bold** and ***both*** 2\\*3\n\n\
+             3. First\n   - Nested\n4. Second\n\n\
+             ```rust\nfn main() {}\n```"
+        );
+    }
+
+    #[test]
+    fn test_to_markdown_list_start() {
+        // A list continuing after a paragraph keeps counting from its start
+        let note = note_from_children(serde_json::json!([
+            list_json("number", None, vec![list_item_json(vec![text_json("One", 0)])]),
+            paragraph_json("Interrupted"),
+            list_json("number", Some(3), vec![
+                list_item_json(vec![text_json("Three", 0)]),
+                list_item_json(vec![text_json("Four", 0)]),
+            ]),
+        ]));
+
+        assert_eq!(note.to_markdown(), "1. One\n\nInterrupted\n\n3. Three\n4. Four");
+    }
+
+    #[test]
+    fn test_from_markdown_pipe_table() {
+        let note = Note::from_markdown(
+            "# Team\n\
+             Who does what:\n\
+             | Name | Role | Since |\n\
+             |:-----|:----:|------:|\n\
+             | Ada | Engineer | 2021 |\n\
+             | Grace \\| Hopper | Admiral |\n\
+             \n\
+             That's all.",
+        );
+
+        let types: Vec<&str> = note.lexical_state.root.children.iter().map(LexicalNode::type_name).collect();
+        assert_eq!(types, vec!["heading", "paragraph", "table", "paragraph"]);
+        let cell = |text: &str, header_state| (text.to_string(), header_state);
+        assert_eq!(
+            cell_grid(&note, 2),
+            vec![
+                vec![cell("Name", 1), cell("Role", 1), cell("Since", 1)],
+                vec![cell("Ada", 0), cell("Engineer", 0), cell("2021", 0)],
+                // The short row is padded
+                vec![cell("Grace | Hopper", 0), cell("Admiral", 0), cell("", 0)],
+            ]
+        );
+
+        // Without an alignment row, pipes are only text
+        let note = Note::from_markdown("a | b\nc | d");
+        assert_eq!(note.get_brief()[0].content, "a | b c | d");
+    }
+
+    #[test]
+    fn test_from_markdown_round_trips_export() {
+        let note = note_from_children(serde_json::json!([
+            heading_json("Plan", "h2"),
+            paragraph_json("Ship 2*3 [soon]"),
+            table_json(vec![
+                vec![cell_json("Quarter", 1, 1, 1), cell_json("Revenue", 1, 1, 1)],
+                vec![cell_json("Q1", 0, 1, 1), cell_json("a|b", 0, 1, 1)],
+            ]),
+            {
+                "type": "code",
+                "version": 1,
+                "format": "",
+                "language": "rust",
+                "children": [text_json("fn main() {}", 0)],
+            },
+            { "type": "quote", "version": 1, "children": [text_json("Be brief", 0)] },
+            { "type": "page-break", "version": 1 },
+            list_json("number", Some(2), vec![
+                list_item_json(vec![text_json("Second", 0)]),
+                list_item_json(vec![list_json("bullet", None, vec![
+                    list_item_json(vec![text_json("Nested", 0)]),
+                ])]),
+                list_item_json(vec![text_json("Third", 0)]),
+            ]),
+        ]));
+
+        let imported = Note::from_markdown(&note.to_markdown());
+        let briefs = |note: &Note| -> Vec<(String, String)> {
+            note.get_brief().into_iter().map(|brief| (brief.node_type, brief.content)).collect()
+        };
+        assert_eq!(briefs(&imported), briefs(&note));
+        assert_eq!(imported.lexical_state.root.children.len(), 7);
+    }
+
+    #[test]
+    fn test_from_markdown_lists() {
+        let note = Note::from_markdown(
+            "Groceries:\n\
+             - Milk\n\
+             - Bread that is\n\
+             \x20 whole wheat\n\
+             \x20 1. Sliced\n\
+             \x20 2. Fresh\n\
+             \n\
+             - Eggs\n\
+             \n\
+             3) Wash\n\
+             4) Cook",
+
+        );
+
+        let types: Vec<&str> = note.lexical_state.root.children.iter().map(LexicalNode::type_name).collect();
+        assert_eq!(types, vec!["paragraph", "list", "list"]);
+        let LexicalNode::List(bullets) = &note.lexical_state.root.children[1] else {
+            panic!("Should be a list");
+        };
+        assert!(matches!(bullets.list_type, ListType::Bullet));
+        // The nested list is in an item of its own, after the item it belongs to
+        assert_eq!(bullets.children.len(), 4);
+        let LexicalNode::ListItem(item) = &bullets.children[2] else {
+            panic!("Should be a list item");
+        };
+        assert!(matches!(&item.children[..], [LexicalNode::List(nested)] if nested.children.len() == 2));
+
+        let LexicalNode::List(steps) = &note.lexical_state.root.children[2] else {
+            panic!("Should be a list");
+        };
+        assert!(matches!(steps.list_type, ListType::Number));
+        assert_eq!(steps.start, Some(3));
+        assert_eq!(
+            note.to_markdown(),
+            "Groceries:\n\n\
+             - Milk\n- Bread that is whole wheat\n  1. Sliced\n  2. Fresh\n- Eggs\n\n\
+             3. Wash\n4. Cook"
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use serde::Serialize;
+
+use super::diff::{NodeKey, longest_common_subsequence};
+use super::{LexicalNode, Note};
+
+/// The result of a three-way merge.
+#[derive(Debug, Clone, Serialize)]
+pub struct MergeResult {
+    /// The merged note, keeping the local version of conflicting nodes
+    pub note: Note,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// A root child edited differently on both sides.
+///
+/// A missing version means the node was deleted on that side.
+#[derive(Debug, Clone, Serialize)]
+pub struct Conflict {
+    /// The index of the node in the merged note
+    pub index: usize,
+    pub base: LexicalNode,
+    pub local: Option<LexicalNode>,
+    pub remote: Option<LexicalNode>,
+}
+
+/// What happened to a base node on one side
+#[derive(Clone, Copy)]
+enum Fate {
+    Kept(usize),
+    Modified(usize),
+    Deleted,
+}
+
+/// How one side changed the root children of the base
+struct SideChanges {
+    /// The fate of each base node
+    fates: Vec<Fate>,
+    /// The nodes inserted before each base node, and at the end
+    inserted: Vec<Vec<usize>>,
+}
+
+impl SideChanges {
+    fn new(base: &[NodeKey], side: &[NodeKey]) -> Self {
+        let mut fates = vec![Fate::Deleted; base.len()];
+        let mut inserted = vec![Vec::new(); base.len() + 1];
+
+        let anchors = longest_common_subsequence(base, side);
+        let (mut base_start, mut side_start) = (0, 0);
+        for &(i, j) in anchors.iter().chain(&[(base.len(), side.len())]) {
+            // Pair the nodes of the same type between anchors as modifications
+            let mut added: Vec<usize> = (side_start..j).collect();
+            for (b, fate) in fates.iter_mut().enumerate().take(i).skip(base_start) {
+                if let Some(pos) = added.iter().position(|&s| side[s].node_type == base[b].node_type) {
+                    *fate = Fate::Modified(added.remove(pos));
+                }
+            }
+            inserted[i] = added;
+
+            if i < base.len() {
+                fates[i] = Fate::Kept(j);
+            }
+            (base_start, side_start) = (i + 1, j + 1);
+        }
+
+        Self { fates, inserted }
+    }
+}
+
+impl Note {
+    /// Merge the root children changed concurrently in `local` and `remote` since `base`.
+    ///
+    /// Changes to different nodes are combined. When both sides insert at the same
+    /// place, the local nodes come first. A node modified on both sides, or modified
+    /// on one side and deleted on the other, is reported as a conflict.
+    pub fn merge(base: &Note, local: &Note, remote: &Note) -> MergeResult {
+        let base_keys = base.node_keys();
+        let local_keys = local.node_keys();
+        let remote_keys = remote.node_keys();
+        let local_changes = SideChanges::new(&base_keys, &local_keys);
+        let remote_changes = SideChanges::new(&base_keys, &remote_keys);
+
+        let base_nodes = &base.lexical_state.root.children;
+        let local_nodes = &local.lexical_state.root.children;
+        let remote_nodes = &remote.lexical_state.root.children;
+
+        let mut children = Vec::new();
+        let mut conflicts = Vec::new();
+        for gap in 0..=base_nodes.len() {
+            let local_inserted = &local_changes.inserted[gap];
+            children.extend(local_inserted.iter().map(|&j| local_nodes[j].clone()));
+            // Skip the nodes both sides inserted
+            for &k in &remote_changes.inserted[gap] {
+                if !local_inserted.iter().any(|&j| local_keys[j] == remote_keys[k]) {
+                    children.push(remote_nodes[k].clone());
+                }
+            }
+
+            if gap == base_nodes.len() {
+                break;
+            }
+
+            let merged = match (local_changes.fates[gap], remote_changes.fates[gap]) {
+                (Fate::Kept(j), Fate::Kept(_)) | (Fate::Modified(j), Fate::Kept(_)) => Some(&local_nodes[j]),
+                (Fate::Kept(_), Fate::Modified(k)) => Some(&remote_nodes[k]),
+                (Fate::Modified(j), Fate::Modified(k)) if local_keys[j] == remote_keys[k] => Some(&local_nodes[j]),
+                (Fate::Deleted, Fate::Kept(_) | Fate::Deleted) | (Fate::Kept(_), Fate::Deleted) => None,
+                (local_fate, remote_fate) => {
+                    let local_node = match local_fate {
+                        Fate::Modified(j) => Some(&local_nodes[j]),
+                        _ => None,
+                    };
+                    let remote_node = match remote_fate {
+                        Fate::Modified(k) => Some(&remote_nodes[k]),
+                        _ => None,
+                    };
+                    conflicts.push(Conflict {
+                        index: children.len(),
+                        base: base_nodes[gap].clone(),
+                        local: local_node.cloned(),
+                        remote: remote_node.cloned(),
+                    });
+                    // Keep the edited version until the conflict is resolved
+                    local_node.or(remote_node)
+                }
+            };
+            children.extend(merged.cloned());
+        }
+
+        let mut note = local.clone();
+        note.lexical_state.root.children = children;
+        MergeResult { note, conflicts }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::super::tests::{note_from_children, paragraph_json, text_json};
+    use super::*;
+
+    fn texts(note: &Note) -> Vec<String> {
+        note.lexical_state
+            .root
+            .children
+            .iter()
+            .map(|node| note.render_node(node).1)
+            .collect()
+    }
+
+    fn base_note() -> Note {
+        note_from_children(json!([
+            paragraph_json("One"),
+            paragraph_json("Two"),
+            paragraph_json("Three"),
+        ]))
+    }
+
+    #[test]
+    fn test_merge_clean() {
+        let local = note_from_children(json!([
+            paragraph_json("Local intro"),
+            paragraph_json("One"),
+            paragraph_json("Two"),
+            paragraph_json("Three"),
+        ]));
+        let remote = note_from_children(json!([
+            paragraph_json("One"),
+            paragraph_json("Two, edited remotely"),
+            paragraph_json("Three"),
+            paragraph_json("Remote outro"),
+        ]));
+
+        let result = Note::merge(&base_note(), &local, &remote);
+        assert!(result.conflicts.is_empty());
+        assert_eq!(
+            texts(&result.note),
+            vec!["Local intro", "One", "Two, edited remotely", "Three", "Remote outro"]
+        );
+    }
+
+    #[test]
+    fn test_merge_insertions_at_same_place() {
+        let local = note_from_children(json!([
+            paragraph_json("One"),
+            paragraph_json("From local"),
+            paragraph_json("Shared"),
+            paragraph_json("Two"),
+            paragraph_json("Three"),
+        ]));
+        let remote = note_from_children(json!([
+            paragraph_json("One"),
+            paragraph_json("Shared"),
+            paragraph_json("From remote"),
+            paragraph_json("Two"),
+            paragraph_json("Three"),
+        ]));
+
+        // Local insertions come first, and identical insertions are kept once
+        let result = Note::merge(&base_note(), &local, &remote);
+        assert!(result.conflicts.is_empty());
+        assert_eq!(
+            texts(&result.note),
+            vec!["One", "From local", "Shared", "From remote", "Two", "Three"]
+        );
+        let swapped = Note::merge(&base_note(), &remote, &local);
+        assert_eq!(
+            texts(&swapped.note),
+            vec!["One", "Shared", "From remote", "From local", "Two", "Three"]
+        );
+    }
+
+    #[test]
+    fn test_merge_conflicting_modifies() {
+        let local = note_from_children(json!([
+            paragraph_json("One"),
+            paragraph_json("Two, local"),
+            paragraph_json("Three"),
+        ]));
+        let remote = note_from_children(json!([
+            paragraph_json("One"),
+            paragraph_json("Two, remote"),
+            paragraph_json("Three"),
+        ]));
+
+        let result = Note::merge(&base_note(), &local, &remote);
+        assert_eq!(texts(&result.note), vec!["One", "Two, local", "Three"]);
+        assert_eq!(result.conflicts.len(), 1);
+
+        let conflict = &result.conflicts[0];
+        assert_eq!(conflict.index, 1);
+        assert_eq!(base_note().render_node(&conflict.base).1, "Two");
+        assert_eq!(local.render_node(conflict.local.as_ref().unwrap()).1, "Two, local");
+        assert_eq!(remote.render_node(conflict.remote.as_ref().unwrap()).1, "Two, remote");
+
+        // The same edit on both sides is not a conflict
+        let result = Note::merge(&base_note(), &local, &local);
+        assert!(result.conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_merge_delete_vs_modify() {
+        let local = note_from_children(json!([
+            paragraph_json("One"),
+            paragraph_json("Three"),
+        ]));
+        let remote = note_from_children(json!([
+            paragraph_json("One"),
+            paragraph_json("Two, remote"),
+            paragraph_json("Three"),
+        ]));
+
+        let result = Note::merge(&base_note(), &local, &remote);
+        assert_eq!(texts(&result.note), vec!["One", "Two, remote", "Three"]);
+        assert_eq!(result.conflicts.len(), 1);
+        assert!(result.conflicts[0].local.is_none());
+        assert!(result.conflicts[0].remote.is_some());
+
+        // A deletion of an unchanged node is applied
+        let result = Note::merge(&base_note(), &local, &base_note());
+        assert!(result.conflicts.is_empty());
+        assert_eq!(texts(&result.note), vec!["One", "Three"]);
+    }
+
+    #[test]
+    fn test_merge_format_only_edit() {
+        let bold_two = json!({ "type": "paragraph", "version": 1, "children": [text_json("Two", 1)] });
+        let local = note_from_children(json!([
+            paragraph_json("One, local"),
+            paragraph_json("Two"),
+            paragraph_json("Three"),
+        ]));
+        let remote = note_from_children(json!([paragraph_json("One"), bold_two, paragraph_json("Three")]));
+
+        // The remote bolding is kept alongside the local edit
+        let result = Note::merge(&base_note(), &local, &remote);
+        assert!(result.conflicts.is_empty());
+        assert_eq!(texts(&result.note), vec!["One, local", "Two", "Three"]);
+        assert_eq!(
+            serde_json::to_value(&result.note.lexical_state.root.children[1]).unwrap(),
+            serde_json::to_value(&remote.lexical_state.root.children[1]).unwrap()
+        );
+
+        // Lexical keys alone are not an edit
+        let mut keyed = base_note();
+        keyed.lexical_state.root.children[1].base_mut().key = Some("k2".to_string());
+        let result = Note::merge(&base_note(), &keyed, &remote);
+        assert!(result.conflicts.is_empty());
+        assert_eq!(result.note.diff(&remote), vec![]);
+    }
+}
@@ -0,0 +1,222 @@
+use std::fmt;
+
+use serde_json::{Value, json};
+
+use super::Note;
+
+/// The schema version of the notes written by this version of the crate.
+///
+/// Notes without a `schemaVersion` predate versioning and count as version 0.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// The migrations in order, the one at index `i` upgrading version `i` to `i + 1`.
+const MIGRATIONS: [fn(&mut Value); SCHEMA_VERSION as usize] = [drop_is_active, wrap_root_text];
+
+/// Error returned when a note cannot be migrated to the current schema.
+#[derive(Debug)]
+pub enum MigrateError {
+    /// The note was written by a newer app, with a schema this crate doesn't know
+    NewerVersion { version: u32 },
+    /// The note is not a valid note, even after migrating
+    Invalid(serde_json::Error),
+}
+
+impl fmt::Display for MigrateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrateError::NewerVersion { version } => write!(
+                f,
+                "The note requires a newer app: its schema version is {version}, this app supports up to {SCHEMA_VERSION}"
+            ),
+            MigrateError::Invalid(err) => write!(f, "Invalid note JSON: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for MigrateError {}
+
+/// The notes parsed from a batch, and the errors of the ones that failed.
+#[derive(Debug)]
+pub struct ParsedNotes {
+    /// The notes that parsed, with their index in the batch
+    pub notes: Vec<(usize, Note)>,
+    /// The notes that failed, with their index in the batch
+    pub errors: Vec<(usize, MigrateError)>,
+}
+
+impl ParsedNotes {
+    /// The indices of the notes that failed to parse.
+    pub fn failed_indices(&self) -> Vec<usize> {
+        self.errors.iter().map(|(index, _)| *index).collect()
+    }
+}
+
+impl Note {
+    /// Parse a JSON array of notes, migrating each like [`migrate`].
+    ///
+    /// A note that fails doesn't fail the batch, its error is kept with its index
+    /// instead. Fails only if the JSON is not an array.
+    pub fn parse_many(json: &str) -> anyhow::Result<ParsedNotes> {
+        let values: Vec<Value> =
+            serde_json::from_str(json).map_err(|e| anyhow::anyhow!("Expected a JSON array of notes: {}", e))?;
+
+        let mut parsed = ParsedNotes {
+            notes: Vec::new(),
+            errors: Vec::new(),
+        };
+        for (index, value) in values.into_iter().enumerate() {
+            match migrate(value) {
+                Ok(note) => parsed.notes.push((index, note)),
+                Err(err) => parsed.errors.push((index, err)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Upgrade a note JSON of any known schema version and parse it.
+///
+/// The migrations for the versions after the note's run in order, and the note
+/// comes out at [`SCHEMA_VERSION`].
+pub fn migrate(mut value: Value) -> Result<Note, MigrateError> {
+    let version = match value.get("schemaVersion") {
+        None | Some(Value::Null) => 0,
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| MigrateError::Invalid(serde::de::Error::custom("schemaVersion is not a version number")))?,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(MigrateError::NewerVersion { version });
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&mut value);
+    }
+
+    let mut note: Note = serde_json::from_value(value).map_err(MigrateError::Invalid)?;
+    note.schema_version = Some(SCHEMA_VERSION);
+    Ok(note)
+}
+
+/// Call `f` on every node object of the note JSON
+fn for_each_node(value: &mut Value, mut f: impl FnMut(&mut serde_json::Map<String, Value>)) {
+    let Some(root) = value.pointer_mut("/lexicalState/root") else {
+        return;
+    };
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        let Value::Object(node) = node else {
+            continue;
+        };
+        f(node);
+        if let Some(Value::Array(children)) = node.get_mut("children") {
+            stack.extend(children.iter_mut());
+        }
+    }
+}
+
+/// Version 1 stopped writing the unused `isActive` flag of chat sessions
+fn drop_is_active(value: &mut Value) {
+    for_each_node(value, |node| {
+        if node.get("type").and_then(Value::as_str) == Some("chat-session") {
+            node.remove("isActive");
+        }
+    });
+}
+
+/// Version 2 requires root children to be blocks, so a bare root text goes into a paragraph
+fn wrap_root_text(value: &mut Value) {
+    let Some(Value::Array(children)) = value.pointer_mut("/lexicalState/root/children") else {
+        return;
+    };
+    for child in children {
+        if child.get("type").and_then(Value::as_str) == Some("text") {
+            let text = child.take();
+            *child = json!({ "type": "paragraph", "version": 1, "children": [text] });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::note::LexicalNode;
+
+    fn fixture(path: &str) -> Value {
+        let json_content = fs::read_to_string(path).expect("Should be able to read the fixture");
+        serde_json::from_str(&json_content).expect("Should be able to parse the fixture")
+    }
+
+    #[test]
+    fn test_migrate_drops_is_active() {
+        let note = migrate(fixture("assets/legacy_is_active_note.json")).expect("Should migrate the note");
+        assert_eq!(note.schema_version, Some(SCHEMA_VERSION));
+
+        let LexicalNode::ChatSession(session) = &note.lexical_state.root.children[0] else {
+            panic!("Should be a chat session");
+        };
+        assert!(!session.base.extra.contains_key("isActive"));
+        assert_eq!(session.messages.len(), 2);
+        let serialized = serde_json::to_value(&note).unwrap();
+        assert_eq!(serialized["schemaVersion"], SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_migrate_wraps_root_text() {
+        let note = migrate(fixture("assets/legacy_root_text_note.json")).expect("Should migrate the note");
+
+        let LexicalNode::Paragraph(paragraph) = &note.lexical_state.root.children[0] else {
+            panic!("Should be a paragraph");
+        };
+        assert!(matches!(&paragraph.children[..], [LexicalNode::Text(text)] if text.format == 1));
+        let texts: Vec<String> = note.get_brief().into_iter().map(|brief| brief.content).collect();
+        assert_eq!(texts, vec!["Written before blocks", "A regular paragraph"]);
+    }
+
+    #[test]
+    fn test_parse_many() {
+        let batch = json!([
+            fixture("assets/example_note.json"),
+            { "noteId": "broken" },
+            fixture("assets/legacy_root_text_note.json"),
+            { "schemaVersion": SCHEMA_VERSION + 1, "lexicalState": { "root": { "type": "root", "version": 1, "children": [] } } },
+            "not a note",
+        ]);
+
+        let parsed = Note::parse_many(&batch.to_string()).expect("Should parse the batch");
+        let indices: Vec<usize> = parsed.notes.iter().map(|(index, _)| *index).collect();
+        assert_eq!(indices, vec![0, 2]);
+        assert_eq!(parsed.notes[1].1.note_id.as_deref(), Some("legacy-root-text"));
+        assert_eq!(parsed.failed_indices(), vec![1, 3, 4]);
+        assert!(parsed.errors[0].1.to_string().contains("lexicalState"));
+        assert!(matches!(parsed.errors[1].1, MigrateError::NewerVersion { .. }));
+
+        assert!(Note::parse_many("[]").unwrap().notes.is_empty());
+        let err = Note::parse_many(r#"{"noteId": null}"#).unwrap_err();
+        assert!(err.to_string().starts_with("Expected a JSON array of notes"));
+    }
+
+    #[test]
+    fn test_migrate_versions() {
+        // A current note is only parsed
+        let mut current = fixture("assets/example_chat_note.json");
+        current["schemaVersion"] = json!(SCHEMA_VERSION);
+        let note = migrate(current).expect("Should parse the current note");
+        let LexicalNode::ChatSession(session) = &note.lexical_state.root.children[1] else {
+            panic!("Should be a chat session");
+        };
+        assert!(session.base.extra.contains_key("isActive"));
+
+        let mut newer = fixture("assets/example_note.json");
+        newer["schemaVersion"] = json!(SCHEMA_VERSION + 1);
+        let err = migrate(newer).unwrap_err();
+        assert!(matches!(err, MigrateError::NewerVersion { version } if version == SCHEMA_VERSION + 1));
+        assert!(err.to_string().starts_with("The note requires a newer app"));
+
+        assert!(matches!(migrate(json!({ "noteId": null })), Err(MigrateError::Invalid(_))));
+        assert!(matches!(migrate(json!({ "schemaVersion": "two" })), Err(MigrateError::Invalid(_))));
+    }
+}
@@ -0,0 +1,173 @@
+use std::cell::Cell;
+
+use serde::{Serialize, Serializer, ser::Error};
+
+use super::{
+    AutoLinkNode, CodeNode, HeadingNode, LexicalNode, LinkNode, ListItemNode, ListNode, MAX_NESTING_DEPTH,
+    ParagraphNode, QuoteNode, TableCellNode, TableNode, TableRowNode,
+};
+
+impl Drop for LexicalNode {
+    /// Drop the descendants one by one, so that only childless nodes are dropped recursively.
+    fn drop(&mut self) {
+        let Some(children) = self.children_mut() else {
+            return;
+        };
+        let mut stack = std::mem::take(children);
+        while let Some(mut node) = stack.pop() {
+            if let Some(children) = node.children_mut() {
+                stack.append(children);
+            }
+        }
+    }
+}
+
+impl Clone for LexicalNode {
+    /// Clone the tree depth-first with an explicit stack, building each node after its children.
+    fn clone(&self) -> Self {
+        let mut stack = vec![(self, false)];
+        let mut clones = Vec::new();
+
+        while let Some((node, expanded)) = stack.pop() {
+            let children = node.children().map_or(&[][..], Vec::as_slice);
+            if !expanded && !children.is_empty() {
+                stack.push((node, true));
+                stack.extend(children.iter().rev().map(|child| (child, false)));
+                continue;
+            }
+
+            let mut clone = node.clone_without_children();
+            if let Some(cloned_children) = clone.children_mut() {
+                *cloned_children = clones.split_off(clones.len() - children.len());
+            }
+            clones.push(clone);
+        }
+
+        clones.pop().expect("The node itself is cloned last")
+    }
+}
+
+impl LexicalNode {
+    /// Clone the node with its children left empty
+    fn clone_without_children(&self) -> LexicalNode {
+        match self {
+            LexicalNode::Paragraph(node) => LexicalNode::Paragraph(ParagraphNode {
+                children: Vec::new(),
+                text_format: node.text_format,
+                text_style: node.text_style.clone(),
+                base: node.base.clone(),
+            }),
+            LexicalNode::Heading(node) => LexicalNode::Heading(HeadingNode {
+                tag: node.tag,
+                children: Vec::new(),
+                base: node.base.clone(),
+            }),
+            LexicalNode::List(node) => LexicalNode::List(ListNode {
+                list_type: node.list_type.clone(),
+                start: node.start,
+                tag: node.tag,
+                children: Vec::new(),
+                base: node.base.clone(),
+            }),
+            LexicalNode::ListItem(node) => LexicalNode::ListItem(ListItemNode {
+                children: Vec::new(),
+                value: node.value,
+                base: node.base.clone(),
+            }),
+            LexicalNode::Quote(node) => LexicalNode::Quote(QuoteNode {
+                children: Vec::new(),
+                base: node.base.clone(),
+            }),
+            LexicalNode::Code(node) => LexicalNode::Code(CodeNode {
+                text: node.text.clone(),
+                language: node.language.clone(),
+                children: node.children.as_ref().map(|_| Vec::new()),
+                format: node.format.clone(),
+                base: node.base.clone(),
+            }),
+            LexicalNode::Link(node) => LexicalNode::Link(LinkNode {
+                url: node.url.clone(),
+                rel: node.rel.clone(),
+                target: node.target.clone(),
+                title: node.title.clone(),
+                children: Vec::new(),
+                base: node.base.clone(),
+            }),
+            LexicalNode::AutoLink(node) => LexicalNode::AutoLink(AutoLinkNode {
+                url: node.url.clone(),
+                children: Vec::new(),
+                base: node.base.clone(),
+            }),
+            LexicalNode::Table(node) => LexicalNode::Table(TableNode {
+                children: Vec::new(),
+                base: node.base.clone(),
+            }),
+            LexicalNode::TableRow(node) => LexicalNode::TableRow(TableRowNode {
+                children: Vec::new(),
+                base: node.base.clone(),
+            }),
+            LexicalNode::TableCell(node) => LexicalNode::TableCell(TableCellNode {
+                children: Vec::new(),
+                header_state: node.header_state,
+                col_span: node.col_span,
+                row_span: node.row_span,
+                width: node.width,
+                background_color: node.background_color.clone(),
+                base: node.base.clone(),
+            }),
+            LexicalNode::Text(node) => LexicalNode::Text(node.clone()),
+            LexicalNode::CodeHighlight(node) => LexicalNode::CodeHighlight(node.clone()),
+            LexicalNode::LineBreak(node) => LexicalNode::LineBreak(node.clone()),
+            LexicalNode::Hashtag(node) => LexicalNode::Hashtag(node.clone()),
+            LexicalNode::PageBreak(node) => LexicalNode::PageBreak(node.clone()),
+            LexicalNode::AIEmbedding(node) => LexicalNode::AIEmbedding(node.clone()),
+            LexicalNode::VoiceInput(node) => LexicalNode::VoiceInput(node.clone()),
+            LexicalNode::ChatMessage(node) => LexicalNode::ChatMessage(node.clone()),
+            LexicalNode::ChatSession(node) => LexicalNode::ChatSession(node.clone()),
+            LexicalNode::Mention(node) => LexicalNode::Mention(node.clone()),
+        }
+    }
+}
+
+thread_local! {
+    /// The number of child lists being serialized, innermost included
+    static SERIALIZE_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Serialize child nodes, failing on trees nested deeper than [`MAX_NESTING_DEPTH`].
+///
+/// Parsing already rejects such notes, but trees built in code have no bound. Serde
+/// serializes nested values by recursion, so the depth is limited instead.
+pub(super) fn serialize_children<S: Serializer>(children: &[LexicalNode], serializer: S) -> Result<S::Ok, S::Error> {
+    let depth = SERIALIZE_DEPTH.get();
+    if depth >= MAX_NESTING_DEPTH {
+        return Err(S::Error::custom(format!(
+            "Note nesting exceeds the maximum depth of {MAX_NESTING_DEPTH}"
+        )));
+    }
+
+    SERIALIZE_DEPTH.set(depth + 1);
+    let result = children.serialize(serializer);
+    SERIALIZE_DEPTH.set(depth);
+    result
+}
+
+/// Serialize the optional children of a code node like [`serialize_children`]
+pub(super) fn serialize_code_children<S: Serializer>(
+    children: &Option<Vec<LexicalNode>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match children {
+        Some(children) => serializer.serialize_some(&SerializeChildren(children)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Child nodes serialized with [`serialize_children`]
+struct SerializeChildren<'a>(&'a [LexicalNode]);
+
+impl Serialize for SerializeChildren<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_children(self.0, serializer)
+    }
+}
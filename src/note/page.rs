@@ -0,0 +1,161 @@
+use super::{BaseNodeProperties, HeadingTag, LexicalNode, LexicalState, Note, RootNode};
+
+/// Options for splitting a note into pages with [`Note::page_sections`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PageSectionOptions {
+    /// Also start a new section at every root H1 heading
+    pub split_at_h1: bool,
+}
+
+/// A section of the note between page breaks.
+#[derive(Debug, Clone)]
+pub struct NoteSection<'a> {
+    /// The title of the section, derived like [`Note::title`] from its nodes
+    pub title: Option<String>,
+    /// The range of root node indices covered by the section, without the page breaks
+    pub range: std::ops::Range<usize>,
+    /// The root nodes in the section
+    pub nodes: &'a [LexicalNode],
+}
+
+impl NoteSection<'_> {
+    /// Copy the section into a note of its own, for briefing only the section.
+    ///
+    /// The root nodes are renumbered from 0 in the copy, and `range.start` maps them
+    /// back to the note.
+    pub fn to_note(&self) -> Note {
+        Note {
+            note_id: None,
+            schema_version: None,
+            lexical_state: LexicalState {
+                root: RootNode {
+                    node_type: "root".to_string(),
+                    children: self.nodes.to_vec(),
+                    base: BaseNodeProperties::default(),
+                },
+            },
+            applied_op_ids: Default::default(),
+            extra: serde_json::Map::new(),
+        }
+    }
+}
+
+impl Note {
+    /// Split the note into sections at its root page breaks.
+    ///
+    /// The page breaks belong to no section, and no section is empty, so leading or
+    /// consecutive page breaks add no sections. A note without breaks is one section,
+    /// and an empty note has none. Unlike [`Note::sections`], headings only split the
+    /// note with [`PageSectionOptions::split_at_h1`], where an H1 starts its section.
+    pub fn page_sections(&self, options: PageSectionOptions) -> Vec<NoteSection<'_>> {
+        let children = &self.lexical_state.root.children;
+        let mut sections = Vec::new();
+        let mut start = 0;
+
+        for (index, node) in children.iter().enumerate() {
+            let next_start = match node {
+                LexicalNode::PageBreak(_) => index + 1,
+                LexicalNode::Heading(heading) if options.split_at_h1 && heading.tag == HeadingTag::H1 => index,
+                _ => continue,
+            };
+            self.push_page_section(&mut sections, start..index);
+            start = next_start;
+        }
+        self.push_page_section(&mut sections, start..children.len());
+
+        sections
+    }
+
+    /// Get the page section containing the root node at the cursor.
+    ///
+    /// A cursor on a page break is in the section after it. Returns `None` past the
+    /// last section.
+    pub fn page_section_at_cursor(&self, cursor: usize, options: PageSectionOptions) -> Option<NoteSection<'_>> {
+        self.page_sections(options).into_iter().find(|section| cursor < section.range.end)
+    }
+
+    /// Helper method to add a page section, skipping an empty one
+    fn push_page_section<'a>(&'a self, sections: &mut Vec<NoteSection<'a>>, range: std::ops::Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        let nodes = &self.lexical_state.root.children[range.clone()];
+        sections.push(NoteSection {
+            title: self.title_of(nodes),
+            range,
+            nodes,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::super::tests::{heading_json, note_from_children, paragraph_json};
+    use super::*;
+
+    fn page_break_json() -> serde_json::Value {
+        json!({ "type": "page-break", "version": 1 })
+    }
+
+    fn ranges(sections: &[NoteSection]) -> Vec<std::ops::Range<usize>> {
+        sections.iter().map(|section| section.range.clone()).collect()
+    }
+
+    #[test]
+    fn test_page_sections() {
+        let note = note_from_children(json!([
+            page_break_json(),
+            heading_json("Intro", "h1"),
+            paragraph_json("Welcome"),
+            page_break_json(),
+            page_break_json(),
+            paragraph_json("Second page"),
+            heading_json("Chapter", "h1"),
+            paragraph_json("Body"),
+            page_break_json(),
+        ]));
+
+        let sections = note.page_sections(PageSectionOptions::default());
+        assert_eq!(ranges(&sections), vec![1..3, 5..8]);
+        assert_eq!(sections[0].title.as_deref(), Some("Intro"));
+        assert_eq!(sections[1].title.as_deref(), Some("Chapter"));
+
+        let sections = note.page_sections(PageSectionOptions { split_at_h1: true });
+        assert_eq!(ranges(&sections), vec![1..3, 5..6, 6..8]);
+        assert_eq!(sections[1].title.as_deref(), Some("Second page"));
+
+        let section = sections[2].to_note();
+        let briefs: Vec<String> = section.get_brief().into_iter().map(|brief| brief.content).collect();
+        assert_eq!(briefs, vec!["Chapter", "Body"]);
+    }
+
+    #[test]
+    fn test_page_sections_without_breaks() {
+        let note = note_from_children(json!([paragraph_json("Only"), paragraph_json("Page")]));
+        assert_eq!(ranges(&note.page_sections(PageSectionOptions::default())), vec![0..2]);
+
+        let empty = note_from_children(json!([]));
+        assert!(empty.page_sections(PageSectionOptions::default()).is_empty());
+        let breaks = note_from_children(json!([page_break_json(), page_break_json()]));
+        assert!(breaks.page_sections(PageSectionOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn test_page_section_at_cursor() {
+        let note = note_from_children(json!([
+            paragraph_json("One"),
+            page_break_json(),
+            paragraph_json("Two"),
+            paragraph_json("Three"),
+        ]));
+        let options = PageSectionOptions::default();
+
+        let range = |cursor| note.page_section_at_cursor(cursor, options).map(|section| section.range);
+        assert_eq!(range(0), Some(0..1));
+        assert_eq!(range(1), Some(2..4));
+        assert_eq!(range(3), Some(2..4));
+        assert_eq!(range(4), None);
+    }
+}
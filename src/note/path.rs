@@ -0,0 +1,452 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use super::{LexicalNode, ListNode, Note, TextExtractOptions};
+
+/// The address of a node, as the child indices leading to it from the root.
+///
+/// Displayed and parsed as dot-separated indices, e.g. `3.1.0` for the first child
+/// of the second child of the fourth root node.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct NodePath(pub Vec<usize>);
+
+impl NodePath {
+    /// Get the path of the root node at `index`.
+    pub fn root(index: usize) -> Self {
+        NodePath(vec![index])
+    }
+
+    /// Get the path of the child at `index` of this node.
+    pub fn child(&self, index: usize) -> Self {
+        let mut segments = self.0.clone();
+        segments.push(index);
+        NodePath(segments)
+    }
+
+    /// Split the path into the parent segments and the last index.
+    fn split_last(&self) -> Result<(&[usize], usize), PathError> {
+        match self.0.split_last() {
+            Some((&last, parent)) => Ok((parent, last)),
+            None => Err(PathError::Empty),
+        }
+    }
+}
+
+impl fmt::Display for NodePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let segments: Vec<String> = self.0.iter().map(usize::to_string).collect();
+        write!(f, "{}", segments.join("."))
+    }
+}
+
+impl FromStr for NodePath {
+    type Err = PathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            return Err(PathError::Empty);
+        }
+        s.split('.')
+            .map(|segment| segment.trim().parse())
+            .collect::<Result<_, _>>()
+            .map(NodePath)
+            .map_err(|_| PathError::Invalid(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for NodePath {
+    type Error = PathError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<NodePath> for String {
+    fn from(path: NodePath) -> Self {
+        path.to_string()
+    }
+}
+
+/// Error returned when a node path cannot be resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathError {
+    /// The path string is not dot-separated indices
+    Invalid(String),
+    /// The path has no segments
+    Empty,
+    /// The index at `segment` is beyond the `len` children there
+    OutOfBounds {
+        path: NodePath,
+        segment: usize,
+        index: usize,
+        len: usize,
+    },
+    /// The path continues below a node without children
+    NotContainer {
+        path: NodePath,
+        segment: usize,
+        node_type: &'static str,
+    },
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Invalid(path) => write!(f, "Invalid node path \"{path}\", expected indices like \"3.1.0\""),
+            PathError::Empty => write!(f, "The node path is empty"),
+            PathError::OutOfBounds {
+                path,
+                segment,
+                index,
+                len,
+            } => write!(
+                f,
+                "Node path {path}: segment {segment} is out of bounds, index {index} but there are {len} nodes"
+            ),
+            PathError::NotContainer {
+                path,
+                segment,
+                node_type,
+            } => write!(
+                f,
+                "Node path {path}: segment {segment} goes into a {node_type} node, which has no children"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+/// A brief of a node addressed by its path.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PathBrief {
+    pub path: NodePath,
+    pub node_type: String,
+    pub content: String,
+}
+
+impl Note {
+    /// Get the node at `path`.
+    pub fn get_by_path(&self, path: &NodePath) -> Result<&LexicalNode, PathError> {
+        let (parent, last) = path.split_last()?;
+        let siblings = self.children_at(path, parent)?;
+        siblings.get(last).ok_or_else(|| out_of_bounds(path, parent.len(), siblings.len()))
+    }
+
+    /// Get the mutable node at `path`.
+    pub fn get_mut_by_path(&mut self, path: &NodePath) -> Result<&mut LexicalNode, PathError> {
+        let (parent, last) = path.split_last()?;
+        let siblings = self.children_at_mut(path, parent)?;
+        let len = siblings.len();
+        siblings.get_mut(last).ok_or_else(|| out_of_bounds(path, parent.len(), len))
+    }
+
+    /// Insert a node at `path`, shifting the node there and its following siblings.
+    ///
+    /// The last index may be one past the last child to append.
+    pub fn insert_at_path(&mut self, path: &NodePath, node: LexicalNode) -> Result<(), PathError> {
+        let (parent, last) = path.split_last()?;
+        let siblings = self.children_at_mut(path, parent)?;
+        if last > siblings.len() {
+            return Err(out_of_bounds(path, parent.len(), siblings.len()));
+        }
+        siblings.insert(last, node);
+        Ok(())
+    }
+
+    /// Delete the node at `path`, returning it.
+    pub fn delete_at_path(&mut self, path: &NodePath) -> Result<LexicalNode, PathError> {
+        let (parent, last) = path.split_last()?;
+        let siblings = self.children_at_mut(path, parent)?;
+        if last >= siblings.len() {
+            return Err(out_of_bounds(path, parent.len(), siblings.len()));
+        }
+        Ok(siblings.remove(last))
+    }
+
+    /// Move the root node at `from` so that it ends up at index `to`.
+    ///
+    /// `to` counts the nodes after the node is taken out, so moving a node down past
+    /// node `i` means `to = i`, and both indices must be less than the number of root nodes.
+    pub fn move_node(&mut self, from: usize, to: usize) -> Result<(), PathError> {
+        let children = &mut self.lexical_state.root.children;
+        for index in [from, to] {
+            if index >= children.len() {
+                return Err(out_of_bounds(&NodePath::root(index), 0, children.len()));
+            }
+        }
+        let node = children.remove(from);
+        children.insert(to, node);
+        Ok(())
+    }
+
+    /// Get the briefs of the root nodes, list items and table cells, addressed by path.
+    pub fn get_path_brief(&self) -> Vec<PathBrief> {
+        let mut briefs = Vec::new();
+        for (index, node) in self.lexical_state.root.children.iter().enumerate() {
+            self.collect_path_briefs(node, NodePath::root(index), None, 0, &mut briefs);
+        }
+        briefs
+    }
+
+    /// Recursively collect the briefs of the node and its list items and table cells.
+    ///
+    /// Lists and list items are rendered as in their root list, indented by the number of
+    /// lists around them, and list items get the marker of their `parent` list.
+    fn collect_path_briefs(
+        &self,
+        node: &LexicalNode,
+        path: NodePath,
+        parent: Option<&ListNode>,
+        lists: usize,
+        briefs: &mut Vec<PathBrief>,
+    ) {
+        let opts = TextExtractOptions::compat();
+        let (node_type, content) = match (node, parent) {
+            (LexicalNode::ListItem(_), Some(list)) => {
+                let index = *path.0.last().expect("Paths are not empty");
+                ("listitem", self.render_list_item(list, index, lists - 1, &opts))
+            }
+            (LexicalNode::List(list), _) => ("list", self.render_list(list, lists, &opts)),
+            _ => self.render_node(node),
+        };
+        if !content.trim().is_empty() {
+            briefs.push(PathBrief {
+                path: path.clone(),
+                node_type: node_type.to_string(),
+                content,
+            });
+        }
+
+        if let LexicalNode::List(_) | LexicalNode::ListItem(_) | LexicalNode::Table(_) | LexicalNode::TableRow(_) =
+            node
+        {
+            let (parent, lists) = match node {
+                LexicalNode::List(list) => (Some(list), lists + 1),
+                _ => (None, lists),
+            };
+            let children = node.children().map(Vec::as_slice).unwrap_or_default();
+            for (index, child) in children.iter().enumerate() {
+                if matches!(
+                    child,
+                    LexicalNode::List(_) | LexicalNode::ListItem(_) | LexicalNode::TableRow(_) | LexicalNode::TableCell(_)
+                ) {
+                    self.collect_path_briefs(child, path.child(index), parent, lists, briefs);
+                }
+            }
+        }
+    }
+
+    /// Get the children of the node at the `parent` segments of `path`
+    fn children_at(&self, path: &NodePath, parent: &[usize]) -> Result<&Vec<LexicalNode>, PathError> {
+        let mut children = &self.lexical_state.root.children;
+        for (segment, &index) in parent.iter().enumerate() {
+            let node = children
+                .get(index)
+                .ok_or_else(|| out_of_bounds(path, segment, children.len()))?;
+            children = node.children().ok_or_else(|| not_container(path, segment + 1, node))?;
+        }
+        Ok(children)
+    }
+
+    /// Get the mutable children of the node at the `parent` segments of `path`
+    fn children_at_mut(&mut self, path: &NodePath, parent: &[usize]) -> Result<&mut Vec<LexicalNode>, PathError> {
+        let mut children = &mut self.lexical_state.root.children;
+        for (segment, &index) in parent.iter().enumerate() {
+            let len = children.len();
+            let node = children.get_mut(index).ok_or_else(|| out_of_bounds(path, segment, len))?;
+            let node_type = node.type_name();
+            children = node.children_mut().ok_or_else(|| PathError::NotContainer {
+                path: path.clone(),
+                segment: segment + 1,
+                node_type,
+            })?;
+        }
+        Ok(children)
+    }
+}
+
+fn out_of_bounds(path: &NodePath, segment: usize, len: usize) -> PathError {
+    PathError::OutOfBounds {
+        path: path.clone(),
+        segment,
+        index: path.0[segment],
+        len,
+    }
+}
+
+fn not_container(path: &NodePath, segment: usize, node: &LexicalNode) -> PathError {
+    PathError::NotContainer {
+        path: path.clone(),
+        segment,
+        node_type: node.type_name(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::super::tests::{
+        cell_json, list_item_json, list_json, note_from_children, paragraph_json, table_json, text_json,
+    };
+    use super::*;
+
+    fn nested_note() -> Note {
+        note_from_children(json!([
+            paragraph_json("Intro"),
+            list_json("bullet", None, vec![
+                list_item_json(vec![json!({ "type": "text", "version": 1, "text": "First", "format": 0 })]),
+                list_item_json(vec![list_json("number", Some(1), vec![
+                    list_item_json(vec![json!({ "type": "text", "version": 1, "text": "Nested one", "format": 0 })]),
+                    list_item_json(vec![json!({ "type": "text", "version": 1, "text": "Nested two", "format": 0 })]),
+                ])]),
+            ]),
+            table_json(vec![
+                vec![cell_json("Name", 1, 1, 1), cell_json("Role", 1, 1, 1)],
+                vec![cell_json("Ada", 0, 1, 1), cell_json("Engineer", 0, 1, 1)],
+            ]),
+        ]))
+    }
+
+    fn text_at(note: &Note, path: &str) -> String {
+        let node = note.get_by_path(&path.parse().unwrap()).expect("Should resolve the path");
+        note.render_node(node).1
+    }
+
+    #[test]
+    fn test_node_path_display_and_parse() {
+        let path: NodePath = "3.1.0".parse().expect("Should parse the path");
+        assert_eq!(path, NodePath(vec![3, 1, 0]));
+        assert_eq!(path.to_string(), "3.1.0");
+        assert_eq!(NodePath::root(3).child(1).child(0), path);
+
+        assert_eq!("".parse::<NodePath>(), Err(PathError::Empty));
+        assert_eq!("3..1".parse::<NodePath>(), Err(PathError::Invalid("3..1".to_string())));
+        assert_eq!("3.-1".parse::<NodePath>(), Err(PathError::Invalid("3.-1".to_string())));
+
+        // Paths serialize as strings
+        assert_eq!(serde_json::to_value(&path).unwrap(), json!("3.1.0"));
+        assert_eq!(serde_json::from_value::<NodePath>(json!("2.0")).unwrap(), NodePath(vec![2, 0]));
+    }
+
+    #[test]
+    fn test_get_by_path_nested_list_and_table() {
+        let note = nested_note();
+        assert_eq!(text_at(&note, "0"), "Intro");
+        assert_eq!(text_at(&note, "1.0.0"), "First");
+        assert_eq!(text_at(&note, "1.1.0.1.0"), "Nested two");
+        assert_eq!(text_at(&note, "2.1.1"), "Engineer");
+
+        let node = note.get_by_path(&"1.1.0".parse().unwrap()).unwrap();
+        assert_eq!(node.type_name(), "list");
+    }
+
+    #[test]
+    fn test_path_errors_name_the_segment() {
+        let note = nested_note();
+
+        let err = note.get_by_path(&"1.5.0".parse().unwrap()).unwrap_err();
+        assert_eq!(
+            err,
+            PathError::OutOfBounds { path: NodePath(vec![1, 5, 0]), segment: 1, index: 5, len: 2 }
+        );
+        assert_eq!(err.to_string(), "Node path 1.5.0: segment 1 is out of bounds, index 5 but there are 2 nodes");
+
+        let err = note.get_by_path(&"2.0.3".parse().unwrap()).unwrap_err();
+        assert!(matches!(err, PathError::OutOfBounds { segment: 2, index: 3, len: 2, .. }));
+
+        let err = note.get_by_path(&"9".parse().unwrap()).unwrap_err();
+        assert!(matches!(err, PathError::OutOfBounds { segment: 0, index: 9, len: 3, .. }));
+
+        // The text node inside the first list item has no children
+        let err = note.get_by_path(&"1.0.0.0".parse().unwrap()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Node path 1.0.0.0: segment 3 goes into a text node, which has no children"
+        );
+
+        assert_eq!(note.get_by_path(&NodePath::default()).unwrap_err(), PathError::Empty);
+    }
+
+    #[test]
+    fn test_insert_and_delete_at_path() {
+        let mut note = nested_note();
+
+        let item: LexicalNode = serde_json::from_value(list_item_json(vec![
+            json!({ "type": "text", "version": 1, "text": "Nested zero", "format": 0 }),
+        ]))
+        .unwrap();
+        note.insert_at_path(&"1.1.0.0".parse().unwrap(), item.clone()).expect("Should insert");
+        assert_eq!(text_at(&note, "1.1.0.0"), "Nested zero");
+        assert_eq!(text_at(&note, "1.1.0.2"), "Nested two");
+
+        // Appending after the last child is allowed, but not beyond
+        note.insert_at_path(&"1.1.0.3".parse().unwrap(), item.clone()).expect("Should append");
+        let err = note.insert_at_path(&"1.1.0.9".parse().unwrap(), item).unwrap_err();
+        assert!(matches!(err, PathError::OutOfBounds { segment: 3, len: 4, .. }));
+
+        // Delete a cell from a table row
+        let cell = note.delete_at_path(&"2.1.0".parse().unwrap()).expect("Should delete");
+        assert_eq!(note.render_node(&cell).1, "Ada");
+        assert_eq!(text_at(&note, "2.1.0"), "Engineer");
+        assert!(note.delete_at_path(&"2.1.1".parse().unwrap()).is_err());
+
+        if let Ok(LexicalNode::Paragraph(paragraph)) = note.get_mut_by_path(&"0".parse().unwrap()) {
+            paragraph.children.clear();
+        }
+        assert_eq!(text_at(&note, "0"), "");
+    }
+
+    #[test]
+    fn test_path_brief() {
+        let note = nested_note();
+        let paths: Vec<String> = note.get_path_brief().iter().map(|brief| brief.path.to_string()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "0", "1", "1.0", "1.1", "1.1.0", "1.1.0.0", "1.1.0.1", "2", "2.0", "2.0.0", "2.0.1", "2.1",
+                "2.1.0", "2.1.1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_path_brief_nested_list_markers() {
+        let sublist = list_json("bullet", None, vec![
+            list_item_json(vec![text_json("Sub a", 0)]),
+            list_item_json(vec![text_json("Sub b", 0)]),
+        ]);
+        let note = note_from_children(json!([list_json("number", Some(3), vec![
+            list_item_json(vec![text_json("One", 0)]),
+            list_item_json(vec![text_json("Two", 0), sublist]),
+            list_item_json(vec![text_json("Three", 0)]),
+        ])]));
+
+        let briefs: Vec<(String, String)> = note
+            .get_path_brief()
+            .into_iter()
+            .map(|brief| (brief.path.to_string(), brief.content))
+            .collect();
+        let brief = |path: &str| briefs.iter().find(|(p, _)| p == path).map(|(_, content)| content.as_str());
+        assert_eq!(brief("0"), Some("3. One\n4. Two\n  • Sub a\n  • Sub b\n5. Three"));
+        assert_eq!(brief("0.0"), Some("3. One"));
+        assert_eq!(brief("0.1"), Some("4. Two\n  • Sub a\n  • Sub b"));
+        assert_eq!(brief("0.1.1"), Some("  • Sub a\n  • Sub b"));
+        assert_eq!(brief("0.1.1.1"), Some("  • Sub b"));
+        assert_eq!(brief("0.2"), Some("5. Three"));
+
+        // Items without their list are bullets, one per line, with nested lists still indented
+        let LexicalNode::List(list) = &note.lexical_state.root.children[0] else {
+            panic!("Should be a list");
+        };
+        assert_eq!(
+            note.extract_text_from_nodes(&list.children[1..]),
+            "• Two\n  • Sub a\n  • Sub b\n• Three"
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{LexicalNode, Note};
+
+/// A hyperlink found in the note.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkRef {
+    pub url: String,
+    /// The rendered anchor text of the link.
+    pub text: String,
+    /// The root index of the node containing the link.
+    pub index: usize,
+    pub is_autolink: bool,
+}
+
+impl LinkRef {
+    /// Remove links with a duplicate URL, keeping the first occurrence.
+    pub fn dedup(links: Vec<LinkRef>) -> Vec<LinkRef> {
+        let mut seen = std::collections::HashSet::new();
+        links
+            .into_iter()
+            .filter(|link| seen.insert(link.url.clone()))
+            .collect()
+    }
+}
+
+/// A hashtag found in the note.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HashtagRef {
+    /// The hashtag text as written, e.g. `#Budget`.
+    pub text: String,
+    /// The root index of the node containing the hashtag.
+    pub index: usize,
+}
+
+/// A mention found in the note.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MentionRef {
+    pub mention_name: String,
+    /// The display text of the mention.
+    pub text: String,
+    /// The root index of the node containing the mention.
+    pub index: usize,
+    /// The child indices from the root node down to the mention node,
+    /// starting with the root index.
+    pub path: Vec<usize>,
+}
+
+impl Note {
+    /// Get all mentions in the note in document order.
+    pub fn extract_mentions(&self) -> Vec<MentionRef> {
+        self.iter_nodes()
+            .filter_map(|(path, node)| match node {
+                LexicalNode::Mention(mention) => Some(MentionRef {
+                    mention_name: mention.mention_name.clone(),
+                    text: mention.text.clone(),
+                    index: path.0[0],
+                    path: path.0,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Get the distinct mention names in the note, in document order.
+    pub fn mention_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for mention in self.extract_mentions() {
+            if !names.contains(&mention.mention_name) {
+                names.push(mention.mention_name);
+            }
+        }
+        names
+    }
+
+    /// Rewrite the display text of mentions.
+    ///
+    /// The function gets the mention name and returns the new display text,
+    /// or `None` to leave the mention unchanged. Only the text of mention nodes
+    /// is changed, their formatting is kept.
+    pub fn map_mentions(&mut self, mut f: impl FnMut(&str) -> Option<String>) {
+        self.visit_mut(|_, node| {
+            if let LexicalNode::Mention(mention) = node
+                && let Some(text) = f(&mention.mention_name)
+            {
+                mention.text = text;
+            }
+        });
+    }
+
+    /// Get all hashtags in the note in document order.
+    pub fn extract_hashtags(&self) -> Vec<HashtagRef> {
+        self.iter_nodes()
+            .filter_map(|(path, node)| match node {
+                LexicalNode::Hashtag(hashtag) => Some(HashtagRef {
+                    text: hashtag.text.clone(),
+                    index: path.0[0],
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Count the hashtags in the note.
+    ///
+    /// Tags are normalized by stripping the leading `#` and lowercasing,
+    /// so `#Budget` and `#budget` count as the same tag.
+    pub fn hashtag_counts(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for hashtag in self.extract_hashtags() {
+            let tag = hashtag.text.trim_start_matches('#').to_lowercase();
+            *counts.entry(tag).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Get all hyperlinks in the note in document order, including duplicates.
+    pub fn extract_links(&self) -> Vec<LinkRef> {
+        self.iter_nodes()
+            .filter_map(|(path, node)| match node {
+                LexicalNode::Link(link) => Some(LinkRef {
+                    url: link.url.clone(),
+                    text: self.extract_text_from_nodes(&link.children),
+                    index: path.0[0],
+                    is_autolink: false,
+                }),
+                LexicalNode::AutoLink(auto_link) => {
+                    let text = self.extract_text_from_nodes(&auto_link.children);
+                    Some(LinkRef {
+                        text: if text.is_empty() { auto_link.url.clone() } else { text },
+                        url: auto_link.url.clone(),
+                        index: path.0[0],
+                        is_autolink: true,
+                    })
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tests::{
+        cell_json, list_item_json, list_json, note_from_children, table_json, text_json,
+    };
+
+    fn link_json(url: &str, text: &str) -> serde_json::Value {
+        serde_json::json!({ "type": "link", "version": 1, "url": url, "children": [text_json(text, 0)] })
+    }
+
+    #[test]
+    fn test_extract_links() {
+        let mut table = table_json(vec![vec![cell_json("", 0, 1, 1)]]);
+        table["children"][0]["children"][0]["children"][0]["children"] =
+            serde_json::json!([link_json("https://b.example", "Cell link")]);
+
+        let note = note_from_children(serde_json::json!([
+            {
+                "type": "paragraph",
+                "version": 1,
+                "children": [text_json("Read ", 0), link_json("https://a.example", "the docs")],
+            },
+            list_json("bullet", None, vec![
+                list_item_json(vec![link_json("https://a.example", "Docs again")]),
+            ]),
+            table,
+            {
+                "type": "quote",
+                "version": 1,
+                "children": [{
+                    "type": "paragraph",
+                    "version": 1,
+                    "children": [{ "type": "autolink", "version": 1, "url": "https://c.example", "children": [] }],
+                }],
+            },
+        ]));
+
+        let links = note.extract_links();
+        let summary: Vec<(&str, &str, usize, bool)> = links
+            .iter()
+            .map(|l| (l.url.as_str(), l.text.as_str(), l.index, l.is_autolink))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("https://a.example", "the docs", 0, false),
+                ("https://a.example", "Docs again", 1, false),
+                ("https://b.example", "Cell link", 2, false),
+                ("https://c.example", "https://c.example", 3, true),
+            ]
+        );
+
+        let unique = LinkRef::dedup(links);
+        assert_eq!(unique.len(), 3);
+        assert_eq!(unique[0].text, "the docs");
+    }
+
+    fn hashtag_json(text: &str) -> serde_json::Value {
+        serde_json::json!({ "type": "hashtag", "version": 1, "text": text, "format": 0 })
+    }
+
+    #[test]
+    fn test_extract_hashtags() {
+        let mut table = table_json(vec![vec![cell_json("", 0, 1, 1)]]);
+        table["children"][0]["children"][0]["children"][0]["children"] =
+            serde_json::json!([hashtag_json("#budget")]);
+
+        let note = note_from_children(serde_json::json!([
+            {
+                "type": "paragraph",
+                "version": 1,
+                "children": [hashtag_json("#Budget"), text_json(" and ", 0), hashtag_json("#Q3")],
+            },
+            list_json("bullet", None, vec![
+                list_item_json(vec![hashtag_json("#BUDGET")]),
+            ]),
+            table,
+        ]));
+
+        let hashtags = note.extract_hashtags();
+        let summary: Vec<(&str, usize)> = hashtags.iter().map(|h| (h.text.as_str(), h.index)).collect();
+        assert_eq!(summary, vec![("#Budget", 0), ("#Q3", 0), ("#BUDGET", 1), ("#budget", 2)]);
+
+        let counts = note.hashtag_counts();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts.get("budget"), Some(&3));
+        assert_eq!(counts.get("q3"), Some(&1));
+    }
+
+    fn mention_json(name: &str, text: &str, format: u32) -> serde_json::Value {
+        serde_json::json!({ "type": "mention", "version": 1, "mentionName": name, "text": text, "format": format })
+    }
+
+    fn mention_note() -> Note {
+        note_from_children(serde_json::json!([
+            {
+                "type": "heading",
+                "version": 1,
+                "tag": "h2",
+                "children": [text_json("Owner: ", 0), mention_json("alice", "@Alice", 1)],
+            },
+            list_json("bullet", None, vec![
+                list_item_json(vec![text_json("Review by ", 0), mention_json("bob", "@Bob", 0)]),
+                list_item_json(vec![mention_json("alice", "@Alice", 2)]),
+            ]),
+        ]))
+    }
+
+    #[test]
+    fn test_extract_mentions() {
+        let mentions = mention_note().extract_mentions();
+        let summary: Vec<(&str, &str, usize, Vec<usize>)> = mentions
+            .iter()
+            .map(|m| (m.mention_name.as_str(), m.text.as_str(), m.index, m.path.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("alice", "@Alice", 0, vec![0, 1]),
+                ("bob", "@Bob", 1, vec![1, 0, 1]),
+                ("alice", "@Alice", 1, vec![1, 1, 0]),
+            ]
+        );
+        assert_eq!(mention_note().mention_names(), vec!["alice", "bob"]);
+    }
+
+    #[test]
+    fn test_map_mentions() {
+        let mut note = mention_note();
+        note.map_mentions(|name| (name == "alice").then(|| "@Alice Smith".to_string()));
+
+        let texts: Vec<String> = note.extract_mentions().into_iter().map(|m| m.text).collect();
+        assert_eq!(texts, vec!["@Alice Smith", "@Bob", "@Alice Smith"]);
+
+        // Formatting of the mentions and the surrounding text is untouched
+        let LexicalNode::Heading(heading) = &note.lexical_state.root.children[0] else {
+            panic!("Expected a heading node");
+        };
+        let LexicalNode::Mention(mention) = &heading.children[1] else {
+            panic!("Expected a mention node");
+        };
+        assert_eq!(mention.format, 1);
+        let LexicalNode::Text(text) = &heading.children[0] else {
+            panic!("Expected a text node");
+        };
+        assert_eq!(text.text, "Owner: ");
+    }
+}
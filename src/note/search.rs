@@ -0,0 +1,209 @@
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use super::{LexicalNode, Note, TextExtractOptions};
+
+/// Number of characters of context on each side of a search hit snippet.
+const SNIPPET_CONTEXT: usize = 30;
+
+/// Options for searching a note.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchOptions {
+    /// Match the letter case of the query exactly.
+    pub case_sensitive: bool,
+    /// Only match the query as a whole word.
+    pub whole_word: bool,
+}
+
+/// A match of a search query in the note.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchHit {
+    /// The root index of the node containing the match.
+    pub index: usize,
+    /// The child indices from the root node down to the matching node,
+    /// starting with the root index.
+    pub path: Vec<usize>,
+    /// The byte range of the match in the matching node's text.
+    pub byte_range: Range<usize>,
+    /// The character range of the match in the matching node's text.
+    pub char_range: Range<usize>,
+    /// The match with some surrounding text.
+    pub snippet: String,
+}
+
+impl Note {
+    /// Search the text of the note.
+    ///
+    /// Matching runs on the text of each block (paragraph, heading, list item,
+    /// table cell, ...), with adjacent text runs merged, so a match may span
+    /// differently formatted text nodes. Matches spanning two blocks are not found.
+    pub fn search(&self, query: &str, opts: SearchOptions) -> Vec<SearchHit> {
+        let query: Vec<char> = query.chars().collect();
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        let targets = self.search_targets();
+
+        let mut hits = Vec::new();
+        for (path, text) in targets {
+            for (byte_range, char_range, snippet) in find_matches(&text, &query, opts) {
+                hits.push(SearchHit {
+                    index: path[0],
+                    path: path.clone(),
+                    byte_range,
+                    char_range,
+                    snippet,
+                });
+            }
+        }
+
+        hits
+    }
+
+    /// Get the blocks to search with their paths and texts, in document order
+    fn search_targets(&self) -> Vec<(Vec<usize>, String)> {
+        let opts = TextExtractOptions::compat();
+        let mut targets = Vec::new();
+        for (path, node) in self.iter_nodes() {
+            // Inline nodes are searched in the text of their block
+            if path.0.len() > 1 && is_inline(node) {
+                continue;
+            }
+
+            let Some(children) = node.children() else {
+                // Leaf nodes are searched in their rendered text
+                targets.push((path.0, self.render_node_with(node, &opts).1));
+                continue;
+            };
+
+            // Inline children form the text of this node, block children are searched on their own
+            let text: String = children
+                .iter()
+                .filter(|child| is_inline(child))
+                .map(|child| self.extract_text_with(std::slice::from_ref(child), &opts))
+                .collect();
+            if !text.is_empty() {
+                targets.push((path.0, text));
+            }
+        }
+        targets
+    }
+}
+
+/// Whether the node is inline content of its parent block
+fn is_inline(node: &LexicalNode) -> bool {
+    match node {
+        LexicalNode::Code(code) => code.children.is_none(),
+        LexicalNode::Text(_)
+        | LexicalNode::CodeHighlight(_)
+        | LexicalNode::LineBreak(_)
+        | LexicalNode::Link(_)
+        | LexicalNode::AutoLink(_)
+        | LexicalNode::Hashtag(_)
+        | LexicalNode::Mention(_) => true,
+        _ => false,
+    }
+}
+
+/// Find the non-overlapping matches of the query in the text
+fn find_matches(text: &str, query: &[char], opts: SearchOptions) -> Vec<(Range<usize>, Range<usize>, String)> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let chars_eq = |a: char, b: char| {
+        if opts.case_sensitive {
+            a == b
+        } else {
+            a == b || a.to_lowercase().eq(b.to_lowercase())
+        }
+    };
+    let is_word_char = |i: usize| chars.get(i).is_some_and(|(_, c)| c.is_alphanumeric() || *c == '_');
+
+    let mut matches = Vec::new();
+    let mut start = 0;
+    while start + query.len() <= chars.len() {
+        let end = start + query.len();
+        let is_match = chars[start..end]
+            .iter()
+            .zip(query)
+            .all(|((_, a), b)| chars_eq(*a, *b));
+        let is_whole_word = !opts.whole_word
+            || ((start == 0 || !is_word_char(start - 1)) && !is_word_char(end));
+
+        if is_match && is_whole_word {
+            let byte_start = chars[start].0;
+            let byte_end = chars.get(end).map_or(text.len(), |(i, _)| *i);
+
+            let snippet_start = chars[start.saturating_sub(SNIPPET_CONTEXT)].0;
+            let snippet_end = chars
+                .get(end + SNIPPET_CONTEXT)
+                .map_or(text.len(), |(i, _)| *i);
+
+            matches.push((
+                byte_start..byte_end,
+                start..end,
+                text[snippet_start..snippet_end].to_string(),
+            ));
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tests::{list_item_json, list_json, note_from_children, paragraph_json, text_json};
+
+    fn search_note() -> Note {
+        note_from_children(serde_json::json!([
+            paragraph_json("The Budget is tight."),
+            {
+                "type": "paragraph",
+                "version": 1,
+                "children": [text_json("Review the bud", 0), text_json("get", 1), text_json(" today", 0)],
+            },
+            list_json("bullet", None, vec![
+                list_item_json(vec![text_json("Budgets for Ünïcode: budget", 0)]),
+            ]),
+        ]))
+    }
+
+    #[test]
+    fn test_search_case_insensitive() {
+        let hits = search_note().search("budget", SearchOptions::default());
+        let locations: Vec<(usize, Vec<usize>)> = hits.iter().map(|h| (h.index, h.path.clone())).collect();
+        assert_eq!(
+            locations,
+            vec![(0, vec![0]), (1, vec![1]), (2, vec![2, 0]), (2, vec![2, 0])]
+        );
+
+        // The match across two differently formatted text nodes
+        assert_eq!(hits[1].byte_range, 11..17);
+        assert_eq!(hits[1].snippet, "Review the budget today");
+
+        // Byte and char offsets differ after non-ASCII characters
+        assert_eq!(hits[3].char_range, 21..27);
+        assert_eq!(hits[3].byte_range, 23..29);
+    }
+
+    #[test]
+    fn test_search_options() {
+        let note = search_note();
+
+        let hits = note.search("Budget", SearchOptions { case_sensitive: true, whole_word: false });
+        assert_eq!(hits.iter().map(|h| h.index).collect::<Vec<_>>(), vec![0, 2]);
+
+        let hits = note.search("budget", SearchOptions { case_sensitive: false, whole_word: true });
+        assert_eq!(hits.iter().map(|h| h.index).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(hits[2].char_range, 21..27);
+
+        assert!(note.search("", SearchOptions::default()).is_empty());
+        assert!(note.search("missing", SearchOptions::default()).is_empty());
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, FixedOffset, ParseError};
+
+use super::{BaseNodeProperties, ChatMessageNode, ChatSessionMessage, ChatSessionNode, LexicalNode, MessageSender, Note};
+use crate::clock::now_iso8601;
+
+/// Error returned when a chat session is not in the note.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotFound(pub String);
+
+impl std::fmt::Display for NotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Chat session not found: {}", self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+impl Note {
+    /// Get all chat sessions embedded in the note, in document order.
+    pub fn chat_sessions(&self) -> Vec<&ChatSessionNode> {
+        self.iter_nodes()
+            .filter_map(|(_, node)| match node {
+                LexicalNode::ChatSession(session) => Some(session),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Find a chat session by its session id.
+    pub fn find_chat_session(&self, session_id: &str) -> Option<&ChatSessionNode> {
+        self.chat_sessions()
+            .into_iter()
+            .find(|session| session.session_id == session_id)
+    }
+
+    /// Find a mutable chat session by its session id.
+    pub fn find_chat_session_mut(&mut self, session_id: &str) -> Option<&mut ChatSessionNode> {
+        find_chat_session_in(&mut self.lexical_state.root.children, session_id)
+    }
+
+    /// Append a message to a chat session.
+    ///
+    /// The message id is replaced with the next sequential id of the session.
+    pub fn append_message_to_session(
+        &mut self,
+        session_id: &str,
+        mut message: ChatSessionMessage,
+    ) -> Result<(), NotFound> {
+        let session = self
+            .find_chat_session_mut(session_id)
+            .ok_or_else(|| NotFound(session_id.to_string()))?;
+
+        message.id = session.next_message_id();
+        session.messages.push(message);
+        Ok(())
+    }
+
+    /// Append a message sent now to a chat session, saving the conversation in the note.
+    ///
+    /// A missing session is created at the end of the note.
+    pub fn append_to_session(&mut self, session_id: &str, sender: MessageSender, content: impl Into<String>) {
+        if self.find_chat_session(session_id).is_none() {
+            self.lexical_state.root.children.push(LexicalNode::ChatSession(ChatSessionNode {
+                session_id: session_id.to_string(),
+                messages: Vec::new(),
+                base: BaseNodeProperties::default(),
+            }));
+        }
+
+        self.append_message_to_session(session_id, ChatSessionMessage::new(sender, content))
+            .expect("The session exists");
+    }
+}
+
+impl ChatSessionNode {
+    /// Get the id for the next message of the session.
+    ///
+    /// This is one more than the highest id, or the lowest unused id once that reaches `u32::MAX`.
+    pub fn next_message_id(&self) -> u32 {
+        let Some(max) = self.messages.iter().map(|msg| msg.id).max() else {
+            return 1;
+        };
+        max.checked_add(1).unwrap_or_else(|| {
+            let used: HashSet<u32> = self.messages.iter().map(|msg| msg.id).collect();
+            (1..u32::MAX)
+                .find(|id| !used.contains(id))
+                .expect("A session has fewer messages than ids")
+        })
+    }
+
+    /// Get the messages in chronological order.
+    ///
+    /// Messages with unparseable timestamps keep their relative order after the others.
+    pub fn sorted_messages(&self) -> Vec<&ChatSessionMessage> {
+        let mut messages: Vec<_> = self
+            .messages
+            .iter()
+            .map(|msg| (msg.timestamp_parsed().ok(), msg))
+            .collect();
+        messages.sort_by_key(|(time, _)| (time.is_none(), *time));
+        messages.into_iter().map(|(_, msg)| msg).collect()
+    }
+}
+
+impl ChatSessionMessage {
+    /// Create a message sent now.
+    ///
+    /// The id is assigned when the message is appended to a session.
+    pub fn new(sender: MessageSender, content: impl Into<String>) -> Self {
+        Self {
+            id: 0,
+            sender,
+            content: content.into(),
+            timestamp: now_iso8601(),
+        }
+    }
+
+    /// Parse the RFC 3339 timestamp of the message.
+    pub fn timestamp_parsed(&self) -> Result<DateTime<FixedOffset>, ParseError> {
+        DateTime::parse_from_rfc3339(&self.timestamp)
+    }
+}
+
+impl ChatMessageNode {
+    /// Parse the RFC 3339 timestamp of the message.
+    pub fn timestamp_parsed(&self) -> Result<DateTime<FixedOffset>, ParseError> {
+        DateTime::parse_from_rfc3339(&self.timestamp)
+    }
+}
+
+/// Recursively find a mutable chat session by its session id
+fn find_chat_session_in<'a>(nodes: &'a mut [LexicalNode], session_id: &str) -> Option<&'a mut ChatSessionNode> {
+    nodes.iter_mut().find_map(|node| match node {
+        LexicalNode::ChatSession(session) => (session.session_id == session_id).then_some(session),
+        _ => node
+            .children_mut()
+            .and_then(|children| find_chat_session_in(children, session_id)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn chat_note() -> Note {
+        let json_content = fs::read_to_string("assets/example_chat_note.json")
+            .expect("Should be able to read assets/example_chat_note.json");
+        serde_json::from_str(&json_content).expect("Should be able to parse example chat note JSON")
+    }
+
+    #[test]
+    fn test_chat_sessions() {
+        let note = chat_note();
+
+        let ids: Vec<&str> = note.chat_sessions().iter().map(|s| s.session_id.as_str()).collect();
+        assert_eq!(ids, vec!["session-planning", "session-budget"]);
+
+        let session = note.find_chat_session("session-budget").expect("Should find the session");
+        assert_eq!(session.messages.len(), 3);
+        assert!(note.find_chat_session("missing").is_none());
+
+        // A stored session can seed a new conversation with the model
+        let messages = session.to_chat_messages();
+        assert_eq!(messages[0].role, "system");
+        assert_eq!(messages[2].role, "assistant");
+        assert_eq!(messages[2].content, "About 12k across three vendors.");
+    }
+
+    #[test]
+    fn test_append_message_to_session() {
+        let mut note = chat_note();
+        let message = ChatSessionMessage {
+            id: 0,
+            sender: MessageSender::User,
+            content: "And in Q3?".to_string(),
+            timestamp: "2025-06-03T14:32:00+02:00".to_string(),
+        };
+
+        note.append_message_to_session("session-budget", message.clone())
+            .expect("Should append to the session");
+        let session = note.find_chat_session("session-budget").expect("Should find the session");
+        assert_eq!(session.messages.len(), 4);
+        // The ids in the fixture go up to 5
+        assert_eq!(session.messages[3].id, 6);
+        assert_eq!(session.messages[3].content, "And in Q3?");
+
+        // Other sessions are untouched
+        let other = note.find_chat_session("session-planning").expect("Should find the session");
+        assert_eq!(other.messages.len(), 2);
+
+        let err = note.append_message_to_session("missing", message).unwrap_err();
+        assert_eq!(err, NotFound("missing".to_string()));
+        assert_eq!(err.to_string(), "Chat session not found: missing");
+    }
+
+    #[test]
+    fn test_next_message_id_reuses_unused_ids_after_the_max() {
+        let mut note = chat_note();
+        let session = note.find_chat_session_mut("session-budget").expect("Should find the session");
+        for (id, msg) in (2..).zip(session.messages.iter_mut()) {
+            msg.id = id;
+        }
+        session.messages[0].id = u32::MAX;
+
+        // Ids 1 and 2 are free, and the next ones skip the ids in use
+        assert_eq!(session.next_message_id(), 1);
+        note.append_message_to_session("session-budget", ChatSessionMessage::new(MessageSender::User, "More"))
+            .expect("Should append to the session");
+        let session = note.find_chat_session("session-budget").expect("Should find the session");
+        assert_eq!(session.next_message_id(), 2);
+        assert_eq!(session.messages.iter().filter(|msg| msg.id == 1).count(), 1);
+    }
+
+    #[test]
+    fn test_new_message_is_timestamped() {
+        let mut note = chat_note();
+        note.append_message_to_session("session-budget", ChatSessionMessage::new(MessageSender::User, "Thanks"))
+            .expect("Should append to the session");
+
+        let session = note.find_chat_session("session-budget").expect("Should find the session");
+        let message = session.messages.last().unwrap();
+        assert_eq!(message.id, 6);
+        assert!(message.timestamp_parsed().is_ok());
+        // The new message is the latest one
+        assert_eq!(session.sorted_messages().last().unwrap().content, "Thanks");
+    }
+
+    #[test]
+    fn test_append_to_session() {
+        let mut note = chat_note();
+        let children = note.lexical_state.root.children.len();
+
+        for (question, answer) in [("Hi", "Hello!"), ("What's next?", "The launch.")] {
+            note.append_to_session("session-new", MessageSender::User, question);
+            note.append_to_session("session-new", MessageSender::Agent, answer);
+        }
+
+        // The session is created once, at the end of the note
+        assert_eq!(note.lexical_state.root.children.len(), children + 1);
+        let session = note.find_chat_session("session-new").expect("Should create the session");
+        let messages: Vec<(u32, &str)> = session.messages.iter().map(|msg| (msg.id, msg.content.as_str())).collect();
+        assert_eq!(messages, vec![(1, "Hi"), (2, "Hello!"), (3, "What's next?"), (4, "The launch.")]);
+        assert!(matches!(session.messages[1].sender, MessageSender::Agent));
+
+        // Existing sessions are appended to in place
+        note.append_to_session("session-budget", MessageSender::User, "And in Q3?");
+        assert_eq!(note.find_chat_session("session-budget").unwrap().messages.len(), 4);
+        assert_eq!(note.lexical_state.root.children.len(), children + 1);
+    }
+
+    #[test]
+    fn test_timestamp_parsed() {
+        let note = chat_note();
+
+        let planning = note.find_chat_session("session-planning").expect("Should find the session");
+        let time = planning.messages[1].timestamp_parsed().expect("Should parse a Z timestamp");
+        assert_eq!(time.offset().local_minus_utc(), 0);
+        assert_eq!(time.timestamp_millis() % 1000, 512);
+
+        let budget = note.find_chat_session("session-budget").expect("Should find the session");
+        let time = budget.messages[0].timestamp_parsed().expect("Should parse an offset timestamp");
+        assert_eq!(time.offset().local_minus_utc(), 2 * 3600);
+        assert_eq!(time.to_rfc3339(), "2025-06-03T14:30:00+02:00");
+
+        let mut message = budget.messages[0].clone();
+        message.timestamp = "yesterday".to_string();
+        assert!(message.timestamp_parsed().is_err());
+    }
+
+    #[test]
+    fn test_sorted_messages() {
+        let mut note = chat_note();
+        let session = note.find_chat_session_mut("session-budget").expect("Should find the session");
+        // 13:00Z is 15:00+02:00, so it is the latest even though it sorts first as a string
+        session.messages[0].timestamp = "2025-06-03T13:00:00Z".to_string();
+        session.messages[1].timestamp = "not a time".to_string();
+
+        let ids: Vec<u32> = session.sorted_messages().iter().map(|msg| msg.id).collect();
+        assert_eq!(ids, vec![5, 1, 2]);
+
+        // The raw timestamps are serialized untouched
+        let json = serde_json::to_value(&*session).unwrap();
+        assert_eq!(json["messages"][0]["timestamp"], "2025-06-03T13:00:00Z");
+        assert_eq!(json["messages"][1]["timestamp"], "not a time");
+    }
+}
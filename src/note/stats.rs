@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::{LexicalNode, Note};
+
+/// Average reading speed used to estimate the reading time.
+const WORDS_PER_MINUTE: usize = 200;
+
+/// Document statistics of a note.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteStats {
+    /// Number of words, using Unicode word segmentation.
+    pub words: usize,
+    /// Number of characters, including whitespace.
+    pub characters: usize,
+    /// Number of characters, excluding whitespace.
+    pub characters_no_whitespace: usize,
+    /// Number of nodes per Lexical type, including nested nodes.
+    pub node_counts: BTreeMap<String, usize>,
+    /// Number of headings per level, e.g. `"h2"`.
+    pub heading_counts: BTreeMap<String, usize>,
+    /// Estimated reading time in minutes, rounded up.
+    pub reading_minutes: usize,
+}
+
+impl Note {
+    /// Compute the document statistics of the note.
+    ///
+    /// Words and characters are counted on the plain text of the note, where
+    /// blocks are separated by newlines. Word segmentation follows Unicode
+    /// (UAX #29), so each CJK ideograph counts as a word.
+    pub fn stats(&self) -> NoteStats {
+        let text = self.to_plain_text();
+        let words = text.unicode_words().count();
+
+        let mut stats = NoteStats {
+            words,
+            characters: text.chars().count(),
+            characters_no_whitespace: text.chars().filter(|c| !c.is_whitespace()).count(),
+            reading_minutes: words.div_ceil(WORDS_PER_MINUTE),
+            ..Default::default()
+        };
+        // Count the nodes per type and the headings per level
+        for (_, node) in self.iter_nodes() {
+            *stats.node_counts.entry(node.type_name().to_string()).or_default() += 1;
+
+            if let LexicalNode::Heading(heading) = node {
+                let level = format!("h{}", heading.tag.level());
+                *stats.heading_counts.entry(level).or_default() += 1;
+            }
+        }
+
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{heading_json, note_from_children, paragraph_json};
+
+    #[test]
+    fn test_stats() {
+        let note = note_from_children(serde_json::json!([
+            heading_json("Budget plan", "h1"),
+            paragraph_json("We spend less."),
+            heading_json("Details", "h2"),
+            {
+                "type": "quote",
+                "version": 1,
+                "children": [paragraph_json("end"), paragraph_json("start")],
+            },
+        ]));
+
+        let stats = note.stats();
+        // "end" and "start" are in separate blocks and must not merge into "endstart"
+        assert_eq!(stats.words, 8);
+        assert_eq!(stats.reading_minutes, 1);
+        assert_eq!(stats.node_counts.get("paragraph"), Some(&3));
+        assert_eq!(stats.node_counts.get("text"), Some(&5));
+        assert_eq!(stats.node_counts.get("quote"), Some(&1));
+        assert_eq!(stats.heading_counts.get("h1"), Some(&1));
+        assert_eq!(stats.heading_counts.get("h2"), Some(&1));
+        assert_eq!(stats.heading_counts.get("h3"), None);
+
+        let text = note.to_plain_text();
+        assert_eq!(stats.characters, text.chars().count());
+        assert_eq!(
+            stats.characters_no_whitespace,
+            "Budgetplan".len() + "Wespendless.".len() + "Details".len() + "endstart".len()
+        );
+    }
+
+    #[test]
+    fn test_stats_cjk() {
+        let note = note_from_children(serde_json::json!([
+            paragraph_json("我们今天开会"),
+            paragraph_json("会议 summary"),
+        ]));
+
+        let stats = note.stats();
+        // Whitespace splitting would find 3 words here
+        assert_eq!(stats.words, 6 + 2 + 1);
+        assert_eq!(stats.characters, 6 + 1 + 10);
+        assert_eq!(stats.characters_no_whitespace, 6 + 9);
+    }
+}
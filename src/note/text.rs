@@ -0,0 +1,847 @@
+use super::code::{fence_code, inline_code};
+use super::{LexicalNode, ListItemNode, ListNode, ListType, NodeIter, Note, TableNode};
+
+/// The deepest node nesting rendered to text by default, deeper content is left out.
+pub const MAX_TEXT_DEPTH: usize = 64;
+
+/// Options for extracting the text of nodes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextExtractOptions {
+    /// Put before a block following other text, unless that text ends a line
+    pub block_separator: String,
+    /// Put before the text of a bullet list item
+    pub item_prefix: String,
+    /// Put between the cells of a table row, a separator containing "|" makes a pipe table
+    pub cell_separator: String,
+    /// Whether AI embeddings, voice inputs and chat nodes contribute their text
+    pub include_custom_nodes: bool,
+    /// The deepest node nesting rendered, deeper content is left out
+    pub max_depth: usize,
+}
+
+impl TextExtractOptions {
+    /// The text used so far by briefs, plain-text export and search.
+    pub fn compat() -> Self {
+        Self {
+            block_separator: "\n".to_string(),
+            item_prefix: "• ".to_string(),
+            cell_separator: " | ".to_string(),
+            include_custom_nodes: true,
+            max_depth: MAX_TEXT_DEPTH,
+        }
+    }
+
+    /// Text for the agent prompt, with blank lines between blocks and "- " bullets.
+    pub fn readable() -> Self {
+        Self {
+            block_separator: "\n\n".to_string(),
+            item_prefix: "- ".to_string(),
+            ..Self::compat()
+        }
+    }
+}
+
+impl Default for TextExtractOptions {
+    fn default() -> Self {
+        Self::compat()
+    }
+}
+
+/// A piece of text to render, with the nesting depth of its nodes.
+enum Job<'a> {
+    /// The text of sibling nodes, concatenated without new lines if `separate`
+    Nodes {
+        nodes: Vec<&'a LexicalNode>,
+        separate: bool,
+        depth: usize,
+    },
+    /// The text a single node contributes among its siblings
+    Node { node: &'a LexicalNode, depth: usize },
+    /// A list with one item per line, indented by `indent` levels, or only the item at `only`
+    List {
+        list: &'a ListNode,
+        indent: usize,
+        only: Option<usize>,
+        depth: usize,
+    },
+    /// A table as a pipe table
+    Table { table: &'a TableNode, depth: usize },
+}
+
+/// How a frame combines the text of its jobs.
+enum Finish<'a> {
+    /// Join the text of the nodes, starting blocks on a new line unless `separate`
+    Nodes {
+        nodes: Vec<&'a LexicalNode>,
+        separate: bool,
+    },
+    /// Concatenate the text after a prefix
+    Prefix(String),
+    /// Concatenate the text as a code block fenced with its language
+    Fence(Option<&'a str>),
+    /// One line per job, each after its prefix, with continuation lines aligned after the prefix
+    Lines(Vec<String>),
+    /// Lay out the cell texts as a table
+    Table(&'a TableNode),
+}
+
+/// A node being rendered, waiting for the text of its jobs.
+struct Frame<'a> {
+    jobs: std::vec::IntoIter<Job<'a>>,
+    parts: Vec<String>,
+    finish: Finish<'a>,
+}
+
+impl<'a> Frame<'a> {
+    fn new(jobs: Vec<Job<'a>>, finish: Finish<'a>) -> Self {
+        Self {
+            jobs: jobs.into_iter(),
+            parts: Vec::new(),
+            finish,
+        }
+    }
+
+    /// Combine the text of the jobs
+    fn finish(self, opts: &TextExtractOptions) -> String {
+        match self.finish {
+            Finish::Nodes { nodes, separate } => {
+                let mut text = String::new();
+                for (node, part) in nodes.into_iter().zip(self.parts) {
+                    // Start block content and list items on a new line so that words across them are not merged
+                    let starts_line = node.is_block() || matches!(node, LexicalNode::ListItem(_));
+                    if !separate && starts_line && !text.is_empty() && !text.ends_with('\n') {
+                        text.push_str(&opts.block_separator);
+                    }
+                    text.push_str(&part);
+                }
+                text
+            }
+            Finish::Prefix(prefix) => prefix + &self.parts.concat(),
+            Finish::Fence(language) => fence_code(&self.parts.concat(), language),
+            Finish::Lines(prefixes) => prefixes
+                .into_iter()
+                .zip(self.parts)
+                .map(|(prefix, part)| {
+                    let continuation = " ".repeat(prefix.chars().count());
+                    let mut lines = part.split('\n');
+                    let mut text = prefix + lines.next().unwrap_or_default();
+                    for line in lines {
+                        text.push('\n');
+                        if !line.is_empty() {
+                            text.push_str(&continuation);
+                            text.push_str(line);
+                        }
+                    }
+                    text
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Finish::Table(table) => layout_table(table, self.parts, &opts.cell_separator),
+        }
+    }
+}
+
+impl<'a> Job<'a> {
+    fn depth(&self) -> usize {
+        match self {
+            Job::Nodes { depth, .. } | Job::Node { depth, .. } | Job::List { depth, .. } | Job::Table { depth, .. } => {
+                *depth
+            }
+        }
+    }
+
+    /// Render the job to text, or to a frame waiting for the text of nested jobs
+    fn expand(self, opts: &TextExtractOptions) -> Result<String, Frame<'a>> {
+        match self {
+            Job::Nodes { nodes, separate, depth } => {
+                // Inline leaves, such as the text of a paragraph, are concatenated at once
+                if nodes.iter().all(|node| node.children().is_none() && !node.is_block()) {
+                    return Ok(nodes
+                        .iter()
+                        .filter_map(|node| Self::expand_node(node, depth, opts).ok())
+                        .collect());
+                }
+                let jobs = nodes.iter().map(|&node| Job::Node { node, depth }).collect();
+                Err(Frame::new(jobs, Finish::Nodes { nodes, separate }))
+            }
+            Job::Node { node, depth } => Self::expand_node(node, depth, opts),
+            Job::List {
+                list,
+                indent,
+                only,
+                depth,
+            } => Err(Self::expand_list(list, indent, only, depth, opts)),
+            Job::Table { table, depth } => {
+                // The cell contents, row by row
+                let jobs = table
+                    .children
+                    .iter()
+                    .filter_map(|row| match row {
+                        LexicalNode::TableRow(row) => Some(&row.children),
+                        _ => None,
+                    })
+                    .flatten()
+                    .filter_map(|cell| match cell {
+                        LexicalNode::TableCell(cell) => Some(Job::Nodes {
+                            nodes: cell.children.iter().collect(),
+                            separate: false,
+                            depth: depth + 3,
+                        }),
+                        _ => None,
+                    })
+                    .collect();
+                Err(Frame::new(jobs, Finish::Table(table)))
+            }
+        }
+    }
+
+    fn expand_node(node: &'a LexicalNode, depth: usize, opts: &TextExtractOptions) -> Result<String, Frame<'a>> {
+        let children = |children: &'a [LexicalNode], prefix: &str| {
+            let job = Job::Nodes {
+                nodes: children.iter().collect(),
+                separate: false,
+                depth: depth + 1,
+            };
+            Err(Frame::new(vec![job], Finish::Prefix(prefix.to_string())))
+        };
+
+        match node {
+            LexicalNode::Text(text_node) => Ok(text_node.text.clone()),
+            LexicalNode::Paragraph(para) => children(&para.children, ""),
+            LexicalNode::Heading(heading) => children(&heading.children, ""),
+            LexicalNode::List(list) => Err(Frame::new(
+                vec![Job::List {
+                    list,
+                    indent: 0,
+                    only: None,
+                    depth,
+                }],
+                Finish::Prefix(String::new()),
+            )),
+            LexicalNode::ListItem(item) => {
+                // Outside its list the item is a bullet, with its nested lists still indented
+                let (mut jobs, mut prefixes) = (Vec::new(), Vec::new());
+                Self::push_item(item, &opts.item_prefix, 0, depth, &mut jobs, &mut prefixes);
+                Err(Frame::new(jobs, Finish::Lines(prefixes)))
+            }
+            LexicalNode::Quote(quote) => children(&quote.children, ""),
+            LexicalNode::Code(code) => match (&code.text, &code.children) {
+                (_, Some(children)) => {
+                    let job = Job::Nodes {
+                        nodes: children.iter().collect(),
+                        separate: false,
+                        depth: depth + 1,
+                    };
+                    Err(Frame::new(vec![job], Finish::Fence(code.language.as_deref())))
+                }
+                (Some(text), None) => Ok(inline_code(text)),
+                (None, None) => Ok(String::new()),
+            },
+            LexicalNode::CodeHighlight(highlight) => Ok(highlight.text.clone()),
+            LexicalNode::LineBreak(_) => Ok("\n".to_string()),
+            LexicalNode::Link(link) => children(&link.children, ""),
+            LexicalNode::AutoLink(auto_link) => children(&auto_link.children, ""),
+            LexicalNode::Hashtag(hashtag) => Ok(hashtag.text.clone()),
+            LexicalNode::Table(table) => Err(Frame::new(vec![Job::Table { table, depth }], Finish::Prefix(String::new()))),
+            LexicalNode::TableRow(row) => children(&row.children, ""),
+            LexicalNode::TableCell(cell) => children(&cell.children, ""),
+            LexicalNode::AIEmbedding(_)
+            | LexicalNode::VoiceInput(_)
+            | LexicalNode::ChatMessage(_)
+            | LexicalNode::ChatSession(_)
+                if !opts.include_custom_nodes =>
+            {
+                Ok(String::new())
+            }
+            LexicalNode::AIEmbedding(ai) => Ok(ai.content.clone()),
+            LexicalNode::VoiceInput(voice) => Ok(voice.content.clone()),
+            LexicalNode::ChatMessage(msg) => Ok(msg.content.clone()),
+            LexicalNode::ChatSession(session) => {
+                let messages: Vec<&str> = session.messages.iter().map(|msg| msg.content.as_str()).collect();
+                Ok(messages.join("\n"))
+            }
+            LexicalNode::Mention(mention) => Ok(mention.text.clone()),
+            // Page breaks don't contribute to text content
+            LexicalNode::PageBreak(_) => Ok(String::new()),
+        }
+    }
+
+    /// Render a list with one item per line.
+    ///
+    /// Bullet items are prefixed with the item prefix, numbered items use their `value`
+    /// or count up from the list's `start` (default 1). Nested lists are indented by two spaces per
+    /// level and do not consume a number of the parent list. Following Lexical,
+    /// an item that only wraps a nested list gets no marker of its own. The blocks of
+    /// an item with several paragraphs go on their own lines, aligned after the marker.
+    /// With `only`, just the child at that index is rendered, numbered as in the whole list.
+    fn expand_list(
+        list: &'a ListNode,
+        indent: usize,
+        only: Option<usize>,
+        depth: usize,
+        opts: &TextExtractOptions,
+    ) -> Frame<'a> {
+        let mut number = list.start.unwrap_or(1);
+        let mut jobs = Vec::new();
+        let mut prefixes = Vec::new();
+
+        for (index, child) in list.children.iter().enumerate() {
+            let rendered = only.is_none_or(|only| only == index);
+            let LexicalNode::ListItem(item) = child else {
+                if rendered {
+                    jobs.push(Job::Nodes {
+                        nodes: vec![child],
+                        separate: false,
+                        depth: depth + 1,
+                    });
+                    prefixes.push("  ".repeat(indent));
+                }
+                continue;
+            };
+
+            let numbered = item.children.iter().any(|node| !matches!(node, LexicalNode::List(_)));
+            if numbered {
+                number = item.value.unwrap_or(number);
+            }
+            if rendered {
+                let marker = match list.list_type {
+                    ListType::Bullet => opts.item_prefix.clone(),
+                    ListType::Number => format!("{number}. "),
+                };
+                Self::push_item(item, &marker, indent, depth + 1, &mut jobs, &mut prefixes);
+            }
+            if numbered {
+                number += 1;
+            }
+        }
+
+        Frame::new(jobs, Finish::Lines(prefixes))
+    }
+
+    /// Add the lines of a list item at `depth`: its inline content after the marker,
+    /// unless it only wraps nested lists, then the nested lists one level deeper
+    fn push_item(
+        item: &'a ListItemNode,
+        marker: &str,
+        indent: usize,
+        depth: usize,
+        jobs: &mut Vec<Job<'a>>,
+        prefixes: &mut Vec<String>,
+    ) {
+        let (nested, inline): (Vec<&LexicalNode>, Vec<&LexicalNode>) = item
+            .children
+            .iter()
+            .partition(|node| matches!(node, LexicalNode::List(_)));
+
+        if !inline.is_empty() {
+            jobs.push(Job::Nodes {
+                nodes: inline,
+                separate: false,
+                depth: depth + 1,
+            });
+            prefixes.push(format!("{}{marker}", "  ".repeat(indent)));
+        }
+
+        for node in nested {
+            if let LexicalNode::List(nested_list) = node {
+                jobs.push(Job::List {
+                    list: nested_list,
+                    indent: indent + 1,
+                    only: None,
+                    depth: depth + 1,
+                });
+                prefixes.push(String::new());
+            }
+        }
+    }
+}
+
+/// Lay out a table one row per line, given the text of its cells row by row.
+///
+/// With a " | " separator this is a GitHub-style pipe table. The first row becomes
+/// the header row (followed by a separator line) if it contains header cells.
+/// Spanned cells are filled with empty cells so that the columns stay aligned.
+/// An empty table renders as an empty string.
+fn layout_table(table: &TableNode, texts: Vec<String>, separator: &str) -> String {
+    let is_pipe_table = separator.contains('|');
+    let mut texts = texts.into_iter();
+    let mut grid: Vec<Vec<String>> = Vec::new();
+    let mut first_row_is_header = false;
+    // Remaining rows each column is still covered by a cell from a previous row
+    let mut row_spans: Vec<u32> = Vec::new();
+
+    for (row_index, row) in table.children.iter().enumerate() {
+        let LexicalNode::TableRow(row) = row else {
+            continue;
+        };
+
+        let mut cells = Vec::new();
+        for cell in &row.children {
+            let LexicalNode::TableCell(cell) = cell else {
+                continue;
+            };
+
+            // Skip columns covered by a row-spanning cell above
+            while row_spans.get(cells.len()).is_some_and(|span| *span > 0) {
+                cells.push(String::new());
+            }
+
+            if row_index == 0 && cell.header_state != 0 {
+                first_row_is_header = true;
+            }
+
+            let mut text = texts.next().unwrap_or_default().replace('\n', " ");
+            if is_pipe_table {
+                text = text.replace('|', "\\|");
+            }
+            let col_span = cell.col_span.max(1) as usize;
+            let start = cells.len();
+            cells.push(text);
+            cells.extend(std::iter::repeat_n(String::new(), col_span - 1));
+
+            if row_spans.len() < cells.len() {
+                row_spans.resize(cells.len(), 0);
+            }
+            for span in &mut row_spans[start..cells.len()] {
+                *span = cell.row_span.max(1);
+            }
+        }
+
+        // Fill trailing columns covered by a row-spanning cell above
+        while row_spans.get(cells.len()).is_some_and(|span| *span > 0) {
+            cells.push(String::new());
+        }
+
+        for span in &mut row_spans {
+            *span = span.saturating_sub(1);
+        }
+        grid.push(cells);
+    }
+
+    let columns = grid.iter().map(Vec::len).max().unwrap_or(0);
+    if columns == 0 {
+        return String::new();
+    }
+
+    // A pipe table also has pipes at both ends of the line
+    let (start, end) = if is_pipe_table {
+        (separator.trim_start(), separator.trim_end())
+    } else {
+        ("", "")
+    };
+    let mut lines = Vec::new();
+    for (row_index, mut cells) in grid.into_iter().enumerate() {
+        cells.resize(columns, String::new());
+        lines.push(format!("{start}{}{end}", cells.join(separator)));
+
+        if row_index == 0 && first_row_is_header {
+            lines.push(format!("{start}{}{end}", vec!["---"; columns].join(separator)));
+        }
+    }
+
+    lines.join("\n")
+}
+
+impl Note {
+    /// Extract the text of sibling nodes.
+    ///
+    /// The nodes are walked with an explicit stack, so deeply nested notes cannot
+    /// overflow the call stack.
+    pub fn extract_text_with(&self, nodes: &[LexicalNode], opts: &TextExtractOptions) -> String {
+        let job = Job::Nodes {
+            nodes: nodes.iter().collect(),
+            separate: false,
+            depth: 1,
+        };
+        render(job, opts)
+    }
+
+    /// Export the note as plain text with light structure markers.
+    ///
+    /// Headings get `#` prefixes for their level, list items "- " bullets or numbers
+    /// indented by nesting, and code blocks are fenced as in the briefs. Unlike the Markdown export,
+    /// nothing is escaped and formatting is dropped, so the text reads well but does
+    /// not round-trip.
+    pub fn to_text_outline(&self) -> String {
+        let opts = TextExtractOptions {
+            item_prefix: "- ".to_string(),
+            ..TextExtractOptions::compat()
+        };
+        self.lexical_state
+            .root
+            .children
+            .iter()
+            .map(|node| {
+                let text = self.render_node_with(node, &opts).1;
+                match node {
+                    LexicalNode::Heading(heading) => format!("{} {text}", "#".repeat(heading.tag.level())),
+                    _ => text,
+                }
+            })
+            .filter(|text| !text.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    /// Extract the text of sibling nodes with the compatible options.
+    ///
+    /// Inline content is walked with the node iterator and its leaf texts concatenated.
+    /// Blocks and list items need the layout of the full rendering.
+    pub(super) fn extract_text_from_nodes(&self, nodes: &[LexicalNode]) -> String {
+        let opts = TextExtractOptions::compat();
+        let mut text = String::new();
+        for (path, node) in NodeIter::over(nodes) {
+            let depth = path.0.len();
+            if node.is_block() || matches!(node, LexicalNode::ListItem(_)) || depth > opts.max_depth {
+                return self.extract_text_with(nodes, &opts);
+            }
+            if let Ok(leaf) = Job::expand_node(node, depth, &opts) {
+                text.push_str(&leaf);
+            }
+        }
+        text
+    }
+
+    /// Render a root list with one item per line, indented by `depth` levels.
+    pub(super) fn render_list(&self, list: &ListNode, depth: usize, opts: &TextExtractOptions) -> String {
+        let job = Job::List {
+            list,
+            indent: depth,
+            only: None,
+            depth: 1,
+        };
+        render(job, opts)
+    }
+
+    /// Render the child of a list at `index` as it appears in the list, with its marker and
+    /// nested lists, indented by `depth` levels.
+    pub(super) fn render_list_item(
+        &self,
+        list: &ListNode,
+        index: usize,
+        depth: usize,
+        opts: &TextExtractOptions,
+    ) -> String {
+        let job = Job::List {
+            list,
+            indent: depth,
+            only: Some(index),
+            depth: 1,
+        };
+        render(job, opts)
+    }
+
+    /// Render a root table one row per line.
+    pub(super) fn render_table(&self, table: &TableNode, opts: &TextExtractOptions) -> String {
+        render(Job::Table { table, depth: 1 }, opts)
+    }
+}
+
+/// Render the job to text without recursion, leaving out jobs deeper than the maximum depth.
+fn render(job: Job<'_>, opts: &TextExtractOptions) -> String {
+    let max_depth = opts.max_depth;
+    let mut stack = vec![Frame::new(vec![job], Finish::Prefix(String::new()))];
+    let mut truncated = false;
+
+    loop {
+        let frame = stack.last_mut().expect("The outermost frame is finished last");
+        if let Some(job) = frame.jobs.next() {
+            if job.depth() > max_depth {
+                truncated = true;
+                frame.parts.push(String::new());
+                continue;
+            }
+            match job.expand(opts) {
+                Ok(text) => frame.parts.push(text),
+                Err(nested) => stack.push(nested),
+            }
+            continue;
+        }
+
+        let text = stack.pop().expect("The frame was just found").finish(opts);
+        match stack.last_mut() {
+            Some(parent) => parent.parts.push(text),
+            None => {
+                if truncated {
+                    tracing::warn!("Left out the text nested deeper than {} levels", max_depth);
+                }
+                return text;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::json;
+
+    use super::super::tests::{cell_json, heading_json, list_item_json, list_json, note_from_children, table_json, text_json};
+    use super::super::{BaseNodeProperties, BriefOptions, ListItemNode, TextNode};
+    use super::*;
+
+    /// Build a bullet list nested `levels` deep, each level holding its number as text
+    fn nested_list(levels: usize) -> LexicalNode {
+        let mut node = LexicalNode::Text(TextNode::new("deepest"));
+        for level in (0..levels).rev() {
+            let item = LexicalNode::ListItem(ListItemNode {
+                children: vec![LexicalNode::Text(TextNode::new(level.to_string())), node],
+                value: None,
+                base: BaseNodeProperties::default(),
+            });
+            node = LexicalNode::List(ListNode {
+                list_type: ListType::Bullet,
+                start: None,
+                tag: None,
+                children: vec![item],
+                base: BaseNodeProperties::default(),
+            });
+        }
+        node
+    }
+
+    /// Run `f`, collecting the events it logs
+    fn capture_logs<T>(f: impl FnOnce() -> T) -> (T, String) {
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || LogWriter(writer.clone()))
+            .with_ansi(false)
+            .without_time()
+            .finish();
+        let result = tracing::subscriber::with_default(subscriber, f);
+        let logs = String::from_utf8(logs.lock().unwrap().clone()).unwrap();
+        (result, logs)
+    }
+
+    /// Writes the log output to a shared buffer
+    struct LogWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_deeply_nested_json_is_rejected() {
+        let levels = 10_000;
+        let json = format!(
+            r#"{{"noteId":null,"lexicalState":{{"root":{{"type":"root","version":1,"children":[{}{}]}}}}}}"#,
+            r#"{"type":"list","version":1,"listType":"bullet","children":[{"type":"listitem","version":1,"children":["#
+                .repeat(levels),
+            "]}]}".repeat(levels),
+        );
+        let err = Note::from_json(&json).unwrap_err();
+        assert!(err.to_string().contains("maximum depth"), "{err}");
+    }
+
+    #[test]
+    fn test_deeply_nested_list() {
+        let mut note = note_from_children(json!([]));
+        note.lexical_state.root.children.push(nested_list(10_000));
+
+        // The note is briefed up to the depth limit, leaving the rest out with a warning
+        let (briefs, logs) = capture_logs(|| note.get_brief());
+        assert!(logs.contains(&format!("Left out the text nested deeper than {MAX_TEXT_DEPTH} levels")), "{logs}");
+        assert_eq!(briefs.len(), 1);
+        let lines: Vec<&str> = briefs[0].content.lines().collect();
+        assert_eq!(lines.len(), MAX_TEXT_DEPTH / 2);
+        assert_eq!(lines[1], "  • 1");
+        assert!(!briefs[0].content.contains("deepest"));
+
+        // Deeper limits render more of the list
+        let opts = TextExtractOptions {
+            max_depth: 2 * MAX_TEXT_DEPTH,
+            ..TextExtractOptions::compat()
+        };
+        let text = note.extract_text_with(&note.lexical_state.root.children, &opts);
+        assert_eq!(text.lines().count(), MAX_TEXT_DEPTH);
+
+        // The note clones and drops without overflowing the stack, and fails to serialize
+        let clone = note.clone();
+        assert_eq!(clone.iter_nodes().count(), note.iter_nodes().count());
+        let err = serde_json::to_string(&clone).unwrap_err();
+        assert!(err.to_string().contains("Note nesting exceeds the maximum depth of 128"), "{err}");
+    }
+
+    #[test]
+    fn test_nested_list_text_matches_lists() {
+        let note = note_from_children(json!([list_json(
+            "number",
+            None,
+            vec![
+                list_item_json(vec![text_json("One", 0)]),
+                list_item_json(vec![list_json("bullet", None, vec![list_item_json(vec![text_json("Nested", 0)])])]),
+                list_item_json(vec![text_json("Two", 0)]),
+            ],
+        )]));
+
+        let text = note.extract_text_from_nodes(&note.lexical_state.root.children);
+        assert_eq!(text, "1. One\n  • Nested\n2. Two");
+        let opts = TextExtractOptions {
+            max_depth: 3,
+            ..TextExtractOptions::compat()
+        };
+        assert_eq!(note.extract_text_with(&note.lexical_state.root.children, &opts), "1. One\n  • \n2. Two");
+    }
+
+    #[test]
+    fn test_extract_text_from_nodes_matches_rendering() {
+        for fixture in ["assets/example_note.json", "assets/example_outline_note.json", "assets/example_table_note.json"] {
+            let note = Note::from_json(&std::fs::read_to_string(fixture).unwrap()).unwrap();
+            let opts = TextExtractOptions::compat();
+            for (path, node) in note.iter_nodes() {
+                let Some(children) = node.children() else {
+                    continue;
+                };
+                assert_eq!(
+                    note.extract_text_from_nodes(children),
+                    note.extract_text_with(children, &opts),
+                    "{fixture} at {path}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_three_level_mixed_list_text() {
+        let paragraph = |text: &str| json!({ "type": "paragraph", "version": 1, "children": [text_json(text, 0)] });
+        let note = note_from_children(json!([list_json(
+            "number",
+            None,
+            vec![
+                list_item_json(vec![text_json("First", 0)]),
+                list_item_json(vec![list_json(
+                    "bullet",
+                    None,
+                    vec![
+                        list_item_json(vec![text_json("Alpha", 0)]),
+                        list_item_json(vec![list_json(
+                            "number",
+                            None,
+                            vec![list_item_json(vec![text_json("Deep one", 0)]), list_item_json(vec![text_json("Deep two", 0)])],
+                        )]),
+                        list_item_json(vec![text_json("Beta", 0)]),
+                    ],
+                )]),
+                list_item_json(vec![paragraph("Second"), paragraph("continued")]),
+                list_item_json(vec![text_json("Third", 0)]),
+            ],
+        )]));
+
+        assert_eq!(
+            note.to_plain_text(),
+            "1. First\n  • Alpha\n    1. Deep one\n    2. Deep two\n  • Beta\n2. Second\n   continued\n3. Third"
+        );
+        assert_eq!(
+            note.to_plain_text_with(&TextExtractOptions::readable()),
+            "1. First\n  - Alpha\n    1. Deep one\n    2. Deep two\n  - Beta\n2. Second\n\n   continued\n3. Third"
+        );
+    }
+
+    #[test]
+    fn test_list_start_numbers_items() {
+        let note = note_from_children(json!([list_json(
+            "number",
+            Some(3),
+            vec![
+                list_item_json(vec![text_json("Three", 0)]),
+                list_item_json(vec![list_json("number", Some(7), vec![list_item_json(vec![text_json("Seven", 0)])])]),
+                list_item_json(vec![text_json("Four", 0)]),
+            ],
+        )]));
+
+        assert_eq!(note.get_brief()[0].content, "3. Three\n  7. Seven\n4. Four");
+        assert_eq!(note.to_text_outline(), "3. Three\n  7. Seven\n4. Four");
+    }
+
+    fn structured_note() -> Note {
+        note_from_children(json!([
+            heading_json("Plan", "h1"),
+            list_json(
+                "bullet",
+                None,
+                vec![list_item_json(vec![text_json("One", 0)]), list_item_json(vec![text_json("Two", 0)])],
+            ),
+            table_json(vec![
+                vec![cell_json("Name", 1, 1, 1), cell_json("Role", 1, 1, 1)],
+                vec![cell_json("Ada", 0, 1, 1), cell_json("Math", 0, 1, 1)],
+            ]),
+            { "type": "ai-embedding", "version": 1, "content": "Generated", "isLoading": false },
+        ]))
+    }
+
+    #[test]
+    fn test_to_text_outline() {
+        let note = note_from_children(json!([
+            heading_json("Plan", "h1"),
+            { "type": "paragraph", "version": 1, "children": [text_json("Ship *it*", 1)] },
+            heading_json("Steps", "h3"),
+            list_json(
+                "bullet",
+                None,
+                vec![
+                    list_item_json(vec![text_json("Design", 0)]),
+                    list_item_json(vec![list_json("number", None, vec![list_item_json(vec![text_json("Sketch", 0)])])]),
+                ],
+            ),
+            { "type": "paragraph", "version": 1, "children": [] },
+            {
+                "type": "code",
+                "version": 1,
+                "format": "",
+                "language": "rust",
+                "children": [text_json("fn main() {}", 0)],
+            },
+        ]));
+
+        assert_eq!(
+            note.to_text_outline(),
+            "# Plan\n\nShip *it*\n\n### Steps\n\n- Design\n  1. Sketch\n\n```rust\nfn main() {}\n```"
+        );
+    }
+
+    #[test]
+    fn test_compat_preset() {
+        let note = structured_note();
+        assert_eq!(note.to_plain_text_with(&TextExtractOptions::compat()), note.to_plain_text());
+        assert_eq!(
+            note.to_plain_text(),
+            "Plan\n• One\n• Two\n| Name | Role |\n| --- | --- |\n| Ada | Math |\nGenerated"
+        );
+    }
+
+    #[test]
+    fn test_readable_preset() {
+        let note = structured_note();
+        assert_eq!(
+            note.to_plain_text_with(&TextExtractOptions::readable()),
+            "Plan\n\n- One\n- Two\n\n| Name | Role |\n| --- | --- |\n| Ada | Math |\n\nGenerated"
+        );
+    }
+
+    #[test]
+    fn test_custom_options() {
+        let note = structured_note();
+        let opts = TextExtractOptions {
+            cell_separator: "\t".to_string(),
+            include_custom_nodes: false,
+            ..TextExtractOptions::compat()
+        };
+
+        assert_eq!(note.to_plain_text_with(&opts), "Plan\n• One\n• Two\nName\tRole\n---\t---\nAda\tMath");
+        let briefs = note.get_brief_with(&BriefOptions {
+            text: opts,
+            ..Default::default()
+        });
+        assert_eq!(briefs.len(), 3);
+        assert_eq!(note.get_brief().len(), 4);
+    }
+}
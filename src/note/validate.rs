@@ -0,0 +1,258 @@
+use serde::Serialize;
+
+use super::{LexicalNode, Note, TableNode};
+
+/// How serious a validation issue is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The note should not be saved as is
+    Error,
+    /// An advisory about a usable note, such as a low-confidence transcript
+    Warning,
+}
+
+/// A problem found while validating a note.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ValidationIssue {
+    /// The child indices leading to the node, starting from the root
+    pub path: Vec<usize>,
+    pub message: String,
+    pub severity: Severity,
+}
+
+impl ValidationIssue {
+    /// Whether the issue makes the note invalid, rather than being a warning
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl Note {
+    /// Check the note for values that parse but are not usable, such as malformed timestamps,
+    /// links with unsafe URL schemes, unset versions and tables with rows of different widths.
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+        let root = &self.lexical_state.root;
+        if root.base.version == 0 {
+            issues.push(ValidationIssue {
+                path: Vec::new(),
+                message: "The root node has no version".to_string(),
+                severity: Severity::Error,
+            });
+        }
+
+        for (path, node) in self.iter_nodes() {
+            validate_node(node, &path.0, &mut issues);
+        }
+        issues
+    }
+}
+
+/// Validate a node and its descendants like [`Note::validate`], with paths relative to the node
+pub(super) fn validate_subtree(node: &LexicalNode) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    let mut stack = vec![(Vec::new(), node)];
+    while let Some((path, node)) = stack.pop() {
+        validate_node(node, &path, &mut issues);
+        for (index, child) in node.children().map_or(&[][..], Vec::as_slice).iter().enumerate().rev() {
+            stack.push(([&path[..], &[index]].concat(), child));
+        }
+    }
+    issues
+}
+
+/// Validate a single node, without its children
+fn validate_node(node: &LexicalNode, path: &[usize], issues: &mut Vec<ValidationIssue>) {
+    if node.base().version == 0 {
+        issues.push(ValidationIssue {
+            path: path.to_vec(),
+            message: format!("The {} node has no version", node.type_name()),
+            severity: Severity::Error,
+        });
+    }
+
+    match node {
+        LexicalNode::Table(table) => validate_table(table, path, issues),
+        LexicalNode::ChatMessage(message) => {
+            if let Err(err) = message.timestamp_parsed() {
+                issues.push(ValidationIssue {
+                    path: path.to_vec(),
+                    message: format!("Invalid timestamp \"{}\": {err}", message.timestamp),
+                    severity: Severity::Error,
+                });
+            }
+        }
+        LexicalNode::ChatSession(session) => {
+            for message in &session.messages {
+                if let Err(err) = message.timestamp_parsed() {
+                    issues.push(ValidationIssue {
+                        path: path.to_vec(),
+                        message: format!(
+                            "Invalid timestamp \"{}\" in message {} of session {}: {err}",
+                            message.timestamp, message.id, session.session_id
+                        ),
+                        severity: Severity::Error,
+                    });
+                }
+            }
+        }
+        LexicalNode::VoiceInput(voice) if voice.is_low_confidence() => {
+            issues.push(ValidationIssue {
+                path: path.to_vec(),
+                message: format!(
+                    "Low-confidence transcript ({:.2}), the text may be garbled",
+                    voice.confidence.unwrap_or_default()
+                ),
+                severity: Severity::Warning,
+            });
+        }
+        LexicalNode::Link(link) if !link.is_safe_url() => {
+            issues.push(ValidationIssue {
+                path: path.to_vec(),
+                message: format!("Unsafe link URL \"{}\"", link.url),
+                severity: Severity::Error,
+            });
+        }
+        LexicalNode::AutoLink(link) if !link.is_safe_url() => {
+            issues.push(ValidationIssue {
+                path: path.to_vec(),
+                message: format!("Unsafe link URL \"{}\"", link.url),
+                severity: Severity::Error,
+            });
+        }
+        _ => {}
+    }
+}
+
+/// Check that every row of the table spans the same number of columns
+fn validate_table(table: &TableNode, path: &[usize], issues: &mut Vec<ValidationIssue>) {
+    // The rows still covered by the cells spanning down from the rows above, per column
+    let mut row_spans: Vec<u32> = Vec::new();
+    let mut expected = None;
+
+    for (row_index, row) in table.children.iter().enumerate() {
+        let LexicalNode::TableRow(row) = row else {
+            issues.push(ValidationIssue {
+                path: [path, &[row_index]].concat(),
+                message: format!("A table has a {} node instead of a row", row.type_name()),
+                severity: Severity::Error,
+            });
+            continue;
+        };
+
+        let mut width = row_spans.len() as u32;
+        row_spans = row_spans.into_iter().filter(|&rows| rows > 1).map(|rows| rows - 1).collect();
+        for cell in &row.children {
+            if let LexicalNode::TableCell(cell) = cell {
+                width += cell.col_span;
+                if cell.row_span > 1 {
+                    row_spans.extend(std::iter::repeat_n(cell.row_span - 1, cell.col_span as usize));
+                }
+            }
+        }
+
+        match expected {
+            None => expected = Some(width),
+            Some(columns) if columns != width => issues.push(ValidationIssue {
+                path: [path, &[row_index]].concat(),
+                message: format!("Table row {row_index} spans {width} columns, but the first row spans {columns}"),
+                severity: Severity::Error,
+            }),
+            Some(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+
+    use super::super::tests::{cell_json, note_from_children, paragraph_json, table_json};
+    use super::*;
+
+    #[test]
+    fn test_validate_valid_notes() {
+        for path in ["assets/example_note.json", "assets/example_chat_note.json"] {
+            let json_content = fs::read_to_string(path).expect("Should be able to read the fixture");
+            let note: Note = serde_json::from_str(&json_content).expect("Should be able to parse the fixture");
+            assert_eq!(note.validate(), vec![], "{path} should be valid");
+        }
+    }
+
+    #[test]
+    fn test_validate_flags_bad_timestamps() {
+        let note = note_from_children(json!([
+            paragraph_json("Intro"),
+            {
+                "type": "chat-message",
+                "version": 1,
+                "sender": "user",
+                "content": "Hi",
+                "timestamp": "2025-06-01 10:00",
+            },
+            {
+                "type": "chat-session",
+                "version": 1,
+                "sessionId": "s1",
+                "messages": [
+                    { "id": 1, "sender": "user", "content": "Hi", "timestamp": "2025-06-01T10:00:00+05:30" },
+                    { "id": 2, "sender": "agent", "content": "Hello", "timestamp": "" },
+                ],
+            },
+        ]));
+
+        let issues = note.validate();
+        assert_eq!(issues.len(), 2, "{issues:?}");
+        assert_eq!(issues[0].path, vec![1]);
+        assert!(issues[0].message.starts_with("Invalid timestamp \"2025-06-01 10:00\""));
+        assert_eq!(issues[1].path, vec![2]);
+        assert!(issues[1].message.contains("message 2 of session s1"));
+    }
+
+    #[test]
+    fn test_validate_flags_unsafe_links() {
+        let link = |url: &str| json!({ "type": "link", "version": 1, "url": url, "children": [] });
+        let note = note_from_children(json!([{
+            "type": "paragraph",
+            "version": 1,
+            "children": [
+                link("https://example.com"),
+                link("JAVASCRIPT:alert(1)"),
+                { "type": "autolink", "version": 1, "url": "data:text/html,hi", "children": [] },
+            ],
+        }]));
+
+        let issues = note.validate();
+        assert_eq!(issues.len(), 2, "{issues:?}");
+        assert_eq!(issues[0].path, vec![0, 1]);
+        assert_eq!(issues[0].message, "Unsafe link URL \"JAVASCRIPT:alert(1)\"");
+        assert_eq!(issues[1].path, vec![0, 2]);
+    }
+
+    #[test]
+    fn test_validate_structure() {
+        let mut note = note_from_children(json!([
+            paragraph_json("Intro"),
+            table_json(vec![
+                vec![cell_json("A", 1, 1, 2), cell_json("B", 1, 1, 1)],
+                vec![cell_json("C", 0, 1, 1)],
+                vec![cell_json("D", 0, 1, 1), cell_json("E", 0, 1, 1), cell_json("F", 0, 1, 1)],
+            ]),
+        ]));
+        note.lexical_state.root.base.version = 0;
+        note.lexical_state.root.children[0].base_mut().version = 0;
+
+        let issues = note.validate();
+        assert_eq!(issues.len(), 3, "{issues:?}");
+        assert_eq!(issues[0].path, Vec::<usize>::new());
+        assert_eq!(issues[0].message, "The root node has no version");
+        assert_eq!(issues[1].path, vec![0]);
+        assert_eq!(issues[1].message, "The paragraph node has no version");
+        // The second row is complete with the cell spanning down from the first row
+        assert_eq!(issues[2].path, vec![1, 2]);
+        assert_eq!(issues[2].message, "Table row 2 spans 3 columns, but the first row spans 2");
+    }
+}
@@ -0,0 +1,315 @@
+use super::{BaseNodeProperties, ChatSessionMessage, ChatSessionNode, LexicalNode, NodePath, Note, PageBreakNode};
+
+/// A depth-first iterator over the nodes of a note, in document order.
+///
+/// Parents come before their children. Chat session messages are not nodes, so
+/// a chat session is a leaf like the other custom nodes; see [`Note::iter_session_messages`].
+pub struct NodeIter<'a> {
+    /// The path and remaining children of each open container, innermost last
+    stack: Vec<(NodePath, std::iter::Enumerate<std::slice::Iter<'a, LexicalNode>>)>,
+    /// The depth below which children are not visited
+    max_depth: usize,
+}
+
+impl<'a> NodeIter<'a> {
+    /// Iterate over sibling nodes and their descendants, with paths relative to the siblings.
+    pub(super) fn over(nodes: &'a [LexicalNode]) -> Self {
+        Self {
+            stack: vec![(NodePath::default(), nodes.iter().enumerate())],
+            max_depth: usize::MAX,
+        }
+    }
+
+    /// Only visit the nodes up to `depth` levels deep, so that `1` visits the root nodes.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+}
+
+impl<'a> Iterator for NodeIter<'a> {
+    type Item = (NodePath, &'a LexicalNode);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (parent, siblings) = self.stack.last_mut()?;
+            let Some((index, node)) = siblings.next() else {
+                self.stack.pop();
+                continue;
+            };
+
+            let path = parent.child(index);
+            if let Some(children) = node.children()
+                && path.0.len() < self.max_depth
+            {
+                self.stack.push((path.clone(), children.iter().enumerate()));
+            }
+            return Some((path, node));
+        }
+    }
+}
+
+impl Note {
+    /// Iterate over all nodes of the note depth-first, with their paths.
+    pub fn iter_nodes(&self) -> NodeIter<'_> {
+        NodeIter::over(&self.lexical_state.root.children)
+    }
+
+    /// Iterate over the messages of all chat sessions in document order, with the path
+    /// and the session they belong to.
+    pub fn iter_session_messages(&self) -> impl Iterator<Item = (NodePath, &ChatSessionNode, &ChatSessionMessage)> {
+        self.iter_nodes()
+            .filter_map(|(path, node)| match node {
+                LexicalNode::ChatSession(session) => Some((path, session)),
+                _ => None,
+            })
+            .flat_map(|(path, session)| {
+                session
+                    .messages
+                    .iter()
+                    .map(move |message| (path.clone(), session, message))
+            })
+    }
+
+    /// Visit all nodes of the note depth-first for in-place changes.
+    ///
+    /// A node is visited before its children, so the children visited are the ones
+    /// the node has after the visit.
+    pub fn visit_mut(&mut self, mut f: impl FnMut(&NodePath, &mut LexicalNode)) {
+        let mut stack = vec![(NodePath::default(), self.lexical_state.root.children.iter_mut().enumerate())];
+
+        while let Some((parent, siblings)) = stack.last_mut() {
+            let Some((index, node)) = siblings.next() else {
+                stack.pop();
+                continue;
+            };
+
+            let path = parent.child(index);
+            f(&path, node);
+            if let Some(children) = node.children_mut() {
+                stack.push((path, children.iter_mut().enumerate()));
+            }
+        }
+    }
+
+    /// Rewrite every text of the note in place.
+    ///
+    /// This covers the text of text, code highlight, hashtag and mention nodes, inline code,
+    /// and the content of AI embeddings, voice inputs and chat messages.
+    pub fn transform_text(&mut self, mut f: impl FnMut(&mut String)) {
+        self.visit_mut(|_, node| match node {
+            LexicalNode::Text(text) => f(&mut text.text),
+            LexicalNode::CodeHighlight(highlight) => f(&mut highlight.text),
+            LexicalNode::Hashtag(hashtag) => f(&mut hashtag.text),
+            LexicalNode::Mention(mention) => f(&mut mention.text),
+            LexicalNode::Code(code) => {
+                if let Some(text) = &mut code.text {
+                    f(text);
+                }
+            }
+            LexicalNode::AIEmbedding(ai) => f(&mut ai.content),
+            LexicalNode::VoiceInput(voice) => f(&mut voice.content),
+            LexicalNode::ChatMessage(message) => f(&mut message.content),
+            LexicalNode::ChatSession(session) => {
+                session.messages.iter_mut().for_each(|message| f(&mut message.content));
+            }
+            _ => {}
+        });
+    }
+
+    /// Replace every node of the note with the result of `f`, depth-first.
+    ///
+    /// A node is mapped before its children, and the children of the returned node
+    /// are mapped next. A node mapped to another type keeps the base properties of the
+    /// original node, such as its key and indent.
+    pub fn map_nodes(&mut self, mut f: impl FnMut(LexicalNode) -> LexicalNode) {
+        self.visit_mut(|_, node| {
+            let placeholder = LexicalNode::PageBreak(PageBreakNode {
+                base: BaseNodeProperties::default(),
+            });
+            let original = std::mem::replace(node, placeholder);
+            let (node_type, base) = (original.type_name(), original.base().clone());
+
+            *node = f(original);
+            if node.type_name() != node_type {
+                *node.base_mut() = base;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::{Value, json};
+
+    use super::super::tests::{list_item_json, list_json, note_from_children, paragraph_json, text_json};
+    use super::*;
+    use crate::note::LinkNode;
+
+    /// Collect the node paths and session messages by recursing over the "children" arrays of the JSON
+    fn recursive_paths(
+        nodes: &[Value],
+        parent: &NodePath,
+        paths: &mut Vec<(NodePath, String)>,
+        messages: &mut Vec<(NodePath, String)>,
+    ) {
+        for (index, node) in nodes.iter().enumerate() {
+            let path = parent.child(index);
+            paths.push((path.clone(), node["type"].as_str().unwrap().to_string()));
+            if let Some(session_messages) = node["messages"].as_array() {
+                for message in session_messages {
+                    messages.push((path.clone(), message["content"].as_str().unwrap().to_string()));
+                }
+            }
+            if let Some(children) = node["children"].as_array() {
+                recursive_paths(children, &path, paths, messages);
+            }
+        }
+    }
+
+    #[test]
+    fn test_iter_nodes_matches_recursive_walk() {
+        for fixture in [
+            "assets/example_note.json",
+            "assets/example_outline_note.json",
+            "assets/example_chat_note.json",
+            "assets/example_code_note.json",
+            "assets/example_table_note.json",
+        ] {
+            let json_content = fs::read_to_string(fixture).expect("Should be able to read the fixture");
+            let note: Note = serde_json::from_str(&json_content).expect("Should be able to parse the fixture");
+            let value: Value = serde_json::from_str(&json_content).unwrap();
+
+            let (mut expected, mut expected_messages) = (Vec::new(), Vec::new());
+            let root_children = value["lexicalState"]["root"]["children"].as_array().unwrap();
+            recursive_paths(root_children, &NodePath::default(), &mut expected, &mut expected_messages);
+            let actual: Vec<(NodePath, String)> = note
+                .iter_nodes()
+                .map(|(path, node)| (path, node.type_name().to_string()))
+                .collect();
+            assert_eq!(actual, expected, "{fixture} should be walked like the recursive walk");
+            let messages: Vec<(NodePath, String)> = note
+                .iter_session_messages()
+                .map(|(path, _, message)| (path, message.content.clone()))
+                .collect();
+            assert_eq!(messages, expected_messages, "{fixture} should have the same session messages");
+
+            // Every path leads back to its node
+            for (path, node) in note.iter_nodes() {
+                assert_eq!(note.get_by_path(&path).unwrap().type_name(), node.type_name());
+            }
+        }
+    }
+
+    #[test]
+    fn test_iter_nodes_order() {
+        let note = note_from_children(json!([
+            paragraph_json("One"),
+            list_json("bullet", None, vec![list_item_json(vec![text_json("Two", 0)])]),
+            { "type": "code", "version": 1, "format": "", "children": [text_json("three()", 0)] },
+        ]));
+
+        let paths: Vec<String> = note.iter_nodes().map(|(path, _)| path.to_string()).collect();
+        assert_eq!(paths, vec!["0", "0.0", "1", "1.0", "1.0.0", "2", "2.0"]);
+        assert_eq!(note_from_children(json!([])).iter_nodes().count(), 0);
+
+        let paths: Vec<String> = note.iter_nodes().max_depth(2).map(|(path, _)| path.to_string()).collect();
+        assert_eq!(paths, vec!["0", "0.0", "1", "1.0", "2", "2.0"]);
+    }
+
+    #[test]
+    fn test_visit_mut() {
+        let mut note = note_from_children(json!([
+            paragraph_json("one"),
+            list_json("bullet", None, vec![list_item_json(vec![text_json("two", 0)])]),
+        ]));
+
+        let mut visited = Vec::new();
+        note.visit_mut(|path, node| {
+            visited.push(path.to_string());
+            if let LexicalNode::Text(text) = node {
+                text.text = text.text.to_uppercase();
+            }
+        });
+
+        assert_eq!(visited, vec!["0", "0.0", "1", "1.0", "1.0.0"]);
+        let texts: Vec<String> = note.get_brief().into_iter().map(|brief| brief.content).collect();
+        assert_eq!(texts, vec!["ONE", "• TWO"]);
+    }
+
+    #[test]
+    fn test_transform_text() {
+        let mut note = note_from_children(json!([
+            paragraph_json("ACME ships"),
+            {
+                "type": "paragraph",
+                "version": 1,
+                "children": [
+                    { "type": "hashtag", "version": 1, "text": "#ACME", "format": 0 },
+                    { "type": "mention", "version": 1, "mentionName": "acme", "text": "@ACME", "format": 0 },
+                ],
+            },
+            { "type": "chat-message", "version": 1, "sender": "user", "content": "Ask ACME", "timestamp": "" },
+            paragraph_json("Untouched"),
+        ]));
+        let untouched = serde_json::to_string(&note.lexical_state.root.children[3]).unwrap();
+
+        note.transform_text(|text| *text = text.replace("ACME", "NewCo"));
+
+        let texts: Vec<String> = note.get_brief().into_iter().map(|brief| brief.content).collect();
+        assert_eq!(texts, vec!["NewCo ships", "#NewCo@NewCo", "[user] Ask NewCo", "Untouched"]);
+        // The mention name is not display text
+        assert_eq!(note.extract_mentions()[0].mention_name, "acme");
+        assert_eq!(serde_json::to_string(&note.lexical_state.root.children[3]).unwrap(), untouched);
+    }
+
+    #[test]
+    fn test_map_nodes_autolink_to_link() {
+        let mut note = note_from_children(json!([
+            {
+                "type": "paragraph",
+                "version": 1,
+                "children": [
+                    text_json("See ", 0),
+                    {
+                        "type": "autolink",
+                        "version": 1,
+                        "key": "7",
+                        "url": "https://example.com",
+                        "children": [text_json("example.com", 0)],
+                    },
+                ],
+            },
+            paragraph_json("Untouched"),
+        ]));
+        let untouched = serde_json::to_string(&note.lexical_state.root.children[1]).unwrap();
+
+        let mut mapped = 0;
+        note.map_nodes(|mut node| {
+            mapped += 1;
+            match &mut node {
+                LexicalNode::AutoLink(auto_link) => LexicalNode::Link(LinkNode {
+                    url: std::mem::take(&mut auto_link.url),
+                    rel: None,
+                    target: None,
+                    title: None,
+                    children: std::mem::take(&mut auto_link.children),
+                    base: BaseNodeProperties::default(),
+                }),
+                _ => node,
+            }
+        });
+
+        // Every node is mapped once, including the children of the new link
+        assert_eq!(mapped, 6);
+        let LexicalNode::Link(link) = &note.get_by_path(&"0.1".parse().unwrap()).unwrap() else {
+            panic!("Should be a link");
+        };
+        assert_eq!(link.url, "https://example.com");
+        assert_eq!(link.base.key.as_deref(), Some("7"));
+        assert!(note.extract_links().iter().all(|link| !link.is_autolink));
+        assert_eq!(serde_json::to_string(&note.lexical_state.root.children[1]).unwrap(), untouched);
+    }
+}
@@ -1,21 +1,184 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::Arc,
+    time::Duration,
+};
+
 use amico_core::types::ChatMessage;
-use reqwest::Client;
+use reqwest::{Client, StatusCode, Url};
 use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{Mutex, oneshot},
+    time::{sleep, timeout},
+};
+use tokio_with_wasm::alias as tokio;
+
+use crate::clock::now_millis;
+
+/// The model answering the chat completions.
+pub const MODEL: &str = "aimo-chat";
 
 /// Aimo AI API model.
 ///
 /// TODO: Integrate amico_sdk's `Model` trait.
 ///
 /// Waiting for amico Model to support WASM.
-#[derive(Debug)]
 pub struct AimoModel {
     base_url: String,
+    completion_path: String,
     jwt: String,
     client: Client,
+    rate_limiter: RateLimiter,
+    inflight: InflightRequests,
+    cache: Option<ResponseCache>,
+    timeout: Duration,
+    hooks: Hooks,
+}
+
+impl std::fmt::Debug for AimoModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The JWT is masked so that it cannot leak into logs
+        f.debug_struct("AimoModel")
+            .field("base_url", &self.base_url)
+            .field("completion_path", &self.completion_path)
+            .field("jwt", &"Bearer ***")
+            .field("client", &self.client)
+            .field("rate_limiter", &self.rate_limiter)
+            .field("inflight", &self.inflight)
+            .field("cache", &self.cache)
+            .field("timeout", &self.timeout)
+            .field("hooks", &self.hooks)
+            .finish()
+    }
 }
 
 const AIMO_BASE_URL: &str = "https://ai.aimoverse.xyz/api/v1.0.0";
 
+/// Default path of the completion endpoint under the base URL.
+const DEFAULT_COMPLETION_PATH: &str = "/chat/completions";
+
+/// Default number of completion requests allowed per second.
+const DEFAULT_REQUESTS_PER_SECOND: f64 = 1.0;
+
+/// Default number of completion requests allowed in a burst.
+const DEFAULT_BURST: u32 = 3;
+
+/// The lowest rate a rate limiter accepts, one request every 1000 seconds.
+const MIN_REQUESTS_PER_SECOND: f64 = 0.001;
+
+/// Default time to wait for a completion response.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A hook observing the body of a completion request or response.
+pub type BodyHook = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// The hooks observing the completion API calls, all no-ops unless set.
+#[derive(Clone, Default)]
+struct Hooks {
+    on_request: Option<BodyHook>,
+    on_response: Option<BodyHook>,
+}
+
+impl std::fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_request", &self.on_request.is_some())
+            .field("on_response", &self.on_response.is_some())
+            .finish()
+    }
+}
+
+/// Errors returned by the service layer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServiceError {
+    /// The JWT was rejected
+    Auth,
+    /// The request could not reach the API
+    Network(String),
+    /// The API rejected the request for exceeding its rate limit
+    RateLimited,
+    /// The response could not be decoded
+    Decode(String),
+    /// The API responded with an error status
+    Server(u16),
+    /// The API did not respond in time
+    Timeout,
+    /// The base URL and completion path do not form an HTTP URL
+    InvalidUrl(String),
+    /// There are no messages with content to send
+    NoMessages,
+    /// The agent finished the chat without a reply or an error of its own
+    NoReply,
+}
+
+impl ServiceError {
+    /// Get a short identifier of the error kind.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ServiceError::Auth => "auth",
+            ServiceError::Network(_) => "network",
+            ServiceError::RateLimited => "rate_limited",
+            ServiceError::Decode(_) => "decode",
+            ServiceError::Server(_) => "server",
+            ServiceError::Timeout => "timeout",
+            ServiceError::InvalidUrl(_) => "invalid_url",
+            ServiceError::NoMessages => "no_messages",
+            ServiceError::NoReply => "no_reply",
+        }
+    }
+
+    /// Whether the same request may succeed when retried.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ServiceError::Network(_) | ServiceError::RateLimited | ServiceError::Timeout | ServiceError::NoReply => true,
+            ServiceError::Server(status) => *status >= 500,
+            ServiceError::Auth | ServiceError::Decode(_) | ServiceError::InvalidUrl(_) | ServiceError::NoMessages => false,
+        }
+    }
+
+    /// Map an unsuccessful response status to an error.
+    fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ServiceError::Auth,
+            StatusCode::TOO_MANY_REQUESTS => ServiceError::RateLimited,
+            _ => ServiceError::Server(status.as_u16()),
+        }
+    }
+}
+
+impl std::fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServiceError::Auth => write!(f, "Authentication failed"),
+            ServiceError::Network(err) => write!(f, "Network error: {err}"),
+            ServiceError::RateLimited => write!(f, "Rate limited by the API"),
+            ServiceError::Decode(err) => write!(f, "Failed to decode response: {err}"),
+            ServiceError::Server(status) => write!(f, "Server error: status {status}"),
+            ServiceError::Timeout => write!(f, "Request timed out"),
+            ServiceError::InvalidUrl(url) => write!(f, "Invalid completion URL: {url}"),
+            ServiceError::NoMessages => write!(f, "No messages to send"),
+            ServiceError::NoReply => write!(f, "The agent did not reply"),
+        }
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+impl From<reqwest::Error> for ServiceError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            ServiceError::Timeout
+        } else if err.is_decode() {
+            ServiceError::Decode(err.to_string())
+        } else if let Some(status) = err.status() {
+            ServiceError::from_status(status)
+        } else {
+            ServiceError::Network(err.to_string())
+        }
+    }
+}
+
 impl AimoModel {
     /// Create a new AimoModel.
     pub fn new(jwt: String) -> Self {
@@ -24,31 +187,520 @@ impl AimoModel {
             jwt,
             client,
             base_url: AIMO_BASE_URL.to_string(),
+            completion_path: DEFAULT_COMPLETION_PATH.to_string(),
+            rate_limiter: RateLimiter::new(DEFAULT_REQUESTS_PER_SECOND, DEFAULT_BURST),
+            inflight: InflightRequests::default(),
+            cache: None,
+            timeout: DEFAULT_TIMEOUT,
+            hooks: Hooks::default(),
+        }
+    }
+
+    /// Limit the completion requests to `requests_per_second`, allowing bursts of `burst` requests.
+    ///
+    /// Requests beyond the limit wait for a permit instead of failing.
+    pub fn with_rate_limit(mut self, requests_per_second: f64, burst: u32) -> Self {
+        self.rate_limiter = RateLimiter::new(requests_per_second, burst);
+        self
+    }
+
+    /// Cache completion responses for `ttl`, keeping at most `capacity` entries.
+    ///
+    /// Caching is disabled unless enabled here.
+    pub fn with_response_cache(mut self, ttl: Duration, capacity: usize) -> Self {
+        self.cache = Some(ResponseCache::new(ttl, capacity));
+        self
+    }
+
+    /// Use another API base URL.
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Use another path for the completion endpoint under the base URL, such as "/v2/chat".
+    pub fn with_completion_path(mut self, path: impl Into<String>) -> Self {
+        self.completion_path = path.into();
+        self
+    }
+
+    /// Get the URL of the completion endpoint, joining the base URL and the completion path.
+    pub fn completion_url(&self) -> Result<Url, ServiceError> {
+        let url = format!(
+            "{}/{}",
+            self.base_url.trim_end_matches('/'),
+            self.completion_path.trim_start_matches('/')
+        );
+        match Url::parse(&url) {
+            Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => Ok(parsed),
+            _ => Err(ServiceError::InvalidUrl(url)),
         }
     }
 
+    /// Fail completion requests taking longer than `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Call `hook` with the JSON body of every completion request sent to the API.
+    ///
+    /// The hook only sees a copy of the body, never the headers, so it cannot change
+    /// the request or read the JWT. Cached and coalesced completions send no request.
+    pub fn with_request_hook(mut self, hook: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.hooks.on_request = Some(Arc::new(hook));
+        self
+    }
+
+    /// Call `hook` with the raw body of every completion response, including error responses.
+    pub fn with_response_hook(mut self, hook: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.hooks.on_response = Some(Arc::new(hook));
+        self
+    }
+
     /// Send a completion request to the Aimo model.
-    pub async fn completion(&self, messages: &Vec<ChatMessage>) -> anyhow::Result<String> {
+    ///
+    /// Concurrent requests with identical messages share a single API call,
+    /// and repeated requests are answered from the response cache if enabled.
+    /// Whitespace-only messages are left out, and without other messages this fails
+    /// with [`ServiceError::NoMessages`].
+    pub async fn completion(&self, messages: &[ChatMessage]) -> Result<String, ServiceError> {
         let request = RequestSchema {
-            model: "aimo-chat".to_string(),
-            messages: messages.clone(),
+            model: MODEL.to_string(),
+            messages: messages_to_send(messages)?,
             temperature: 0.5,
             max_tokens: 1000,
             top_p: 0.95,
             stream: 0,
         };
+        // The key covers the model, messages and sampling parameters
+        let key = serde_json::to_string(&request).expect("Request schema should serialize");
+
+        if let Some(content) = self.cache.as_ref().and_then(|cache| cache.get(&key)) {
+            tracing::debug!("Completion served from cache");
+            return Ok(content);
+        }
 
-        let response = self
-            .client
-            .post(format!("{}/chat/completions", self.base_url))
-            .header("Authorization", format!("Bearer {}", self.jwt))
-            .json(&request)
-            .send()
-            .await?
-            .json::<ResponseSchema>()
+        let content = self
+            .inflight
+            .coalesce(key.clone(), || self.send_completion(&request))
             .await?;
 
-        Ok(response.choices[0].message.content.clone())
+        if let Some(cache) = &self.cache {
+            cache.insert(key, content.clone());
+        }
+
+        Ok(content)
+    }
+
+    /// Stream a completion from the Aimo model, calling `on_delta` with each piece of content.
+    ///
+    /// Resolves with the assembled content. Streamed completions are neither cached
+    /// nor coalesced. In the browser the body is read at once, so the deltas arrive together.
+    pub async fn completion_stream(
+        &self,
+        messages: &[ChatMessage],
+        mut on_delta: impl FnMut(&str),
+    ) -> Result<String, ServiceError> {
+        let request = RequestSchema {
+            model: MODEL.to_string(),
+            messages: messages_to_send(messages)?,
+            temperature: 0.5,
+            max_tokens: 1000,
+            top_p: 0.95,
+            stream: 1,
+        };
+        let url = self.completion_url()?;
+        let body = serde_json::to_string(&request).expect("Request schema should serialize");
+        self.rate_limiter.acquire().await;
+
+        if let Some(hook) = &self.hooks.on_request {
+            hook(&body);
+        }
+
+        timeout(self.timeout, async {
+            #[cfg_attr(target_arch = "wasm32", allow(unused_mut))]
+            let mut response = self
+                .client
+                .post(url)
+                .header("Authorization", format!("Bearer {}", self.jwt))
+                .header("Content-Type", "application/json")
+                .body(body)
+                .send()
+                .await?;
+
+            let status = response.status();
+            if !status.is_success() {
+                let text = response.text().await?;
+                if let Some(hook) = &self.hooks.on_response {
+                    hook(&text);
+                }
+                return Err(ServiceError::from_status(status));
+            }
+
+            let mut events = EventStream::default();
+            let mut content = String::new();
+            let mut raw = Vec::new();
+            let mut push = |chunk: &[u8]| -> Result<(), ServiceError> {
+                raw.extend_from_slice(chunk);
+                for delta in events.push(chunk)? {
+                    on_delta(&delta);
+                    content.push_str(&delta);
+                }
+                Ok(())
+            };
+
+            #[cfg(not(target_arch = "wasm32"))]
+            while let Some(chunk) = response.chunk().await? {
+                push(&chunk)?;
+            }
+            #[cfg(target_arch = "wasm32")]
+            push(&response.bytes().await?)?;
+            push(b"\n")?;
+
+            if let Some(hook) = &self.hooks.on_response {
+                hook(&String::from_utf8_lossy(&raw));
+            }
+            Ok(content)
+        })
+        .await
+        .map_err(|_| ServiceError::Timeout)?
+    }
+
+    /// Send a completion request to the API.
+    async fn send_completion(&self, request: &RequestSchema) -> Result<String, ServiceError> {
+        let url = self.completion_url()?;
+        let body = serde_json::to_string(request).expect("Request schema should serialize");
+        self.rate_limiter.acquire().await;
+
+        if let Some(hook) = &self.hooks.on_request {
+            hook(&body);
+        }
+
+        let response = timeout(self.timeout, async {
+            let response = self
+                .client
+                .post(url)
+                .header("Authorization", format!("Bearer {}", self.jwt))
+                .header("Content-Type", "application/json")
+                .body(body)
+                .send()
+                .await?;
+
+            let status = response.status();
+            let text = response.text().await?;
+            if let Some(hook) = &self.hooks.on_response {
+                hook(&text);
+            }
+
+            if !status.is_success() {
+                return Err(ServiceError::from_status(status));
+            }
+
+            serde_json::from_str::<ResponseSchema>(&text).map_err(|err| ServiceError::Decode(err.to_string()))
+        })
+        .await
+        .map_err(|_| ServiceError::Timeout)??;
+
+        response
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message.content)
+            .ok_or_else(|| ServiceError::Decode("Response has no choices".to_string()))
+    }
+}
+
+/// Get the messages worth sending, without whitespace-only ones.
+///
+/// Fails before any request if none are left, as the API rejects an empty list
+/// with an unclear error.
+fn messages_to_send(messages: &[ChatMessage]) -> Result<Vec<ChatMessage>, ServiceError> {
+    let messages: Vec<ChatMessage> = messages
+        .iter()
+        .filter(|message| !message.content.trim().is_empty())
+        .cloned()
+        .collect();
+    if messages.is_empty() {
+        return Err(ServiceError::NoMessages);
+    }
+    Ok(messages)
+}
+
+/// A token bucket rate limiter.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bucket: Mutex<TokenBucket>,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_ms: f64,
+    last_refill: f64,
+}
+
+impl RateLimiter {
+    /// Create a rate limiter allowing `requests_per_second`, with bursts of `burst` requests.
+    ///
+    /// A rate that is not a positive number, such as 0 or NaN, would never permit a request
+    /// once the burst is used, so it falls back to the default rate. Lower positive rates
+    /// are raised to one request every 1000 seconds.
+    pub fn new(requests_per_second: f64, burst: u32) -> Self {
+        let requests_per_second = if requests_per_second.is_finite() && requests_per_second > 0.0 {
+            requests_per_second.max(MIN_REQUESTS_PER_SECOND)
+        } else {
+            tracing::warn!(
+                "Invalid rate limit of {} requests per second, using {}",
+                requests_per_second,
+                DEFAULT_REQUESTS_PER_SECOND
+            );
+            DEFAULT_REQUESTS_PER_SECOND
+        };
+        let capacity = f64::from(burst.max(1));
+        Self {
+            bucket: Mutex::new(TokenBucket {
+                capacity,
+                tokens: capacity,
+                refill_per_ms: requests_per_second / 1000.0,
+                last_refill: now_millis(),
+            }),
+        }
+    }
+
+    /// Wait until a request is permitted.
+    pub async fn acquire(&self) {
+        loop {
+            let wait_ms = {
+                let mut bucket = self.bucket.lock().await;
+                let now = now_millis();
+                let elapsed = (now - bucket.last_refill).max(0.0);
+                bucket.tokens = (bucket.tokens + elapsed * bucket.refill_per_ms).min(bucket.capacity);
+                bucket.last_refill = now;
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+
+                (1.0 - bucket.tokens) / bucket.refill_per_ms
+            };
+
+            tracing::debug!("Rate limited, waiting {:.0}ms", wait_ms);
+            sleep(Duration::from_secs_f64(wait_ms.max(1.0) / 1000.0)).await;
+        }
+    }
+}
+
+/// A caller waiting for the result of an in-flight request.
+type Waiter = oneshot::Sender<Result<String, ServiceError>>;
+
+/// Coalesces concurrent identical requests into a single in-flight request.
+#[derive(Debug, Default)]
+pub struct InflightRequests {
+    /// The requests in flight, with the callers waiting for their result
+    waiters: std::sync::Mutex<HashMap<String, Vec<Waiter>>>,
+}
+
+impl InflightRequests {
+    /// Run the request, or wait for the in-flight request with the same key.
+    ///
+    /// The entry is removed once the request resolves, so later calls run a new request.
+    /// If the call running the request is dropped first, the entry is removed too and
+    /// the waiting calls fail.
+    pub async fn coalesce<F, Fut>(&self, key: String, request: F) -> Result<String, ServiceError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<String, ServiceError>>,
+    {
+        let receiver = {
+            let mut waiters = self.waiters.lock().unwrap_or_else(|err| err.into_inner());
+            match waiters.get_mut(&key) {
+                Some(pending) => {
+                    let (tx, rx) = oneshot::channel();
+                    pending.push(tx);
+                    Some(rx)
+                }
+                None => {
+                    waiters.insert(key.clone(), Vec::new());
+                    None
+                }
+            }
+        };
+
+        if let Some(receiver) = receiver {
+            tracing::debug!("Joining in-flight request");
+            return receiver
+                .await
+                .unwrap_or_else(|_| Err(ServiceError::Network("In-flight request was dropped".to_string())));
+        }
+
+        let leader = LeaderGuard {
+            waiters: &self.waiters,
+            key: Some(key),
+        };
+        let result = request().await;
+        for tx in leader.resolve() {
+            // The waiter may have given up, nothing to do then.
+            let _ = tx.send(result.clone());
+        }
+
+        result
+    }
+}
+
+/// The entry of the request a call runs, removed even if the call is dropped before the
+/// request resolves, so that its waiters don't wait forever.
+struct LeaderGuard<'a> {
+    waiters: &'a std::sync::Mutex<HashMap<String, Vec<Waiter>>>,
+    /// The key of the entry, `None` once removed
+    key: Option<String>,
+}
+
+impl LeaderGuard<'_> {
+    /// Remove the entry of the resolved request, returning its waiters
+    fn resolve(mut self) -> Vec<Waiter> {
+        self.remove()
+    }
+
+    fn remove(&mut self) -> Vec<Waiter> {
+        let Some(key) = self.key.take() else {
+            return Vec::new();
+        };
+        self.waiters
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .remove(&key)
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for LeaderGuard<'_> {
+    fn drop(&mut self) {
+        // Dropping the senders fails the waiters
+        let waiters = self.remove();
+        if !waiters.is_empty() {
+            tracing::debug!("In-flight request dropped with {} waiting calls", waiters.len());
+        }
+    }
+}
+
+/// A least-recently-used cache of completion responses with a time-to-live.
+#[derive(Debug)]
+pub struct ResponseCache {
+    ttl_ms: f64,
+    capacity: usize,
+    entries: std::sync::Mutex<CacheEntries>,
+}
+
+#[derive(Debug, Default)]
+struct CacheEntries {
+    /// The cached responses, with the time they were stored
+    values: HashMap<String, (String, f64)>,
+    /// The keys from least to most recently used
+    order: VecDeque<String>,
+}
+
+impl CacheEntries {
+    fn remove(&mut self, key: &str) {
+        self.values.remove(key);
+        self.order.retain(|k| k != key);
+    }
+
+    fn touch(&mut self, key: &str) {
+        self.order.retain(|k| k != key);
+        self.order.push_back(key.to_string());
+    }
+}
+
+impl ResponseCache {
+    /// Create a cache keeping at most `capacity` responses for `ttl` each.
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl_ms: ttl.as_secs_f64() * 1000.0,
+            capacity,
+            entries: std::sync::Mutex::new(CacheEntries::default()),
+        }
+    }
+
+    /// Get the cached response for `key`, if present and not expired.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        let (value, stored_at) = entries.values.get(key)?.clone();
+
+        if now_millis() - stored_at >= self.ttl_ms {
+            entries.remove(key);
+            return None;
+        }
+
+        entries.touch(key);
+        Some(value)
+    }
+
+    /// Store the response for `key`, evicting the least recently used entry when full.
+    pub fn insert(&self, key: String, value: String) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        if !entries.values.contains_key(&key)
+            && entries.values.len() >= self.capacity
+            && let Some(oldest) = entries.order.pop_front()
+        {
+            entries.values.remove(&oldest);
+        }
+
+        entries.touch(&key);
+        entries.values.insert(key, (value, now_millis()));
+    }
+}
+
+/// Splits a server-sent event stream of completion chunks into content deltas.
+#[derive(Debug, Default)]
+struct EventStream {
+    /// The bytes of the incomplete last line
+    line: Vec<u8>,
+    done: bool,
+}
+
+impl EventStream {
+    /// Take the next bytes of the stream, returning the content deltas of the completed lines.
+    ///
+    /// Lines other than `data:` lines are ignored, and so is everything after `data: [DONE]`.
+    fn push(&mut self, bytes: &[u8]) -> Result<Vec<String>, ServiceError> {
+        let mut deltas = Vec::new();
+        for &byte in bytes {
+            if byte != b'\n' {
+                self.line.push(byte);
+                continue;
+            }
+
+            let line = std::mem::take(&mut self.line);
+            let line = String::from_utf8_lossy(&line);
+            let Some(data) = line.trim_end_matches('\r').strip_prefix("data:").map(str::trim) else {
+                continue;
+            };
+            if self.done || data.is_empty() {
+                continue;
+            }
+            if data == "[DONE]" {
+                self.done = true;
+                continue;
+            }
+
+            let chunk: StreamChunkSchema =
+                serde_json::from_str(data).map_err(|err| ServiceError::Decode(err.to_string()))?;
+            deltas.extend(
+                chunk
+                    .choices
+                    .into_iter()
+                    .filter_map(|choice| choice.delta.and_then(|delta| delta.content))
+                    .filter(|content| !content.is_empty()),
+            );
+        }
+        Ok(deltas)
     }
 }
 
@@ -80,9 +732,401 @@ struct ChoiceSchema {
     delta: Option<ChatMessage>,
 }
 
+#[derive(Debug, Deserialize)]
+struct StreamChunkSchema {
+    choices: Vec<StreamChoiceSchema>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamChoiceSchema {
+    delta: Option<DeltaSchema>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeltaSchema {
+    content: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct UsageSchema {
     prompt_tokens: u32,
     completion_tokens: u32,
     total_tokens: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+    };
+
+    use super::*;
+
+    /// Serve a single canned HTTP response on a local port, returning the base URL.
+    fn serve_once(response: &'static str) -> String {
+        serve_once_recording(response).0
+    }
+
+    /// Serve a single canned HTTP response, also returning a receiver for the request line.
+    fn serve_once_recording(response: &'static str) -> (String, std::sync::mpsc::Receiver<String>) {
+        let (request_tx, request_rx) = std::sync::mpsc::channel();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // Read the whole request before answering
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            while let Ok(n) = stream.read(&mut buf) {
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let length = text
+                        .lines()
+                        .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap()))
+                        .unwrap_or(0);
+                    if request.len() >= end + 4 + length {
+                        break;
+                    }
+                }
+                if n == 0 {
+                    break;
+                }
+            }
+            let request = String::from_utf8_lossy(&request);
+            let _ = request_tx.send(request.lines().next().unwrap_or_default().to_string());
+            // An empty response stalls until the client gives up
+            if response.is_empty() {
+                thread::sleep(Duration::from_millis(500));
+                return;
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        (format!("http://{addr}"), request_rx)
+    }
+
+    /// Build an HTTP response with a JSON body.
+    fn json_response(status: &str, body: &str) -> &'static str {
+        format!(
+            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+        .leak()
+    }
+
+    async fn complete_with(response: &'static str) -> Result<String, ServiceError> {
+        let model = AimoModel::new("jwt".to_string())
+            .with_base_url(serve_once(response))
+            .with_timeout(Duration::from_millis(100));
+        model.completion(&[ChatMessage { content: "Hi".to_string(), role: "user".to_string() }]).await
+    }
+
+    #[tokio::test]
+    async fn test_completion_errors_map_to_variants() {
+        let reply = r#"{"id":"1","object":"chat.completion","created":0,"model":"aimo-chat","choices":[{"index":0,"message":{"role":"assistant","content":"Hello"},"finish_reason":"stop","delta":null}],"usage":{"prompt_tokens":1,"completion_tokens":1,"total_tokens":2}}"#;
+        assert_eq!(complete_with(json_response("200 OK", reply)).await, Ok("Hello".to_string()));
+
+        assert_eq!(complete_with(json_response("401 Unauthorized", "{}")).await, Err(ServiceError::Auth));
+        assert_eq!(complete_with(json_response("403 Forbidden", "{}")).await, Err(ServiceError::Auth));
+        assert_eq!(
+            complete_with(json_response("429 Too Many Requests", "{}")).await,
+            Err(ServiceError::RateLimited)
+        );
+        assert_eq!(
+            complete_with(json_response("502 Bad Gateway", "{}")).await,
+            Err(ServiceError::Server(502))
+        );
+        assert!(matches!(
+            complete_with(json_response("200 OK", "not json")).await,
+            Err(ServiceError::Decode(_))
+        ));
+        assert_eq!(complete_with("").await, Err(ServiceError::Timeout));
+    }
+
+    #[tokio::test]
+    async fn test_completion_path() {
+        let reply = r#"{"id":"1","object":"chat.completion","created":0,"model":"aimo-chat","choices":[{"index":0,"message":{"role":"assistant","content":"Hello"},"finish_reason":"stop","delta":null}],"usage":{"prompt_tokens":1,"completion_tokens":1,"total_tokens":2}}"#;
+        let (base_url, requests) = serve_once_recording(json_response("200 OK", reply));
+        let model = AimoModel::new("jwt".to_string())
+            .with_base_url(format!("{base_url}/api/"))
+            .with_completion_path("v2/chat");
+
+        assert_eq!(model.completion_url().unwrap().as_str(), format!("{base_url}/api/v2/chat"));
+        let content = model
+            .completion(&[ChatMessage { content: "Hi".to_string(), role: "user".to_string() }])
+            .await;
+        assert_eq!(content, Ok("Hello".to_string()));
+        assert_eq!(requests.recv().unwrap(), "POST /api/v2/chat HTTP/1.1");
+
+        // The default path keeps the previous endpoint
+        let model = AimoModel::new("jwt".to_string());
+        assert_eq!(model.completion_url().unwrap().as_str(), "https://ai.aimoverse.xyz/api/v1.0.0/chat/completions");
+
+        let model = AimoModel::new("jwt".to_string()).with_base_url("ftp://example.com");
+        let err = model
+            .completion(&[ChatMessage { content: "Hi".to_string(), role: "user".to_string() }])
+            .await
+            .unwrap_err();
+        assert_eq!(err, ServiceError::InvalidUrl("ftp://example.com/chat/completions".to_string()));
+        assert!(!err.is_retryable());
+        assert!(AimoModel::new("jwt".to_string()).with_base_url("not a url").completion_url().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_completion_hooks() {
+        let reply = r#"{"id":"1","object":"chat.completion","created":0,"model":"aimo-chat","choices":[{"index":0,"message":{"role":"assistant","content":"Hello"},"finish_reason":"stop","delta":null}],"usage":{"prompt_tokens":1,"completion_tokens":1,"total_tokens":2}}"#;
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let responses = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let model = AimoModel::new("secret-jwt".to_string())
+            .with_base_url(serve_once(json_response("200 OK", reply)))
+            .with_response_cache(Duration::from_secs(60), 4)
+            .with_request_hook({
+                let requests = requests.clone();
+                move |body| requests.lock().unwrap().push(body.to_string())
+            })
+            .with_response_hook({
+                let responses = responses.clone();
+                move |body| responses.lock().unwrap().push(body.to_string())
+            });
+
+        let messages = [ChatMessage { content: "Hi".to_string(), role: "user".to_string() }];
+        assert_eq!(model.completion(&messages).await, Ok("Hello".to_string()));
+        // The cached completion makes no API call
+        assert_eq!(model.completion(&messages).await, Ok("Hello".to_string()));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].contains(r#""content":"Hi""#));
+        assert!(!requests[0].contains("secret-jwt"));
+        assert_eq!(*responses.lock().unwrap(), vec![reply.to_string()]);
+        assert!(format!("{model:?}").contains("on_request: true"));
+    }
+
+    #[tokio::test]
+    async fn test_completion_without_messages() {
+        let requests = Arc::new(std::sync::Mutex::new(0));
+        // No server is listening, so a request would fail with a network error
+        let model = AimoModel::new("jwt".to_string())
+            .with_base_url("http://127.0.0.1:9".to_string())
+            .with_request_hook({
+                let requests = requests.clone();
+                move |_| *requests.lock().unwrap() += 1
+            });
+
+        assert_eq!(model.completion(&[]).await, Err(ServiceError::NoMessages));
+        let blank = [
+            ChatMessage { content: "  ".to_string(), role: "user".to_string() },
+            ChatMessage { content: "\n\t".to_string(), role: "user".to_string() },
+        ];
+        assert_eq!(model.completion(&blank).await, Err(ServiceError::NoMessages));
+        assert_eq!(model.completion_stream(&blank, |_| {}).await, Err(ServiceError::NoMessages));
+        assert_eq!(*requests.lock().unwrap(), 0);
+        assert_eq!(ServiceError::NoMessages.to_string(), "No messages to send");
+    }
+
+    #[test]
+    fn test_messages_to_send_drops_blank_messages() {
+        let messages = [
+            ChatMessage { content: "System".to_string(), role: "system".to_string() },
+            ChatMessage { content: " ".to_string(), role: "user".to_string() },
+            ChatMessage { content: "Hi".to_string(), role: "user".to_string() },
+        ];
+        let sent: Vec<String> = messages_to_send(&messages).unwrap().into_iter().map(|m| m.content).collect();
+        assert_eq!(sent, vec!["System", "Hi"]);
+    }
+
+    #[tokio::test]
+    async fn test_completion_stream() {
+        let events = concat!(
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\"}}]}\r\n\r\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"{\\\"action\\\": \"}}]}\n\n",
+            ": keep-alive\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"\\\"reply\\\"}\"}}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{events}",
+            events.len()
+        )
+        .leak();
+        let model = AimoModel::new("jwt".to_string()).with_base_url(serve_once(response));
+
+        let mut deltas = Vec::new();
+        let content = model
+            .completion_stream(&[ChatMessage { content: "Hi".to_string(), role: "user".to_string() }], |delta| {
+                deltas.push(delta.to_string())
+            })
+            .await;
+        assert_eq!(content, Ok(r#"{"action": "reply"}"#.to_string()));
+        assert_eq!(deltas, vec![r#"{"action": "#, r#""reply"}"#]);
+    }
+
+    #[test]
+    fn test_event_stream_split_across_chunks() {
+        let mut events = EventStream::default();
+        assert_eq!(events.push(b"data: {\"choices\":[{\"delta\":{\"con").unwrap(), Vec::<String>::new());
+        assert_eq!(events.push(b"tent\":\"Hel\"}}]}\n\ndata: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\n").unwrap(), vec!["Hel", "lo"]);
+        assert_eq!(events.push(b"data: [DONE]\ndata: {\"choices\":[]}\n").unwrap(), Vec::<String>::new());
+        assert!(matches!(EventStream::default().push(b"data: nope\n"), Err(ServiceError::Decode(_))));
+    }
+
+    #[test]
+    fn test_debug_masks_jwt() {
+        let model = AimoModel::new("eyJhbGciOiJIUzI1NiJ9.secret-payload.signature".to_string());
+        let debug = format!("{model:?}");
+
+        assert!(!debug.contains("secret-payload"), "{debug}");
+        assert!(debug.contains("Bearer ***"));
+        assert!(debug.contains("https://ai.aimoverse.xyz/api/v1.0.0"));
+    }
+
+    #[tokio::test]
+    async fn test_completion_network_error() {
+        // Nothing listens on the port once the listener is dropped
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let model = AimoModel::new("jwt".to_string()).with_base_url(format!("http://{addr}"));
+
+        let err = model
+            .completion(&[ChatMessage { content: "Hi".to_string(), role: "user".to_string() }])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), "network");
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_invalid_rates() {
+        // The rates would wait forever or panic, so they fall back to the default
+        for rate in [0.0, -5.0, f64::NAN, f64::INFINITY] {
+            let limiter = RateLimiter::new(rate, 1);
+            assert_eq!(limiter.bucket.lock().await.refill_per_ms, DEFAULT_REQUESTS_PER_SECOND / 1000.0);
+        }
+        let limiter = RateLimiter::new(0.0, 1);
+        limiter.acquire().await;
+        assert!(timeout(Duration::from_millis(1500), limiter.acquire()).await.is_ok());
+        let limiter = RateLimiter::new(1e-300, 1);
+        assert_eq!(limiter.bucket.lock().await.refill_per_ms, MIN_REQUESTS_PER_SECOND / 1000.0);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_spaces_requests() {
+        // 20 requests per second is one every 50ms after a burst of 2
+        let limiter = RateLimiter::new(20.0, 2);
+        let start = now_millis();
+
+        let mut times = Vec::new();
+        for _ in 0..5 {
+            limiter.acquire().await;
+            times.push(now_millis() - start);
+        }
+
+        assert!(times[1] < 25.0, "The burst should not wait: {times:?}");
+        for pair in times[1..].windows(2) {
+            assert!(pair[1] - pair[0] >= 45.0, "Requests should be spaced: {times:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_inflight_requests_coalesce() {
+        let inflight = InflightRequests::default();
+        let calls = AtomicUsize::new(0);
+        let request = || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            sleep(Duration::from_millis(20)).await;
+            Ok("reply".to_string())
+        };
+
+        let (first, second) = tokio::join!(
+            inflight.coalesce("same".to_string(), request),
+            inflight.coalesce("same".to_string(), request),
+        );
+        assert_eq!(first.unwrap(), "reply");
+        assert_eq!(second.unwrap(), "reply");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // The entry expires once resolved
+        inflight.coalesce("same".to_string(), request).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // Different keys do not share a request
+        let (_, _) = tokio::join!(
+            inflight.coalesce("a".to_string(), request),
+            inflight.coalesce("b".to_string(), request),
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_inflight_requests_dropped_leader() {
+        let inflight = InflightRequests::default();
+        let mut leader = Box::pin(inflight.coalesce("key".to_string(), std::future::pending));
+        let mut waiter = Box::pin(inflight.coalesce("key".to_string(), || async {
+            panic!("The waiter should not run the request")
+        }));
+        // Start both calls, the second one waits for the first
+        assert!(timeout(Duration::from_millis(10), &mut leader).await.is_err());
+        assert!(timeout(Duration::from_millis(10), &mut waiter).await.is_err());
+
+        drop(leader);
+        assert_eq!(
+            waiter.await,
+            Err(ServiceError::Network("In-flight request was dropped".to_string()))
+        );
+        // A later call runs a new request instead of waiting forever
+        let result = inflight.coalesce("key".to_string(), || async { Ok("fresh".to_string()) });
+        assert_eq!(timeout(Duration::from_millis(100), result).await, Ok(Ok("fresh".to_string())));
+    }
+
+    #[tokio::test]
+    async fn test_inflight_requests_share_errors() {
+        let inflight = InflightRequests::default();
+        let request = || async {
+            sleep(Duration::from_millis(20)).await;
+            Err(ServiceError::Auth)
+        };
+
+        let (first, second) = tokio::join!(
+            inflight.coalesce("key".to_string(), request),
+            inflight.coalesce("key".to_string(), request),
+        );
+        assert_eq!(first.unwrap_err(), ServiceError::Auth);
+        assert_eq!(second.unwrap_err(), ServiceError::Auth);
+    }
+
+    #[test]
+    fn test_response_cache_hit() {
+        let cache = ResponseCache::new(Duration::from_secs(60), 4);
+        assert_eq!(cache.get("prompt"), None);
+
+        cache.insert("prompt".to_string(), "reply".to_string());
+        assert_eq!(cache.get("prompt").as_deref(), Some("reply"));
+        assert_eq!(cache.get("other"), None);
+    }
+
+    #[tokio::test]
+    async fn test_response_cache_expiry() {
+        let cache = ResponseCache::new(Duration::from_millis(30), 4);
+        cache.insert("prompt".to_string(), "reply".to_string());
+        assert!(cache.get("prompt").is_some());
+
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(cache.get("prompt"), None);
+    }
+
+    #[test]
+    fn test_response_cache_evicts_least_recently_used() {
+        let cache = ResponseCache::new(Duration::from_secs(60), 2);
+        cache.insert("a".to_string(), "1".to_string());
+        cache.insert("b".to_string(), "2".to_string());
+
+        // Using "a" makes "b" the least recently used
+        assert!(cache.get("a").is_some());
+        cache.insert("c".to_string(), "3".to_string());
+
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("a").as_deref(), Some("1"));
+        assert_eq!(cache.get("c").as_deref(), Some("3"));
+    }
+}